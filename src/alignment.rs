@@ -1,24 +1,714 @@
-use anyhow::Result;
-use crate::binary_optimizer::BinaryOptimizer;
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use crate::long_align::{
+    anchored_align, banded_global, hirschberg_global, score_ops, AlignmentMode, AUTO_FULL_CELLS, DEFAULT_X_DROP,
+};
+use crate::kmer_filter::base_code;
+use crate::minimizer::{ChainParams, MinimizerIndex};
+use crate::striped_sw::{scalar_local_score, LocalScore, ScorePrecision, StripedProfile};
+use crate::substitution::SubstitutionMatrix;
+use std::sync::Arc;
+
+/// Largest DP matrix (query × target cells) the full-matrix aligner will allocate
+const MAX_DP_CELLS: usize = 1 << 30;
+
+/// Pairwise alignment mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentAlgorithm {
+    /// Needleman-Wunsch: both sequences aligned end to end
+    Global,
+    /// Smith-Waterman: best-scoring pair of subsequences
+    Local,
+    /// End gaps on either sequence are free (overlap / glocal alignment)
+    SemiGlobal,
+}
+
+impl std::str::FromStr for AlignmentAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "global" | "nw" | "needleman-wunsch" => Ok(AlignmentAlgorithm::Global),
+            "local" | "sw" | "smith-waterman" => Ok(AlignmentAlgorithm::Local),
+            "semi-global" | "semiglobal" | "glocal" => Ok(AlignmentAlgorithm::SemiGlobal),
+            _ => bail!("Unknown alignment algorithm: {} (use global, local or semi-global)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for AlignmentAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlignmentAlgorithm::Global => write!(f, "global"),
+            AlignmentAlgorithm::Local => write!(f, "local"),
+            AlignmentAlgorithm::SemiGlobal => write!(f, "semi-global"),
+        }
+    }
+}
+
+/// Match/mismatch and affine gap scores
+///
+/// A gap of length L costs `gap_open + L * gap_extend`, as in BLAST. With a
+/// substitution matrix set, residue pairs score from the matrix instead of
+/// the match/mismatch pair.
+#[derive(Debug, Clone)]
+pub struct ScoringScheme {
+    pub match_score: i32,
+    pub mismatch: i32,
+    pub gap_open: i32,
+    pub gap_extend: i32,
+    pub matrix: Option<Arc<SubstitutionMatrix>>,
+}
+
+impl Default for ScoringScheme {
+    /// BLASTN defaults: +2/-3, open 5, extend 2
+    fn default() -> Self {
+        Self {
+            match_score: 2,
+            mismatch: -3,
+            gap_open: 5,
+            gap_extend: 2,
+            matrix: None,
+        }
+    }
+}
+
+impl ScoringScheme {
+    pub fn score(&self, a: u8, b: u8) -> i32 {
+        if let Some(matrix) = &self.matrix {
+            matrix.score(a, b)
+        } else if self.is_match(a, b) {
+            self.match_score
+        } else {
+            self.mismatch
+        }
+    }
+
+    /// Whether an aligned pair counts as an identity
+    ///
+    /// Matrix scoring compares residues as letters; nucleotide scoring only
+    /// matches A, C, G and T/U, so N and IUPAC codes are always mismatches.
+    /// The striped Smith-Waterman profile is built from this same rule.
+    pub fn is_match(&self, a: u8, b: u8) -> bool {
+        if self.matrix.is_some() {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            base_code(a).is_some() && base_code(a) == base_code(b)
+        }
+    }
+}
+
+/// One column of an alignment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignOp {
+    Match,
+    Mismatch,
+    /// Base in the query only
+    Insertion,
+    /// Base in the target only
+    Deletion,
+}
+
+/// Result of aligning a query against a target
+#[derive(Debug, Clone)]
+pub struct PairwiseAlignment {
+    pub algorithm: AlignmentAlgorithm,
+    pub score: i32,
+    /// Zero-based half-open span of the query covered by the alignment
+    pub query_start: usize,
+    pub query_end: usize,
+    /// Zero-based half-open span of the target covered by the alignment
+    pub target_start: usize,
+    pub target_end: usize,
+    pub ops: Vec<AlignOp>,
+    pub aligned_query: Vec<u8>,
+    pub aligned_target: Vec<u8>,
+}
+
+impl PairwiseAlignment {
+    /// Assemble an alignment from its operations and the sequences it spans
+    ///
+    /// `start` is the (query, target) offset of the first column. Diagonal
+    /// columns are classified as match or mismatch by the scoring scheme.
+    pub fn from_ops(
+        algorithm: AlignmentAlgorithm,
+        score: i32,
+        scoring: &ScoringScheme,
+        query: &[u8],
+        target: &[u8],
+        start: (usize, usize),
+        mut ops: Vec<AlignOp>,
+    ) -> Self {
+        let (query_start, target_start) = start;
+        let mut aligned_query = Vec::with_capacity(ops.len());
+        let mut aligned_target = Vec::with_capacity(ops.len());
+        let (mut i, mut j) = (query_start, target_start);
+        for op in ops.iter_mut() {
+            match op {
+                AlignOp::Match | AlignOp::Mismatch => {
+                    *op = if scoring.is_match(query[i], target[j]) {
+                        AlignOp::Match
+                    } else {
+                        AlignOp::Mismatch
+                    };
+                    aligned_query.push(query[i]);
+                    aligned_target.push(target[j]);
+                    i += 1;
+                    j += 1;
+                }
+                AlignOp::Insertion => {
+                    aligned_query.push(query[i]);
+                    aligned_target.push(b'-');
+                    i += 1;
+                }
+                AlignOp::Deletion => {
+                    aligned_query.push(b'-');
+                    aligned_target.push(target[j]);
+                    j += 1;
+                }
+            }
+        }
+
+        Self {
+            algorithm,
+            score,
+            query_start,
+            query_end: i,
+            target_start,
+            target_end: j,
+            ops,
+            aligned_query,
+            aligned_target,
+        }
+    }
+
+    pub fn columns(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn matches(&self) -> usize {
+        self.ops.iter().filter(|&&op| op == AlignOp::Match).count()
+    }
+
+    pub fn mismatches(&self) -> usize {
+        self.ops.iter().filter(|&&op| op == AlignOp::Mismatch).count()
+    }
+
+    pub fn gap_bases(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|&&op| matches!(op, AlignOp::Insertion | AlignOp::Deletion))
+            .count()
+    }
+
+    pub fn gap_opens(&self) -> usize {
+        self.ops
+            .iter()
+            .zip(std::iter::once(&AlignOp::Match).chain(self.ops.iter()))
+            .filter(|&(&op, &prev)| matches!(op, AlignOp::Insertion | AlignOp::Deletion) && op != prev)
+            .count()
+    }
+
+    /// Aligned residue pairs with a positive score, identities included
+    pub fn positives(&self, scoring: &ScoringScheme) -> usize {
+        self.aligned_query
+            .iter()
+            .zip(&self.aligned_target)
+            .filter(|&(&q, &t)| q != b'-' && t != b'-' && scoring.score(q, t) > 0)
+            .count()
+    }
+
+    /// Identical columns over all alignment columns, as BLAST reports it
+    pub fn identity(&self) -> f64 {
+        if self.ops.is_empty() {
+            return 0.0;
+        }
+        self.matches() as f64 / self.columns() as f64
+    }
+
+    /// Identity where each gap counts as a single difference
+    pub fn gap_compressed_identity(&self) -> f64 {
+        let length = self.columns() - self.gap_bases() + self.gap_opens();
+        if length == 0 {
+            return 0.0;
+        }
+        self.matches() as f64 / length as f64
+    }
+
+    /// SAM-style CIGAR using M/I/D
+    pub fn cigar(&self) -> String {
+        self.run_length(|op| match op {
+            AlignOp::Match | AlignOp::Mismatch => 'M',
+            AlignOp::Insertion => 'I',
+            AlignOp::Deletion => 'D',
+        })
+    }
+
+    /// Extended CIGAR distinguishing matches (=) from mismatches (X)
+    pub fn extended_cigar(&self) -> String {
+        self.run_length(|op| match op {
+            AlignOp::Match => '=',
+            AlignOp::Mismatch => 'X',
+            AlignOp::Insertion => 'I',
+            AlignOp::Deletion => 'D',
+        })
+    }
+
+    fn run_length(&self, symbol: impl Fn(AlignOp) -> char) -> String {
+        let mut cigar = String::new();
+        let mut current: Option<(char, usize)> = None;
+        for &op in &self.ops {
+            let c = symbol(op);
+            current = match current {
+                Some((prev, len)) if prev == c => Some((prev, len + 1)),
+                Some((prev, len)) => {
+                    cigar.push_str(&format!("{}{}", len, prev));
+                    Some((c, 1))
+                }
+                None => Some((c, 1)),
+            };
+        }
+        if let Some((c, len)) = current {
+            cigar.push_str(&format!("{}{}", len, c));
+        }
+        cigar
+    }
+
+    /// BLAST-style pairwise display with 1-based coordinates
+    pub fn pretty(&self, width: usize) -> String {
+        let width = width.max(10);
+        let coord_width = self.query_end.max(self.target_end).to_string().len();
+        let mut out = String::new();
+        let (mut q, mut t) = (self.query_start, self.target_start);
+
+        for start in (0..self.ops.len()).step_by(width) {
+            let end = (start + width).min(self.ops.len());
+            let q_bases = self.aligned_query[start..end].iter().filter(|&&c| c != b'-').count();
+            let t_bases = self.aligned_target[start..end].iter().filter(|&&c| c != b'-').count();
+            let midline: String = self.ops[start..end]
+                .iter()
+                .map(|op| match op {
+                    AlignOp::Match => '|',
+                    AlignOp::Mismatch => '.',
+                    _ => ' ',
+                })
+                .collect();
+
+            out.push_str(&format!(
+                "Query  {:>w$}  {}  {}\n",
+                q + 1,
+                String::from_utf8_lossy(&self.aligned_query[start..end]),
+                q + q_bases,
+                w = coord_width
+            ));
+            out.push_str(&format!("       {:>w$}  {}\n", "", midline, w = coord_width));
+            out.push_str(&format!(
+                "Sbjct  {:>w$}  {}  {}\n\n",
+                t + 1,
+                String::from_utf8_lossy(&self.aligned_target[start..end]),
+                t + t_bases,
+                w = coord_width
+            ));
+            q += q_bases;
+            t += t_bases;
+        }
+
+        out
+    }
+}
 
 /// Ultra-fast sequence alignment engine
 pub struct AlignmentEngine {
     binary_optimized: bool,
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
 }
 
 impl AlignmentEngine {
     pub fn new(binary_optimized: bool) -> Result<Self> {
-        Ok(Self { binary_optimized })
-    }
-    
-    pub fn compare_files(
-        &self,
-        file1: &str,
-        file2: &str,
-        algorithm: &str,
-        optimizer: &BinaryOptimizer,
-    ) -> Result<f64> {
-        // Stub implementation - returns similarity score
-        Ok(0.92) // 92% similarity
+        Ok(Self {
+            binary_optimized,
+            scoring: ScoringScheme::default(),
+            mode: AlignmentMode::Auto,
+        })
+    }
+
+    pub fn with_scoring(mut self, scoring: ScoringScheme) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: AlignmentMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn scoring(&self) -> &ScoringScheme {
+        &self.scoring
+    }
+
+    pub fn mode(&self) -> AlignmentMode {
+        self.mode
+    }
+
+    /// Align a query against a target, narrowing the target first when it pays
+    ///
+    /// For local and semi-global alignment of a short query against a much
+    /// longer target, minimizer chaining first narrows the target to the
+    /// candidate window around the best chain.
+    pub fn compare(&self, query: &[u8], target: &[u8], algorithm: AlignmentAlgorithm) -> Result<PairwiseAlignment> {
+        if algorithm != AlignmentAlgorithm::Global {
+            if let Some((start, end)) = self.candidate_window(query, target) {
+                let mut alignment = self.align(query, &target[start..end], algorithm)?;
+                alignment.target_start += start;
+                alignment.target_end += start;
+                return Ok(alignment);
+            }
+        }
+
+        self.align(query, target, algorithm)
+    }
+
+    /// Target window around the best minimizer chain, when worth narrowing
+    fn candidate_window(&self, query: &[u8], target: &[u8]) -> Option<(usize, usize)> {
+        if target.len() < 10_000 || target.len() < query.len() * 4 {
+            return None;
+        }
+
+        let index = MinimizerIndex::build(&[("target".to_string(), target.to_vec())], 10, 15);
+        let chain = index.map_query(query, &ChainParams::default()).into_iter().next()?;
+        if chain.reverse {
+            return None;
+        }
+
+        // Pad by the unchained query flanks plus slack for indels
+        let slack = query.len() / 10 + 100;
+        let start = (chain.target_start as usize)
+            .saturating_sub(chain.query_start as usize + slack);
+        let end = (chain.target_end as usize + (query.len() - chain.query_end as usize) + slack)
+            .min(target.len());
+        Some((start, end))
+    }
+
+    /// Local alignment scores of one query against many targets, in parallel
+    ///
+    /// With binary optimization enabled this runs striped SIMD Smith-Waterman
+    /// from a single query profile; otherwise, or when scoring with a
+    /// substitution matrix, a scalar linear-memory DP. Hits come back in
+    /// target order.
+    pub fn local_align_batch(&self, query: &[u8], targets: &[(String, String)]) -> Vec<LocalScore> {
+        if self.binary_optimized && self.scoring.matrix.is_none() {
+            let profile = StripedProfile::new(query, &self.scoring);
+            targets
+                .par_iter()
+                .map(|(_, target)| profile.align(query, target.as_bytes()))
+                .collect()
+        } else {
+            targets
+                .par_iter()
+                .map(|(_, target)| {
+                    let (score, target_end) = scalar_local_score(query, target.as_bytes(), &self.scoring);
+                    LocalScore { score, target_end, precision: ScorePrecision::Scalar }
+                })
+                .collect()
+        }
+    }
+
+    /// Affine-gap alignment of `query` against `target`
+    ///
+    /// In auto mode inputs up to `AUTO_FULL_CELLS` DP cells use the
+    /// full-matrix Gotoh DP. Longer ones are anchored on minimizer chains and
+    /// the pieces aligned with full, banded or linear-space DP as they fit;
+    /// global alignments without any chain fall back to Hirschberg, which
+    /// needs linear memory but quadratic time. Anchored extension cannot keep
+    /// semi-global free end gaps, so semi-global alignments always use the
+    /// full matrix and fail above `MAX_DP_CELLS`.
+    pub fn align(&self, query: &[u8], target: &[u8], algorithm: AlignmentAlgorithm) -> Result<PairwiseAlignment> {
+        let (n, m) = (query.len(), target.len());
+        let cells = (n + 1).saturating_mul(m + 1);
+        let global = algorithm == AlignmentAlgorithm::Global;
+
+        match self.mode {
+            AlignmentMode::Auto if cells <= AUTO_FULL_CELLS => Ok(gotoh(query, target, algorithm, &self.scoring)),
+            AlignmentMode::Auto if algorithm == AlignmentAlgorithm::SemiGlobal => {
+                if cells > MAX_DP_CELLS {
+                    bail!(
+                        "Sequences too long for semi-global alignment ({} x {} bases); try global or local",
+                        n,
+                        m
+                    );
+                }
+                Ok(gotoh(query, target, algorithm, &self.scoring))
+            }
+            AlignmentMode::Full => {
+                if cells > MAX_DP_CELLS {
+                    bail!(
+                        "Sequences too long for full-matrix alignment ({} x {} bases); try --mode auto",
+                        n,
+                        m
+                    );
+                }
+                Ok(gotoh(query, target, algorithm, &self.scoring))
+            }
+            AlignmentMode::XDrop(_) if algorithm == AlignmentAlgorithm::SemiGlobal => {
+                bail!("X-drop mode only supports global and local alignment")
+            }
+            AlignmentMode::Auto | AlignmentMode::XDrop(_) => {
+                let x_drop = match self.mode {
+                    AlignmentMode::XDrop(x) => x,
+                    _ => DEFAULT_X_DROP,
+                };
+                if let Some(alignment) = anchored_align(query, target, algorithm, &self.scoring, x_drop)? {
+                    return Ok(alignment);
+                }
+                if global {
+                    return Ok(self.linear_global(query, target));
+                }
+                if cells <= MAX_DP_CELLS {
+                    return Ok(gotoh(query, target, algorithm, &self.scoring));
+                }
+                bail!(
+                    "No shared seeds between the sequences ({} x {} bases); nothing to extend",
+                    n,
+                    m
+                )
+            }
+            AlignmentMode::Banded(band) => {
+                if !global {
+                    bail!("Banded mode only supports global alignment");
+                }
+                let (stride, rows) = (2 * band + 1, n + 1);
+                if rows.saturating_mul(stride) > MAX_DP_CELLS {
+                    bail!("Band of {} is too wide for {} query bases", band, n);
+                }
+                let (score, ops) = banded_global(query, target, &self.scoring, band);
+                Ok(PairwiseAlignment::from_ops(algorithm, score, &self.scoring, query, target, (0, 0), ops))
+            }
+            AlignmentMode::Hirschberg => {
+                if !global {
+                    bail!("Hirschberg mode only supports global alignment");
+                }
+                Ok(self.linear_global(query, target))
+            }
+        }
+    }
+
+    fn linear_global(&self, query: &[u8], target: &[u8]) -> PairwiseAlignment {
+        let ops = hirschberg_global(query, target, &self.scoring);
+        let score = score_ops(&ops, query, target, &self.scoring);
+        PairwiseAlignment::from_ops(AlignmentAlgorithm::Global, score, &self.scoring, query, target, (0, 0), ops)
+    }
+}
+
+// Trace byte layout: low two bits say where H came from, then E/F extension flags
+pub const FROM_STOP: u8 = 0;
+pub const FROM_DIAG: u8 = 1;
+pub const FROM_E: u8 = 2;
+pub const FROM_F: u8 = 3;
+pub const E_EXTENDED: u8 = 4;
+pub const F_EXTENDED: u8 = 8;
+
+/// Full-matrix Gotoh DP with traceback
+///
+/// H is the best score ending at (i, j), E the best ending in a gap in the
+/// query (a deletion, consuming target) and F the best ending in a gap in the
+/// target (an insertion, consuming query).
+pub fn gotoh(query: &[u8], target: &[u8], algorithm: AlignmentAlgorithm, scoring: &ScoringScheme) -> PairwiseAlignment {
+    const NEG: i32 = i32::MIN / 4;
+    let (n, m) = (query.len(), target.len());
+    let (open, ext) = (scoring.gap_open, scoring.gap_extend);
+    let local = algorithm == AlignmentAlgorithm::Local;
+    let free_ends = algorithm != AlignmentAlgorithm::Global;
+
+    let width = m + 1;
+    let mut trace = vec![FROM_STOP; (n + 1) * width];
+    let mut h_prev = vec![0i32; width];
+    let mut h_curr = vec![0i32; width];
+    // F runs down columns, so it is kept per column across rows
+    let mut f_col = vec![NEG; width];
+
+    for j in 1..=m {
+        if !free_ends {
+            h_prev[j] = -(open + ext * j as i32);
+            trace[j] = FROM_E | if j > 1 { E_EXTENDED } else { 0 };
+        }
+    }
+
+    // Local alignments may be empty; semi-global ones may end anywhere on the last row or column
+    let mut best = match algorithm {
+        AlignmentAlgorithm::Local => (0, 0usize, 0usize),
+        _ => (h_prev[m], 0, m),
+    };
+
+    for i in 1..=n {
+        let mut e = NEG;
+        h_curr[0] = if free_ends { 0 } else { -(open + ext * i as i32) };
+        trace[i * width] = if free_ends {
+            FROM_STOP
+        } else {
+            FROM_F | if i > 1 { F_EXTENDED } else { 0 }
+        };
+
+        let qi = query[i - 1];
+        for j in 1..=m {
+            let mut bits = 0u8;
+
+            let e_open = h_curr[j - 1] - open - ext;
+            let e_ext = e - ext;
+            e = if e_ext > e_open {
+                bits |= E_EXTENDED;
+                e_ext
+            } else {
+                e_open
+            };
+
+            let f_open = h_prev[j] - open - ext;
+            let f_ext = f_col[j] - ext;
+            f_col[j] = if f_ext > f_open {
+                bits |= F_EXTENDED;
+                f_ext
+            } else {
+                f_open
+            };
+
+            let diag = h_prev[j - 1] + scoring.score(qi, target[j - 1]);
+            let (mut h, mut from) = (diag, FROM_DIAG);
+            if e > h {
+                h = e;
+                from = FROM_E;
+            }
+            if f_col[j] > h {
+                h = f_col[j];
+                from = FROM_F;
+            }
+            if local && h <= 0 {
+                h = 0;
+                from = FROM_STOP;
+            }
+
+            h_curr[j] = h;
+            trace[i * width + j] = bits | from;
+
+            if local && h > best.0 {
+                best = (h, i, j);
+            }
+        }
+
+        if algorithm == AlignmentAlgorithm::SemiGlobal && h_curr[m] > best.0 {
+            best = (h_curr[m], i, m);
+        }
+        std::mem::swap(&mut h_prev, &mut h_curr);
+    }
+
+    // h_prev now holds the last row
+    match algorithm {
+        AlignmentAlgorithm::Global => best = (h_prev[m], n, m),
+        AlignmentAlgorithm::SemiGlobal => {
+            for (j, &h) in h_prev.iter().enumerate() {
+                if h > best.0 {
+                    best = (h, n, j);
+                }
+            }
+        }
+        AlignmentAlgorithm::Local => {}
+    }
+
+    let (score, end_i, end_j) = best;
+    let (ops, start_i, start_j) = traceback(&trace, width, end_i, end_j, algorithm);
+    PairwiseAlignment::from_ops(algorithm, score, scoring, query, target, (start_i, start_j), ops)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    H,
+    E,
+    F,
+}
+
+fn traceback(
+    trace: &[u8],
+    width: usize,
+    mut i: usize,
+    mut j: usize,
+    algorithm: AlignmentAlgorithm,
+) -> (Vec<AlignOp>, usize, usize) {
+    let mut ops = Vec::new();
+    let mut state = State::H;
+
+    loop {
+        let at_edge = i == 0 || j == 0;
+        if state == State::H && at_edge && algorithm != AlignmentAlgorithm::Global {
+            break;
+        }
+        if i == 0 && j == 0 {
+            break;
+        }
+
+        let bits = trace[i * width + j];
+        match state {
+            State::H => match bits & 0b11 {
+                FROM_DIAG => {
+                    ops.push(AlignOp::Match);
+                    i -= 1;
+                    j -= 1;
+                }
+                FROM_E => state = State::E,
+                FROM_F => state = State::F,
+                _ => break,
+            },
+            State::E => {
+                ops.push(AlignOp::Deletion);
+                if bits & E_EXTENDED == 0 {
+                    state = State::H;
+                }
+                j -= 1;
+            }
+            State::F => {
+                ops.push(AlignOp::Insertion);
+                if bits & F_EXTENDED == 0 {
+                    state = State::H;
+                }
+                i -= 1;
+            }
+        }
+    }
+
+    ops.reverse();
+    (ops, i, j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ambiguous_bases_never_match() {
+        let engine = AlignmentEngine::new(false).unwrap().with_mode(AlignmentMode::Full);
+        let alignment = engine.compare(b"ACGTNACGT", b"ACGTNACGT", AlignmentAlgorithm::Global).unwrap();
+        assert_eq!(alignment.cigar(), "9M");
+        assert_eq!(alignment.extended_cigar(), "4=1X4=");
+        assert_eq!(alignment.score, 8 * 2 - 3);
+    }
+
+    #[test]
+    fn affine_gap_traceback_is_contiguous_in_every_mode() {
+        let (query, target) = (b"GATTACACCGTGAAGTC", b"GATTACATGAAGTC");
+        for mode in [AlignmentMode::Full, AlignmentMode::Hirschberg, AlignmentMode::Banded(8)] {
+            let engine = AlignmentEngine::new(false).unwrap().with_mode(mode);
+            let alignment = engine.compare(query, target, AlignmentAlgorithm::Global).unwrap();
+            assert_eq!(alignment.cigar(), "7M3I7M", "{:?}", mode);
+            assert_eq!(alignment.score, 14 * 2 - 5 - 3 * 2, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn local_alignment_trims_unrelated_flanks() {
+        let engine = AlignmentEngine::new(false).unwrap().with_mode(AlignmentMode::Full);
+        let alignment = engine
+            .compare(b"TTTTTTGATTACAGATTACATTTTTT", b"CCCCGATTACAGATTACACCCC", AlignmentAlgorithm::Local)
+            .unwrap();
+        assert_eq!((alignment.query_start, alignment.query_end), (6, 20));
+        assert_eq!((alignment.target_start, alignment.target_end), (4, 18));
+        assert_eq!(alignment.cigar(), "14M");
     }
 }
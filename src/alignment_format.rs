@@ -0,0 +1,585 @@
+use anyhow::{bail, Context, Result};
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+use std::io::{BufWriter, Write};
+use crate::alignment::{AlignOp, PairwiseAlignment, ScoringScheme};
+use crate::mapper::{reg2bin, sam_header};
+
+const FLAG_UNMAPPED: u16 = 0x4;
+const FLAG_SECONDARY: u16 = 0x100;
+
+/// Gapped NCBI BLASTN parameters: (match, mismatch, open, extend, lambda, K, H)
+const NCBI_GAPPED: [(i32, i32, i32, i32, f64, f64, f64); 1] = [(2, -3, 5, 2, 0.625, 0.41, 0.78)];
+
+/// Gapped NCBI BLASTP parameters: (matrix, open, extend, lambda, K, H)
+const NCBI_PROTEIN_GAPPED: [(&str, i32, i32, f64, f64, f64); 1] = [("BLOSUM62", 11, 1, 0.267, 0.041, 0.14)];
+
+/// Robinson & Robinson (1991) amino acid background frequencies
+const ROBINSON_FREQUENCIES: [(u8, f64); 20] = [
+    (b'A', 0.07805),
+    (b'R', 0.05129),
+    (b'N', 0.04487),
+    (b'D', 0.05364),
+    (b'C', 0.01925),
+    (b'Q', 0.04264),
+    (b'E', 0.06295),
+    (b'G', 0.07377),
+    (b'H', 0.02199),
+    (b'I', 0.05142),
+    (b'L', 0.09019),
+    (b'K', 0.05744),
+    (b'M', 0.02243),
+    (b'F', 0.03856),
+    (b'P', 0.05203),
+    (b'S', 0.07120),
+    (b'T', 0.05841),
+    (b'W', 0.01330),
+    (b'Y', 0.03216),
+    (b'V', 0.06441),
+];
+
+/// Karlin-Altschul statistics for turning raw scores into bit scores and E-values
+#[derive(Debug, Clone, Copy)]
+pub struct KarlinAltschul {
+    pub lambda: f64,
+    pub k: f64,
+    /// Relative entropy, in nats per aligned pair
+    pub h: f64,
+    /// Whether the parameters account for gaps
+    pub gapped: bool,
+}
+
+impl KarlinAltschul {
+    /// Parameters for a scoring scheme
+    ///
+    /// Schemes with published NCBI gapped values use them. Others fall back to
+    /// ungapped parameters computed from the residue score distribution, which
+    /// slightly overstate the significance of gapped alignments. Nucleotides
+    /// assume uniform base composition, proteins the Robinson-Robinson
+    /// amino acid frequencies.
+    pub fn for_scoring(scoring: &ScoringScheme) -> Result<Self> {
+        let (open, extend) = (scoring.gap_open, scoring.gap_extend);
+        let matrix = match &scoring.matrix {
+            None => return Self::nucleotide(scoring.match_score, scoring.mismatch, open, extend),
+            Some(matrix) => matrix,
+        };
+
+        if matrix.is_protein() {
+            let name = matrix.name.to_uppercase();
+            if let Some(&(.., lambda, k, h)) = NCBI_PROTEIN_GAPPED.iter().find(|p| (p.0, p.1, p.2) == (name.as_str(), open, extend)) {
+                return Ok(Self { lambda, k, h, gapped: true });
+            }
+            return Self::from_frequencies(&ROBINSON_FREQUENCIES, |a, b| matrix.score(a, b));
+        }
+
+        // Nucleotide matrices that reduce to match/mismatch on ACGT can use the BLASTN table
+        let diagonal = matrix.score(b'A', b'A');
+        let off_diagonal = matrix.score(b'A', b'C');
+        let simple = b"ACGT".iter().all(|&a| {
+            b"ACGT".iter().all(|&b| matrix.score(a, b) == if a == b { diagonal } else { off_diagonal })
+        });
+        if simple {
+            return Self::nucleotide(diagonal, off_diagonal, open, extend);
+        }
+        let uniform = [(b'A', 0.25), (b'C', 0.25), (b'G', 0.25), (b'T', 0.25)];
+        Self::from_frequencies(&uniform, |a, b| matrix.score(a, b))
+    }
+
+    fn nucleotide(match_score: i32, mismatch: i32, open: i32, extend: i32) -> Result<Self> {
+        let key = (match_score, mismatch, open, extend);
+        if let Some(&(.., lambda, k, h)) = NCBI_GAPPED.iter().find(|p| (p.0, p.1, p.2, p.3) == key) {
+            return Ok(Self { lambda, k, h, gapped: true });
+        }
+        Self::ungapped(match_score, mismatch)
+    }
+
+    /// Ungapped parameters for +match/-mismatch scoring with equal base frequencies
+    pub fn ungapped(match_score: i32, mismatch: i32) -> Result<Self> {
+        Self::from_distribution(&[(match_score, 0.25), (mismatch, 0.75)])
+    }
+
+    /// Ungapped parameters for residue background frequencies and a pair score
+    pub fn from_frequencies(frequencies: &[(u8, f64)], score: impl Fn(u8, u8) -> i32) -> Result<Self> {
+        let mut distribution: Vec<(i32, f64)> = Vec::new();
+        for &(a, pa) in frequencies {
+            for &(b, pb) in frequencies {
+                let s = score(a, b);
+                match distribution.iter_mut().find(|(value, _)| *value == s) {
+                    Some((_, p)) => *p += pa * pb,
+                    None => distribution.push((s, pa * pb)),
+                }
+            }
+        }
+        let total: f64 = distribution.iter().map(|(_, p)| p).sum();
+        for (_, p) in distribution.iter_mut() {
+            *p /= total;
+        }
+        Self::from_distribution(&distribution)
+    }
+
+    /// Ungapped parameters for a distribution of (score, probability) pairs
+    pub fn from_distribution(distribution: &[(i32, f64)]) -> Result<Self> {
+        let distribution: Vec<(i32, f64)> = distribution.iter().copied().filter(|&(_, p)| p > 0.0).collect();
+        let expected: f64 = distribution.iter().map(|&(s, p)| s as f64 * p).sum();
+        let highest = distribution.iter().map(|&(s, _)| s).max().unwrap_or(0);
+        if highest <= 0 || expected >= 0.0 {
+            bail!(
+                "Scores have a non-negative expected value ({:.3}) or no positive score; E-values are undefined",
+                expected
+            );
+        }
+
+        // Lambda is the positive root of sum p(s) e^(lambda s) = 1
+        let moment = |lambda: f64| distribution.iter().map(|&(s, p)| p * (lambda * s as f64).exp()).sum::<f64>() - 1.0;
+        let mut hi = 1.0;
+        while moment(hi) <= 0.0 {
+            hi *= 2.0;
+        }
+        let mut lo = 0.0;
+        for _ in 0..100 {
+            let mid = (lo + hi) / 2.0;
+            if moment(mid) > 0.0 {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        let lambda = (lo + hi) / 2.0;
+        let h = lambda
+            * distribution
+                .iter()
+                .map(|&(s, p)| s as f64 * p * (lambda * s as f64).exp())
+                .sum::<f64>();
+
+        // K from the Karlin-Altschul series over random walks of k steps; the
+        // distribution of the k-step sum is built up by convolution
+        let delta = distribution.iter().fold(0, |g, &(s, _)| gcd(g, s)) as f64;
+        let lowest = distribution.iter().map(|&(s, _)| s).min().unwrap_or(0);
+        let mut walk = vec![1.0f64];
+        let mut sigma = 0.0;
+        for k in 1..=1000i32 {
+            let mut next = vec![0.0; walk.len() + (highest - lowest) as usize];
+            for (offset, &p) in walk.iter().enumerate() {
+                if p == 0.0 {
+                    continue;
+                }
+                for &(s, q) in &distribution {
+                    next[offset + (s - lowest) as usize] += p * q;
+                }
+            }
+            walk = next;
+
+            // walk[i] holds the probability of the sum k * lowest + i
+            let term: f64 = walk
+                .iter()
+                .enumerate()
+                .map(|(i, &p)| {
+                    let sum = (k * lowest + i as i32) as f64;
+                    if sum >= 0.0 { p } else { p * (lambda * sum).exp() }
+                })
+                .sum();
+            sigma += term / k as f64;
+            if term / (k as f64) < 1e-12 {
+                break;
+            }
+        }
+        let k = lambda * delta * (-2.0 * sigma).exp() / (h * (1.0 - (-lambda * delta).exp()));
+
+        Ok(Self { lambda, k, h, gapped: false })
+    }
+
+    /// Normalised score in bits
+    pub fn bit_score(&self, score: i32) -> f64 {
+        (self.lambda * score as f64 - self.k.ln()) / std::f64::consts::LN_2
+    }
+
+    /// Expected chance hits with at least this score in a query x database search space
+    pub fn evalue(&self, score: i32, query_len: usize, database_len: usize) -> f64 {
+        self.k * query_len as f64 * database_len as f64 * (-self.lambda * score as f64).exp()
+    }
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 { a.abs() } else { gcd(b, a % b) }
+}
+
+/// Machine-readable alignment report format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable pairwise display
+    Text,
+    Sam,
+    Bam,
+    /// minimap2 pairwise mapping format
+    Paf,
+    /// BLAST tabular (`-outfmt 6`)
+    Blast6,
+    /// BLAST tabular with comment lines (`-outfmt 7`)
+    Blast7,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" | "pretty" => Ok(OutputFormat::Text),
+            "sam" => Ok(OutputFormat::Sam),
+            "bam" => Ok(OutputFormat::Bam),
+            "paf" => Ok(OutputFormat::Paf),
+            "blast6" | "6" | "m8" => Ok(OutputFormat::Blast6),
+            "blast7" | "7" | "m9" => Ok(OutputFormat::Blast7),
+            _ => Err(anyhow::anyhow!("Unknown output format: {} (use text, sam, bam, paf, blast6 or blast7)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Sam => write!(f, "SAM"),
+            OutputFormat::Bam => write!(f, "BAM"),
+            OutputFormat::Paf => write!(f, "PAF"),
+            OutputFormat::Blast6 => write!(f, "BLAST tabular"),
+            OutputFormat::Blast7 => write!(f, "BLAST tabular with comments"),
+        }
+    }
+}
+
+/// One alignment of a query against a target, with its statistics
+#[derive(Debug, Clone)]
+pub struct AlignmentHit {
+    /// Index into the writer's target list
+    pub target: usize,
+    pub alignment: PairwiseAlignment,
+    pub evalue: f64,
+    pub bit_score: f64,
+}
+
+impl AlignmentHit {
+    pub fn new(target: usize, alignment: PairwiseAlignment, stats: &KarlinAltschul, query_len: usize, database_len: usize) -> Self {
+        Self {
+            target,
+            evalue: stats.evalue(alignment.score, query_len, database_len),
+            bit_score: stats.bit_score(alignment.score),
+            alignment,
+        }
+    }
+}
+
+enum Sink {
+    Htslib(Box<bam::Writer>),
+    Text(BufWriter<std::fs::File>),
+}
+
+/// Streams alignments of successive queries to a SAM/BAM, PAF or BLAST tabular file
+pub struct AlignmentWriter {
+    format: OutputFormat,
+    sink: Sink,
+    /// Target IDs and lengths, in @SQ order
+    targets: Vec<(String, usize)>,
+    database: String,
+    queries: usize,
+}
+
+impl AlignmentWriter {
+    /// `database` names the target set in BLAST comment lines
+    pub fn create(
+        path: &str,
+        format: OutputFormat,
+        targets: &[(String, usize)],
+        database: &str,
+        command_line: &str,
+    ) -> Result<Self> {
+        let targets: Vec<(String, usize)> = targets
+            .iter()
+            .map(|(name, length)| (sequence_id(name).to_string(), *length))
+            .collect();
+
+        let sink = match format {
+            OutputFormat::Sam | OutputFormat::Bam => {
+                let refs: Vec<(&str, usize)> = targets.iter().map(|(name, length)| (name.as_str(), *length)).collect();
+                let header = sam_header(&refs, command_line, false);
+                let hts_format = if format == OutputFormat::Sam { bam::Format::Sam } else { bam::Format::Bam };
+                let writer = bam::Writer::from_path(path, &header, hts_format)
+                    .with_context(|| format!("Could not create output file: {}", path))?;
+                Sink::Htslib(Box::new(writer))
+            }
+            OutputFormat::Paf | OutputFormat::Blast6 | OutputFormat::Blast7 => {
+                let file = std::fs::File::create(path).with_context(|| format!("Could not create output file: {}", path))?;
+                Sink::Text(BufWriter::new(file))
+            }
+            OutputFormat::Text => bail!("Text output is written by the caller, not an AlignmentWriter"),
+        };
+
+        Ok(Self { format, sink, targets, database: database.to_string(), queries: 0 })
+    }
+
+    /// Write every hit of one query, best first; queries without hits still
+    /// get an unmapped SAM record or a BLAST comment block
+    ///
+    /// Hits that align no query base against a target base are not placements
+    /// and are dropped, so a query left with none is written as unmapped.
+    pub fn write_query(&mut self, query_name: &str, query: &[u8], hits: &[AlignmentHit]) -> Result<()> {
+        self.queries += 1;
+        let id = sequence_id(query_name);
+        let hits: Vec<&AlignmentHit> = hits
+            .iter()
+            .filter(|hit| hit.alignment.ops.iter().any(|&op| matches!(op, AlignOp::Match | AlignOp::Mismatch)))
+            .collect();
+
+        match &mut self.sink {
+            Sink::Htslib(writer) => {
+                if hits.is_empty() {
+                    writer.write(&unmapped_record(id, query))?;
+                }
+                for (n, &hit) in hits.iter().enumerate() {
+                    let flags = if n == 0 { 0 } else { FLAG_SECONDARY };
+                    writer.write(&sam_record(id, query, hit, flags)?)?;
+                }
+            }
+            Sink::Text(out) => {
+                if self.format == OutputFormat::Blast7 {
+                    writeln!(out, "# INSTANT-DNA {}", env!("CARGO_PKG_VERSION"))?;
+                    writeln!(out, "# Query: {}", query_name)?;
+                    writeln!(out, "# Database: {}", self.database)?;
+                    if !hits.is_empty() {
+                        writeln!(out, "# Fields: query acc.ver, subject acc.ver, % identity, alignment length, mismatches, gap opens, q. start, q. end, s. start, s. end, evalue, bit score")?;
+                    }
+                    writeln!(out, "# {} hits found", hits.len())?;
+                }
+                for &hit in &hits {
+                    let (target, target_len) = &self.targets[hit.target];
+                    let line = match self.format {
+                        OutputFormat::Paf => paf_line(id, query.len(), target, *target_len, &hit.alignment),
+                        _ => blast_tabular_line(id, target, hit),
+                    };
+                    writeln!(out, "{}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write trailing comments and flush
+    pub fn finish(mut self) -> Result<()> {
+        if let Sink::Text(out) = &mut self.sink {
+            if self.format == OutputFormat::Blast7 {
+                writeln!(out, "# BLAST processed {} queries", self.queries)?;
+            }
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Sequence ID as BLAST, SAM and PAF use it: the header up to the first space
+pub fn sequence_id(name: &str) -> &str {
+    name.split_whitespace().next().unwrap_or("*")
+}
+
+/// PAF line with residue matches, block length and a `cg:Z` CIGAR
+///
+/// Alignments here are forward-strand only: the query is never reverse
+/// complemented, so the strand column is always `+`.
+pub fn paf_line(query: &str, query_len: usize, target: &str, target_len: usize, alignment: &PairwiseAlignment) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t+\t{}\t{}\t{}\t{}\t{}\t{}\t255\ttp:A:P\tNM:i:{}\tAS:i:{}\tcg:Z:{}",
+        query,
+        query_len,
+        alignment.query_start,
+        alignment.query_end,
+        target,
+        target_len,
+        alignment.target_start,
+        alignment.target_end,
+        alignment.matches(),
+        alignment.columns(),
+        alignment.mismatches() + alignment.gap_bases(),
+        alignment.score,
+        alignment.cigar()
+    )
+}
+
+/// BLAST `-outfmt 6` line with BLAST's number formatting
+pub fn blast_tabular_line(query: &str, target: &str, hit: &AlignmentHit) -> String {
+    let a = &hit.alignment;
+    format!(
+        "{}\t{}\t{:.3}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        query,
+        target,
+        a.identity() * 100.0,
+        a.columns(),
+        a.mismatches(),
+        a.gap_opens(),
+        a.query_start + 1,
+        a.query_end,
+        a.target_start + 1,
+        a.target_end,
+        format_evalue(hit.evalue),
+        format_bit_score(hit.bit_score)
+    )
+}
+
+/// E-value formatted as BLAST+ tabular output does
+pub fn format_evalue(evalue: f64) -> String {
+    if evalue < 1e-180 {
+        "0.0".to_string()
+    } else if evalue < 0.0009 {
+        format!("{:.0e}", evalue)
+    } else if evalue < 0.1 {
+        format!("{:.3}", evalue)
+    } else if evalue < 1.0 {
+        format!("{:.2}", evalue)
+    } else if evalue < 10.0 {
+        format!("{:.1}", evalue)
+    } else {
+        format!("{:.0}", evalue)
+    }
+}
+
+pub fn format_bit_score(bits: f64) -> String {
+    if bits > 9999.0 {
+        format!("{:.3e}", bits)
+    } else if bits > 99.9 {
+        format!("{:.0}", bits)
+    } else {
+        format!("{:.1}", bits)
+    }
+}
+
+/// SAM record for one hit; unaligned query ends become soft clips
+pub fn sam_record(query_name: &str, query: &[u8], hit: &AlignmentHit, flags: u16) -> Result<bam::Record> {
+    let a = &hit.alignment;
+    // Terminal gaps in global alignments: insertions are clipped, deletions move the start
+    let ops = &a.ops[..];
+    let leading_del = ops.iter().take_while(|&&op| op == AlignOp::Deletion).count();
+    let trailing_del = ops.iter().rev().take_while(|&&op| op == AlignOp::Deletion).count();
+    let ops = &ops[leading_del..ops.len() - trailing_del.min(ops.len() - leading_del)];
+    let leading_ins = ops.iter().take_while(|&&op| op == AlignOp::Insertion).count();
+    let trailing_ins = ops.iter().rev().take_while(|&&op| op == AlignOp::Insertion).count();
+    let core = &ops[leading_ins..ops.len() - trailing_ins.min(ops.len() - leading_ins)];
+
+    let head_clip = a.query_start + leading_ins;
+    let tail_clip = query.len() - a.query_end + trailing_ins;
+    let mut cigar = Vec::new();
+    if head_clip > 0 {
+        cigar.push(Cigar::SoftClip(head_clip as u32));
+    }
+    for &op in core {
+        let next = match op {
+            AlignOp::Match | AlignOp::Mismatch => Cigar::Match(1),
+            AlignOp::Insertion => Cigar::Ins(1),
+            AlignOp::Deletion => Cigar::Del(1),
+        };
+        match (cigar.last_mut(), next) {
+            (Some(Cigar::Match(n)), Cigar::Match(_)) | (Some(Cigar::Ins(n)), Cigar::Ins(_)) | (Some(Cigar::Del(n)), Cigar::Del(_)) => *n += 1,
+            _ => cigar.push(next),
+        }
+    }
+    if tail_clip > 0 {
+        cigar.push(Cigar::SoftClip(tail_clip as u32));
+    }
+
+    let qname = &query_name.as_bytes()[..query_name.len().min(254)];
+    let quality = vec![255u8; query.len()];
+    let mut record = bam::Record::new();
+    record.set(qname, Some(&CigarString(cigar)), query, &quality);
+
+    let start = (a.target_start + leading_del) as i64;
+    let end = (a.target_end - trailing_del) as i64;
+    record.set_flags(flags);
+    record.set_tid(hit.target as i32);
+    record.set_pos(start);
+    record.set_bin(reg2bin(start, end.max(start + 1)));
+    record.set_mapq(255);
+    record.set_mtid(-1);
+    record.set_mpos(-1);
+    record.push_aux(b"NM", Aux::I32((a.mismatches() + a.gap_bases() - leading_del - trailing_del - leading_ins - trailing_ins) as i32))?;
+    record.push_aux(b"AS", Aux::I32(a.score))?;
+    Ok(record)
+}
+
+fn unmapped_record(query_name: &str, query: &[u8]) -> bam::Record {
+    let qname = &query_name.as_bytes()[..query_name.len().min(254)];
+    let quality = vec![255u8; query.len()];
+    let mut record = bam::Record::new();
+    record.set(qname, None, query, &quality);
+    record.set_flags(FLAG_UNMAPPED);
+    record.set_tid(-1);
+    record.set_pos(-1);
+    record.set_bin(reg2bin(-1, 0));
+    record.set_mapq(0);
+    record.set_mtid(-1);
+    record.set_mpos(-1);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::AlignmentAlgorithm;
+
+    fn hit(query: &[u8], target: &[u8], ops: Vec<AlignOp>) -> AlignmentHit {
+        let scoring = ScoringScheme::default();
+        let alignment = PairwiseAlignment::from_ops(AlignmentAlgorithm::Global, 0, &scoring, query, target, (0, 0), ops);
+        AlignmentHit { target: 0, alignment, evalue: 1.0, bit_score: 0.0 }
+    }
+
+    #[test]
+    fn hit_without_aligned_bases_is_written_unmapped() {
+        let path = std::env::temp_dir().join(format!("instant-dna-unmapped-{}.sam", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let mut writer = AlignmentWriter::create(path_str, OutputFormat::Sam, &[("t".to_string(), 4)], "t", "test").unwrap();
+        let gaps = hit(b"AC", b"GT", vec![AlignOp::Insertion, AlignOp::Insertion, AlignOp::Deletion, AlignOp::Deletion]);
+        writer.write_query("q1", b"AC", &[gaps]).unwrap();
+        let aligned = hit(b"AC", b"AC", vec![AlignOp::Match, AlignOp::Match]);
+        writer.write_query("q2", b"AC", &[aligned]).unwrap();
+        writer.finish().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let flags: Vec<(&str, &str)> = text
+            .lines()
+            .filter(|line| !line.starts_with('@'))
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[0], fields[1])
+            })
+            .collect();
+        assert_eq!(flags, [("q1", "4"), ("q2", "0")]);
+    }
+
+    #[test]
+    fn karlin_altschul_matches_published_ungapped_values() {
+        // NCBI BLASTN ungapped parameters for +1/-3
+        let stats = KarlinAltschul::ungapped(1, -3).unwrap();
+        assert!((stats.lambda - 1.374).abs() < 1e-3 && (stats.k - 0.711).abs() < 1e-3 && (stats.h - 1.31).abs() < 0.01);
+        assert!(!stats.gapped);
+    }
+
+    #[test]
+    fn karlin_altschul_lambda_and_entropy_solve_their_equations() {
+        let stats = KarlinAltschul::ungapped(1, -2).unwrap();
+        let (matched, mismatched) = (stats.lambda.exp(), (-2.0 * stats.lambda).exp());
+        assert!((0.25 * matched + 0.75 * mismatched - 1.0).abs() < 1e-12);
+        assert!((stats.h - stats.lambda * (0.25 * matched - 1.5 * mismatched)).abs() < 1e-12);
+        assert!(stats.k > 0.0 && stats.k < 1.0);
+    }
+
+    #[test]
+    fn karlin_altschul_rejects_non_negative_drift() {
+        assert!(KarlinAltschul::from_distribution(&[(1, 0.5), (-1, 0.5)]).is_err());
+        assert!(KarlinAltschul::from_distribution(&[(-1, 1.0)]).is_err());
+    }
+
+    #[test]
+    fn evalue_halves_with_each_bit() {
+        let stats = KarlinAltschul::for_scoring(&ScoringScheme::default()).unwrap();
+        for score in [20, 40] {
+            let expected = 1000.0 * 1e6 * (-stats.bit_score(score)).exp2();
+            assert!((stats.evalue(score, 1000, 1_000_000) / expected - 1.0).abs() < 1e-9);
+        }
+    }
+}
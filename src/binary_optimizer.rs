@@ -1,4 +1,8 @@
+use anyhow::Result;
 use rayon::prelude::*;
+use wide::u8x16;
+use crate::kmer_filter::base_code;
+use crate::seq_container::{ContainerReader, PackedContainer};
 
 /// Binary optimization engine for ultra-fast DNA processing
 pub struct BinaryOptimizer {
@@ -55,19 +59,77 @@ impl BinaryOptimizer {
         matches
     }
     
-    /// Parallel sequence comparison with binary optimization
-    pub fn compare_sequences(&self, seq1: &[u8], seq2: &[u8]) -> f64 {
+    /// Sequence comparison using gapped alignment identity
+    pub fn compare_sequences(&self, seq1: &[u8], seq2: &[u8]) -> Result<SimilarityResult> {
+        self.compare_with_metric(seq1, seq2, SimilarityMetric::BlastIdentity)
+    }
+    
+    /// Sequence comparison with an explicit metric
+    ///
+    /// Bases are compared as `ScoringScheme::is_match` does: case-insensitive,
+    /// T equal to U, and N or other ambiguity codes never matching. Hamming
+    /// distance is only defined for equal-length sequences and returns an
+    /// error otherwise; the alignment metrics return one when the sequences
+    /// are too divergent to align within `MAX_DP_CELLS`.
+    pub fn compare_with_metric(
+        &self,
+        seq1: &[u8],
+        seq2: &[u8],
+        metric: SimilarityMetric,
+    ) -> Result<SimilarityResult> {
         if seq1.is_empty() || seq2.is_empty() {
-            return 0.0;
+            return Ok(SimilarityResult { metric, similarity: 0.0, differences: 0, length: 0 });
         }
         
-        let matches: usize = seq1
-            .par_iter()
-            .zip(seq2.par_iter())
-            .map(|(a, b)| if a == b { 1 } else { 0 })
-            .sum();
+        let result = match metric {
+            SimilarityMetric::Hamming => {
+                if seq1.len() != seq2.len() {
+                    anyhow::bail!(
+                        "Hamming distance needs equal-length sequences ({} vs {} bases)",
+                        seq1.len(),
+                        seq2.len()
+                    );
+                }
+                let mismatches: usize = seq1
+                    .par_iter()
+                    .zip(seq2.par_iter())
+                    .filter(|(&a, &b)| !same_base(a, b))
+                    .count();
+                SimilarityResult::from_differences(metric, mismatches, seq1.len())
+            }
+            SimilarityMetric::EditDistance => {
+                let alignment = EditAlignment::new(seq1, seq2)?;
+                SimilarityResult::from_differences(metric, alignment.distance, seq1.len().max(seq2.len()))
+            }
+            SimilarityMetric::BlastIdentity => {
+                let alignment = EditAlignment::new(seq1, seq2)?;
+                SimilarityResult::from_differences(
+                    metric,
+                    alignment.columns - alignment.matches,
+                    alignment.columns,
+                )
+            }
+            SimilarityMetric::GapCompressedIdentity => {
+                // Each gap counts once regardless of its length
+                let alignment = EditAlignment::new(seq1, seq2)?;
+                let length = alignment.columns - alignment.gap_bases + alignment.gap_opens;
+                SimilarityResult::from_differences(metric, length - alignment.matches, length)
+            }
+            SimilarityMetric::KmerJaccard(k) => {
+                let k = k.max(1);
+                let kmers1: ahash::AHashSet<&[u8]> = seq1.windows(k).collect();
+                let kmers2: ahash::AHashSet<&[u8]> = seq2.windows(k).collect();
+                let shared = kmers1.intersection(&kmers2).count();
+                let union = kmers1.len() + kmers2.len() - shared;
+                if union == 0 {
+                    SimilarityResult { metric, similarity: 0.0, differences: 0, length: 0 }
+                } else {
+                    SimilarityResult::from_differences(metric, union - shared, union)
+                }
+            }
+        };
         
-        matches as f64 / seq1.len().min(seq2.len()) as f64
+        Ok(result)
     }
     
     /// Binary k-mer counting for ultra-fast analysis
@@ -87,82 +149,728 @@ impl BinaryOptimizer {
         kmer_counts
     }
     
-    /// Compress DNA sequence using binary representation
+    /// Compress an ASCII DNA sequence into a single-record packed container
+    ///
+    /// N runs and soft-masked (lowercase) runs are kept as side tables, so
+    /// sequences of A, C, G, T and N round-trip exactly through
+    /// `decompress_sequence`; other IUPAC codes come back as N and U as T.
     pub fn compress_sequence(&self, sequence: &[u8]) -> Vec<u8> {
-        // Pack 4 bases per byte (2 bits per base)
-        let mut compressed = Vec::with_capacity((sequence.len() + 3) / 4);
+        let mut container = PackedContainer::new();
+        container.add_record("sequence", sequence);
+        container.to_bytes()
+    }
+    
+    /// Decompress a container produced by `compress_sequence` back to ASCII
+    pub fn decompress_sequence(&self, compressed: &[u8]) -> Result<Vec<u8>> {
+        let mut reader = ContainerReader::from_bytes(compressed)?;
+        let name = reader
+            .records()
+            .first()
+            .map(|r| r.name.clone())
+            .ok_or_else(|| anyhow::anyhow!("Container holds no sequences"))?;
+        reader.fetch_record(&name)
+    }
+}
+
+/// Distance or identity measure used by `BinaryOptimizer::compare_with_metric`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimilarityMetric {
+    /// Mismatches over equal-length sequences
+    Hamming,
+    /// Levenshtein distance, normalised by the longer sequence
+    EditDistance,
+    /// Identical columns over all alignment columns, as BLAST reports it
+    BlastIdentity,
+    /// Identity where each gap counts as a single difference
+    GapCompressedIdentity,
+    /// Shared over total distinct k-mers of the given size
+    KmerJaccard(usize),
+}
+
+impl std::fmt::Display for SimilarityMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimilarityMetric::Hamming => write!(f, "Hamming"),
+            SimilarityMetric::EditDistance => write!(f, "edit distance"),
+            SimilarityMetric::BlastIdentity => write!(f, "BLAST identity"),
+            SimilarityMetric::GapCompressedIdentity => write!(f, "gap-compressed identity"),
+            SimilarityMetric::KmerJaccard(k) => write!(f, "{}-mer Jaccard", k),
+        }
+    }
+}
+
+impl std::str::FromStr for SimilarityMetric {
+    type Err = anyhow::Error;
+    
+    /// Parses `hamming`, `edit`, `identity`, `gap-compressed` and `jaccard[:k]`
+    fn from_str(s: &str) -> Result<Self> {
+        let lower = s.to_lowercase();
+        match lower.split_once(':') {
+            Some(("jaccard", k)) => Ok(SimilarityMetric::KmerJaccard(k.parse()?)),
+            _ => match lower.as_str() {
+                "hamming" => Ok(SimilarityMetric::Hamming),
+                "edit" | "levenshtein" => Ok(SimilarityMetric::EditDistance),
+                "identity" | "blast" => Ok(SimilarityMetric::BlastIdentity),
+                "gap-compressed" | "gap_compressed" => Ok(SimilarityMetric::GapCompressedIdentity),
+                "jaccard" => Ok(SimilarityMetric::KmerJaccard(16)),
+                _ => Err(anyhow::anyhow!("Unknown similarity metric: {}", s)),
+            },
+        }
+    }
+}
+
+/// Outcome of a sequence comparison, tagged with the metric that produced it
+#[derive(Debug, Clone)]
+pub struct SimilarityResult {
+    pub metric: SimilarityMetric,
+    /// Similarity in [0, 1]
+    pub similarity: f64,
+    /// Mismatches, edits or unshared k-mers, depending on the metric
+    pub differences: usize,
+    /// Denominator the similarity was computed over
+    pub length: usize,
+}
+
+impl SimilarityResult {
+    fn from_differences(metric: SimilarityMetric, differences: usize, length: usize) -> Self {
+        Self {
+            metric,
+            similarity: 1.0 - differences as f64 / length as f64,
+            differences,
+            length,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditOp {
+    Match,
+    Mismatch,
+    /// Base present in the first sequence only
+    Deletion,
+    /// Base present in the second sequence only
+    Insertion,
+}
+
+/// Largest traceback matrix the banded alignment may allocate, in bytes
+const MAX_TRACE_BYTES: usize = 1 << 28;
+
+/// Most DP cells an alignment may fill, a few seconds to a minute of work;
+/// pairs whose edit distance needs a wider band are rejected
+const MAX_DP_CELLS: usize = 1 << 32;
+
+/// Whether two bases are the same nucleotide
+fn same_base(a: u8, b: u8) -> bool {
+    base_code(a).is_some() && base_code(a) == base_code(b)
+}
+
+/// Unit-cost global alignment used for edit distance and identity
+///
+/// Runs Ukkonen's banded DP with a doubling band: any alignment of cost d
+/// stays within d diagonals of the main one, so once the distance fits the
+/// band it is exact. Time and memory are O(n·d) rather than O(n·m) for
+/// similar sequences. Once the traceback would outgrow `MAX_TRACE_BYTES` it
+/// switches to a linear-space pass that carries the column tallies along with
+/// the scores, and gives up once the band would exceed `MAX_DP_CELLS`.
+struct EditAlignment {
+    distance: usize,
+    columns: usize,
+    matches: usize,
+    gap_bases: usize,
+    gap_opens: usize,
+}
+
+impl EditAlignment {
+    fn new(a: &[u8], b: &[u8]) -> Result<Self> {
+        let mut band = a.len().abs_diff(b.len()).max(16);
+        loop {
+            let cells = (a.len() + 1).saturating_mul((2 * band + 1).min(b.len() + 1));
+            if cells > MAX_DP_CELLS {
+                anyhow::bail!(
+                    "Sequences of {} and {} bases are too divergent to align within {} DP cells",
+                    a.len(),
+                    b.len(),
+                    MAX_DP_CELLS
+                );
+            }
+            let alignment = if (a.len() + 1).saturating_mul(2 * band + 1) > MAX_TRACE_BYTES {
+                Self::score_only(a, b, band)
+            } else {
+                Self::banded(a, b, band)
+            };
+            if let Some(alignment) = alignment {
+                return Ok(alignment);
+            }
+            band *= 2;
+        }
+    }
+    
+    fn from_ops(distance: usize, ops: &[EditOp]) -> Self {
+        let is_gap = |op: &EditOp| matches!(op, EditOp::Deletion | EditOp::Insertion);
+        let mut gap_opens = 0;
+        let mut previous = EditOp::Match;
+        for &op in ops {
+            if is_gap(&op) && op != previous {
+                gap_opens += 1;
+            }
+            previous = op;
+        }
+        
+        Self {
+            distance,
+            columns: ops.len(),
+            matches: ops.iter().filter(|&&op| op == EditOp::Match).count(),
+            gap_bases: ops.iter().filter(|op| is_gap(op)).count(),
+            gap_opens,
+        }
+    }
+    
+    /// Banded DP keeping only two rows; each cell carries the tallies of the
+    /// path that reached it, preferring to extend an open gap on ties
+    fn score_only(a: &[u8], b: &[u8], band: usize) -> Option<Self> {
+        #[derive(Clone, Copy)]
+        struct Cell {
+            cost: usize,
+            columns: usize,
+            matches: usize,
+            gap_opens: usize,
+            last: EditOp,
+        }
+        
+        impl Cell {
+            fn step(self, op: EditOp) -> Self {
+                let opens_gap = matches!(op, EditOp::Deletion | EditOp::Insertion) && op != self.last;
+                Self {
+                    cost: self.cost + (op != EditOp::Match) as usize,
+                    columns: self.columns + 1,
+                    matches: self.matches + (op == EditOp::Match) as usize,
+                    gap_opens: self.gap_opens + opens_gap as usize,
+                    last: op,
+                }
+            }
+            
+        }
+        
+        // Rows are laid out by diagonal as in `banded`; cells outside the band stay unreachable
+        let (n, m) = (a.len(), b.len());
+        let width = 2 * band + 1;
+        let unreachable = Cell { cost: usize::MAX / 4, columns: 0, matches: 0, gap_opens: 0, last: EditOp::Match };
+        let mut prev = vec![unreachable; width];
+        let mut curr = vec![unreachable; width];
+        prev[band] = Cell { cost: 0, ..unreachable };
+        for j in 1..=band.min(m) {
+            prev[j + band] = prev[j + band - 1].step(EditOp::Insertion);
+        }
         
-        for chunk in sequence.chunks(4) {
-            let mut byte = 0u8;
-            for (i, &base) in chunk.iter().enumerate() {
-                byte |= (base & 0b11) << (i * 2);
+        for i in 1..=n {
+            curr.fill(unreachable);
+            for j in i.saturating_sub(band)..=(i + band).min(m) {
+                let d = j + band - i;
+                let mut best = unreachable;
+                if j > 0 {
+                    best = prev[d].step(if same_base(a[i - 1], b[j - 1]) { EditOp::Match } else { EditOp::Mismatch });
+                }
+                let up = (d + 1 < width).then(|| (prev[d + 1], EditOp::Deletion));
+                let left = (d > 0).then(|| (curr[d - 1], EditOp::Insertion));
+                for (from, op) in up.into_iter().chain(left) {
+                    let candidate = from.step(op);
+                    if candidate.cost < best.cost || (candidate.cost == best.cost && from.last == op) {
+                        best = candidate;
+                    }
+                }
+                curr[d] = best;
             }
-            compressed.push(byte);
+            std::mem::swap(&mut prev, &mut curr);
         }
         
-        compressed
+        let end = prev[m + band - n];
+        if end.cost > band {
+            return None;
+        }
+        Some(Self {
+            distance: end.cost,
+            columns: end.columns,
+            matches: end.matches,
+            gap_bases: end.columns - (n + m - end.columns),
+            gap_opens: end.gap_opens,
+        })
     }
     
-    /// Decompress binary representation back to sequence
-    pub fn decompress_sequence(&self, compressed: &[u8], original_length: usize) -> Vec<u8> {
-        let mut sequence = Vec::with_capacity(original_length);
+    fn banded(a: &[u8], b: &[u8], band: usize) -> Option<Self> {
+        const INF: u32 = u32::MAX / 2;
+        // Trace bits record every optimal predecessor so traceback can keep gaps contiguous
+        const DIAG: u8 = 1;
+        const UP: u8 = 2;
+        const LEFT: u8 = 4;
+        
+        let (n, m) = (a.len(), b.len());
+        let width = 2 * band + 1;
+        // Row i holds columns j = i - band ..= i + band at offset j + band - i
+        let mut prev = vec![INF; width];
+        let mut curr = vec![INF; width];
+        let mut trace = vec![0u8; (n + 1) * width];
+        
+        for j in 0..=band.min(m) {
+            prev[j + band] = j as u32;
+            trace[j + band] = LEFT;
+        }
+        
+        for i in 1..=n {
+            curr.fill(INF);
+            let lo = i.saturating_sub(band);
+            let hi = (i + band).min(m);
+            for j in lo..=hi {
+                let d = j + band - i;
+                let diag = if j == 0 { INF } else { prev[d] + !same_base(a[i - 1], b[j - 1]) as u32 };
+                let up = if d + 1 < width { prev[d + 1] + 1 } else { INF };
+                let left = if d > 0 { curr[d - 1] + 1 } else { INF };
+                let best = diag.min(up).min(left);
+                
+                curr[d] = best;
+                trace[i * width + d] = ((diag == best) as u8 * DIAG)
+                    | ((up == best) as u8 * UP)
+                    | ((left == best) as u8 * LEFT);
+            }
+            std::mem::swap(&mut prev, &mut curr);
+        }
         
-        for (i, &byte) in compressed.iter().enumerate() {
-            for j in 0..4 {
-                if sequence.len() >= original_length {
-                    break;
+        let distance = prev[m + band - n] as usize;
+        if distance > band {
+            return None;
+        }
+        
+        let mut ops = Vec::with_capacity(n.max(m));
+        let (mut i, mut j) = (n, m);
+        let mut last = EditOp::Match;
+        while i > 0 || j > 0 {
+            let bits = trace[i * width + j + band - i];
+            let op = if last == EditOp::Deletion && bits & UP != 0 {
+                EditOp::Deletion
+            } else if last == EditOp::Insertion && bits & LEFT != 0 {
+                EditOp::Insertion
+            } else if bits & DIAG != 0 {
+                if same_base(a[i - 1], b[j - 1]) { EditOp::Match } else { EditOp::Mismatch }
+            } else if bits & UP != 0 {
+                EditOp::Deletion
+            } else {
+                EditOp::Insertion
+            };
+            
+            match op {
+                EditOp::Match | EditOp::Mismatch => {
+                    i -= 1;
+                    j -= 1;
                 }
-                let base = (byte >> (j * 2)) & 0b11;
-                sequence.push(base);
+                EditOp::Deletion => i -= 1,
+                EditOp::Insertion => j -= 1,
             }
+            ops.push(op);
+            last = op;
         }
         
-        sequence.truncate(original_length);
-        sequence
+        Some(Self::from_ops(distance, &ops))
     }
 }
 
 /// SIMD processor for specialized operations
 pub struct SimdProcessor;
 
+/// Buffers larger than this are split across the rayon pool
+const PARALLEL_CHUNK: usize = 1 << 16;
+
 impl SimdProcessor {
     pub fn new() -> Self {
         Self
     }
     
     /// High-speed base counting using SIMD operations
+    ///
+    /// Counts the 2-bit codes (A=0, T=1, G=2, C=3) of a binary sequence.
     pub fn count_bases_simd(sequence: &[u8]) -> [u32; 4] {
+        if sequence.len() <= PARALLEL_CHUNK {
+            return Self::count_bases_chunk(sequence);
+        }
+        
+        sequence
+            .par_chunks(PARALLEL_CHUNK)
+            .map(Self::count_bases_chunk)
+            .reduce(
+                || [0u32; 4],
+                |mut acc, counts| {
+                    for (total, count) in acc.iter_mut().zip(counts) {
+                        *total += count;
+                    }
+                    acc
+                },
+            )
+    }
+    
+    fn count_bases_chunk(sequence: &[u8]) -> [u32; 4] {
         let mut counts = [0u32; 4];
+        let mask = u8x16::splat(0b11);
+        let codes = [
+            u8x16::splat(0b00),
+            u8x16::splat(0b01),
+            u8x16::splat(0b10),
+            u8x16::splat(0b11),
+        ];
         
-        // Simple scalar version for now - real SIMD would use vector operations
-        for &base in sequence {
-            if (base & 0b11) < 4 {
-                counts[base as usize & 0b11] += 1;
+        let mut lanes = sequence.chunks_exact(16);
+        for lane in &mut lanes {
+            let bases = u8x16::from(<[u8; 16]>::try_from(lane).unwrap()) & mask;
+            for (count, code) in counts.iter_mut().zip(&codes) {
+                *count += bases.cmp_eq(*code).move_mask().count_ones();
             }
         }
         
+        for &base in lanes.remainder() {
+            counts[(base & 0b11) as usize] += 1;
+        }
+        
         counts
     }
     
     /// Parallel quality score analysis
     pub fn analyze_quality_simd(quality_scores: &[u8]) -> QualityStats {
-        let sum: u32 = quality_scores.par_iter().map(|&q| q as u32).sum();
-        let min_quality = *quality_scores.iter().min().unwrap_or(&0);
-        let max_quality = *quality_scores.iter().max().unwrap_or(&0);
+        let histogram = Self::quality_histogram(quality_scores);
+        QualityStats::from_histogram(histogram, Vec::new())
+    }
+    
+    /// Phred score histogram, built in parallel over large buffers
+    fn quality_histogram(quality_scores: &[u8]) -> [u64; 256] {
+        let chunk_histogram = |chunk: &[u8]| {
+            // Four interleaved tables avoid store-to-load stalls on runs of
+            // identical scores, which are the norm in modern FASTQ.
+            let mut tables = [[0u32; 256]; 4];
+            let mut quads = chunk.chunks_exact(4);
+            for quad in &mut quads {
+                tables[0][quad[0] as usize] += 1;
+                tables[1][quad[1] as usize] += 1;
+                tables[2][quad[2] as usize] += 1;
+                tables[3][quad[3] as usize] += 1;
+            }
+            for &q in quads.remainder() {
+                tables[0][q as usize] += 1;
+            }
+            
+            let mut histogram = [0u64; 256];
+            for table in &tables {
+                for (total, &count) in histogram.iter_mut().zip(table) {
+                    *total += count as u64;
+                }
+            }
+            histogram
+        };
         
-        QualityStats {
-            mean: sum as f64 / quality_scores.len() as f64,
-            min: min_quality,
-            max: max_quality,
-            total_bases: quality_scores.len(),
+        quality_scores
+            .par_chunks(PARALLEL_CHUNK)
+            .map(chunk_histogram)
+            .reduce(
+                || [0u64; 256],
+                |mut acc, hist| {
+                    for (total, count) in acc.iter_mut().zip(hist) {
+                        *total += count;
+                    }
+                    acc
+                },
+            )
+    }
+}
+
+/// Per-position Phred histograms fed one batch of reads at a time, so a whole
+/// FASTQ can be summarised without holding it in memory
+#[derive(Debug, Clone, Default)]
+pub struct QualityAccumulator {
+    positions: Vec<[u64; 256]>,
+}
+
+impl QualityAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add the quality strings of a batch of reads
+    pub fn add_reads<T: AsRef<[u8]> + Sync>(&mut self, reads: &[T]) {
+        let max_len = reads.iter().map(|r| r.as_ref().len()).max().unwrap_or(0);
+        let batch = reads
+            .par_iter()
+            .fold(
+                || vec![[0u64; 256]; max_len],
+                |mut hists, read| {
+                    for (hist, &q) in hists.iter_mut().zip(read.as_ref()) {
+                        hist[q as usize] += 1;
+                    }
+                    hists
+                },
+            )
+            .reduce(
+                || vec![[0u64; 256]; max_len],
+                |mut acc, hists| {
+                    for (a, h) in acc.iter_mut().zip(&hists) {
+                        for (x, y) in a.iter_mut().zip(h) {
+                            *x += y;
+                        }
+                    }
+                    acc
+                },
+            );
+        
+        if self.positions.len() < max_len {
+            self.positions.resize(max_len, [0u64; 256]);
+        }
+        for (total, hist) in self.positions.iter_mut().zip(&batch) {
+            for (x, y) in total.iter_mut().zip(hist) {
+                *x += y;
+            }
+        }
+    }
+    
+    pub fn finish(&self) -> QualityStats {
+        let mut overall = [0u64; 256];
+        let per_position = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(position, hist)| {
+                for (total, count) in overall.iter_mut().zip(hist) {
+                    *total += count;
+                }
+                let summary = HistogramSummary::new(hist);
+                PositionQuality {
+                    position,
+                    mean: summary.mean,
+                    median: summary.median,
+                    q1: summary.q1,
+                    q3: summary.q3,
+                    count: summary.total as usize,
+                }
+            })
+            .collect();
+        
+        QualityStats::from_histogram(overall, per_position)
+    }
+}
+
+/// Order statistics derived from a Phred histogram
+struct HistogramSummary {
+    total: u64,
+    mean: f64,
+    median: f64,
+    q1: f64,
+    q3: f64,
+}
+
+impl HistogramSummary {
+    fn new(histogram: &[u64; 256]) -> Self {
+        let total: u64 = histogram.iter().sum();
+        if total == 0 {
+            return Self { total, mean: 0.0, median: 0.0, q1: 0.0, q3: 0.0 };
+        }
+        
+        let sum: u64 = histogram
+            .iter()
+            .enumerate()
+            .map(|(q, &count)| q as u64 * count)
+            .sum();
+        
+        Self {
+            total,
+            mean: sum as f64 / total as f64,
+            median: Self::quantile(histogram, total, 0.5),
+            q1: Self::quantile(histogram, total, 0.25),
+            q3: Self::quantile(histogram, total, 0.75),
+        }
+    }
+    
+    /// Linear-interpolated quantile, matching numpy's default definition
+    fn quantile(histogram: &[u64; 256], total: u64, fraction: f64) -> f64 {
+        let rank = fraction * (total - 1) as f64;
+        let lower = rank.floor() as u64;
+        let upper = rank.ceil() as u64;
+        let lo = Self::value_at(histogram, lower) as f64;
+        let hi = Self::value_at(histogram, upper) as f64;
+        lo + (hi - lo) * (rank - lower as f64)
+    }
+    
+    /// Score at a zero-based rank in sorted order
+    fn value_at(histogram: &[u64; 256], rank: u64) -> u8 {
+        let mut seen = 0;
+        for (q, &count) in histogram.iter().enumerate() {
+            seen += count;
+            if seen > rank {
+                return q as u8;
+            }
         }
+        255
     }
 }
 
-#[derive(Debug)]
+/// Quality summary for a single read position
+#[derive(Debug, Clone)]
+pub struct PositionQuality {
+    pub position: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub q1: f64,
+    pub q3: f64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone)]
 pub struct QualityStats {
     pub mean: f64,
     pub min: u8,
     pub max: u8,
     pub total_bases: usize,
+    pub median: f64,
+    pub q1: f64,
+    pub q3: f64,
+    /// Fraction of bases with Phred >= 20
+    pub q20_fraction: f64,
+    /// Fraction of bases with Phred >= 30
+    pub q30_fraction: f64,
+    /// Base counts indexed by Phred score, up to and including `max`
+    pub histogram: Vec<u64>,
+    /// Per-position breakdown; empty when the input was a flat score buffer
+    pub per_position: Vec<PositionQuality>,
+}
+
+impl QualityStats {
+    fn from_histogram(histogram: [u64; 256], per_position: Vec<PositionQuality>) -> Self {
+        let summary = HistogramSummary::new(&histogram);
+        let min = histogram.iter().position(|&c| c > 0).unwrap_or(0) as u8;
+        let max = histogram.iter().rposition(|&c| c > 0).unwrap_or(0) as u8;
+        
+        let fraction_at_least = |threshold: usize| {
+            if summary.total == 0 {
+                return 0.0;
+            }
+            histogram[threshold..].iter().sum::<u64>() as f64 / summary.total as f64
+        };
+        
+        let histogram = if summary.total == 0 {
+            Vec::new()
+        } else {
+            histogram[..=max as usize].to_vec()
+        };
+        
+        QualityStats {
+            mean: summary.mean,
+            min,
+            max,
+            total_bases: summary.total as usize,
+            median: summary.median,
+            q1: summary.q1,
+            q3: summary.q3,
+            q20_fraction: fraction_at_least(20),
+            q30_fraction: fraction_at_least(30),
+            histogram,
+            per_position,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_only_agrees_with_banded_traceback() {
+        let pairs: [(&[u8], &[u8]); 4] = [
+            (b"ACGTACGTTTGACCA", b"ACGTCGTTTGAACCA"),
+            (b"AAAAAAAAAA", b"AAAATTTAAAAAAA"),
+            (b"GATTACA", b"CTAG"),
+            (b"ACGTNNNNACGT", b"ACGTACGT"),
+        ];
+        for (a, b) in pairs {
+            let banded = EditAlignment::new(a, b).unwrap();
+            let linear = EditAlignment::score_only(a, b, a.len().max(b.len())).unwrap();
+            assert_eq!(linear.distance, banded.distance);
+            assert_eq!(linear.columns - linear.gap_bases, banded.columns - banded.gap_bases);
+            assert_eq!(2 * linear.columns - linear.gap_bases, a.len() + b.len());
+        }
+    }
+
+    #[test]
+    fn ambiguous_bases_never_match() {
+        let optimizer = BinaryOptimizer::new();
+        let hamming = optimizer.compare_with_metric(b"ACNNT", b"acNRu", SimilarityMetric::Hamming).unwrap();
+        assert_eq!(hamming.differences, 2);
+        let identity = optimizer.compare_with_metric(b"ACGTNNNNACGT", b"ACGTNNNNACGT", SimilarityMetric::BlastIdentity).unwrap();
+        assert_eq!((identity.differences, identity.length), (4, 12));
+    }
+
+    #[test]
+    fn score_only_gives_up_outside_its_band() {
+        assert!(EditAlignment::score_only(b"AAAAAAAAAA", b"CCCCCCCCCC", 4).is_none());
+        assert_eq!(EditAlignment::score_only(b"AAAAAAAAAA", b"CCCCCCCCCC", 10).unwrap().distance, 10);
+    }
+
+    #[test]
+    fn contiguous_gap_counts_once() {
+        let alignment = EditAlignment::score_only(b"ACGTTTTTACGT", b"ACGTACGT", 4).unwrap();
+        assert_eq!(alignment.distance, 4);
+        assert_eq!(alignment.gap_bases, 4);
+        assert_eq!(alignment.gap_opens, 1);
+    }
+
+    #[test]
+    fn simd_base_counts_match_scalar() {
+        let mut state: u64 = 11;
+        for length in [0, 15, 16, 1001, PARALLEL_CHUNK * 3 + 7] {
+            let sequence: Vec<u8> = (0..length)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (state >> 33) as u8
+                })
+                .collect();
+            let mut expected = [0u32; 4];
+            for &base in &sequence {
+                expected[(base & 0b11) as usize] += 1;
+            }
+            assert_eq!(SimdProcessor::count_bases_simd(&sequence), expected, "length {}", length);
+        }
+    }
+
+    #[test]
+    fn quality_quantiles_interpolate_like_numpy() {
+        let scores: Vec<u8> = (1..=10).collect();
+        let stats = SimdProcessor::analyze_quality_simd(&scores);
+        assert_eq!((stats.min, stats.max, stats.total_bases), (1, 10, 10));
+        assert_eq!((stats.mean, stats.median, stats.q1, stats.q3), (5.5, 5.5, 3.25, 7.75));
+        assert_eq!(stats.histogram, [0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1]);
+        assert_eq!((stats.q20_fraction, stats.q30_fraction), (0.0, 0.0));
+
+        let stats = SimdProcessor::analyze_quality_simd(&[40, 40, 30, 20, 10]);
+        assert_eq!((stats.q20_fraction, stats.q30_fraction), (0.8, 0.6));
+        assert_eq!(stats.histogram.len(), 41);
+    }
+
+    #[test]
+    fn empty_quality_input_has_zeroed_stats() {
+        for stats in [SimdProcessor::analyze_quality_simd(&[]), QualityAccumulator::new().finish()] {
+            assert_eq!((stats.min, stats.max, stats.total_bases), (0, 0, 0));
+            assert_eq!((stats.mean, stats.median, stats.q1, stats.q3), (0.0, 0.0, 0.0, 0.0));
+            assert_eq!((stats.q20_fraction, stats.q30_fraction), (0.0, 0.0));
+            assert!(stats.histogram.is_empty() && stats.per_position.is_empty());
+        }
+    }
+
+    #[test]
+    fn accumulator_tracks_positions_across_batches() {
+        let mut accumulator = QualityAccumulator::new();
+        accumulator.add_reads(&[vec![30u8, 20], vec![10]]);
+        accumulator.add_reads(&[vec![40u8, 20, 35]]);
+        let stats = accumulator.finish();
+
+        let counts: Vec<usize> = stats.per_position.iter().map(|p| p.count).collect();
+        assert_eq!(counts, [3, 2, 1]);
+        let medians: Vec<f64> = stats.per_position.iter().map(|p| p.median).collect();
+        assert_eq!(medians, [30.0, 20.0, 35.0]);
+        assert_eq!((stats.per_position[0].q1, stats.per_position[0].q3), (20.0, 35.0));
+
+        let flat = SimdProcessor::analyze_quality_simd(&[30, 20, 10, 40, 20, 35]);
+        assert_eq!((stats.total_bases, stats.mean, stats.median), (flat.total_bases, flat.mean, flat.median));
+        assert_eq!(stats.histogram, flat.histogram);
+    }
 }
@@ -0,0 +1,545 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use crate::coverage::load_targets;
+use crate::mapper::Reference;
+use crate::seq_container::Region;
+use crate::vcf_norm::parse_gt;
+use crate::vcf_processor::{SNPVariant, VCFProcessor};
+
+/// IUPAC codes indexed by a bit set of A=1, C=2, G=4, T=8
+const IUPAC_BY_MASK: &[u8; 16] = b"NACMGRSVTWYHKDBN";
+
+/// IUPAC code covering every base in `bases`; anything but ACGT makes it N
+pub fn iupac_code(bases: &[u8]) -> u8 {
+    let mut mask = 0;
+    for base in bases {
+        mask |= match base.to_ascii_uppercase() {
+            b'A' => 1,
+            b'C' => 2,
+            b'G' => 4,
+            b'T' => 8,
+            _ => return b'N',
+        };
+    }
+    IUPAC_BY_MASK[mask]
+}
+
+/// How genotypes are turned into sequence
+#[derive(Debug, Clone, Default)]
+pub struct ConsensusParams {
+    /// Write one sequence per haplotype for phased genotypes instead of IUPAC codes
+    pub haplotypes: bool,
+    /// Apply records whatever their FILTER, not only PASS
+    pub include_filtered: bool,
+}
+
+/// What happened to the VCF records inside the requested regions
+#[derive(Debug, Clone, Default)]
+pub struct ConsensusStats {
+    /// Substitutions applied (SNPs and MNPs)
+    pub substitutions: usize,
+    /// Insertions, deletions and complex replacements applied
+    pub indels: usize,
+    /// Heterozygous sites written as IUPAC codes
+    pub iupac_sites: usize,
+    pub skipped_filtered: usize,
+    pub skipped_missing: usize,
+    /// Symbolic or breakend ALTs
+    pub skipped_symbolic: usize,
+    /// Unphased heterozygous indels, which have no IUPAC encoding
+    pub skipped_heterozygous_indels: usize,
+    /// Records overlapping an applied record or the region edge
+    pub skipped_overlapping: usize,
+    /// Records whose REF disagrees with the reference
+    pub skipped_mismatched: usize,
+    /// Records touching a masked base
+    pub skipped_masked: usize,
+    /// Reference bases replaced by N
+    pub masked_bases: u64,
+}
+
+impl ConsensusStats {
+    pub fn skipped(&self) -> usize {
+        self.skipped_filtered
+            + self.skipped_missing
+            + self.skipped_symbolic
+            + self.skipped_heterozygous_indels
+            + self.skipped_overlapping
+            + self.skipped_mismatched
+            + self.skipped_masked
+    }
+}
+
+/// A consensus sequence and its alignment to the reference region it came from
+#[derive(Debug, Clone)]
+pub struct ConsensusSequence {
+    pub name: String,
+    pub sequence: Vec<u8>,
+    pub chrom: String,
+    pub chrom_length: u64,
+    /// Reference span, 0-based half-open
+    pub start: u64,
+    pub end: u64,
+    /// Chain alignment as (ungapped size, reference gap, consensus gap); the last gaps are zero
+    pub blocks: Vec<(u64, u64, u64)>,
+}
+
+/// Consensus sequences for one sample
+#[derive(Debug, Clone)]
+pub struct Consensus {
+    /// Empty for a sites-only VCF
+    pub sample: String,
+    pub sequences: Vec<ConsensusSequence>,
+    pub stats: ConsensusStats,
+}
+
+impl Consensus {
+    /// Write the sequences as FASTA, 60 bases per line
+    pub fn write_fasta(&self, path: &str) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("Failed to create FASTA file: {}", path))?;
+        let mut writer = BufWriter::new(file);
+        for record in &self.sequences {
+            if self.sample.is_empty() {
+                writeln!(writer, ">{}", record.name)?;
+            } else {
+                writeln!(writer, ">{} sample={}", record.name, self.sample)?;
+            }
+            for line in record.sequence.chunks(60) {
+                writer.write_all(line)?;
+                writeln!(writer)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write a UCSC chain file mapping reference coordinates onto each consensus sequence
+    pub fn write_chain(&self, path: &str) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("Failed to create chain file: {}", path))?;
+        let mut writer = BufWriter::new(file);
+        for (id, record) in self.sequences.iter().enumerate() {
+            let score: u64 = record.blocks.iter().map(|(size, _, _)| size).sum();
+            let length = record.sequence.len();
+            writeln!(writer, "chain {} {} {} + {} {} {} {} + 0 {} {}",
+                score, record.chrom, record.chrom_length, record.start, record.end,
+                record.name, length, length, id + 1)?;
+            for (i, (size, dt, dq)) in record.blocks.iter().enumerate() {
+                if i + 1 == record.blocks.len() {
+                    writeln!(writer, "{}", size)?;
+                } else {
+                    writeln!(writer, "{}\t{}\t{}", size, dt, dq)?;
+                }
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A record accepted for a region: REF span and the replacement on each output sequence
+struct Edit {
+    start: u64,
+    ref_len: u64,
+    alleles: Vec<Option<Vec<u8>>>,
+}
+
+/// Applies a sample's variants to reference regions
+pub struct ConsensusBuilder {
+    reference: Reference,
+    params: ConsensusParams,
+    /// Masked intervals per contig, 0-based half-open
+    masks: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl ConsensusBuilder {
+    pub fn new(reference: Reference) -> Self {
+        Self { reference, params: ConsensusParams::default(), masks: HashMap::new() }
+    }
+
+    pub fn with_params(mut self, params: ConsensusParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Mask the intervals of a BED file
+    pub fn with_mask(mut self, path: &Path) -> Result<Self> {
+        for target in load_targets(path)? {
+            self.masks.entry(target.chrom).or_default().push((target.start, target.end));
+        }
+        self.merge_masks();
+        Ok(self)
+    }
+
+    /// Mask bases below `min_depth` in a bedGraph; bases it does not cover count as depth zero
+    pub fn with_depth_mask(mut self, path: &Path, min_depth: u32) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open bedGraph: {}", path.display()))?;
+        let reader: Box<dyn BufRead> = if path.extension().unwrap_or_default() == "gz" {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+
+        let mut covered: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                bail!("bedGraph line {} has fewer than 4 columns", number + 1);
+            }
+            let start: u64 = fields[1].parse().with_context(|| format!("Bad start on bedGraph line {}", number + 1))?;
+            let end: u64 = fields[2].parse().with_context(|| format!("Bad end on bedGraph line {}", number + 1))?;
+            let depth: f64 = fields[3].parse().with_context(|| format!("Bad depth on bedGraph line {}", number + 1))?;
+            if depth >= min_depth as f64 && end > start {
+                covered.entry(fields[0].to_string()).or_default().push((start, end));
+            }
+        }
+
+        for (name, sequence) in self.reference.names.iter().zip(&self.reference.sequences) {
+            let mut intervals = covered.remove(name).unwrap_or_default();
+            intervals.sort_unstable();
+            let mask = self.masks.entry(name.clone()).or_default();
+            let mut cursor = 0;
+            for (start, end) in intervals {
+                if start > cursor {
+                    mask.push((cursor, start));
+                }
+                cursor = cursor.max(end);
+            }
+            if cursor < sequence.len() as u64 {
+                mask.push((cursor, sequence.len() as u64));
+            }
+        }
+        self.merge_masks();
+        Ok(self)
+    }
+
+    fn merge_masks(&mut self) {
+        for intervals in self.masks.values_mut() {
+            intervals.sort_unstable();
+            let mut merged: Vec<(u64, u64)> = Vec::with_capacity(intervals.len());
+            for &(start, end) in intervals.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            *intervals = merged;
+        }
+    }
+
+    fn is_masked(&self, chrom: &str, start: u64, end: u64) -> bool {
+        self.masks.get(chrom).is_some_and(|intervals| {
+            let i = intervals.partition_point(|&(_, e)| e <= start);
+            intervals.get(i).is_some_and(|&(s, _)| s < end)
+        })
+    }
+
+    /// Build consensus sequences for `sample` (default: the first) over `regions` (default: every contig)
+    pub fn build(&self, vcf: &VCFProcessor, sample: Option<&str>, regions: &[Region]) -> Result<Consensus> {
+        let sample_index = match sample {
+            Some(name) => Some(vcf.samples.iter().position(|s| s == name).with_context(|| {
+                format!("Sample {} not in VCF (available: {})", name, vcf.samples.join(", "))
+            })?),
+            None if vcf.samples.is_empty() => None,
+            None => Some(0),
+        };
+        let sample = sample_index.map(|i| vcf.samples[i].clone()).unwrap_or_default();
+
+        let regions: Vec<Region> = if regions.is_empty() {
+            self.reference.names.iter().map(|name| Region { name: name.clone(), start: None, end: None }).collect()
+        } else {
+            regions.to_vec()
+        };
+
+        let mut by_chrom: HashMap<&str, Vec<&SNPVariant>> = HashMap::new();
+        for variant in &vcf.variants {
+            by_chrom.entry(variant.chromosome.as_str()).or_default().push(variant);
+        }
+        for variants in by_chrom.values_mut() {
+            variants.sort_by_key(|v| v.position);
+        }
+
+        let outputs = if self.params.haplotypes { 2 } else { 1 };
+        let mut stats = ConsensusStats::default();
+        let mut sequences = Vec::new();
+        for region in &regions {
+            let Some(index) = self.reference.names.iter().position(|n| *n == region.name) else {
+                bail!("Region {} names a sequence not in the reference", region);
+            };
+            let reference = &self.reference.sequences[index];
+            let (start, end) = region.bounds(reference.len() as u64);
+
+            let mut edits = Vec::new();
+            let mut last_end = vec![start; outputs];
+            let variants = by_chrom.get(region.name.as_str()).map(Vec::as_slice).unwrap_or_default();
+            let first = variants.partition_point(|v| v.position < start + 1);
+            for variant in &variants[first..] {
+                let position = variant.position - 1;
+                if position >= end {
+                    break;
+                }
+                let Some(alleles) = self.resolve(variant, sample_index, outputs, &mut stats) else {
+                    continue;
+                };
+                let ref_len = variant.reference.len() as u64;
+                if alleles.iter().all(Option::is_none) {
+                    continue;
+                }
+                let span_end = position + ref_len;
+                if span_end > end
+                    || alleles.iter().zip(&last_end).any(|(allele, &last)| allele.is_some() && position < last)
+                {
+                    stats.skipped_overlapping += 1;
+                    continue;
+                }
+                if !reference
+                    .get(position as usize..span_end as usize)
+                    .is_some_and(|bases| bases.eq_ignore_ascii_case(variant.reference.as_bytes()))
+                {
+                    stats.skipped_mismatched += 1;
+                    continue;
+                }
+                if self.is_masked(&region.name, position, span_end) {
+                    stats.skipped_masked += 1;
+                    continue;
+                }
+
+                for (allele, last) in alleles.iter().zip(last_end.iter_mut()) {
+                    if allele.is_some() {
+                        *last = span_end;
+                    }
+                }
+                let is_substitution = alleles.iter().flatten().all(|a| a.len() as u64 == ref_len);
+                if is_substitution {
+                    stats.substitutions += 1;
+                } else {
+                    stats.indels += 1;
+                }
+                edits.push(Edit { start: position, ref_len, alleles });
+            }
+
+            // Masking is applied to the template so applied variants keep their bases
+            let mut template = reference[start as usize..end as usize].to_vec();
+            for &(mask_start, mask_end) in self.masks.get(&region.name).map(Vec::as_slice).unwrap_or_default() {
+                let (from, to) = (mask_start.max(start), mask_end.min(end));
+                if from < to {
+                    template[(from - start) as usize..(to - start) as usize].fill(b'N');
+                    stats.masked_bases += to - from;
+                }
+            }
+
+            let name = if region.start.is_some() { region.to_string() } else { region.name.clone() };
+            for output in 0..outputs {
+                let (sequence, blocks) = apply_edits(reference, &template, start, end, &edits, output);
+                let name = if outputs > 1 { format!("{}_hap{}", name, output + 1) } else { name.clone() };
+                sequences.push(ConsensusSequence {
+                    name,
+                    sequence,
+                    chrom: region.name.clone(),
+                    chrom_length: reference.len() as u64,
+                    start,
+                    end,
+                    blocks,
+                });
+            }
+        }
+
+        Ok(Consensus { sample, sequences, stats })
+    }
+
+    /// Replacement for each output sequence, `None` where it keeps the reference;
+    /// returns `None` (and counts why) when the record cannot be applied
+    fn resolve(
+        &self,
+        variant: &SNPVariant,
+        sample_index: Option<usize>,
+        outputs: usize,
+        stats: &mut ConsensusStats,
+    ) -> Option<Vec<Option<Vec<u8>>>> {
+        if !self.params.include_filtered && !matches!(variant.filter.as_str(), "PASS" | "." | "") {
+            stats.skipped_filtered += 1;
+            return None;
+        }
+
+        let (genotype, phased) = match sample_index {
+            Some(i) => match variant.samples.get(i) {
+                Some(gt) => parse_gt(gt),
+                None => (vec![None], false),
+            },
+            // Sites-only VCFs carry no genotypes: every record is taken as present
+            None => (vec![Some(1)], false),
+        };
+        let alleles: Vec<&str> = std::iter::once(variant.reference.as_str())
+            .chain(variant.alternative.split(','))
+            .collect();
+        let Some(genotype) = genotype
+            .into_iter()
+            .map(|a| a.filter(|&a| a < alleles.len()))
+            .collect::<Option<Vec<usize>>>()
+        else {
+            stats.skipped_missing += 1;
+            return None;
+        };
+        if genotype.iter().any(|&a| a > 0 && (alleles[a].starts_with('<') || alleles[a].contains(['[', ']', '.']))) {
+            stats.skipped_symbolic += 1;
+            return None;
+        }
+
+        // A spanning deletion (`*`) was already applied by the record it overlaps
+        let sequence = |a: usize| (a > 0 && alleles[a] != "*").then(|| alleles[a].to_ascii_uppercase().into_bytes());
+        if genotype.iter().all(|&a| a == genotype[0]) {
+            return Some(vec![sequence(genotype[0]); outputs]);
+        }
+        if outputs > 1 && phased {
+            return Some((0..outputs).map(|h| sequence(genotype[h.min(genotype.len() - 1)])).collect());
+        }
+
+        let ref_len = variant.reference.len();
+        let called: Vec<&str> = genotype.iter().map(|&a| alleles[a]).filter(|a| *a != "*").collect();
+        if called.iter().any(|a| a.len() != ref_len) {
+            stats.skipped_heterozygous_indels += 1;
+            return None;
+        }
+        let codes: Vec<u8> = (0..ref_len)
+            .map(|i| {
+                let bases: Vec<u8> = called.iter().map(|a| a.as_bytes()[i]).collect();
+                iupac_code(&bases)
+            })
+            .collect();
+        stats.iupac_sites += 1;
+        Some(vec![Some(codes); outputs])
+    }
+}
+
+/// Apply the edits for one output to the masked template, tracking the chain alignment
+fn apply_edits(
+    reference: &[u8],
+    template: &[u8],
+    start: u64,
+    end: u64,
+    edits: &[Edit],
+    output: usize,
+) -> (Vec<u8>, Vec<(u64, u64, u64)>) {
+    let mut sequence = Vec::with_capacity(template.len());
+    let mut blocks: Vec<(u64, u64, u64)> = Vec::new();
+    let mut block = 0;
+    let mut cursor = start;
+    for edit in edits {
+        let Some(allele) = &edit.alleles[output] else {
+            continue;
+        };
+        sequence.extend_from_slice(&template[(cursor - start) as usize..(edit.start - start) as usize]);
+        block += edit.start - cursor;
+        sequence.extend_from_slice(allele);
+        cursor = edit.start + edit.ref_len;
+
+        let ref_bases = &reference[edit.start as usize..cursor as usize];
+        if ref_bases.len() == allele.len() {
+            block += edit.ref_len;
+            continue;
+        }
+        // Shared leading and trailing bases stay aligned around the gap
+        let prefix = ref_bases.iter().zip(allele).take_while(|(a, b)| a == b).count();
+        let suffix = ref_bases[prefix..]
+            .iter()
+            .rev()
+            .zip(allele[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let dt = (ref_bases.len() - prefix - suffix) as u64;
+        let dq = (allele.len() - prefix - suffix) as u64;
+        block += prefix as u64;
+        match blocks.last_mut() {
+            Some(last) if block == 0 => {
+                last.1 += dt;
+                last.2 += dq;
+            }
+            _ => blocks.push((block, dt, dq)),
+        }
+        block = suffix as u64;
+    }
+    sequence.extend_from_slice(&template[(cursor - start) as usize..]);
+    block += end - cursor;
+    blocks.push((block, 0, 0));
+    (sequence, blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFERENCE: &[u8] = b"ACGTACGTAC";
+
+    fn edit(start: u64, ref_len: u64, alleles: &[Option<&str>]) -> Edit {
+        Edit { start, ref_len, alleles: alleles.iter().map(|a| a.map(|a| a.as_bytes().to_vec())).collect() }
+    }
+
+    /// Blocks must account for every base on both sides of the chain
+    fn check_spans(blocks: &[(u64, u64, u64)], ref_span: u64, sequence: &[u8]) {
+        let sizes: u64 = blocks.iter().map(|b| b.0).sum();
+        assert_eq!(sizes + blocks.iter().map(|b| b.1).sum::<u64>(), ref_span);
+        assert_eq!(sizes + blocks.iter().map(|b| b.2).sum::<u64>(), sequence.len() as u64);
+    }
+
+    #[test]
+    fn substitutions_keep_one_block() {
+        let (sequence, blocks) = apply_edits(REFERENCE, REFERENCE, 0, 10, &[edit(2, 1, &[Some("T")])], 0);
+        assert_eq!(sequence, b"ACTTACGTAC");
+        assert_eq!(blocks, vec![(10, 0, 0)]);
+    }
+
+    #[test]
+    fn deletion_gap_follows_the_anchor_base() {
+        let (sequence, blocks) = apply_edits(REFERENCE, REFERENCE, 0, 10, &[edit(2, 3, &[Some("G")])], 0);
+        assert_eq!(sequence, b"ACGCGTAC");
+        assert_eq!(blocks, vec![(3, 2, 0), (5, 0, 0)]);
+        check_spans(&blocks, 10, &sequence);
+    }
+
+    #[test]
+    fn shared_trailing_base_stays_aligned() {
+        let (sequence, blocks) = apply_edits(REFERENCE, REFERENCE, 0, 10, &[edit(4, 3, &[Some("G")])], 0);
+        assert_eq!(sequence, b"ACGTGTAC");
+        assert_eq!(blocks, vec![(4, 2, 0), (4, 0, 0)]);
+        check_spans(&blocks, 10, &sequence);
+    }
+
+    #[test]
+    fn adjacent_gaps_merge_into_one_chain_gap() {
+        let edits = [edit(3, 1, &[Some("TGG")]), edit(4, 3, &[Some("T")])];
+        let (sequence, blocks) = apply_edits(REFERENCE, REFERENCE, 0, 10, &edits, 0);
+        assert_eq!(sequence, b"ACGTGGTTAC");
+        assert_eq!(blocks, vec![(4, 3, 3), (3, 0, 0)]);
+        check_spans(&blocks, 10, &sequence);
+    }
+
+    #[test]
+    fn other_haplotype_edits_are_skipped() {
+        let edits = [edit(2, 3, &[Some("G"), None]), edit(7, 1, &[None, Some("TCC")])];
+        let (first, first_blocks) = apply_edits(REFERENCE, REFERENCE, 0, 10, &edits, 0);
+        let (second, second_blocks) = apply_edits(REFERENCE, REFERENCE, 0, 10, &edits, 1);
+        assert_eq!(first, b"ACGCGTAC");
+        assert_eq!(second, b"ACGTACGTCCAC");
+        assert_eq!(second_blocks, vec![(8, 0, 2), (2, 0, 0)]);
+        check_spans(&first_blocks, 10, &first);
+        check_spans(&second_blocks, 10, &second);
+    }
+
+    #[test]
+    fn region_offsets_and_masked_template() {
+        // Region 2..10 with a masked base; blocks count from the region start
+        let template = b"GTNCGTAC";
+        let (sequence, blocks) = apply_edits(REFERENCE, template, 2, 10, &[edit(5, 3, &[Some("C")])], 0);
+        assert_eq!(sequence, b"GTNCAC");
+        assert_eq!(blocks, vec![(4, 2, 0), (2, 0, 0)]);
+        check_spans(&blocks, 8, &sequence);
+    }
+}
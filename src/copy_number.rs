@@ -0,0 +1,1003 @@
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use rust_htslib::bam::{self, Read};
+use rust_htslib::faidx;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use crate::identity_matrix::xml_escape;
+use crate::variant_caller::sample_name;
+
+/// GC content is stratified into whole percentages
+const GC_STRATA: usize = 101;
+/// Bins a GC stratum pools, from its neighbours if needed, before its median is trusted
+const MIN_STRATUM_BINS: usize = 50;
+/// Bins with more reference N than this are masked
+const MAX_N_FRACTION: f64 = 0.5;
+/// Bins where the reference depth is below this fraction of its median are masked
+const MIN_REFERENCE_DEPTH: f64 = 0.3;
+/// Floor on log2 ratios, so empty bins stay finite
+const MIN_LOG2: f64 = -5.0;
+/// Longest arc CBS tests directly; longer changes are found by splits at the ends
+const MAX_ARC_BINS: usize = 1000;
+/// Copy-number states of the HMM run from zero to this
+const MAX_HMM_COPY: u32 = 6;
+/// Chance per bin that the HMM leaves its current state
+const HMM_SWITCH_PROBABILITY: f64 = 1e-4;
+
+const PLOT_WIDTH: f64 = 1400.0;
+const PLOT_HEIGHT: f64 = 360.0;
+/// Log2 ratios beyond this are drawn on the plot edge
+const PLOT_LOG2_LIMIT: f64 = 2.5;
+const GAIN_COLOUR: &str = "rgb(210,40,40)";
+const LOSS_COLOUR: &str = "rgb(30,70,200)";
+
+/// How bins are grouped into segments of equal copy number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentationMethod {
+    /// Circular binary segmentation (Olshen et al. 2004)
+    Cbs,
+    /// Viterbi path through integer copy-number states
+    Hmm,
+}
+
+impl std::str::FromStr for SegmentationMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "cbs" => Ok(SegmentationMethod::Cbs),
+            "hmm" => Ok(SegmentationMethod::Hmm),
+            _ => bail!("Unknown segmentation method: {} (use cbs or hmm)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for SegmentationMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SegmentationMethod::Cbs => write!(f, "CBS"),
+            SegmentationMethod::Hmm => write!(f, "HMM"),
+        }
+    }
+}
+
+/// Binning, masking, segmentation and calling thresholds
+#[derive(Debug, Clone, Copy)]
+pub struct CnvParams {
+    /// Width of the depth bins, in bases
+    pub bin_size: u64,
+    /// Reads below this mapping quality do not count towards depth
+    pub min_mapping_quality: u8,
+    /// Bins less mappable than this are masked
+    pub min_mappability: f64,
+    pub method: SegmentationMethod,
+    /// Significance level of each CBS split
+    pub alpha: f64,
+    /// Fewest bins in a segment
+    pub min_bins: usize,
+    /// CBS segment log2 ratios at or above this are gains
+    pub gain_threshold: f64,
+    /// CBS segment log2 ratios at or below this are losses
+    pub loss_threshold: f64,
+    /// Copy number of the genome at a log2 ratio of zero
+    pub ploidy: u32,
+}
+
+impl Default for CnvParams {
+    fn default() -> Self {
+        Self {
+            bin_size: 1000,
+            min_mapping_quality: 20,
+            min_mappability: 0.5,
+            method: SegmentationMethod::Cbs,
+            alpha: 0.01,
+            min_bins: 3,
+            gain_threshold: 0.25,
+            loss_threshold: -0.25,
+            ploidy: 2,
+        }
+    }
+}
+
+/// Direction of a copy-number change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CnvCall {
+    Gain,
+    Loss,
+}
+
+impl std::fmt::Display for CnvCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CnvCall::Gain => write!(f, "GAIN"),
+            CnvCall::Loss => write!(f, "LOSS"),
+        }
+    }
+}
+
+/// Run of bins with one copy number
+#[derive(Debug, Clone)]
+pub struct CnvSegment {
+    pub chrom: String,
+    /// 0-based start of the first bin
+    pub start: u64,
+    /// End of the last bin, exclusive
+    pub end: u64,
+    /// Unmasked bins in the segment
+    pub bins: usize,
+    /// Mean log2 ratio of the bins
+    pub log2: f64,
+    pub copy_number: u32,
+    pub call: Option<CnvCall>,
+}
+
+impl std::fmt::Display for CnvSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}-{} ({}, log2 {:.2}, CN {}, {} bins)",
+            self.chrom,
+            self.start + 1,
+            self.end,
+            self.call.map_or_else(|| "neutral".to_string(), |call| call.to_string()),
+            self.log2,
+            self.copy_number,
+            self.bins
+        )
+    }
+}
+
+/// Log2 ratios of one unmasked bin
+#[derive(Debug, Clone, Copy)]
+pub struct BinRatio {
+    /// Bin number along its contig
+    pub index: u64,
+    pub log2: f64,
+}
+
+/// Segmented copy-number profile of one sample
+#[derive(Debug, Clone)]
+pub struct CnvProfile {
+    pub sample: String,
+    pub contigs: Vec<(String, u64)>,
+    pub params: CnvParams,
+    /// Unmasked bins of each contig, in order
+    pub ratios: Vec<Vec<BinRatio>>,
+    pub segments: Vec<CnvSegment>,
+    /// Control samples the ratios are relative to; none means the sample's own median
+    pub controls: usize,
+    /// Median reads per unmasked bin in the sample
+    pub median_reads: f64,
+    /// Robust standard deviation of the log2 ratios between neighbouring bins
+    pub noise: f64,
+    pub masked_bins: usize,
+}
+
+impl CnvProfile {
+    pub fn calls(&self) -> impl Iterator<Item = &CnvSegment> {
+        self.segments.iter().filter(|segment| segment.call.is_some())
+    }
+
+    pub fn count(&self, call: CnvCall) -> usize {
+        self.segments.iter().filter(|segment| segment.call == Some(call)).count()
+    }
+
+    /// What the log2 ratios are relative to
+    pub fn normalization(&self) -> String {
+        match self.controls {
+            0 => "sample median".to_string(),
+            1 => "matched control".to_string(),
+            n => format!("panel of {} normals", n),
+        }
+    }
+
+    pub fn bin_count(&self) -> usize {
+        self.ratios.iter().map(Vec::len).sum()
+    }
+
+    /// Segments in the SEG format IGV and DNAcopy use, starts 1-based
+    pub fn write_seg(&self, path: &str) -> Result<()> {
+        let file = std::fs::File::create(path).with_context(|| format!("Could not create SEG file: {}", path))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "ID\tchrom\tloc.start\tloc.end\tnum.mark\tseg.mean")?;
+        for segment in &self.segments {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{:.4}",
+                self.sample,
+                segment.chrom,
+                segment.start + 1,
+                segment.end,
+                segment.bins,
+                segment.log2
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Gains and losses as BED, with the log2 ratio, copy number and bin count
+    pub fn write_bed(&self, path: &str) -> Result<()> {
+        let file = std::fs::File::create(path).with_context(|| format!("Could not create BED file: {}", path))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "#chrom\tstart\tend\tcall\tlog2\tcopy_number\tbins")?;
+        for segment in self.calls() {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{:.4}\t{}\t{}",
+                segment.chrom,
+                segment.start,
+                segment.end,
+                segment.call.map_or_else(String::new, |call| call.to_string()),
+                segment.log2,
+                segment.copy_number,
+                segment.bins
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Genome-wide scatter of bin log2 ratios with segment means drawn over them
+    ///
+    /// Bins sharing a pixel column are drawn as their median, so the plot
+    /// stays small for whole genomes.
+    pub fn to_svg(&self) -> String {
+        let (left, top) = (60.0, 40.0);
+        let genome: u64 = self.contigs.iter().map(|(_, length)| length).sum::<u64>().max(1);
+        let scale = PLOT_WIDTH / genome as f64;
+        let y = |log2: f64| top + (PLOT_LOG2_LIMIT - log2.clamp(-PLOT_LOG2_LIMIT, PLOT_LOG2_LIMIT)) / (2.0 * PLOT_LOG2_LIMIT) * PLOT_HEIGHT;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" font-family=\"sans-serif\" font-size=\"11\">\n",
+            left + PLOT_WIDTH + 20.0,
+            top + PLOT_HEIGHT + 50.0
+        );
+        svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+        svg.push_str(&format!(
+            "<text x=\"{:.2}\" y=\"24\" font-size=\"13\">Copy number: {} vs {} ({} bins of {} bp, {} segmentation; red gain, blue loss)</text>\n",
+            left,
+            xml_escape(&self.sample),
+            self.normalization(),
+            self.bin_count(),
+            self.params.bin_size,
+            self.params.method
+        ));
+
+        // Alternate contig shading, names underneath
+        let mut offset = 0u64;
+        for (index, (name, length)) in self.contigs.iter().enumerate() {
+            let (x, width) = (left + offset as f64 * scale, *length as f64 * scale);
+            if index % 2 == 1 {
+                svg.push_str(&format!(
+                    "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"#f2f2f2\"/>\n",
+                    x, top, width, PLOT_HEIGHT
+                ));
+            }
+            if width >= 24.0 {
+                svg.push_str(&format!(
+                    "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"middle\">{}</text>\n",
+                    x + width / 2.0,
+                    top + PLOT_HEIGHT + 16.0,
+                    xml_escape(name.strip_prefix("chr").unwrap_or(name))
+                ));
+            }
+            offset += length;
+        }
+        for log2 in [-2.0, -1.0, 0.0, 1.0, 2.0] {
+            svg.push_str(&format!(
+                "<line x1=\"{left:.2}\" y1=\"{0:.2}\" x2=\"{1:.2}\" y2=\"{0:.2}\" stroke=\"{2}\"/>\n<text x=\"{3:.2}\" y=\"{4:.2}\" text-anchor=\"end\">{log2}</text>\n",
+                y(log2),
+                left + PLOT_WIDTH,
+                if log2 == 0.0 { "#888" } else { "#ddd" },
+                left - 6.0,
+                y(log2) + 4.0
+            ));
+        }
+        svg.push_str(&format!(
+            "<text x=\"14\" y=\"{0:.2}\" text-anchor=\"middle\" font-size=\"12\" transform=\"rotate(-90 14 {0:.2})\">log2 ratio</text>\n",
+            top + PLOT_HEIGHT / 2.0
+        ));
+
+        let mut offset = 0u64;
+        for (tid, (name, length)) in self.contigs.iter().enumerate() {
+            let x = |pos: u64| left + (offset + pos) as f64 * scale;
+            let mut column: Vec<f64> = Vec::new();
+            let mut column_x = f64::NAN;
+            let bins = &self.ratios[tid];
+            for (i, bin) in bins.iter().enumerate() {
+                let bin_x = x(bin.index * self.params.bin_size).floor();
+                if bin_x != column_x && !column.is_empty() {
+                    svg.push_str(&point(column_x, y(median(&mut column))));
+                    column.clear();
+                }
+                column_x = bin_x;
+                column.push(bin.log2);
+                if i + 1 == bins.len() {
+                    svg.push_str(&point(column_x, y(median(&mut column))));
+                }
+            }
+            for segment in self.segments.iter().filter(|segment| &segment.chrom == name) {
+                let colour = match segment.call {
+                    Some(CnvCall::Gain) => GAIN_COLOUR,
+                    Some(CnvCall::Loss) => LOSS_COLOUR,
+                    None => "rgb(240,150,0)",
+                };
+                svg.push_str(&format!(
+                    "<line x1=\"{:.2}\" y1=\"{2:.2}\" x2=\"{:.2}\" y2=\"{2:.2}\" stroke=\"{3}\" stroke-width=\"2.5\"/>\n",
+                    x(segment.start),
+                    x(segment.end).max(x(segment.start) + 1.0),
+                    y(segment.log2),
+                    colour
+                ));
+            }
+            offset += length;
+        }
+        svg.push_str(&format!(
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"none\" stroke=\"black\"/>\n",
+            left, top, PLOT_WIDTH, PLOT_HEIGHT
+        ));
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn point(x: f64, y: f64) -> String {
+    format!("<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"1.3\" fill=\"#777\"/>\n", x + 0.5, y)
+}
+
+/// Reads starting in each bin of one alignment file
+struct ReadCounts {
+    sample: String,
+    contigs: Vec<(String, u64)>,
+    /// Reads at or above the mapping-quality cutoff
+    unique: Vec<Vec<u32>>,
+    /// All primary reads, for masking multi-mapping bins without a track
+    total: Vec<Vec<u32>>,
+}
+
+/// GC fraction and callable bases of each reference bin
+struct Composition {
+    gc: Vec<Vec<f64>>,
+    /// A, C, G or T bases in the bin
+    acgt: Vec<Vec<u64>>,
+}
+
+/// Mappability scores from a BED or bedGraph track, per bin
+pub struct MappabilityTrack {
+    intervals: HashMap<String, Vec<(u64, u64, f64)>>,
+}
+
+impl MappabilityTrack {
+    /// Load `chrom start end [score]` lines; intervals without a score count as fully mappable
+    pub fn load(path: &str) -> Result<Self> {
+        let file = std::fs::File::open(path).with_context(|| format!("Could not open mappability track: {}", path))?;
+        let mut intervals: HashMap<String, Vec<(u64, u64, f64)>> = HashMap::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 3 {
+                bail!("Mappability track line {} has fewer than 3 columns", number + 1);
+            }
+            let start: u64 = fields[1].parse().with_context(|| format!("Bad start on mappability line {}", number + 1))?;
+            let end: u64 = fields[2].parse().with_context(|| format!("Bad end on mappability line {}", number + 1))?;
+            let score = match fields.get(3) {
+                Some(score) => score.parse().with_context(|| format!("Bad score on mappability line {}", number + 1))?,
+                None => 1.0,
+            };
+            intervals.entry(fields[0].to_string()).or_default().push((start, end, score));
+        }
+        for list in intervals.values_mut() {
+            list.sort_by_key(|&(start, end, _)| (start, end));
+        }
+        Ok(Self { intervals })
+    }
+
+    /// Length-weighted mean score of each bin; bases outside the track score zero
+    fn bins(&self, chrom: &str, length: u64, bin_size: u64) -> Vec<f64> {
+        let mut covered = vec![0.0; bin_count(length, bin_size)];
+        for &(start, end, score) in self.intervals.get(chrom).map_or(&[][..], Vec::as_slice) {
+            let mut pos = start;
+            while pos < end.min(length) {
+                let bin = pos / bin_size;
+                let next = ((bin + 1) * bin_size).min(end).min(length);
+                covered[bin as usize] += (next - pos) as f64 * score;
+                pos = next;
+            }
+        }
+        for (bin, value) in covered.iter_mut().enumerate() {
+            let start = bin as u64 * bin_size;
+            *value /= ((start + bin_size).min(length) - start) as f64;
+        }
+        covered
+    }
+}
+
+fn bin_count(length: u64, bin_size: u64) -> usize {
+    length.div_ceil(bin_size) as usize
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_unstable_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Read-depth copy-number caller
+///
+/// Reads are counted per bin by their start, corrected for mappability and
+/// then for GC content by the median depth of bins with the same GC
+/// percentage. A matched control, or the per-bin median of a panel of
+/// normals corrected the same way, is the reference the sample's log2 ratios
+/// are taken against; without controls the ratios are relative to the
+/// sample's own median.
+pub struct CopyNumberCaller {
+    reference: String,
+    params: CnvParams,
+    mappability: Option<MappabilityTrack>,
+}
+
+impl CopyNumberCaller {
+    pub fn new(reference: &str) -> Self {
+        Self { reference: reference.to_string(), params: CnvParams::default(), mappability: None }
+    }
+
+    pub fn with_params(mut self, params: CnvParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn with_mappability(mut self, track: MappabilityTrack) -> Self {
+        self.mappability = Some(track);
+        self
+    }
+
+    pub fn call(&self, input_path: &str, controls: &[String]) -> Result<CnvProfile> {
+        let params = self.params;
+        if params.bin_size == 0 {
+            bail!("Bin size must be at least 1 base");
+        }
+        if params.loss_threshold >= 0.0 || params.gain_threshold <= 0.0 {
+            bail!(
+                "Loss threshold must be negative and gain threshold positive (got {} and {})",
+                params.loss_threshold,
+                params.gain_threshold
+            );
+        }
+        // The HMM needs a state above the baseline to call gains
+        if params.ploidy == 0 || params.ploidy >= MAX_HMM_COPY {
+            bail!("Ploidy must be between 1 and {} (got {})", MAX_HMM_COPY - 1, params.ploidy);
+        }
+
+        let paths: Vec<&str> = std::iter::once(input_path).chain(controls.iter().map(String::as_str)).collect();
+        let counts: Vec<ReadCounts> = paths.par_iter().map(|path| count_reads(path, &params)).collect::<Result<_>>()?;
+        let contigs = counts[0].contigs.clone();
+        for (other, path) in counts.iter().zip(&paths).skip(1) {
+            if other.contigs != contigs {
+                bail!("Control {} was aligned to different contigs than {}", path, input_path);
+            }
+        }
+        let composition = self.composition(&contigs)?;
+        let mappability: Option<Vec<Vec<f64>>> = self.mappability.as_ref().map(|track| {
+            contigs.iter().map(|(name, length)| track.bins(name, *length, params.bin_size)).collect()
+        });
+
+        let mut depths = counts
+            .iter()
+            .map(|sample| corrected_depth(sample, &composition, mappability.as_deref(), &params))
+            .collect::<Result<Vec<_>>>()?;
+        let median_reads = {
+            let mut reads: Vec<f64> = counts[0]
+                .unique
+                .iter()
+                .zip(&depths[0])
+                .flat_map(|(counts, depth)| counts.iter().zip(depth).filter(|(_, d)| d.is_some()).map(|(&c, _)| c as f64))
+                .collect();
+            if reads.is_empty() { 0.0 } else { median(&mut reads) }
+        };
+        let sample_depth = depths.remove(0);
+        let reference = reference_depth(&depths, &contigs, params.bin_size);
+
+        // Log2 ratios against the reference, centred on their median
+        let mut ratios: Vec<Vec<BinRatio>> = Vec::with_capacity(contigs.len());
+        let mut masked_bins = 0;
+        for (tid, (_, length)) in contigs.iter().enumerate() {
+            let mut bins = Vec::new();
+            for index in 0..bin_count(*length, params.bin_size) {
+                match (sample_depth[tid][index], reference[tid][index]) {
+                    (Some(depth), Some(expected)) if expected >= MIN_REFERENCE_DEPTH => bins.push(BinRatio {
+                        index: index as u64,
+                        log2: (depth / expected).log2().max(MIN_LOG2),
+                    }),
+                    _ => masked_bins += 1,
+                }
+            }
+            ratios.push(bins);
+        }
+        let mut all: Vec<f64> = ratios.iter().flatten().map(|bin| bin.log2).collect();
+        if all.len() < params.min_bins {
+            bail!("Too few unmasked bins to call copy number ({}); check coverage and bin size", all.len());
+        }
+        let centre = median(&mut all);
+        for bin in ratios.iter_mut().flatten() {
+            bin.log2 -= centre;
+        }
+        let noise = noise(&ratios);
+
+        let segments: Vec<CnvSegment> = contigs
+            .par_iter()
+            .zip(&ratios)
+            .flat_map_iter(|((chrom, length), bins)| {
+                let values: Vec<f64> = bins.iter().map(|bin| bin.log2).collect();
+                let runs = match params.method {
+                    SegmentationMethod::Cbs => circular_binary_segmentation(&values, noise, &params)
+                        .into_iter()
+                        .map(|(start, end)| (start, end, None))
+                        .collect(),
+                    SegmentationMethod::Hmm => hmm_segmentation(&values, noise, &params),
+                };
+                runs.into_iter()
+                    .map(|(start, end, state)| {
+                        let log2 = values[start..end].iter().sum::<f64>() / (end - start) as f64;
+                        segment(chrom, *length, &bins[start..end], log2, state, &params)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        Ok(CnvProfile {
+            sample: counts[0].sample.clone(),
+            contigs,
+            params,
+            ratios,
+            segments,
+            controls: controls.len(),
+            median_reads,
+            noise,
+            masked_bins,
+        })
+    }
+
+    fn composition(&self, contigs: &[(String, u64)]) -> Result<Composition> {
+        let fasta = faidx::Reader::from_path(&self.reference)
+            .map_err(|e| anyhow::anyhow!("Failed to open reference {}: {}", self.reference, e))?;
+        let bin_size = self.params.bin_size as usize;
+        let mut composition = Composition { gc: Vec::new(), acgt: Vec::new() };
+        for (name, length) in contigs {
+            let sequence = fasta
+                .fetch_seq(name, 0, (*length as usize).saturating_sub(1))
+                .map_err(|e| anyhow::anyhow!("Contig {} not found in reference: {}", name, e))?;
+            let (mut gc, mut acgt) = (Vec::new(), Vec::new());
+            for chunk in sequence.chunks(bin_size) {
+                let strong = chunk.iter().filter(|b| matches!(b.to_ascii_uppercase(), b'G' | b'C')).count();
+                let weak = chunk.iter().filter(|b| matches!(b.to_ascii_uppercase(), b'A' | b'T')).count();
+                gc.push(if strong + weak == 0 { 0.0 } else { strong as f64 / (strong + weak) as f64 });
+                acgt.push((strong + weak) as u64);
+            }
+            gc.resize(bin_count(*length, self.params.bin_size), 0.0);
+            acgt.resize(bin_count(*length, self.params.bin_size), 0);
+            composition.gc.push(gc);
+            composition.acgt.push(acgt);
+        }
+        Ok(composition)
+    }
+}
+
+fn count_reads(path: &str, params: &CnvParams) -> Result<ReadCounts> {
+    let mut reader = bam::Reader::from_path(path).with_context(|| format!("Could not open alignments: {}", path))?;
+    let header = reader.header().clone();
+    let contigs: Vec<(String, u64)> = (0..header.target_count())
+        .map(|tid| (String::from_utf8_lossy(header.tid2name(tid)).into_owned(), header.target_len(tid).unwrap_or(0)))
+        .collect();
+    let mut unique: Vec<Vec<u32>> = contigs.iter().map(|(_, length)| vec![0; bin_count(*length, params.bin_size)]).collect();
+    let mut total = unique.clone();
+    for result in reader.records() {
+        let record = result.with_context(|| format!("Could not read alignment record from {}", path))?;
+        if record.is_unmapped()
+            || record.is_secondary()
+            || record.is_supplementary()
+            || record.is_quality_check_failed()
+            || record.is_duplicate()
+            || record.tid() < 0
+        {
+            continue;
+        }
+        let (tid, bin) = (record.tid() as usize, (record.pos().max(0) as u64 / params.bin_size) as usize);
+        if let Some(count) = total[tid].get_mut(bin) {
+            *count += 1;
+            if record.mapq() >= params.min_mapping_quality {
+                unique[tid][bin] += 1;
+            }
+        }
+    }
+    Ok(ReadCounts { sample: sample_name(header.as_bytes(), path), contigs, unique, total })
+}
+
+/// Depth of each bin scaled to a median of one, or `None` where it is masked
+///
+/// Counts are scaled up for uncallable reference bases and, with a track,
+/// for low mappability. Without a track, bins where too few reads pass the
+/// mapping-quality cutoff are masked but not rescaled. Each bin is then
+/// divided by the median of its GC stratum, pooling neighbouring strata
+/// until there are enough bins to trust the median.
+fn corrected_depth(
+    counts: &ReadCounts,
+    composition: &Composition,
+    mappability: Option<&[Vec<f64>]>,
+    params: &CnvParams,
+) -> Result<Vec<Vec<Option<f64>>>> {
+    let mut depth: Vec<Vec<Option<f64>>> = Vec::with_capacity(counts.contigs.len());
+    let mut strata: Vec<Vec<f64>> = vec![Vec::new(); GC_STRATA];
+    for (tid, (_, length)) in counts.contigs.iter().enumerate() {
+        let mut bins = Vec::with_capacity(counts.unique[tid].len());
+        for (bin, &unique) in counts.unique[tid].iter().enumerate() {
+            let bases = ((bin as u64 + 1) * params.bin_size).min(*length) - bin as u64 * params.bin_size;
+            let callable = composition.acgt[tid][bin] as f64 / bases as f64;
+            let mappable = match mappability {
+                Some(track) => track[tid][bin],
+                None if counts.total[tid][bin] == 0 => 1.0,
+                None => unique as f64 / counts.total[tid][bin] as f64,
+            };
+            if 1.0 - callable > MAX_N_FRACTION || mappable < params.min_mappability {
+                bins.push(None);
+                continue;
+            }
+            // Dividing by the unique share would just count every read again
+            let scale = if mappability.is_some() { mappable } else { 1.0 };
+            let value = unique as f64 / (callable * bases as f64 / params.bin_size as f64) / scale;
+            strata[gc_stratum(composition.gc[tid][bin])].push(value);
+            bins.push(Some(value));
+        }
+        depth.push(bins);
+    }
+
+    let mut all: Vec<f64> = strata.iter().flatten().copied().collect();
+    if all.is_empty() {
+        bail!("No unmasked bins in {}", counts.sample);
+    }
+    let overall = median(&mut all);
+    if overall <= 0.0 {
+        bail!("Median depth of {} is zero; use larger bins or check the alignments", counts.sample);
+    }
+    let stratum_medians: Vec<Option<f64>> = (0..GC_STRATA)
+        .map(|stratum| {
+            if strata[stratum].is_empty() {
+                return None;
+            }
+            let mut width = 0;
+            let mut pooled: Vec<f64> = strata[stratum].clone();
+            while pooled.len() < MIN_STRATUM_BINS && width < GC_STRATA {
+                width += 1;
+                for neighbour in [stratum.checked_sub(width), Some(stratum + width)].into_iter().flatten() {
+                    if let Some(values) = strata.get(neighbour) {
+                        pooled.extend_from_slice(values);
+                    }
+                }
+            }
+            Some(median(&mut pooled)).filter(|&m| m > 0.0)
+        })
+        .collect();
+
+    for (tid, bins) in depth.iter_mut().enumerate() {
+        for (bin, value) in bins.iter_mut().enumerate() {
+            *value = match (*value, stratum_medians[gc_stratum(composition.gc[tid][bin])]) {
+                (Some(depth), Some(expected)) => Some(depth / expected),
+                _ => None,
+            };
+        }
+    }
+    Ok(depth)
+}
+
+fn gc_stratum(gc: f64) -> usize {
+    ((gc * 100.0).round() as usize).min(GC_STRATA - 1)
+}
+
+/// Expected depth of each bin: flat without controls, the control itself,
+/// or the median over a panel where at least half of it is unmasked
+fn reference_depth(controls: &[Vec<Vec<Option<f64>>>], contigs: &[(String, u64)], bin_size: u64) -> Vec<Vec<Option<f64>>> {
+    contigs
+        .iter()
+        .enumerate()
+        .map(|(tid, (_, length))| {
+            (0..bin_count(*length, bin_size))
+                .map(|bin| {
+                    if controls.is_empty() {
+                        return Some(1.0);
+                    }
+                    let mut values: Vec<f64> = controls.iter().filter_map(|control| control[tid][bin]).collect();
+                    if values.len() * 2 < controls.len() || values.is_empty() {
+                        None
+                    } else {
+                        Some(median(&mut values))
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Standard deviation of the log2 ratios from the median absolute difference
+/// between neighbouring bins, which copy-number changes barely affect
+fn noise(ratios: &[Vec<BinRatio>]) -> f64 {
+    let mut differences: Vec<f64> = ratios
+        .iter()
+        .flat_map(|bins| bins.windows(2).map(|pair| (pair[1].log2 - pair[0].log2).abs()))
+        .collect();
+    if differences.is_empty() {
+        return 1.0;
+    }
+    (1.4826 * median(&mut differences) / std::f64::consts::SQRT_2).max(1e-3)
+}
+
+fn segment(chrom: &str, length: u64, bins: &[BinRatio], log2: f64, state: Option<u32>, params: &CnvParams) -> CnvSegment {
+    let ploidy = params.ploidy;
+    // HMM states are copy numbers already; CBS means are thresholded and rounded
+    let (call, copy_number) = match state {
+        Some(copy) => (
+            match copy.cmp(&ploidy) {
+                std::cmp::Ordering::Greater => Some(CnvCall::Gain),
+                std::cmp::Ordering::Less => Some(CnvCall::Loss),
+                std::cmp::Ordering::Equal => None,
+            },
+            copy,
+        ),
+        None => {
+            let copy = (ploidy as f64 * log2.exp2()).round() as u32;
+            if log2 >= params.gain_threshold {
+                (Some(CnvCall::Gain), copy.max(ploidy + 1))
+            } else if log2 <= params.loss_threshold {
+                (Some(CnvCall::Loss), copy.min(ploidy.saturating_sub(1)))
+            } else {
+                (None, ploidy)
+            }
+        }
+    };
+    CnvSegment {
+        chrom: chrom.to_string(),
+        start: bins[0].index * params.bin_size,
+        end: ((bins[bins.len() - 1].index + 1) * params.bin_size).min(length),
+        bins: bins.len(),
+        log2,
+        copy_number,
+        call,
+    }
+}
+
+/// Half-open bin ranges from circular binary segmentation
+///
+/// Each range is searched for the arc (i, j) whose mean differs most from
+/// the rest, scored as a two-sample z statistic with the genome-wide noise.
+/// The split is kept when the statistic beats a Bonferroni bound over every
+/// arc tested, and both sides are segmented again. Arcs are limited to
+/// `MAX_ARC_BINS` to keep the search linear in the contig length; longer
+/// changes are found one edge at a time by arcs touching the range ends.
+fn circular_binary_segmentation(values: &[f64], sigma: f64, params: &CnvParams) -> Vec<(usize, usize)> {
+    let mut segments = Vec::new();
+    if values.is_empty() {
+        return segments;
+    }
+    let mut pending = vec![(0, values.len())];
+    while let Some((start, end)) = pending.pop() {
+        match best_arc(&values[start..end], sigma, params) {
+            Some((i, j)) => {
+                for (a, b) in [(0, i), (i, j), (j, end - start)] {
+                    if b > a {
+                        pending.push((start + a, start + b));
+                    }
+                }
+            }
+            None => segments.push((start, end)),
+        }
+    }
+    segments.sort_unstable();
+    segments
+}
+
+fn best_arc(values: &[f64], sigma: f64, params: &CnvParams) -> Option<(usize, usize)> {
+    let n = values.len();
+    let min_bins = params.min_bins.max(1);
+    if n < 2 * min_bins {
+        return None;
+    }
+    let mut prefix = vec![0.0; n + 1];
+    for (i, value) in values.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + value;
+    }
+    let total = prefix[n];
+    let mut best: Option<(usize, usize, f64)> = None;
+    let mut tests = 0u64;
+    for i in 0..n {
+        if i != 0 && i < min_bins {
+            continue;
+        }
+        let reach = if i == 0 { n } else { (i + MAX_ARC_BINS).min(n) };
+        let ends = (i + min_bins..=reach).chain((reach < n).then_some(n));
+        for j in ends {
+            let (inside, outside) = (j - i, n - (j - i));
+            if (i == 0 && j == n) || outside < min_bins || (j != n && n - j < min_bins) {
+                continue;
+            }
+            tests += 1;
+            let sum = prefix[j] - prefix[i];
+            let difference = sum / inside as f64 - (total - sum) / outside as f64;
+            let z = difference.abs() / (1.0 / inside as f64 + 1.0 / outside as f64).sqrt();
+            if best.is_none_or(|(_, _, top)| z > top) {
+                best = Some((i, j, z));
+            }
+        }
+    }
+    let (i, j, z) = best?;
+    let threshold = normal_upper_quantile(params.alpha / (2.0 * tests as f64));
+    (z / sigma > threshold).then_some((i, j))
+}
+
+/// z with an upper-tail probability of `p`, by bisection on `erfc`
+fn normal_upper_quantile(p: f64) -> f64 {
+    let (mut low, mut high) = (0.0, 40.0);
+    for _ in 0..100 {
+        let mid = (low + high) / 2.0;
+        if 0.5 * erfc(mid / std::f64::consts::SQRT_2) > p {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Complementary error function, Chebyshev fit with relative error below 1.2e-7
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let value = t * poly.exp();
+    if x >= 0.0 { value } else { 2.0 - value }
+}
+
+/// Half-open bin ranges and their copy number from the Viterbi path of a
+/// Gaussian HMM over integer copy-number states
+///
+/// Each state expects a log2 ratio of log2(copy / ploidy) with the
+/// genome-wide noise; runs shorter than `min_bins` are absorbed by the
+/// longer of their neighbours.
+fn hmm_segmentation(values: &[f64], sigma: f64, params: &CnvParams) -> Vec<(usize, usize, Option<u32>)> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let states = (MAX_HMM_COPY + 1) as usize;
+    let ploidy = params.ploidy as usize;
+    let expected: Vec<f64> = (0..states).map(|copy| (copy as f64 / ploidy as f64).log2().max(MIN_LOG2)).collect();
+    let stay = (1.0 - HMM_SWITCH_PROBABILITY).ln();
+    let switch = (HMM_SWITCH_PROBABILITY / (states - 1) as f64).ln();
+    let emission = |value: f64, state: usize| -((value - expected[state]) / sigma).powi(2) / 2.0;
+
+    let mut score: Vec<f64> = (0..states)
+        .map(|state| emission(values[0], state) + if state == ploidy { stay } else { switch })
+        .collect();
+    let mut back: Vec<Vec<u8>> = Vec::with_capacity(values.len());
+    for &value in &values[1..] {
+        let mut next = vec![f64::NEG_INFINITY; states];
+        let mut from = vec![0u8; states];
+        for (to, slot) in next.iter_mut().enumerate() {
+            for (previous, &prior) in score.iter().enumerate() {
+                let candidate = prior + if previous == to { stay } else { switch };
+                if candidate > *slot {
+                    *slot = candidate;
+                    from[to] = previous as u8;
+                }
+            }
+            *slot += emission(value, to);
+        }
+        back.push(from);
+        score = next;
+    }
+    let mut state = (0..states).max_by(|&a, &b| score[a].total_cmp(&score[b])).unwrap_or(ploidy);
+    let mut path = vec![0usize; values.len()];
+    for i in (0..values.len()).rev() {
+        path[i] = state;
+        if i > 0 {
+            state = back[i - 1][state] as usize;
+        }
+    }
+
+    let mut runs: Vec<(usize, usize, usize)> = Vec::new();
+    for (i, &state) in path.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run) if run.2 == state => run.1 = i + 1,
+            _ => runs.push((i, i + 1, state)),
+        }
+    }
+    while runs.len() > 1 {
+        let Some(short) = (0..runs.len()).filter(|&r| runs[r].1 - runs[r].0 < params.min_bins).min_by_key(|&r| runs[r].1 - runs[r].0) else {
+            break;
+        };
+        let length = |r: usize| runs[r].1 - runs[r].0;
+        let into = match (short.checked_sub(1), (short + 1 < runs.len()).then_some(short + 1)) {
+            (Some(left), Some(right)) => if length(left) >= length(right) { left } else { right },
+            (Some(left), None) => left,
+            (None, Some(right)) => right,
+            (None, None) => break,
+        };
+        let (start, end) = (runs[short.min(into)].0, runs[short.max(into)].1);
+        runs[into] = (start, end, runs[into].2);
+        runs.remove(short);
+        // Neighbours left in the same state join up
+        let mut merged: Vec<(usize, usize, usize)> = Vec::with_capacity(runs.len());
+        for run in runs.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.2 == run.2 => last.1 = run.1,
+                _ => merged.push(run),
+            }
+        }
+        runs = merged;
+    }
+    runs.into_iter().map(|(start, end, state)| (start, end, Some(state as u32))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Log2 ratios at `ploidy` with runs at other copy numbers, plus uniform noise of ±0.15
+    fn ratios(runs: &[(usize, u32)], ploidy: u32) -> Vec<f64> {
+        let mut state: u64 = 7;
+        let mut values = Vec::new();
+        for &(length, copy) in runs {
+            for _ in 0..length {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let noise = ((state >> 33) as f64 / (1u64 << 31) as f64 - 0.5) * 0.3;
+                values.push((copy as f64 / ploidy as f64).log2() + noise);
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn cbs_finds_exact_breakpoints() {
+        let params = CnvParams::default();
+        let values = ratios(&[(200, 2), (40, 3), (300, 2), (25, 1), (100, 2)], 2);
+        let segments = circular_binary_segmentation(&values, 0.1, &params);
+        assert_eq!(segments, vec![(0, 200), (200, 240), (240, 540), (540, 565), (565, 665)]);
+    }
+
+    #[test]
+    fn cbs_keeps_flat_noise_whole() {
+        let values = ratios(&[(500, 2)], 2);
+        assert_eq!(circular_binary_segmentation(&values, 0.1, &CnvParams::default()), vec![(0, 500)]);
+    }
+
+    #[test]
+    fn hmm_assigns_integer_copy_numbers() {
+        let params = CnvParams { ploidy: 2, ..CnvParams::default() };
+        let values = ratios(&[(150, 2), (30, 4), (150, 2), (20, 0)], 2);
+        let segments = hmm_segmentation(&values, 0.1, &params);
+        assert_eq!(segments, vec![(0, 150, Some(2)), (150, 180, Some(4)), (180, 330, Some(2)), (330, 350, Some(0))]);
+    }
+
+    #[test]
+    fn hmm_absorbs_runs_shorter_than_min_bins() {
+        let params = CnvParams { min_bins: 5, ..CnvParams::default() };
+        let values = ratios(&[(100, 2), (3, 3), (100, 2)], 2);
+        assert_eq!(hmm_segmentation(&values, 0.1, &params), vec![(0, 203, Some(2))]);
+    }
+
+    #[test]
+    fn triploid_baseline_sits_at_zero() {
+        let params = CnvParams { ploidy: 3, ..CnvParams::default() };
+        let values = ratios(&[(100, 3), (50, 5)], 3);
+        assert_eq!(hmm_segmentation(&values, 0.1, &params), vec![(0, 100, Some(3)), (100, 150, Some(5))]);
+    }
+}
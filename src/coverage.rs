@@ -0,0 +1,497 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use rust_htslib::bam::{self, record::Cigar, Read};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use crate::variant_caller::sample_name;
+
+/// Read filters and the depths coverage is reported at
+#[derive(Debug, Clone)]
+pub struct CoverageParams {
+    /// Reads below this mapping quality are not counted
+    pub min_mapping_quality: u8,
+    /// Depths for the share of bases covered at least that deep
+    pub thresholds: Vec<u32>,
+}
+
+impl Default for CoverageParams {
+    fn default() -> Self {
+        Self { min_mapping_quality: 20, thresholds: vec![10, 20, 30] }
+    }
+}
+
+/// One region of interest, 0-based half-open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub name: String,
+}
+
+impl Target {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+/// Load targets from a BED file, optionally gzipped; unnamed targets are named `chrom:start-end`
+pub fn load_targets(path: &Path) -> Result<Vec<Target>> {
+    let file = File::open(path).with_context(|| format!("Failed to open BED file: {}", path.display()))?;
+    let reader: Box<dyn BufRead> = if path.extension().unwrap_or_default() == "gz" {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let mut targets = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            bail!("BED line {} has fewer than 3 columns", number + 1);
+        }
+        let start: u64 = fields[1].trim().parse().with_context(|| format!("Bad start on BED line {}", number + 1))?;
+        let end: u64 = fields[2].trim().parse().with_context(|| format!("Bad end on BED line {}", number + 1))?;
+        if end <= start {
+            bail!("BED line {} is empty or reversed ({}-{})", number + 1, start, end);
+        }
+        let name = match fields.get(3) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => format!("{}:{}-{}", fields[0], start + 1, end),
+        };
+        targets.push(Target { chrom: fields[0].to_string(), start, end, name });
+    }
+    if targets.is_empty() {
+        bail!("No targets in {}", path.display());
+    }
+    Ok(targets)
+}
+
+/// Bases at each depth
+#[derive(Debug, Clone, Default)]
+pub struct DepthHistogram {
+    bases: BTreeMap<u32, u64>,
+}
+
+impl DepthHistogram {
+    fn add(&mut self, depth: u32, bases: u64) {
+        *self.bases.entry(depth).or_default() += bases;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.bases.values().sum()
+    }
+
+    pub fn mean(&self) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            return 0.0;
+        }
+        self.bases.iter().map(|(&depth, &bases)| depth as f64 * bases as f64).sum::<f64>() / total as f64
+    }
+
+    /// Depth of the middle base, the lower one for an even count
+    pub fn median(&self) -> u32 {
+        let half = self.total().div_ceil(2);
+        let mut seen = 0;
+        for (&depth, &bases) in &self.bases {
+            seen += bases;
+            if seen >= half {
+                return depth;
+            }
+        }
+        0
+    }
+
+    pub fn min(&self) -> u32 {
+        self.bases.keys().next().copied().unwrap_or(0)
+    }
+
+    pub fn max(&self) -> u32 {
+        self.bases.keys().next_back().copied().unwrap_or(0)
+    }
+
+    /// Percentage of bases covered at least `depth` deep
+    pub fn percent_at_least(&self, depth: u32) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            return 0.0;
+        }
+        100.0 * self.bases.range(depth..).map(|(_, &bases)| bases).sum::<u64>() as f64 / total as f64
+    }
+}
+
+/// Depth over one target
+#[derive(Debug, Clone)]
+pub struct TargetCoverage {
+    pub target: Target,
+    pub histogram: DepthHistogram,
+}
+
+#[derive(Serialize)]
+struct ThresholdSummary {
+    depth: u32,
+    percent: f64,
+}
+
+#[derive(Serialize)]
+struct CoverageSummary<'a> {
+    sample: &'a str,
+    input: &'a str,
+    targets: Option<&'a str>,
+    target_count: usize,
+    target_bases: u64,
+    min_mapping_quality: u8,
+    reads_used: u64,
+    reads_filtered: u64,
+    mean_depth: f64,
+    median_depth: u32,
+    max_depth: u32,
+    percent_at_least: Vec<ThresholdSummary>,
+    percent_targets_below_mean: Vec<ThresholdSummary>,
+}
+
+/// Coverage of one BAM over its targets, or over whole contigs without a BED
+#[derive(Debug, Clone)]
+pub struct CoverageReport {
+    pub sample: String,
+    pub input: String,
+    /// BED the targets came from
+    pub targets_file: Option<String>,
+    pub params: CoverageParams,
+    pub targets: Vec<TargetCoverage>,
+    /// Depth over the union of the targets, each base counted once
+    pub overall: DepthHistogram,
+    pub reads_used: u64,
+    pub reads_filtered: u64,
+}
+
+impl CoverageReport {
+    /// Targets whose mean depth is under `depth`
+    pub fn targets_below(&self, depth: u32) -> usize {
+        self.targets.iter().filter(|target| target.histogram.mean() < depth as f64).count()
+    }
+
+    /// One line per target: length, mean, median, minimum and maximum depth, then % of bases at each threshold
+    pub fn write_targets(&self, path: &str) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("Could not create target coverage file: {}", path))?;
+        let mut out = BufWriter::new(file);
+        write!(out, "#chrom\tstart\tend\tname\tlength\tmean_depth\tmedian_depth\tmin_depth\tmax_depth")?;
+        for depth in &self.params.thresholds {
+            write!(out, "\tpct_{}x", depth)?;
+        }
+        writeln!(out)?;
+        for TargetCoverage { target, histogram } in &self.targets {
+            write!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{:.2}\t{}\t{}\t{}",
+                target.chrom,
+                target.start,
+                target.end,
+                target.name,
+                target.len(),
+                histogram.mean(),
+                histogram.median(),
+                histogram.min(),
+                histogram.max()
+            )?;
+            for &depth in &self.params.thresholds {
+                write!(out, "\t{:.2}", histogram.percent_at_least(depth))?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Overall depth and threshold percentages, and how many targets fall short of each threshold, as JSON
+    pub fn write_summary(&self, path: &str) -> Result<()> {
+        let summary = CoverageSummary {
+            sample: &self.sample,
+            input: &self.input,
+            targets: self.targets_file.as_deref(),
+            target_count: self.targets.len(),
+            target_bases: self.overall.total(),
+            min_mapping_quality: self.params.min_mapping_quality,
+            reads_used: self.reads_used,
+            reads_filtered: self.reads_filtered,
+            mean_depth: (self.overall.mean() * 100.0).round() / 100.0,
+            median_depth: self.overall.median(),
+            max_depth: self.overall.max(),
+            percent_at_least: self
+                .params
+                .thresholds
+                .iter()
+                .map(|&depth| ThresholdSummary { depth, percent: (self.overall.percent_at_least(depth) * 100.0).round() / 100.0 })
+                .collect(),
+            percent_targets_below_mean: self
+                .params
+                .thresholds
+                .iter()
+                .map(|&depth| ThresholdSummary {
+                    depth,
+                    percent: (10000.0 * self.targets_below(depth) as f64 / self.targets.len().max(1) as f64).round() / 100.0,
+                })
+                .collect(),
+        };
+        let json = serde_json::to_string_pretty(&summary)?;
+        std::fs::write(path, json + "\n").with_context(|| format!("Could not write coverage summary: {}", path))?;
+        Ok(())
+    }
+}
+
+/// Intervals of one contig that runs of constant depth are credited to
+///
+/// Runs arrive in order, so only the intervals overlapping the current run
+/// are kept active.
+struct IntervalTracker {
+    /// (start, end, histogram index), sorted by start
+    intervals: Vec<(u64, u64, usize)>,
+    next: usize,
+    active: Vec<(u64, u64, usize)>,
+}
+
+impl IntervalTracker {
+    fn new(mut intervals: Vec<(u64, u64, usize)>) -> Self {
+        intervals.sort_unstable();
+        Self { intervals, next: 0, active: Vec::new() }
+    }
+
+    fn add_run(&mut self, start: u64, end: u64, depth: u32, histograms: &mut [DepthHistogram]) {
+        while self.next < self.intervals.len() && self.intervals[self.next].0 < end {
+            self.active.push(self.intervals[self.next]);
+            self.next += 1;
+        }
+        for &(interval_start, interval_end, index) in &self.active {
+            let (from, to) = (start.max(interval_start), end.min(interval_end));
+            if to > from {
+                histograms[index].add(depth, to - from);
+            }
+        }
+        self.active.retain(|&(_, interval_end, _)| interval_end > end);
+    }
+}
+
+/// Per-base depth of the current contig as runs, from aligned blocks of sorted reads
+struct RunBuilder<W: Write> {
+    chrom: String,
+    length: u64,
+    /// Depth changes at positions not yet emitted
+    changes: BTreeMap<u64, i64>,
+    position: u64,
+    depth: i64,
+    /// Run being extended while the depth stays the same
+    open: Option<(u64, u64, u32)>,
+    targets: IntervalTracker,
+    union: IntervalTracker,
+    bedgraph: W,
+}
+
+impl<W: Write> RunBuilder<W> {
+    fn add_block(&mut self, start: u64, end: u64) {
+        let end = end.min(self.length);
+        if end > start {
+            *self.changes.entry(start).or_default() += 1;
+            *self.changes.entry(end).or_default() -= 1;
+        }
+    }
+
+    /// Emit depth up to `until`; no later read may start before it
+    fn advance(&mut self, until: u64, target_stats: &mut [DepthHistogram], overall: &mut DepthHistogram) -> Result<()> {
+        let until = until.min(self.length);
+        let rest = self.changes.split_off(&until);
+        for (pos, delta) in std::mem::replace(&mut self.changes, rest) {
+            if pos > self.position {
+                self.run(pos, target_stats, overall)?;
+            }
+            self.depth += delta;
+        }
+        if until > self.position {
+            self.run(until, target_stats, overall)?;
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, end: u64, target_stats: &mut [DepthHistogram], overall: &mut DepthHistogram) -> Result<()> {
+        let (start, depth) = (self.position, self.depth.max(0) as u32);
+        self.targets.add_run(start, end, depth, target_stats);
+        self.union.add_run(start, end, depth, std::slice::from_mut(overall));
+        self.open = match self.open {
+            Some((open_start, _, open_depth)) if open_depth == depth => Some((open_start, end, depth)),
+            Some((open_start, open_end, open_depth)) => {
+                writeln!(self.bedgraph, "{}\t{}\t{}\t{}", self.chrom, open_start, open_end, open_depth)?;
+                Some((start, end, depth))
+            }
+            None => Some((start, end, depth)),
+        };
+        self.position = end;
+        Ok(())
+    }
+
+    /// Emit the rest of the contig and hand back the output
+    fn finish(mut self, target_stats: &mut [DepthHistogram], overall: &mut DepthHistogram) -> Result<W> {
+        self.advance(self.length, target_stats, overall)?;
+        if let Some((start, end, depth)) = self.open {
+            writeln!(self.bedgraph, "{}\t{}\t{}\t{}", self.chrom, start, end, depth)?;
+        }
+        Ok(self.bedgraph)
+    }
+}
+
+/// Per-base depth calculator for a coordinate-sorted BAM
+///
+/// Depth counts the aligned bases (CIGAR M, = and X) of reads that pass the
+/// same filters as the variant caller: primary, mapped, not duplicates or QC
+/// failures, and at least the minimum mapping quality. Deletions and skipped
+/// regions add no depth.
+pub struct CoverageCalculator {
+    params: CoverageParams,
+}
+
+impl CoverageCalculator {
+    pub fn new() -> Self {
+        Self { params: CoverageParams::default() }
+    }
+
+    pub fn with_params(mut self, params: CoverageParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Stream the BAM once, writing bedGraph runs for every contig and
+    /// collecting depth over the targets (whole contigs when there are none)
+    pub fn compute(&self, input_path: &str, targets: Option<(&str, Vec<Target>)>, bedgraph_path: &str) -> Result<CoverageReport> {
+        let mut reader =
+            bam::Reader::from_path(input_path).with_context(|| format!("Could not open alignments: {}", input_path))?;
+        let header = reader.header().clone();
+        let contigs: Vec<(String, u64)> = (0..header.target_count())
+            .map(|tid| (String::from_utf8_lossy(header.tid2name(tid)).into_owned(), header.target_len(tid).unwrap_or(0)))
+            .collect();
+        if contigs.is_empty() {
+            bail!("{} has no contigs in its header", input_path);
+        }
+        let order: HashMap<&str, usize> = contigs.iter().enumerate().map(|(i, (name, _))| (name.as_str(), i)).collect();
+
+        let (targets_file, targets) = match targets {
+            Some((path, targets)) => (Some(path.to_string()), targets),
+            None => (
+                None,
+                contigs
+                    .iter()
+                    .filter(|(_, length)| *length > 0)
+                    .map(|(name, length)| Target { chrom: name.clone(), start: 0, end: *length, name: name.clone() })
+                    .collect(),
+            ),
+        };
+        let mut per_contig: Vec<Vec<(u64, u64, usize)>> = vec![Vec::new(); contigs.len()];
+        for (index, target) in targets.iter().enumerate() {
+            let Some(&tid) = order.get(target.chrom.as_str()) else {
+                bail!("Target {} is on contig {}, which is not in {}", target.name, target.chrom, input_path);
+            };
+            per_contig[tid].push((target.start, target.end.min(contigs[tid].1), index));
+        }
+
+        let mut report = CoverageReport {
+            sample: sample_name(header.as_bytes(), input_path),
+            input: input_path.to_string(),
+            targets_file,
+            params: self.params.clone(),
+            targets: Vec::new(),
+            overall: DepthHistogram::default(),
+            reads_used: 0,
+            reads_filtered: 0,
+        };
+        let mut target_stats = vec![DepthHistogram::default(); targets.len()];
+        let file = File::create(bedgraph_path).with_context(|| format!("Could not create bedGraph: {}", bedgraph_path))?;
+        let mut bedgraph = BufWriter::new(file);
+        writeln!(bedgraph, "track type=bedGraph name=\"{} depth\"", report.sample)?;
+
+        let builder = |tid: usize, bedgraph| RunBuilder {
+            chrom: contigs[tid].0.clone(),
+            length: contigs[tid].1,
+            changes: BTreeMap::new(),
+            position: 0,
+            depth: 0,
+            open: None,
+            targets: IntervalTracker::new(per_contig[tid].clone()),
+            union: IntervalTracker::new(merge_intervals(&per_contig[tid])),
+            bedgraph,
+        };
+        let mut current = builder(0, bedgraph);
+        let mut current_tid = 0usize;
+        let mut last = (0usize, 0u64);
+        for result in reader.records() {
+            let record = result.context("Could not read alignment record")?;
+            if record.is_unmapped()
+                || record.is_secondary()
+                || record.is_supplementary()
+                || record.is_quality_check_failed()
+                || record.is_duplicate()
+                || record.tid() < 0
+                || record.mapq() < self.params.min_mapping_quality
+            {
+                report.reads_filtered += 1;
+                continue;
+            }
+            let (tid, pos) = (record.tid() as usize, record.pos().max(0) as u64);
+            if (tid, pos) < last {
+                bail!("{} is not coordinate-sorted (position {} after {})", input_path, pos + 1, last.1 + 1);
+            }
+            last = (tid, pos);
+            // Contigs passed over, reads or not, are finished in order
+            while current_tid < tid {
+                let bedgraph = current.finish(&mut target_stats, &mut report.overall)?;
+                current_tid += 1;
+                current = builder(current_tid, bedgraph);
+            }
+
+            current.advance(pos, &mut target_stats, &mut report.overall)?;
+            let mut ref_pos = pos;
+            for op in record.cigar().iter() {
+                match *op {
+                    Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                        current.add_block(ref_pos, ref_pos + len as u64);
+                        ref_pos += len as u64;
+                    }
+                    Cigar::Del(len) | Cigar::RefSkip(len) => ref_pos += len as u64,
+                    _ => {}
+                }
+            }
+            report.reads_used += 1;
+        }
+        let mut bedgraph = current.finish(&mut target_stats, &mut report.overall)?;
+        for tid in current_tid + 1..contigs.len() {
+            bedgraph = builder(tid, bedgraph).finish(&mut target_stats, &mut report.overall)?;
+        }
+        bedgraph.flush()?;
+
+        report.targets = targets.into_iter().zip(target_stats).map(|(target, histogram)| TargetCoverage { target, histogram }).collect();
+        Ok(report)
+    }
+}
+
+impl Default for CoverageCalculator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Overlapping or touching intervals merged, for counting each base once
+fn merge_intervals(intervals: &[(u64, u64, usize)]) -> Vec<(u64, u64, usize)> {
+    let mut sorted: Vec<(u64, u64)> = intervals.iter().map(|&(start, end, _)| (start, end)).collect();
+    sorted.sort_unstable();
+    let mut merged: Vec<(u64, u64, usize)> = Vec::new();
+    for (start, end) in sorted {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end, 0)),
+        }
+    }
+    merged
+}
@@ -0,0 +1,490 @@
+use ahash::AHashMap;
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use std::io::Write;
+use crate::identity_matrix::xml_escape;
+use crate::kmer_filter::base_code;
+use crate::mapper::reverse_complement;
+
+/// Longest seed word that packs into a u64
+const MAX_SEED: usize = 32;
+
+const FORWARD_COLOUR: (u8, u8, u8) = (30, 70, 200);
+const REVERSE_COLOUR: (u8, u8, u8) = (210, 40, 40);
+
+/// Dot-plot word matching parameters
+#[derive(Debug, Clone, Copy)]
+pub struct DotPlotParams {
+    /// Window length compared at each position
+    pub window: usize,
+    /// Identical bases a window needs to make a dot; equal to `window` for exact words
+    pub stringency: usize,
+    /// Seed words more frequent than this in the second sequence are skipped
+    pub max_occurrences: usize,
+}
+
+impl Default for DotPlotParams {
+    fn default() -> Self {
+        Self { window: 20, stringency: 20, max_occurrences: 1000 }
+    }
+}
+
+impl DotPlotParams {
+    /// Exact seed length that cannot miss a qualifying window
+    ///
+    /// A window with at most `window - stringency` mismatches has a run of at
+    /// least `stringency / (mismatches + 1)` identical bases (pigeonhole).
+    fn seed_length(&self) -> usize {
+        let mismatches = self.window - self.stringency;
+        self.stringency.div_ceil(mismatches + 1).clamp(1, MAX_SEED)
+    }
+}
+
+/// Run of dots along a diagonal, in sequence coordinates
+///
+/// `x` runs along the first sequence and `y` along the second. Reverse-strand
+/// segments run from high to low `x` as `y` increases.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+    pub reverse: bool,
+}
+
+impl Segment {
+    pub fn len(&self) -> usize {
+        self.y1 - self.y0
+    }
+}
+
+/// Word-match dot plot of two sequences on both strands
+pub struct DotPlot {
+    pub names: (String, String),
+    pub lengths: (usize, usize),
+    pub params: DotPlotParams,
+    pub segments: Vec<Segment>,
+}
+
+impl DotPlot {
+    pub fn compute(first: (&str, &[u8]), second: (&str, &[u8]), params: DotPlotParams) -> Result<Self> {
+        if params.window == 0 || params.stringency == 0 || params.stringency > params.window {
+            bail!(
+                "Dot plot stringency must be between 1 and the window size ({} of {})",
+                params.stringency,
+                params.window
+            );
+        }
+        let (x_seq, y_seq) = (first.1, second.1);
+        let index = WordIndex::build(y_seq, params.seed_length());
+
+        let forward = diagonal_runs(&dots(x_seq, y_seq, &index, &params), params.window);
+        let x_rc = reverse_complement(x_seq);
+        let reverse = diagonal_runs(&dots(&x_rc, y_seq, &index, &params), params.window);
+
+        // Reverse-strand runs were found against the reverse complement; flip x back
+        let n = x_seq.len();
+        let mut segments: Vec<Segment> = forward
+            .into_iter()
+            .map(|(x0, y0, x1, y1)| Segment { x0, y0, x1, y1, reverse: false })
+            .collect();
+        segments.extend(
+            reverse
+                .into_iter()
+                .map(|(a0, y0, a1, y1)| Segment { x0: n - a0, y0, x1: n - a1, y1, reverse: true }),
+        );
+
+        Ok(Self {
+            names: (first.0.to_string(), second.0.to_string()),
+            lengths: (x_seq.len(), y_seq.len()),
+            params,
+            segments,
+        })
+    }
+
+    /// Longest segment on each strand, forward then reverse
+    pub fn longest(&self) -> (usize, usize) {
+        let longest = |reverse: bool| {
+            self.segments.iter().filter(|s| s.reverse == reverse).map(Segment::len).max().unwrap_or(0)
+        };
+        (longest(false), longest(true))
+    }
+
+    fn layout(&self) -> Layout {
+        const PLOT: f64 = 800.0;
+        let (nx, ny) = (self.lengths.0.max(1) as f64, self.lengths.1.max(1) as f64);
+        let scale = PLOT / nx.max(ny);
+        Layout {
+            left: 90.0,
+            top: 50.0,
+            width: (nx * scale).max(1.0),
+            height: (ny * scale).max(1.0),
+            scale,
+        }
+    }
+
+    pub fn to_svg(&self) -> String {
+        let layout = self.layout();
+        let (total_width, total_height) = (layout.left + layout.width + 30.0, layout.top + layout.height + 70.0);
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" font-family=\"sans-serif\" font-size=\"11\">\n",
+            total_width, total_height
+        );
+        svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+        svg.push_str(&format!(
+            "<text x=\"{:.2}\" y=\"24\" font-size=\"13\">Dot plot: window {}, stringency {} (blue forward, red reverse)</text>\n",
+            layout.left, self.params.window, self.params.stringency
+        ));
+        svg.push_str(&format!(
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"none\" stroke=\"black\"/>\n",
+            layout.left, layout.top, layout.width, layout.height
+        ));
+
+        // Axis ticks: first sequence along the top edge, second down the left
+        for tick in ticks(self.lengths.0) {
+            let x = layout.x(tick);
+            svg.push_str(&format!(
+                "<line x1=\"{x:.2}\" y1=\"{:.2}\" x2=\"{x:.2}\" y2=\"{:.2}\" stroke=\"#ddd\"/>\n<text x=\"{x:.2}\" y=\"{:.2}\" text-anchor=\"middle\">{}</text>\n",
+                layout.top,
+                layout.top + layout.height,
+                layout.top + layout.height + 14.0,
+                base_label(tick)
+            ));
+        }
+        for tick in ticks(self.lengths.1) {
+            let y = layout.y(tick);
+            svg.push_str(&format!(
+                "<line x1=\"{:.2}\" y1=\"{y:.2}\" x2=\"{:.2}\" y2=\"{y:.2}\" stroke=\"#ddd\"/>\n<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"end\">{}</text>\n",
+                layout.left,
+                layout.left + layout.width,
+                layout.left - 4.0,
+                y + 4.0,
+                base_label(tick)
+            ));
+        }
+        svg.push_str(&format!(
+            "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"middle\" font-size=\"12\">{}</text>\n",
+            layout.left + layout.width / 2.0,
+            layout.top + layout.height + 36.0,
+            xml_escape(&self.names.0)
+        ));
+        svg.push_str(&format!(
+            "<text x=\"14\" y=\"{:.2}\" text-anchor=\"middle\" font-size=\"12\" transform=\"rotate(-90 14 {:.2})\">{}</text>\n",
+            layout.top + layout.height / 2.0,
+            layout.top + layout.height / 2.0,
+            xml_escape(&self.names.1)
+        ));
+
+        for segment in &self.segments {
+            let (r, g, b) = if segment.reverse { REVERSE_COLOUR } else { FORWARD_COLOUR };
+            svg.push_str(&format!(
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"rgb({},{},{})\" stroke-width=\"1.2\" stroke-linecap=\"round\"/>\n",
+                layout.x(segment.x0),
+                layout.y(segment.y0),
+                layout.x(segment.x1),
+                layout.y(segment.y1),
+                r,
+                g,
+                b
+            ));
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Raster version of the plot area and axes, as an RGB PNG
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let layout = self.layout();
+        let width = (layout.left + layout.width + 30.0).ceil() as usize;
+        let height = (layout.top + layout.height + 70.0).ceil() as usize;
+        let mut canvas = Canvas::new(width, height);
+
+        for tick in ticks(self.lengths.0) {
+            let x = layout.x(tick);
+            canvas.line(x, layout.top, x, layout.top + layout.height, (221, 221, 221));
+        }
+        for tick in ticks(self.lengths.1) {
+            let y = layout.y(tick);
+            canvas.line(layout.left, y, layout.left + layout.width, y, (221, 221, 221));
+        }
+        for segment in &self.segments {
+            let colour = if segment.reverse { REVERSE_COLOUR } else { FORWARD_COLOUR };
+            canvas.line(
+                layout.x(segment.x0),
+                layout.y(segment.y0),
+                layout.x(segment.x1),
+                layout.y(segment.y1),
+                colour,
+            );
+        }
+
+        let (left, top) = (layout.left, layout.top);
+        let (right, bottom) = (left + layout.width, top + layout.height);
+        for (x0, y0, x1, y1) in [(left, top, right, top), (left, bottom, right, bottom), (left, top, left, bottom), (right, top, right, bottom)] {
+            canvas.line(x0, y0, x1, y1, (0, 0, 0));
+        }
+
+        canvas.to_png()
+    }
+}
+
+struct Layout {
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+    /// Pixels per base
+    scale: f64,
+}
+
+impl Layout {
+    fn x(&self, position: usize) -> f64 {
+        self.left + position as f64 * self.scale
+    }
+
+    fn y(&self, position: usize) -> f64 {
+        self.top + position as f64 * self.scale
+    }
+}
+
+/// k-mer positions of the second sequence
+struct WordIndex {
+    k: usize,
+    positions: AHashMap<u64, Vec<u32>>,
+}
+
+impl WordIndex {
+    fn build(sequence: &[u8], k: usize) -> Self {
+        let mut positions: AHashMap<u64, Vec<u32>> = AHashMap::new();
+        for_each_word(sequence, k, |pos, word| positions.entry(word).or_default().push(pos as u32));
+        Self { k, positions }
+    }
+}
+
+/// Calls `f(start, packed_word)` for every k-mer without ambiguous bases
+fn for_each_word(sequence: &[u8], k: usize, mut f: impl FnMut(usize, u64)) {
+    let mask = if k == 32 { u64::MAX } else { (1u64 << (2 * k)) - 1 };
+    let (mut word, mut valid) = (0u64, 0usize);
+    for (i, &base) in sequence.iter().enumerate() {
+        match base_code(base) {
+            Some(code) => {
+                word = ((word << 2) | code as u64) & mask;
+                valid += 1;
+                if valid >= k {
+                    f(i + 1 - k, word);
+                }
+            }
+            None => valid = 0,
+        }
+    }
+}
+
+/// Window start positions (x, y) with at least `stringency` identical bases
+fn dots(x_seq: &[u8], y_seq: &[u8], index: &WordIndex, params: &DotPlotParams) -> Vec<(usize, usize)> {
+    let k = index.k;
+    let mut seeds: Vec<(usize, usize)> = Vec::new();
+    for_each_word(x_seq, k, |i, word| {
+        if let Some(hits) = index.positions.get(&word) {
+            if hits.len() <= params.max_occurrences {
+                seeds.extend(hits.iter().map(|&j| (i, j as usize)));
+            }
+        }
+    });
+
+    // Group seeds by diagonal so each diagonal is swept once
+    let diagonal = |&(i, j): &(usize, usize)| (j as i64 - i as i64, i);
+    seeds.par_sort_unstable_by_key(diagonal);
+    if k == params.window && params.stringency == params.window {
+        return seeds;
+    }
+
+    let (w, s) = (params.window, params.stringency);
+    // 2-bit codes with 4 for anything ambiguous, which never matches
+    let codes = |seq: &[u8]| -> Vec<u8> { seq.iter().map(|&b| base_code(b).unwrap_or(4)).collect() };
+    let (x_codes, y_codes) = (codes(x_seq), codes(y_seq));
+    let same = |x: usize, y: usize| (x_codes[x] == y_codes[y] && x_codes[x] < 4) as usize;
+
+    let per_diagonal: Vec<Vec<(usize, usize)>> = seeds
+        .chunk_by(|a, b| a.1 as i64 - a.0 as i64 == b.1 as i64 - b.0 as i64)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|group| {
+            let d = group[0].1 as i64 - group[0].0 as i64;
+            let to_y = |x: usize| (x as i64 + d) as usize;
+            let last_x = (x_seq.len() as i64 - w as i64).min(y_seq.len() as i64 - w as i64 - d);
+            let mut dots = Vec::new();
+            let mut next = (-d).max(0) as usize;
+            for &(i, _) in group {
+                // Window starts whose window contains this seed
+                let start = i.saturating_sub(w - k).max(next);
+                if last_x < start as i64 {
+                    continue;
+                }
+                let end = i.min(last_x as usize);
+
+                let mut matches: usize = (start..start + w).map(|x| same(x, to_y(x))).sum();
+                for x in start..=end {
+                    if x > start {
+                        matches = matches + same(x + w - 1, to_y(x + w - 1)) - same(x - 1, to_y(x - 1));
+                    }
+                    if matches >= s {
+                        dots.push((x, to_y(x)));
+                    }
+                }
+                next = end + 1;
+            }
+            dots
+        })
+        .collect();
+    per_diagonal.concat()
+}
+
+/// Merge dots on consecutive positions of a diagonal into (x0, y0, x1, y1) runs
+fn diagonal_runs(dots: &[(usize, usize)], window: usize) -> Vec<(usize, usize, usize, usize)> {
+    let mut runs = Vec::new();
+    let mut current: Option<(usize, usize, usize, usize)> = None;
+    for &(x, y) in dots {
+        current = match current {
+            Some((x0, y0, x1, y1)) if x == x1 + 1 && y == y1 + 1 => Some((x0, y0, x, y)),
+            Some(run) => {
+                runs.push(run);
+                Some((x, y, x, y))
+            }
+            None => Some((x, y, x, y)),
+        };
+    }
+    runs.extend(current);
+    // Runs cover the full window past their last dot
+    runs.into_iter().map(|(x0, y0, x1, y1)| (x0, y0, x1 + window, y1 + window)).collect()
+}
+
+/// Round tick positions, about five per axis
+fn ticks(length: usize) -> Vec<usize> {
+    if length == 0 {
+        return Vec::new();
+    }
+    let rough = length as f64 / 5.0;
+    let magnitude = 10f64.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= rough)
+        .unwrap_or(10.0 * magnitude)
+        .max(1.0) as usize;
+    (0..=length).step_by(step).collect()
+}
+
+fn base_label(position: usize) -> String {
+    match position {
+        p if p >= 1_000_000 => format!("{:.1} Mb", p as f64 / 1e6),
+        p if p >= 1_000 => format!("{:.0} kb", p as f64 / 1e3),
+        p => format!("{} bp", p),
+    }
+}
+
+/// Minimal RGB raster for PNG export
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self { width, height, pixels: vec![255; width * height * 3] }
+    }
+
+    fn set(&mut self, x: i64, y: i64, (r, g, b): (u8, u8, u8)) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            let offset = (y as usize * self.width + x as usize) * 3;
+            self.pixels[offset..offset + 3].copy_from_slice(&[r, g, b]);
+        }
+    }
+
+    /// Bresenham line between pixel centres
+    fn line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64, colour: (u8, u8, u8)) {
+        let (mut x, mut y) = (x0.round() as i64, y0.round() as i64);
+        let (x_end, y_end) = (x1.round() as i64, y1.round() as i64);
+        let (dx, dy) = ((x_end - x).abs(), -(y_end - y).abs());
+        let (sx, sy) = (if x < x_end { 1 } else { -1 }, if y < y_end { 1 } else { -1 });
+        let mut error = dx + dy;
+        loop {
+            self.set(x, y, colour);
+            if x == x_end && y == y_end {
+                break;
+            }
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// 8-bit RGB PNG with unfiltered scanlines
+    fn to_png(&self) -> Result<Vec<u8>> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&raw)?;
+        let compressed = encoder.finish()?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // Bit depth 8, colour type 2 (RGB), default compression, filter and interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [(b"IHDR", &header[..]), (b"IDAT", &compressed[..]), (b"IEND", &[][..])] {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            let mut crc = flate2::Crc::new();
+            crc.update(kind);
+            crc.update(data);
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            png.extend_from_slice(&crc.sum().to_be_bytes());
+        }
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn n_gaps_keep_coordinates_and_split_segments() {
+        let mut state = 3u64;
+        let mut random = |len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    b"ACGT"[(state >> 33) as usize % 4]
+                })
+                .collect()
+        };
+        let (left, right) = (random(60), random(70));
+        let gapped = [left.as_slice(), &[b'N'; 30], right.as_slice()].concat();
+
+        let plot = DotPlot::compute(("gapped", &gapped), ("gapped", &gapped), DotPlotParams::default()).unwrap();
+        let mut main: Vec<(usize, usize, usize, usize)> = plot
+            .segments
+            .iter()
+            .filter(|s| !s.reverse && s.x0 == s.y0)
+            .map(|s| (s.x0, s.y0, s.x1, s.y1))
+            .collect();
+        main.sort_unstable();
+        assert_eq!(main, [(0, 0, 60, 60), (90, 90, 160, 160)]);
+    }
+}
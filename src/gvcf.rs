@@ -0,0 +1,812 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use crate::variant_caller::{
+    genotype_alleles, genotype_index, genotype_priors, log10_sum, vcf_header, INDEL_MODEL, MAX_GQ, SNP_MODEL,
+};
+
+/// Symbolic allele standing for any allele not listed in the record
+pub const NON_REF: &str = "<NON_REF>";
+
+/// Lower bounds of the GQ bands reference positions are grouped by
+pub const GQ_BANDS: [u32; 4] = [0, 1, 20, 60];
+
+/// Phred likelihood given to genotypes a sample has no evidence about
+const UNSEEN_PL: u32 = 255;
+
+/// ALT allele for samples whose upstream deletion removes the site
+const SPANNING_DELETION: &str = "*";
+
+/// EM rounds when estimating cohort allele frequencies
+const EM_ROUNDS: usize = 20;
+
+/// Meta lines a gVCF needs on top of the caller's VCF header
+pub const GVCF_HEADER_LINES: [&str; 3] = [
+    "##ALT=<ID=NON_REF,Description=\"Represents any possible alternative allele not already represented at this location\">",
+    "##INFO=<ID=END,Number=1,Type=Integer,Description=\"Stop position of the reference block\">",
+    "##FORMAT=<ID=MIN_DP,Number=1,Type=Integer,Description=\"Minimum depth in the reference block\">",
+];
+
+/// Meta lines for the INFO fields of a cohort VCF
+const COHORT_HEADER_LINES: [&str; 4] = [
+    "##INFO=<ID=AC,Number=A,Type=Integer,Description=\"Allele count in called genotypes\">",
+    "##INFO=<ID=AN,Number=1,Type=Integer,Description=\"Total alleles in called genotypes\">",
+    "##INFO=<ID=AF,Number=A,Type=Float,Description=\"Allele frequency in called genotypes\">",
+    "##INFO=<ID=NS,Number=1,Type=Integer,Description=\"Samples with data\">",
+];
+
+/// Reference confidence at one covered position
+#[derive(Debug, Clone, Copy)]
+pub struct ReferenceSite {
+    pub pos: u64,
+    pub base: u8,
+    pub depth: u32,
+    pub genotype_quality: u32,
+    /// Phred likelihoods of 0/0, 0/<NON_REF> and <NON_REF>/<NON_REF>
+    pub likelihoods: [u32; 3],
+}
+
+/// Run of adjacent reference positions in one GQ band, written as one record
+#[derive(Debug, Clone)]
+pub struct ReferenceBlock {
+    pub chrom: String,
+    /// 0-based first position
+    pub start: u64,
+    /// 0-based exclusive end
+    pub end: u64,
+    /// Reference base at `start`
+    pub base: u8,
+    pub min_depth: u32,
+    pub depth_sum: u64,
+    /// Lowest GQ in the block
+    pub genotype_quality: u32,
+    /// Least confident likelihoods in the block
+    pub likelihoods: [u32; 3],
+}
+
+impl ReferenceBlock {
+    fn open(chrom: &str, site: &ReferenceSite) -> Self {
+        Self {
+            chrom: chrom.to_string(),
+            start: site.pos,
+            end: site.pos + 1,
+            base: site.base,
+            min_depth: site.depth,
+            depth_sum: site.depth as u64,
+            genotype_quality: site.genotype_quality,
+            likelihoods: site.likelihoods,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Mean depth over the block
+    pub fn depth(&self) -> u32 {
+        (self.depth_sum as f64 / self.len().max(1) as f64).round() as u32
+    }
+
+    pub fn to_vcf_line(&self) -> String {
+        format!(
+            "{}\t{}\t.\t{}\t{}\t.\t.\tEND={}\tGT:DP:GQ:MIN_DP:PL\t0/0:{}:{}:{}:{},{},{}",
+            self.chrom,
+            self.start + 1,
+            self.base as char,
+            NON_REF,
+            self.end,
+            self.depth(),
+            self.genotype_quality,
+            self.min_depth,
+            self.likelihoods[0],
+            self.likelihoods[1],
+            self.likelihoods[2]
+        )
+    }
+}
+
+fn gq_band(genotype_quality: u32) -> usize {
+    GQ_BANDS.iter().rposition(|&low| genotype_quality >= low).unwrap_or(0)
+}
+
+/// Folds per-position reference confidence into GQ-banded blocks
+///
+/// Positions arrive in order; anything skipped between them that is not a
+/// variant call becomes an uncovered block with no depth and GQ 0.
+#[derive(Debug, Default)]
+pub struct BlockBuilder {
+    blocks: Vec<ReferenceBlock>,
+    open: Option<ReferenceBlock>,
+    /// Contig being built and the next position it expects
+    cursor: Option<(String, u64)>,
+    finished: Vec<String>,
+}
+
+impl BlockBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chrom: &str, sequence: &[u8], site: ReferenceSite) {
+        let next = self.advance(chrom, sequence, site.pos);
+        if site.pos < next {
+            return;
+        }
+        if let Some(block) = &mut self.open {
+            if block.end == site.pos && gq_band(block.genotype_quality) == gq_band(site.genotype_quality) {
+                block.end += 1;
+                block.min_depth = block.min_depth.min(site.depth);
+                block.depth_sum += site.depth as u64;
+                block.genotype_quality = block.genotype_quality.min(site.genotype_quality);
+                for (kept, new) in block.likelihoods.iter_mut().zip(site.likelihoods).skip(1) {
+                    *kept = (*kept).min(new);
+                }
+                self.cursor = Some((chrom.to_string(), site.pos + 1));
+                return;
+            }
+        }
+        self.close();
+        self.open = Some(ReferenceBlock::open(chrom, &site));
+        self.cursor = Some((chrom.to_string(), site.pos + 1));
+    }
+
+    /// Leave `pos` out of the blocks, because a variant record covers it
+    pub fn skip(&mut self, chrom: &str, sequence: &[u8], pos: u64) {
+        let next = self.advance(chrom, sequence, pos);
+        if pos >= next {
+            self.close();
+            self.cursor = Some((chrom.to_string(), pos + 1));
+        }
+    }
+
+    /// Close the contig, filling the tail after the last read as uncovered
+    pub fn finish_contig(&mut self, chrom: &str, sequence: &[u8]) {
+        self.advance(chrom, sequence, sequence.len() as u64);
+        self.close();
+        self.cursor = None;
+        self.finished.push(chrom.to_string());
+    }
+
+    /// A block with no coverage over `start..end`
+    pub fn uncovered(&mut self, chrom: &str, start: u64, end: u64, base: u8) {
+        self.blocks.push(ReferenceBlock {
+            chrom: chrom.to_string(),
+            start,
+            end,
+            base,
+            min_depth: 0,
+            depth_sum: 0,
+            genotype_quality: 0,
+            likelihoods: [0, 0, 0],
+        });
+        if !self.finished.iter().any(|name| name == chrom) {
+            self.finished.push(chrom.to_string());
+        }
+    }
+
+    pub fn has_contig(&self, chrom: &str) -> bool {
+        self.finished.iter().any(|name| name == chrom)
+    }
+
+    /// All blocks, ordered as the contigs are
+    pub fn into_blocks(mut self, contigs: &[(String, u64)]) -> Vec<ReferenceBlock> {
+        self.close();
+        let order: HashMap<&str, usize> = contigs.iter().enumerate().map(|(i, (name, _))| (name.as_str(), i)).collect();
+        self.blocks
+            .sort_by_key(|block| (order.get(block.chrom.as_str()).copied().unwrap_or(usize::MAX), block.start));
+        self.blocks
+    }
+
+    /// Fill the gap up to `pos` with an uncovered block; returns the next expected position
+    fn advance(&mut self, chrom: &str, sequence: &[u8], pos: u64) -> u64 {
+        let next = match &self.cursor {
+            Some((name, next)) if name == chrom => *next,
+            _ => {
+                self.close();
+                0
+            }
+        };
+        if pos > next {
+            self.close();
+            let base = sequence.get(next as usize).copied().unwrap_or(b'N');
+            self.uncovered(chrom, next, pos, base);
+            self.finished.retain(|name| name != chrom);
+            self.cursor = Some((chrom.to_string(), pos));
+            return pos;
+        }
+        next
+    }
+
+    fn close(&mut self) {
+        if let Some(block) = self.open.take() {
+            self.blocks.push(block);
+        }
+    }
+}
+
+/// One record of a single-sample gVCF (or plain VCF)
+#[derive(Debug, Clone)]
+pub struct GvcfRecord {
+    pub contig: usize,
+    /// 0-based start
+    pub pos: u64,
+    /// 0-based exclusive end: END for blocks, else past the REF allele
+    pub end: u64,
+    pub reference: String,
+    /// Alternate alleles, without `<NON_REF>`
+    pub alternates: Vec<String>,
+    /// Allele index of `<NON_REF>`, when the record has it
+    pub non_ref: Option<usize>,
+    pub likelihoods: Option<Vec<u32>>,
+    pub depth: Option<u32>,
+    pub allele_depths: Option<Vec<u32>>,
+}
+
+impl GvcfRecord {
+    pub fn is_block(&self) -> bool {
+        self.alternates.is_empty()
+    }
+}
+
+/// A per-sample gVCF loaded for joint genotyping
+#[derive(Debug, Clone)]
+pub struct SampleGvcf {
+    pub sample: String,
+    pub contigs: Vec<(String, u64)>,
+    /// Records sorted by contig and position
+    pub records: Vec<GvcfRecord>,
+    /// Furthest end of any record up to each index on its contig, bounding covering scans
+    reach: Vec<u64>,
+}
+
+impl SampleGvcf {
+    /// Load a single-sample gVCF or VCF, optionally gzipped
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open gVCF: {}", path.display()))?;
+        let reader: Box<dyn BufRead> = if path.extension().unwrap_or_default() == "gz" {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+
+        let mut contigs: Vec<(String, u64)> = Vec::new();
+        let mut sample = None;
+        let mut records = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if let Some(contig) = line.strip_prefix("##contig=<") {
+                let field = |key: &str| {
+                    contig.trim_end_matches('>').split(',').find_map(|f| f.strip_prefix(key)).map(str::to_string)
+                };
+                if let Some(id) = field("ID=") {
+                    contigs.push((id, field("length=").and_then(|l| l.parse().ok()).unwrap_or(0)));
+                }
+            } else if line.starts_with("#CHROM") {
+                let columns: Vec<&str> = line.split('\t').collect();
+                if columns.len() != 10 {
+                    bail!("{} has {} samples; joint genotyping takes single-sample gVCFs", path.display(), columns.len().saturating_sub(9));
+                }
+                sample = Some(columns[9].to_string());
+            } else if !line.starts_with('#') && !line.is_empty() {
+                let record = parse_record(&line, &mut contigs)
+                    .with_context(|| format!("{} line {}: malformed record", path.display(), number + 1))?;
+                records.push(record);
+            }
+        }
+
+        let sample = sample.with_context(|| format!("{} has no #CHROM header line", path.display()))?;
+        records.sort_by_key(|record| (record.contig, record.pos));
+        let mut reach = Vec::with_capacity(records.len());
+        for (i, record) in records.iter().enumerate() {
+            let before = if i > 0 && records[i - 1].contig == record.contig { reach[i - 1] } else { 0 };
+            reach.push(record.end.max(before));
+        }
+        Ok(Self { sample, contigs, records, reach })
+    }
+
+    /// The record describing `pos`: a variant starting there, else a variant
+    /// spanning it (an upstream deletion), else the reference block over it
+    fn covering(&self, contig: usize, pos: u64) -> Option<&GvcfRecord> {
+        let after = self.records.partition_point(|r| (r.contig, r.pos) <= (contig, pos));
+        let overlapping = || {
+            (0..after)
+                .rev()
+                .take_while(|&i| self.records[i].contig == contig && self.reach[i] > pos)
+                .map(|i| &self.records[i])
+                .filter(|r| r.end > pos)
+        };
+        overlapping()
+            .find(|r| r.pos == pos && !r.is_block())
+            .or_else(|| overlapping().find(|r| !r.is_block()))
+            .or_else(|| overlapping().next())
+    }
+}
+
+fn parse_record(line: &str, contigs: &mut Vec<(String, u64)>) -> Result<GvcfRecord> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < 10 {
+        bail!("expected 10 columns, found {}", fields.len());
+    }
+    let contig = match contigs.iter().position(|(name, _)| name == fields[0]) {
+        Some(index) => index,
+        None => {
+            contigs.push((fields[0].to_string(), 0));
+            contigs.len() - 1
+        }
+    };
+    let pos = fields[1].parse::<u64>().context("bad POS")?.saturating_sub(1);
+    let reference = fields[3].to_string();
+    let all_alternates: Vec<&str> = fields[4].split(',').filter(|alt| *alt != ".").collect();
+    let non_ref = all_alternates.iter().position(|alt| *alt == NON_REF || *alt == "<*>").map(|i| i + 1);
+    let alternates: Vec<String> = all_alternates
+        .iter()
+        .filter(|alt| **alt != NON_REF && **alt != "<*>")
+        .map(|alt| alt.to_string())
+        .collect();
+    let end = fields[7]
+        .split(';')
+        .find_map(|entry| entry.strip_prefix("END="))
+        .map(|value| value.parse::<u64>().context("bad END"))
+        .transpose()?
+        .unwrap_or(pos + reference.len() as u64);
+
+    let keys: Vec<&str> = fields[8].split(':').collect();
+    let values: Vec<&str> = fields[9].split(':').collect();
+    let value = |key: &str| keys.iter().position(|k| *k == key).and_then(|i| values.get(i)).filter(|v| **v != ".");
+    let numbers = |text: &str| text.split(',').map(|n| n.parse::<u32>()).collect::<std::result::Result<Vec<_>, _>>();
+
+    Ok(GvcfRecord {
+        contig,
+        pos,
+        end,
+        reference,
+        alternates,
+        non_ref,
+        likelihoods: value("PL").map(|v| numbers(v)).transpose().context("bad PL")?,
+        depth: value("DP").map(|v| v.parse()).transpose().context("bad DP")?,
+        allele_depths: value("AD").map(|v| numbers(v)).transpose().context("bad AD")?,
+    })
+}
+
+/// A sample's genotype at a cohort site
+#[derive(Debug, Clone)]
+pub struct SampleGenotype {
+    pub genotype: (usize, usize),
+    pub genotype_quality: u32,
+    pub depth: Option<u32>,
+    pub allele_depths: Option<Vec<u32>>,
+    pub likelihoods: Vec<u32>,
+}
+
+/// One jointly genotyped site
+#[derive(Debug, Clone)]
+pub struct CohortSite {
+    pub chrom: String,
+    pub pos: u64,
+    pub reference: String,
+    pub alternates: Vec<String>,
+    pub quality: f64,
+    /// Per sample, `None` where the sample has no data
+    pub genotypes: Vec<Option<SampleGenotype>>,
+}
+
+impl CohortSite {
+    /// Called copies of each alternate allele, and of all alleles
+    pub fn allele_counts(&self) -> (Vec<u32>, u32) {
+        let mut counts = vec![0u32; self.alternates.len()];
+        let mut total = 0;
+        for genotype in self.genotypes.iter().flatten() {
+            for allele in [genotype.genotype.0, genotype.genotype.1] {
+                total += 1;
+                if allele > 0 {
+                    counts[allele - 1] += 1;
+                }
+            }
+        }
+        (counts, total)
+    }
+
+    pub fn to_vcf_line(&self) -> String {
+        let (counts, total) = self.allele_counts();
+        let join = |values: &[u32]| values.iter().map(u32::to_string).collect::<Vec<_>>().join(",");
+        let frequencies: Vec<String> =
+            counts.iter().map(|&c| format!("{:.4}", c as f64 / total.max(1) as f64)).collect();
+        let depth: u32 = self.genotypes.iter().flatten().filter_map(|g| g.depth).sum();
+        let samples = self.genotypes.iter().flatten().count();
+
+        let mut line = format!(
+            "{}\t{}\t.\t{}\t{}\t{:.2}\tPASS\tAC={};AF={};AN={};DP={};NS={}\tGT:GQ:DP:AD:PL",
+            self.chrom,
+            self.pos + 1,
+            self.reference,
+            self.alternates.join(","),
+            self.quality,
+            join(&counts),
+            frequencies.join(","),
+            total,
+            depth,
+            samples
+        );
+        for genotype in &self.genotypes {
+            match genotype {
+                Some(g) => line.push_str(&format!(
+                    "\t{}/{}:{}:{}:{}:{}",
+                    g.genotype.0,
+                    g.genotype.1,
+                    g.genotype_quality,
+                    g.depth.map_or(".".to_string(), |d| d.to_string()),
+                    g.allele_depths.as_deref().map_or(".".to_string(), join),
+                    join(&g.likelihoods)
+                )),
+                None => line.push_str("\t./.:.:.:.:."),
+            }
+        }
+        line
+    }
+}
+
+/// Jointly genotyped cohort, ready to write as a multi-sample VCF
+#[derive(Debug, Clone)]
+pub struct Cohort {
+    pub samples: Vec<String>,
+    pub contigs: Vec<(String, u64)>,
+    pub sites: Vec<CohortSite>,
+}
+
+impl Cohort {
+    pub fn write_vcf(&self, path: &str, source: &str) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("Could not create VCF: {}", path))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(vcf_header(&self.contigs, source, &self.samples, &COHORT_HEADER_LINES).as_bytes())?;
+        for site in &self.sites {
+            writeln!(out, "{}", site.to_vcf_line())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Merge per-sample gVCFs into cohort genotypes at every site any sample called
+///
+/// Alleles are unified per site (REF extended to the longest, ALTs padded to
+/// match) and each sample's likelihoods remapped onto them, alleles it never
+/// saw taking its `<NON_REF>` likelihood. Samples inside a reference block
+/// contribute the block's likelihoods. Genotypes come from posteriors under a
+/// Hardy-Weinberg prior at the cohort allele frequencies, estimated by EM; QUAL
+/// is the probability that every sample is homozygous reference under the
+/// single-sample priors. Alternate alleles no sample carries are dropped.
+pub fn joint_genotype(gvcfs: &[SampleGvcf], min_quality: f64) -> Result<Cohort> {
+    if gvcfs.is_empty() {
+        bail!("Joint genotyping needs at least one gVCF");
+    }
+    let mut contigs: Vec<(String, u64)> = Vec::new();
+    for gvcf in gvcfs {
+        for (name, length) in &gvcf.contigs {
+            match contigs.iter_mut().find(|(n, _)| n == name) {
+                Some((_, known)) => *known = (*known).max(*length),
+                None => contigs.push((name.clone(), *length)),
+            }
+        }
+    }
+    // Each sample's contig indices in the merged order
+    let contig_maps: Vec<Vec<usize>> = gvcfs
+        .iter()
+        .map(|gvcf| gvcf.contigs.iter().map(|(name, _)| contigs.iter().position(|(n, _)| n == name).unwrap_or(0)).collect())
+        .collect();
+
+    let sites: BTreeSet<(usize, u64)> = gvcfs
+        .iter()
+        .zip(&contig_maps)
+        .flat_map(|(gvcf, map)| gvcf.records.iter().filter(|r| !r.is_block()).map(move |r| (map[r.contig], r.pos)))
+        .collect();
+
+    let mut cohort_sites = Vec::new();
+    for (contig, pos) in sites {
+        // This sample's record for the merged contig, if any
+        let records: Vec<Option<&GvcfRecord>> = gvcfs
+            .iter()
+            .zip(&contig_maps)
+            .map(|(gvcf, map)| map.iter().position(|&c| c == contig).and_then(|local| gvcf.covering(local, pos)))
+            .collect();
+        if let Some(site) = genotype_site(&contigs[contig].0, pos, &records, min_quality) {
+            cohort_sites.push(site);
+        }
+    }
+
+    Ok(Cohort { samples: gvcfs.iter().map(|g| g.sample.clone()).collect(), contigs, sites: cohort_sites })
+}
+
+/// A sample's merged-genotype log10 likelihoods, its record, and its alleles behind each merged allele
+type SampleEvidence<'a> = (Vec<f64>, &'a GvcfRecord, Vec<Vec<usize>>);
+
+/// A sample's most likely cohort genotype with the evidence behind it
+struct CalledGenotype<'a> {
+    genotype: usize,
+    genotype_quality: u32,
+    likelihoods: &'a [f64],
+    record: &'a GvcfRecord,
+    /// The sample's alleles behind each merged allele
+    local: &'a [Vec<usize>],
+}
+
+fn genotype_site(chrom: &str, pos: u64, records: &[Option<&GvcfRecord>], min_quality: f64) -> Option<CohortSite> {
+    let variants: Vec<&GvcfRecord> =
+        records.iter().flatten().copied().filter(|r| r.pos == pos && !r.is_block()).collect();
+    let reference = variants.iter().map(|r| r.reference.as_str()).max_by_key(|r| r.len())?.to_string();
+
+    let mut alternates: Vec<String> = Vec::new();
+    for record in &variants {
+        if !reference.starts_with(&record.reference) {
+            continue;
+        }
+        for alt in &record.alternates {
+            let padded = format!("{}{}", alt, &reference[record.reference.len()..]);
+            if padded != reference && !alternates.contains(&padded) {
+                alternates.push(padded);
+            }
+        }
+    }
+    if alternates.is_empty() {
+        return None;
+    }
+    // Samples whose deletion starts upstream and removes this position carry `*`
+    let spanning = |record: &GvcfRecord| !record.is_block() && record.pos < pos && pos < record.end;
+    let deletes_site = |record: &GvcfRecord, alt: &str| record.pos + alt.len() as u64 <= pos;
+    if records.iter().flatten().any(|r| spanning(r) && r.alternates.iter().any(|alt| deletes_site(r, alt))) {
+        alternates.push(SPANNING_DELETION.to_string());
+    }
+    let alleles = alternates.len() + 1;
+    let genotypes = alleles * (alleles + 1) / 2;
+
+    // Per-sample log10 likelihoods over the merged genotypes. Each merged allele
+    // maps to the sample's own alleles that imply it; a genotype takes the best
+    // PL over those combinations, or UNSEEN_PL when the sample has none.
+    let evidence: Vec<Option<SampleEvidence>> = records
+        .iter()
+        .map(|record| {
+            let record = (*record)?;
+            let pl = record.likelihoods.as_ref()?;
+            let unseen: Vec<usize> = record.non_ref.into_iter().collect();
+            let local: Vec<Vec<usize>> = if record.is_block() {
+                (0..alleles).map(|a| if a == 0 { vec![0] } else { vec![record.non_ref.unwrap_or(1)] }).collect()
+            } else if record.pos == pos && reference.starts_with(&record.reference) {
+                let suffix = &reference[record.reference.len()..];
+                (0..alleles)
+                    .map(|a| match a {
+                        0 => vec![0],
+                        _ => record
+                            .alternates
+                            .iter()
+                            .position(|alt| format!("{}{}", alt, suffix) == alternates[a - 1])
+                            .map_or_else(|| unseen.clone(), |i| vec![i + 1]),
+                    })
+                    .collect()
+            } else if spanning(record) {
+                let (deleting, kept): (Vec<usize>, Vec<usize>) =
+                    (1..=record.alternates.len()).partition(|&i| deletes_site(record, &record.alternates[i - 1]));
+                (0..alleles)
+                    .map(|a| match a {
+                        0 => std::iter::once(0).chain(kept.iter().copied()).collect(),
+                        _ if alternates[a - 1] == SPANNING_DELETION => deleting.clone(),
+                        _ => unseen.clone(),
+                    })
+                    .collect()
+            } else {
+                return None;
+            };
+            let likelihoods = (0..genotypes)
+                .map(|g| {
+                    let (a, b) = genotype_alleles(g);
+                    let phred = local[a]
+                        .iter()
+                        .flat_map(|&x| local[b].iter().map(move |&y| (x, y)))
+                        .filter_map(|(x, y)| pl.get(genotype_index(x, y)).copied())
+                        .min()
+                        .unwrap_or(UNSEEN_PL);
+                    -(phred as f64) / 10.0
+                })
+                .collect();
+            Some((likelihoods, record, local))
+        })
+        .collect();
+
+    let indel = alternates.iter().any(|alt| alt != SPANNING_DELETION && alt.len() != reference.len());
+    let model = if indel { INDEL_MODEL } else { SNP_MODEL };
+    let priors = genotype_priors(alleles, model.heterozygosity);
+    let quality: f64 = evidence
+        .iter()
+        .flatten()
+        .map(|(likelihoods, ..)| {
+            let posteriors: Vec<f64> = likelihoods.iter().zip(&priors).map(|(l, p)| l + p).collect();
+            -10.0 * (posteriors[0] - log10_sum(&posteriors))
+        })
+        .sum();
+    if quality < min_quality {
+        return None;
+    }
+
+    // EM over allele frequencies with a Hardy-Weinberg genotype prior
+    let mut frequencies: Vec<f64> =
+        (0..alleles).map(|a| if a == 0 { 1.0 - model.heterozygosity } else { model.heterozygosity / (alleles - 1) as f64 }).collect();
+    let hwe = |frequencies: &[f64]| -> Vec<f64> {
+        (0..genotypes)
+            .map(|g| {
+                let (a, b) = genotype_alleles(g);
+                let p = frequencies[a] * frequencies[b] * if a == b { 1.0 } else { 2.0 };
+                p.max(1e-300).log10()
+            })
+            .collect()
+    };
+    let posterior = |likelihoods: &[f64], prior: &[f64]| -> Vec<f64> {
+        let joint: Vec<f64> = likelihoods.iter().zip(prior).map(|(l, p)| l + p).collect();
+        let total = log10_sum(&joint);
+        joint.iter().map(|j| 10f64.powf(j - total)).collect()
+    };
+    for _ in 0..EM_ROUNDS {
+        let prior = hwe(&frequencies);
+        let mut counts = vec![0.0f64; alleles];
+        for (likelihoods, ..) in evidence.iter().flatten() {
+            for (g, p) in posterior(likelihoods, &prior).iter().enumerate() {
+                let (a, b) = genotype_alleles(g);
+                counts[a] += p;
+                counts[b] += p;
+            }
+        }
+        let total: f64 = counts.iter().sum();
+        if total == 0.0 {
+            break;
+        }
+        frequencies = counts.iter().map(|c| c / total).collect();
+    }
+
+    let prior = hwe(&frequencies);
+    let called: Vec<Option<CalledGenotype>> = evidence
+        .iter()
+        .map(|e| {
+            let (likelihoods, record, local) = e.as_ref()?;
+            let probabilities = posterior(likelihoods, &prior);
+            let (best, p) = probabilities.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+            let genotype_quality = (-10.0 * (1.0 - p).max(1e-30).log10()).clamp(0.0, MAX_GQ).round() as u32;
+            Some(CalledGenotype { genotype: best, genotype_quality, likelihoods, record, local })
+        })
+        .collect();
+
+    // Keep REF plus the alternate alleles some sample carries
+    let mut keep = vec![0usize];
+    for a in 1..alleles {
+        if called.iter().flatten().any(|c| {
+            let (x, y) = genotype_alleles(c.genotype);
+            x == a || y == a
+        }) {
+            keep.push(a);
+        }
+    }
+    if keep.len() == 1 {
+        return None;
+    }
+
+    let genotypes_out = called
+        .iter()
+        .map(|c| {
+            let CalledGenotype { genotype, genotype_quality, likelihoods, record, local } = c.as_ref()?;
+            let (a, b) = genotype_alleles(*genotype);
+            let remap = |allele: usize| keep.iter().position(|&k| k == allele).unwrap_or(0);
+            let subset: Vec<f64> = (0..keep.len() * (keep.len() + 1) / 2)
+                .map(|g| {
+                    let (x, y) = genotype_alleles(g);
+                    likelihoods[genotype_index(keep[x], keep[y])]
+                })
+                .collect();
+            let allele_depths = if record.is_block() {
+                record.depth.map(|d| std::iter::once(d).chain(std::iter::repeat_n(0, keep.len() - 1)).collect())
+            } else {
+                // Reads for the sample's own alleles behind each kept allele; <NON_REF> has none
+                record.allele_depths.as_ref().map(|ad| {
+                    keep.iter()
+                        .map(|&k| {
+                            local[k]
+                                .iter()
+                                .filter(|&&x| Some(x) != record.non_ref)
+                                .filter_map(|&x| ad.get(x))
+                                .sum()
+                        })
+                        .collect()
+                })
+            };
+            Some(SampleGenotype {
+                genotype: (remap(a), remap(b)),
+                genotype_quality: *genotype_quality,
+                depth: record.depth,
+                allele_depths,
+                likelihoods: crate::variant_caller::phred_likelihoods(&subset),
+            })
+        })
+        .collect();
+
+    Some(CohortSite {
+        chrom: chrom.to_string(),
+        pos,
+        reference,
+        alternates: keep[1..].iter().map(|&k| alternates[k - 1].clone()).collect(),
+        quality,
+        genotypes: genotypes_out,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, body: &str) -> SampleGvcf {
+        let path = std::env::temp_dir().join(format!("instant-dna-{}-{}.g.vcf", name, std::process::id()));
+        let header = format!(
+            "##fileformat=VCFv4.2\n##contig=<ID=chr1,length=1000>\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}\n",
+            name
+        );
+        std::fs::write(&path, header + &body.replace(' ', "\t")).unwrap();
+        let gvcf = SampleGvcf::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        gvcf
+    }
+
+    #[test]
+    fn upstream_deletion_is_genotyped_as_spanning_allele() {
+        // The deletion sample also has a reference block inside the deletion, as GATK writes them
+        let deletion = load(
+            "del",
+            "chr1 1 . A <NON_REF> . . END=99 GT:DP:GQ:PL 0/0:30:60:0,90,900\n\
+             chr1 100 . ATT A,<NON_REF> 500 . . GT:AD:DP:GQ:PL 1/1:0,30,0:30:90:900,90,0,900,90,900\n\
+             chr1 101 . T <NON_REF> . . END=102 GT:DP:GQ:PL 0/0:30:0:0,0,0\n\
+             chr1 103 . C <NON_REF> . . END=200 GT:DP:GQ:PL 0/0:30:60:0,90,900\n",
+        );
+        let snp = load(
+            "snp",
+            "chr1 1 . A <NON_REF> . . END=100 GT:DP:GQ:PL 0/0:30:60:0,90,900\n\
+             chr1 101 . T C,<NON_REF> 500 . . GT:AD:DP:GQ:PL 0/1:15,15,0:30:99:500,0,500,900,900,900\n\
+             chr1 102 . T <NON_REF> . . END=200 GT:DP:GQ:PL 0/0:30:60:0,90,900\n",
+        );
+
+        let cohort = joint_genotype(&[deletion, snp], 0.0).unwrap();
+        let site = cohort.sites.iter().find(|s| s.pos == 100).expect("site at the SNP");
+        assert_eq!(site.reference, "T");
+        assert_eq!(site.alternates, ["C", "*"]);
+        let genotypes: Vec<(usize, usize)> = site.genotypes.iter().map(|g| g.as_ref().unwrap().genotype).collect();
+        assert_eq!(genotypes, [(2, 2), (0, 1)]);
+        assert_eq!(site.genotypes[0].as_ref().unwrap().allele_depths.as_deref(), Some(&[0, 0, 30][..]));
+
+        let deletion_site = cohort.sites.iter().find(|s| s.pos == 99).expect("site at the deletion");
+        let genotypes: Vec<(usize, usize)> = deletion_site.genotypes.iter().map(|g| g.as_ref().unwrap().genotype).collect();
+        assert_eq!(genotypes, [(1, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn gzipped_gvcf_reads_every_member() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        // BGZF files are a series of gzip members; split the records across two
+        let member = |text: &str| {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(text.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        };
+        let mut bytes = member(
+            "##fileformat=VCFv4.2\n##contig=<ID=chr1,length=1000>\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tgz\n\
+             chr1\t1\t.\tA\t<NON_REF>\t.\t.\tEND=99\tGT:DP:GQ:PL\t0/0:30:60:0,90,900\n",
+        );
+        bytes.extend(member("chr1\t100\t.\tA\t<NON_REF>\t.\t.\tEND=200\tGT:DP:GQ:PL\t0/0:30:60:0,90,900\n"));
+
+        let path = std::env::temp_dir().join(format!("instant-dna-members-{}.g.vcf.gz", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let gvcf = SampleGvcf::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(gvcf.sample, "gz");
+        assert_eq!(gvcf.records.len(), 2);
+        assert!(gvcf.covering(0, 150).is_some());
+    }
+}
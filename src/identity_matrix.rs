@@ -0,0 +1,270 @@
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use crate::binary_optimizer::{BinaryOptimizer, SimilarityMetric};
+use crate::msa::{newick_label, GuideTree};
+
+/// Viridis colour stops, from low to high similarity
+const VIRIDIS: [(u8, u8, u8); 5] = [(68, 1, 84), (59, 82, 139), (33, 145, 140), (94, 201, 98), (253, 231, 37)];
+
+/// All-vs-all similarity matrix of a set of sequences
+pub struct PairwiseMatrix {
+    pub names: Vec<String>,
+    pub metric: SimilarityMetric,
+    /// Symmetric similarities in [0, 1], with 1 on the diagonal
+    pub similarity: Vec<Vec<f64>>,
+}
+
+impl PairwiseMatrix {
+    /// Compare every pair of (name, sequence) records in parallel
+    pub fn compute(records: &[(String, String)], metric: SimilarityMetric, optimizer: &BinaryOptimizer) -> Result<Self> {
+        let n = records.len();
+        if n < 2 {
+            bail!("Need at least two sequences for a pairwise matrix, found {}", n);
+        }
+
+        let pairs: Vec<(usize, usize)> = (0..n).flat_map(|i| ((i + 1)..n).map(move |j| (i, j))).collect();
+        let scores: Vec<f64> = pairs
+            .par_iter()
+            .map(|&(i, j)| {
+                optimizer
+                    .compare_with_metric(records[i].1.as_bytes(), records[j].1.as_bytes(), metric)
+                    .map(|result| result.similarity)
+                    .with_context(|| format!("Comparing {} with {}", records[i].0, records[j].0))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut similarity = vec![vec![1.0; n]; n];
+        for (&(i, j), &score) in pairs.iter().zip(&scores) {
+            similarity[i][j] = score;
+            similarity[j][i] = score;
+        }
+
+        Ok(Self {
+            names: records.iter().map(|(name, _)| name.clone()).collect(),
+            metric,
+            similarity,
+        })
+    }
+
+    /// Distance between two sequences
+    ///
+    /// One minus the similarity, except for k-mer Jaccard where the Mash
+    /// distance `-ln(2J / (1 + J)) / k` is used instead: it estimates the
+    /// per-base divergence, so it is comparable with alignment distances.
+    pub fn distance(&self, i: usize, j: usize) -> f64 {
+        if i == j {
+            return 0.0;
+        }
+        let s = self.similarity[i][j];
+        match self.metric {
+            SimilarityMetric::KmerJaccard(k) if s > 0.0 => (-(2.0 * s / (1.0 + s)).ln() / k as f64).min(1.0),
+            SimilarityMetric::KmerJaccard(_) => 1.0,
+            _ => 1.0 - s,
+        }
+    }
+
+    pub fn distances(&self) -> Vec<Vec<f64>> {
+        let n = self.names.len();
+        (0..n).map(|i| (0..n).map(|j| self.distance(i, j)).collect()).collect()
+    }
+
+    /// Similarity matrix as CSV with a header row and a name column
+    pub fn to_csv(&self) -> String {
+        let quote = |name: &str| {
+            if name.contains([',', '"']) {
+                format!("\"{}\"", name.replace('"', "\"\""))
+            } else {
+                name.to_string()
+            }
+        };
+
+        let mut out = String::from("sequence");
+        for name in &self.names {
+            out.push(',');
+            out.push_str(&quote(name));
+        }
+        out.push('\n');
+        for (name, row) in self.names.iter().zip(&self.similarity) {
+            out.push_str(&quote(name));
+            for value in row {
+                out.push_str(&format!(",{:.6}", value));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Square PHYLIP distance matrix; names longer than ten characters use relaxed PHYLIP
+    pub fn to_phylip(&self) -> String {
+        let mut out = format!("{}\n", self.names.len());
+        for (i, name) in self.names.iter().enumerate() {
+            let label = newick_label(name);
+            out.push_str(&format!("{:<10}", label));
+            if label.len() >= 10 {
+                out.push(' ');
+            }
+            let row: Vec<String> = (0..self.names.len()).map(|j| format!("{:.6}", self.distance(i, j))).collect();
+            out.push_str(&row.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Average-linkage clustering of the distances, for ordering the heatmap
+    pub fn cluster(&self) -> GuideTree {
+        GuideTree::upgma(&self.distances())
+    }
+
+    /// Heatmap with rows and columns in cluster order and the dendrogram alongside
+    pub fn to_heatmap_svg(&self) -> String {
+        let n = self.names.len();
+        let tree = self.cluster();
+        let order = tree.leaf_order();
+        let labels: Vec<String> = self.names.iter().map(|name| newick_label(name)).collect();
+        let escaped: Vec<String> = labels.iter().map(|label| xml_escape(label)).collect();
+
+        let cell = (800.0 / n as f64).clamp(2.0, 16.0);
+        let font = (cell * 0.75).clamp(6.0, 11.0);
+        let show_labels = cell >= 6.0;
+        let label_width = if show_labels {
+            labels.iter().map(|l| l.len()).max().unwrap_or(0) as f64 * font * 0.62 + 10.0
+        } else {
+            0.0
+        };
+        let (dendro_width, margin) = (140.0, 20.0);
+        let grid = cell * n as f64;
+        let (left, top) = (margin + dendro_width, margin + 30.0);
+        let width = left + grid + label_width + margin;
+        let height = top + grid + label_width + 70.0;
+
+        let min = self
+            .similarity
+            .iter()
+            .flatten()
+            .copied()
+            .fold(1.0f64, f64::min);
+        let span = (1.0 - min).max(f64::EPSILON);
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" font-family=\"sans-serif\">\n",
+            width, height
+        );
+        svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-size=\"14\">Pairwise {} ({} sequences)</text>\n",
+            margin,
+            margin + 4.0,
+            self.metric,
+            n
+        ));
+
+        for (row, &i) in order.iter().enumerate() {
+            for (col, &j) in order.iter().enumerate() {
+                let value = self.similarity[i][j];
+                let (r, g, b) = viridis((value - min) / span);
+                svg.push_str(&format!(
+                    "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"rgb({},{},{})\"><title>{} vs {}: {:.4}</title></rect>\n",
+                    left + col as f64 * cell,
+                    top + row as f64 * cell,
+                    cell,
+                    cell,
+                    r,
+                    g,
+                    b,
+                    escaped[i],
+                    escaped[j],
+                    value
+                ));
+            }
+        }
+
+        if show_labels {
+            for (pos, &i) in order.iter().enumerate() {
+                let centre = pos as f64 * cell + cell / 2.0 + font / 3.0;
+                svg.push_str(&format!(
+                    "<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"{:.1}\">{}</text>\n",
+                    left + grid + 4.0,
+                    top + centre,
+                    font,
+                    escaped[i]
+                ));
+                let x = left + pos as f64 * cell + cell / 2.0 - font / 3.0;
+                let y = top + grid + 4.0;
+                svg.push_str(&format!(
+                    "<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"{:.1}\" transform=\"rotate(90 {:.2} {:.2})\">{}</text>\n",
+                    x,
+                    y,
+                    font,
+                    x,
+                    y,
+                    escaped[i]
+                ));
+            }
+        }
+
+        // Dendrogram: leaves at the grid edge, the root furthest left
+        let max_height = tree.nodes[tree.root()].height.max(f64::EPSILON);
+        let x_of = |node: usize| left - 4.0 - (dendro_width - 10.0) * tree.nodes[node].height / max_height;
+        let mut y_of = vec![0.0; tree.nodes.len()];
+        for (pos, &leaf) in order.iter().enumerate() {
+            y_of[leaf] = top + pos as f64 * cell + cell / 2.0;
+        }
+        for node in n..tree.nodes.len() {
+            let (a, b) = tree.nodes[node].children.expect("internal node");
+            y_of[node] = (y_of[a] + y_of[b]) / 2.0;
+            let x = x_of(node);
+            svg.push_str(&format!(
+                "<path d=\"M{:.2},{:.2} H{:.2} V{:.2} H{:.2}\" fill=\"none\" stroke=\"#333\" stroke-width=\"1\"/>\n",
+                x_of(a),
+                y_of[a],
+                x,
+                y_of[b],
+                x_of(b)
+            ));
+        }
+
+        // Colour legend
+        let legend_y = top + grid + label_width + 20.0;
+        let legend_width = grid.clamp(120.0, 300.0);
+        svg.push_str("<defs><linearGradient id=\"scale\">");
+        for (k, &(r, g, b)) in VIRIDIS.iter().enumerate() {
+            svg.push_str(&format!(
+                "<stop offset=\"{:.0}%\" stop-color=\"rgb({},{},{})\"/>",
+                k as f64 * 100.0 / (VIRIDIS.len() - 1) as f64,
+                r,
+                g,
+                b
+            ));
+        }
+        svg.push_str("</linearGradient></defs>\n");
+        svg.push_str(&format!(
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"12\" fill=\"url(#scale)\"/>\n",
+            left, legend_y, legend_width
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"10\">{:.3}</text>\n<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"10\" text-anchor=\"end\">1.000</text>\n",
+            left,
+            legend_y + 26.0,
+            min,
+            left + legend_width,
+            legend_y + 26.0
+        ));
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Linear interpolation between viridis stops, `t` in [0, 1]
+fn viridis(t: f64) -> (u8, u8, u8) {
+    let t = t.clamp(0.0, 1.0) * (VIRIDIS.len() - 1) as f64;
+    let k = (t.floor() as usize).min(VIRIDIS.len() - 2);
+    let f = t - k as f64;
+    let (a, b) = (VIRIDIS[k], VIRIDIS[k + 1]);
+    let mix = |x: u8, y: u8| (x as f64 + (y as f64 - x as f64) * f).round() as u8;
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,393 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::Crc;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// File signature for serialized k-mer Bloom filters
+const MAGIC: &[u8; 8] = b"IDNABLM1";
+
+/// Largest k that fits a 2-bit encoded k-mer in a u64
+pub const MAX_K: usize = 32;
+
+/// Probabilistic k-mer membership set for host and contaminant screening
+///
+/// K-mers are stored in canonical form (the smaller of the k-mer and its
+/// reverse complement, using the engine's A=0 T=1 G=2 C=3 encoding), so a
+/// read matches regardless of the strand it was sequenced from. K-mers
+/// containing N or other ambiguity codes are skipped.
+pub struct KmerBloomFilter {
+    k: usize,
+    num_hashes: u32,
+    num_bits: u64,
+    bits: Vec<u64>,
+    inserted: u64,
+}
+
+impl KmerBloomFilter {
+    /// Size a filter for `expected_kmers` distinct k-mers at the given false-positive rate
+    pub fn new(k: usize, expected_kmers: u64, false_positive_rate: f64) -> Result<Self> {
+        if k == 0 || k > MAX_K {
+            bail!("K-mer size must be between 1 and {}", MAX_K);
+        }
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            bail!("False-positive rate must be between 0 and 1");
+        }
+
+        let n = expected_kmers.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = ((-n * false_positive_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+
+        Ok(Self {
+            k,
+            num_hashes,
+            num_bits,
+            bits: vec![0u64; num_bits.div_ceil(64) as usize],
+            inserted: 0,
+        })
+    }
+
+    /// Build a filter from every k-mer of a (optionally gzipped) FASTA reference
+    pub fn from_fasta(path: &Path, k: usize, false_positive_rate: f64) -> Result<Self> {
+        // First pass sizes the filter from the total base count
+        let mut total_bases = 0u64;
+        for_each_fasta_record(path, |_, sequence| {
+            total_bases += sequence.len().saturating_sub(k - 1) as u64;
+            Ok(())
+        })?;
+
+        let mut filter = Self::new(k, total_bases, false_positive_rate)?;
+        for_each_fasta_record(path, |_, sequence| {
+            filter.insert_sequence(sequence);
+            Ok(())
+        })?;
+
+        Ok(filter)
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// Number of k-mer insertions, counting repeats
+    pub fn inserted(&self) -> u64 {
+        self.inserted
+    }
+
+    /// Expected false-positive rate given the current fill
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        let set: u64 = self.bits.iter().map(|w| w.count_ones() as u64).sum();
+        (set as f64 / self.num_bits as f64).powi(self.num_hashes as i32)
+    }
+
+    pub fn insert_sequence(&mut self, sequence: &[u8]) {
+        for kmer in CanonicalKmers::new(sequence, self.k) {
+            self.insert_kmer(kmer);
+        }
+    }
+
+    pub fn insert_kmer(&mut self, kmer: u64) {
+        let (h1, h2) = hash_pair(kmer);
+        for i in 0..self.num_hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.inserted += 1;
+    }
+
+    pub fn contains_kmer(&self, kmer: u64) -> bool {
+        let (h1, h2) = hash_pair(kmer);
+        (0..self.num_hashes as u64).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+
+    /// Count the read's valid k-mers and how many of them hit the filter
+    pub fn query_sequence(&self, sequence: &[u8]) -> KmerHits {
+        let mut hits = KmerHits::default();
+        for kmer in CanonicalKmers::new(sequence, self.k) {
+            hits.total += 1;
+            if self.contains_kmer(kmer) {
+                hits.matched += 1;
+            }
+        }
+        hits
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create filter file: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        let mut crc = Crc::new();
+
+        let mut header = Vec::with_capacity(32);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&(self.k as u32).to_le_bytes());
+        header.extend_from_slice(&self.num_hashes.to_le_bytes());
+        header.extend_from_slice(&self.num_bits.to_le_bytes());
+        header.extend_from_slice(&self.inserted.to_le_bytes());
+        crc.update(&header);
+        writer.write_all(&header)?;
+
+        for chunk in self.bits.chunks(1 << 16) {
+            let bytes: Vec<u8> = chunk.iter().flat_map(|w| w.to_le_bytes()).collect();
+            crc.update(&bytes);
+            writer.write_all(&bytes)?;
+        }
+
+        writer.write_all(&crc.sum().to_le_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open filter file: {}", path.display()))?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut crc = Crc::new();
+
+        let mut header = [0u8; 32];
+        reader.read_exact(&mut header).context("Filter file is truncated")?;
+        if &header[..8] != MAGIC {
+            bail!("Not a k-mer filter file: {}", path.display());
+        }
+        crc.update(&header);
+
+        let k = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let num_hashes = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let num_bits = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let inserted = u64::from_le_bytes(header[24..32].try_into().unwrap());
+        if k == 0 || k > MAX_K || num_bits == 0 {
+            bail!("Corrupt k-mer filter header: {}", path.display());
+        }
+        // The checksum comes after the bit vector, so check its size against the file first
+        let expected_len = num_bits.div_ceil(64).checked_mul(8).and_then(|bytes| bytes.checked_add(32 + 4));
+        if expected_len != Some(file_len) {
+            bail!("K-mer filter header does not match the file size: {}", path.display());
+        }
+
+        let mut bytes = vec![0u8; num_bits.div_ceil(64) as usize * 8];
+        reader.read_exact(&mut bytes).context("Filter file is truncated")?;
+        crc.update(&bytes);
+
+        let mut stored = [0u8; 4];
+        reader.read_exact(&mut stored).context("Filter file is truncated")?;
+        if crc.sum() != u32::from_le_bytes(stored) {
+            bail!("Checksum mismatch in k-mer filter: {}", path.display());
+        }
+
+        let bits = bytes
+            .chunks_exact(8)
+            .map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+
+        Ok(Self { k, num_hashes, num_bits, bits, inserted })
+    }
+}
+
+/// K-mer hit counts for a single read
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KmerHits {
+    pub total: u32,
+    pub matched: u32,
+}
+
+impl KmerHits {
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.matched as f64 / self.total as f64
+        }
+    }
+}
+
+/// Outcome of screening one read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadClass {
+    Host,
+    Contaminant,
+    Clean,
+}
+
+/// Classifies reads against optional host and contaminant filters
+pub struct ReadScreener {
+    host: Option<KmerBloomFilter>,
+    contaminant: Option<KmerBloomFilter>,
+    min_fraction: f64,
+}
+
+impl ReadScreener {
+    /// `min_fraction` is the share of a read's k-mers that must hit a filter
+    pub fn new(
+        host: Option<KmerBloomFilter>,
+        contaminant: Option<KmerBloomFilter>,
+        min_fraction: f64,
+    ) -> Self {
+        Self { host, contaminant, min_fraction }
+    }
+
+    /// Host takes precedence when a read hits both filters
+    pub fn classify(&self, sequence: &[u8]) -> ReadClass {
+        let passes = |filter: &Option<KmerBloomFilter>| {
+            filter.as_ref().is_some_and(|f| {
+                let hits = f.query_sequence(sequence);
+                hits.total > 0 && hits.fraction() >= self.min_fraction
+            })
+        };
+
+        if passes(&self.host) {
+            ReadClass::Host
+        } else if passes(&self.contaminant) {
+            ReadClass::Contaminant
+        } else {
+            ReadClass::Clean
+        }
+    }
+}
+
+/// Rolling iterator over canonical 2-bit k-mers, restarting after ambiguous bases
+pub struct CanonicalKmers<'a> {
+    sequence: &'a [u8],
+    k: usize,
+    pos: usize,
+    valid: usize,
+    forward: u64,
+    reverse: u64,
+    mask: u64,
+}
+
+impl<'a> CanonicalKmers<'a> {
+    pub fn new(sequence: &'a [u8], k: usize) -> Self {
+        Self {
+            sequence,
+            k,
+            pos: 0,
+            valid: 0,
+            forward: 0,
+            reverse: 0,
+            mask: if k >= 32 { u64::MAX } else { (1u64 << (2 * k)) - 1 },
+        }
+    }
+}
+
+impl Iterator for CanonicalKmers<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        while self.pos < self.sequence.len() {
+            let base = self.sequence[self.pos];
+            self.pos += 1;
+
+            let Some(code) = base_code(base) else {
+                self.valid = 0;
+                continue;
+            };
+
+            self.forward = ((self.forward << 2) | code as u64) & self.mask;
+            // Complement in this encoding is code ^ 1 (A<->T, G<->C)
+            self.reverse = (self.reverse >> 2) | (((code ^ 1) as u64) << (2 * (self.k - 1)));
+            self.valid += 1;
+
+            if self.valid >= self.k {
+                return Some(self.forward.min(self.reverse));
+            }
+        }
+        None
+    }
+}
+
+/// 2-bit code for a nucleotide, or `None` for N and other ambiguity codes
+pub fn base_code(base: u8) -> Option<u8> {
+    match base {
+        b'A' | b'a' => Some(0b00),
+        b'T' | b't' | b'U' | b'u' => Some(0b01),
+        b'G' | b'g' => Some(0b10),
+        b'C' | b'c' => Some(0b11),
+        _ => None,
+    }
+}
+
+/// Two independent 64-bit hashes for Kirsch-Mitzenmacher double hashing
+fn hash_pair(kmer: u64) -> (u64, u64) {
+    let h1 = splitmix64(kmer);
+    let h2 = splitmix64(h1 ^ 0x9e37_79b9_7f4a_7c15) | 1;
+    (h1, h2)
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Stream the records of a (optionally gzipped) FASTA file without loading it whole
+pub fn for_each_fasta_record<F>(path: &Path, mut on_record: F) -> Result<()>
+where
+    F: FnMut(&str, &[u8]) -> Result<()>,
+{
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open FASTA file: {}", path.display()))?;
+    let reader: Box<dyn BufRead> = if path.extension().unwrap_or_default() == "gz" {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let mut name: Option<String> = None;
+    let mut sequence = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(header) = line.strip_prefix('>') {
+            if let Some(previous) = name.take() {
+                on_record(&previous, &sequence)?;
+            }
+            name = Some(header.split_whitespace().next().unwrap_or("").to_string());
+            sequence.clear();
+        } else {
+            sequence.extend_from_slice(line.trim_end().as_bytes());
+        }
+    }
+    if let Some(previous) = name {
+        on_record(&previous, &sequence)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_round_trips_and_rejects_oversized_headers() {
+        let path = std::env::temp_dir().join(format!("instant-dna-filter-{}.bin", std::process::id()));
+        let mut filter = KmerBloomFilter::new(21, 1000, 0.01).unwrap();
+        filter.insert_sequence(b"ACGTACGTACGTACGTACGTACGTTTGACCA");
+        filter.save(&path).unwrap();
+        let loaded = KmerBloomFilter::load(&path).unwrap();
+        assert_eq!((loaded.k, loaded.num_bits, loaded.inserted()), (filter.k, filter.num_bits, filter.inserted()));
+        assert_eq!(loaded.bits, filter.bits);
+
+        // A header claiming a huge bit vector fails on the size check rather than allocating
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[16..24].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        let error = KmerBloomFilter::load(&path).err().unwrap().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("does not match the file size"), "{}", error);
+    }
+}
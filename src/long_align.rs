@@ -0,0 +1,745 @@
+use anyhow::{bail, Result};
+use crate::alignment::{
+    gotoh, AlignOp, AlignmentAlgorithm, PairwiseAlignment, ScoringScheme, E_EXTENDED, F_EXTENDED, FROM_DIAG,
+    FROM_E, FROM_F, FROM_STOP,
+};
+use crate::minimizer::{Anchor, ChainParams, MinimizerIndex};
+
+const NEG: i32 = i32::MIN / 4;
+
+/// Pieces up to this many DP cells are aligned with the full-matrix Gotoh DP
+pub const AUTO_FULL_CELLS: usize = 1 << 26;
+
+/// X-drop used to extend anchored local alignments when none is given
+pub const DEFAULT_X_DROP: i32 = 200;
+
+/// How `AlignmentEngine::align` lays out its dynamic programming
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentMode {
+    /// Full matrix for small inputs, anchored linear-memory modes for long ones
+    Auto,
+    /// Full-matrix Gotoh; memory grows with query × target
+    Full,
+    /// Global alignment restricted to this many cells either side of the diagonal
+    Banded(usize),
+    /// Myers-Miller (affine Hirschberg) global alignment in linear memory
+    Hirschberg,
+    /// Seed-and-chain core extended until the score drops this far below its best
+    XDrop(i32),
+}
+
+impl std::str::FromStr for AlignmentMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let lower = s.to_lowercase();
+        let (name, param) = match lower.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (lower.as_str(), None),
+        };
+
+        match (name, param) {
+            ("auto", None) => Ok(AlignmentMode::Auto),
+            ("full", None) => Ok(AlignmentMode::Full),
+            ("hirschberg" | "linear", None) => Ok(AlignmentMode::Hirschberg),
+            ("banded", width) => {
+                let width = width.map_or(Ok(256), |w| w.parse())?;
+                if width == 0 {
+                    bail!("Band width must be positive");
+                }
+                Ok(AlignmentMode::Banded(width))
+            }
+            ("xdrop" | "x-drop", x) => {
+                let x = x.map_or(Ok(DEFAULT_X_DROP), |x| x.parse())?;
+                if x <= 0 {
+                    bail!("X-drop must be positive");
+                }
+                Ok(AlignmentMode::XDrop(x))
+            }
+            _ => bail!(
+                "Unknown alignment mode: {} (use auto, full, banded[:width], hirschberg or xdrop[:x])",
+                s
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for AlignmentMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlignmentMode::Auto => write!(f, "auto"),
+            AlignmentMode::Full => write!(f, "full"),
+            AlignmentMode::Banded(width) => write!(f, "banded:{}", width),
+            AlignmentMode::Hirschberg => write!(f, "hirschberg"),
+            AlignmentMode::XDrop(x) => write!(f, "xdrop:{}", x),
+        }
+    }
+}
+
+/// Score a list of alignment operations starting at the beginning of both sequences
+pub fn score_ops(ops: &[AlignOp], query: &[u8], target: &[u8], scoring: &ScoringScheme) -> i32 {
+    let (mut i, mut j, mut score) = (0, 0, 0);
+    let mut prev: Option<AlignOp> = None;
+    for &op in ops {
+        match op {
+            AlignOp::Match | AlignOp::Mismatch => {
+                score += scoring.score(query[i], target[j]);
+                i += 1;
+                j += 1;
+            }
+            AlignOp::Insertion | AlignOp::Deletion => {
+                score -= scoring.gap_extend;
+                if prev != Some(op) {
+                    score -= scoring.gap_open;
+                }
+                if op == AlignOp::Insertion {
+                    i += 1;
+                } else {
+                    j += 1;
+                }
+            }
+        }
+        prev = Some(op);
+    }
+    score
+}
+
+/// Optimal affine-gap global alignment in linear memory
+///
+/// Myers and Miller's divide and conquer: the best crossing point of the
+/// middle query row is found from a forward and a reverse score-only pass,
+/// keeping track of alignments that cross the row inside a query gap so that
+/// the gap is only charged one opening. Runs in about twice the time of the
+/// full-matrix DP with O(target) memory.
+pub fn hirschberg_global(query: &[u8], target: &[u8], scoring: &ScoringScheme) -> Vec<AlignOp> {
+    let mut ops = Vec::with_capacity(query.len().max(target.len()));
+    myers_miller(query, target, scoring.gap_open, scoring.gap_open, scoring, &mut ops);
+    ops
+}
+
+/// `start_open` / `end_open` are the opening cost of a query gap touching
+/// the start / end of this block: zero when it continues a gap from outside
+fn myers_miller(
+    a: &[u8],
+    b: &[u8],
+    start_open: i32,
+    end_open: i32,
+    scoring: &ScoringScheme,
+    ops: &mut Vec<AlignOp>,
+) {
+    let (m, n) = (a.len(), b.len());
+    let (open, ext) = (scoring.gap_open, scoring.gap_extend);
+
+    if n == 0 {
+        ops.extend(std::iter::repeat_n(AlignOp::Insertion, m));
+        return;
+    }
+    if m == 0 {
+        ops.extend(std::iter::repeat_n(AlignOp::Deletion, n));
+        return;
+    }
+
+    if m == 1 {
+        let target_gap = |len: usize| if len == 0 { 0 } else { -(open + ext * len as i32) };
+        // Either the query base sits in a gap of its own, or it faces some b[j]
+        let mut best = -(start_open.min(end_open) + ext) + target_gap(n);
+        let mut best_j = None;
+        for (j, &base) in b.iter().enumerate() {
+            let score = target_gap(j) + scoring.score(a[0], base) + target_gap(n - 1 - j);
+            if score > best {
+                best = score;
+                best_j = Some(j);
+            }
+        }
+
+        match best_j {
+            Some(j) => {
+                ops.extend(std::iter::repeat_n(AlignOp::Deletion, j));
+                ops.push(AlignOp::Match);
+                ops.extend(std::iter::repeat_n(AlignOp::Deletion, n - 1 - j));
+            }
+            None if start_open <= end_open => {
+                ops.push(AlignOp::Insertion);
+                ops.extend(std::iter::repeat_n(AlignOp::Deletion, n));
+            }
+            None => {
+                ops.extend(std::iter::repeat_n(AlignOp::Deletion, n));
+                ops.push(AlignOp::Insertion);
+            }
+        }
+        return;
+    }
+
+    let mid = m / 2;
+    let (forward_h, forward_gap) = last_row_scores(a[..mid].iter(), b.iter(), n, start_open, scoring);
+    let (reverse_h, reverse_gap) = last_row_scores(a[mid..].iter().rev(), b.iter().rev(), n, end_open, scoring);
+
+    // Crossing in a query gap pays two openings in the halves; refund one
+    let mut best = (NEG, 0usize, false);
+    for j in 0..=n {
+        let through = forward_h[j] + reverse_h[n - j];
+        if through > best.0 {
+            best = (through, j, false);
+        }
+        let in_gap = forward_gap[j] + reverse_gap[n - j] + open;
+        if in_gap > best.0 {
+            best = (in_gap, j, true);
+        }
+    }
+
+    let (_, split, in_gap) = best;
+    if in_gap {
+        myers_miller(&a[..mid - 1], &b[..split], start_open, 0, scoring, ops);
+        ops.push(AlignOp::Insertion);
+        ops.push(AlignOp::Insertion);
+        myers_miller(&a[mid + 1..], &b[split..], 0, end_open, scoring, ops);
+    } else {
+        myers_miller(&a[..mid], &b[..split], start_open, open, scoring, ops);
+        myers_miller(&a[mid..], &b[split..], open, end_open, scoring, ops);
+    }
+}
+
+/// Score-only Gotoh pass returning the last row of H and of the query-gap state
+fn last_row_scores<'a>(
+    a: impl Iterator<Item = &'a u8>,
+    b: impl Iterator<Item = &'a u8> + Clone,
+    n: usize,
+    start_open: i32,
+    scoring: &ScoringScheme,
+) -> (Vec<i32>, Vec<i32>) {
+    let (open, ext) = (scoring.gap_open, scoring.gap_extend);
+    let mut h: Vec<i32> = (0..=n)
+        .map(|j| if j == 0 { 0 } else { -(open + ext * j as i32) })
+        .collect();
+    let mut gap = vec![NEG; n + 1];
+
+    for (i, &qi) in a.enumerate() {
+        let mut diag = h[0];
+        h[0] = -(start_open + ext * (i + 1) as i32);
+        gap[0] = h[0];
+        let mut e = NEG;
+
+        for (j, &tj) in b.clone().enumerate() {
+            let j = j + 1;
+            gap[j] = gap[j].max(h[j] - open) - ext;
+            e = e.max(h[j - 1] - open) - ext;
+            let score = (diag + scoring.score(qi, tj)).max(gap[j]).max(e);
+            diag = h[j];
+            h[j] = score;
+        }
+    }
+
+    (h, gap)
+}
+
+/// Affine-gap global alignment confined to a band around the diagonal
+///
+/// The band follows the straight line from (0, 0) to the end of both
+/// sequences, so a length difference does not widen it. The result is
+/// optimal whenever the optimal path stays inside the band.
+pub fn banded_global(query: &[u8], target: &[u8], scoring: &ScoringScheme, band: usize) -> (i32, Vec<AlignOp>) {
+    let (n, m) = (query.len(), target.len());
+    if n == 0 || m == 0 {
+        let ops = hirschberg_global(query, target, scoring);
+        return (score_ops(&ops, query, target, scoring), ops);
+    }
+
+    let (open, ext) = (scoring.gap_open, scoring.gap_extend);
+    // Consecutive rows must overlap for the band to stay connected
+    let band = band.max(m.div_ceil(n) + 1);
+    let stride = 2 * band + 1;
+    let center = |i: usize| (i as u128 * m as u128 / n as u128) as usize;
+    let bounds = |i: usize| (center(i).saturating_sub(band), (center(i) + band).min(m));
+
+    let mut trace = vec![FROM_STOP; (n + 1) * stride];
+    let (mut h_prev, mut f_prev) = (vec![NEG; stride], vec![NEG; stride]);
+    let (mut h_curr, mut f_curr) = (vec![NEG; stride], vec![NEG; stride]);
+
+    let (_, hi0) = bounds(0);
+    for j in 0..=hi0 {
+        if j > 0 {
+            h_prev[j] = -(open + ext * j as i32);
+            trace[j] = FROM_E | if j > 1 { E_EXTENDED } else { 0 };
+        } else {
+            h_prev[0] = 0;
+        }
+    }
+
+    for i in 1..=n {
+        let (plo, phi) = bounds(i - 1);
+        let (lo, hi) = bounds(i);
+        let prev = |v: &[i32], j: usize| if j >= plo && j <= phi { v[j - plo] } else { NEG };
+        let qi = query[i - 1];
+        let mut e = NEG;
+
+        for j in lo..=hi {
+            let k = j - lo;
+            if j == 0 {
+                h_curr[0] = -(open + ext * i as i32);
+                f_curr[0] = NEG;
+                trace[i * stride] = FROM_F | if i > 1 { F_EXTENDED } else { 0 };
+                continue;
+            }
+
+            let mut bits = 0u8;
+            let e_open = if j > lo { h_curr[k - 1] - open - ext } else { NEG };
+            let e_ext = e - ext;
+            e = if e_ext > e_open {
+                bits |= E_EXTENDED;
+                e_ext
+            } else {
+                e_open
+            };
+
+            let f_open = prev(&h_prev, j) - open - ext;
+            let f_ext = prev(&f_prev, j) - ext;
+            let f = if f_ext > f_open {
+                bits |= F_EXTENDED;
+                f_ext
+            } else {
+                f_open
+            };
+
+            let diag = prev(&h_prev, j - 1) + scoring.score(qi, target[j - 1]);
+            let (mut h, mut from) = (diag, FROM_DIAG);
+            if e > h {
+                h = e;
+                from = FROM_E;
+            }
+            if f > h {
+                h = f;
+                from = FROM_F;
+            }
+
+            h_curr[k] = h;
+            f_curr[k] = f;
+            trace[i * stride + k] = bits | from;
+        }
+
+        std::mem::swap(&mut h_prev, &mut h_curr);
+        std::mem::swap(&mut f_prev, &mut f_curr);
+    }
+
+    let (lo_n, _) = bounds(n);
+    let score = h_prev[m - lo_n];
+    let ops = trace_back(|i, j| trace[i * stride + j - bounds(i).0], n, m, |i, j| i == 0 && j == 0);
+    (score, ops)
+}
+
+/// Result of extending an alignment from the start of both sequences
+#[derive(Debug, Clone)]
+pub struct Extension {
+    pub score: i32,
+    /// Query and target bases consumed by the extension
+    pub query_len: usize,
+    pub target_len: usize,
+    pub ops: Vec<AlignOp>,
+}
+
+/// Gapped X-drop extension anchored at the start of both sequences
+///
+/// Cells scoring more than `x_drop` below the best score seen so far are
+/// pruned, so only a narrow region around the alignment is explored and
+/// stored. The extension ends at the best-scoring cell and may be empty.
+pub fn xdrop_extend(query: &[u8], target: &[u8], scoring: &ScoringScheme, x_drop: i32) -> Extension {
+    let (n, m) = (query.len(), target.len());
+    let (open, ext) = (scoring.gap_open, scoring.gap_extend);
+
+    // Each stored row: first column, then one trace byte per column
+    let mut rows: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut best = (0, 0usize, 0usize);
+
+    let mut h_prev = vec![0];
+    let mut f_prev = vec![NEG];
+    let mut first_trace = vec![FROM_STOP];
+    for j in 1..=m {
+        let h = -(open + ext * j as i32);
+        if h < -x_drop {
+            break;
+        }
+        h_prev.push(h);
+        f_prev.push(NEG);
+        first_trace.push(FROM_E | if j > 1 { E_EXTENDED } else { 0 });
+    }
+    rows.push((0, first_trace));
+    let mut prev_lo = 0;
+
+    for i in 1..=n {
+        let prev_hi = prev_lo + h_prev.len() - 1;
+        let prev = |v: &[i32], j: usize| if j >= prev_lo && j <= prev_hi { v[j - prev_lo] } else { NEG };
+        let floor = best.0 - x_drop;
+        let qi = query[i - 1];
+
+        let (mut h_curr, mut f_curr, mut trace) = (Vec::new(), Vec::new(), Vec::new());
+        let mut e = NEG;
+        let mut j = prev_lo;
+        while j <= m {
+            let mut bits = 0u8;
+            let e_open = h_curr.last().map_or(NEG, |&h: &i32| h - open - ext);
+            let e_ext = e - ext;
+            e = if e_ext > e_open {
+                bits |= E_EXTENDED;
+                e_ext
+            } else {
+                e_open
+            };
+
+            let f_open = prev(&h_prev, j) - open - ext;
+            let f_ext = prev(&f_prev, j) - ext;
+            let mut f = if f_ext > f_open {
+                bits |= F_EXTENDED;
+                f_ext
+            } else {
+                f_open
+            };
+
+            let diag = if j > 0 { prev(&h_prev, j - 1) + scoring.score(qi, target[j - 1]) } else { NEG };
+            let (mut h, mut from) = (diag, FROM_DIAG);
+            if e > h {
+                h = e;
+                from = FROM_E;
+            }
+            if f > h {
+                h = f;
+                from = FROM_F;
+            }
+
+            if h < floor {
+                h = NEG;
+                f = NEG;
+                e = NEG;
+            } else if h > best.0 {
+                best = (h, i, j);
+            }
+            h_curr.push(h);
+            f_curr.push(f);
+            trace.push(bits | from);
+
+            // Past the previous row only a horizontal gap can keep the row alive
+            if j > prev_hi && h == NEG {
+                break;
+            }
+            j += 1;
+        }
+
+        let Some(first) = h_curr.iter().position(|&h| h > NEG) else {
+            break;
+        };
+        let last = h_curr.iter().rposition(|&h| h > NEG).unwrap_or(first);
+        let lo = prev_lo + first;
+        rows.push((lo, trace[first..=last].to_vec()));
+        h_prev = h_curr[first..=last].to_vec();
+        f_prev = f_curr[first..=last].to_vec();
+        prev_lo = lo;
+    }
+
+    let (score, end_i, end_j) = best;
+    let ops = trace_back(|i, j| rows[i].1[j - rows[i].0], end_i, end_j, |i, j| i == 0 && j == 0);
+    Extension {
+        score,
+        query_len: end_i,
+        target_len: end_j,
+        ops,
+    }
+}
+
+/// Follow trace bytes from (i, j) back to the cell where `done` holds
+pub fn trace_back(
+    bits_at: impl Fn(usize, usize) -> u8,
+    mut i: usize,
+    mut j: usize,
+    done: impl Fn(usize, usize) -> bool,
+) -> Vec<AlignOp> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        H,
+        E,
+        F,
+    }
+
+    let mut ops = Vec::new();
+    let mut state = State::H;
+    while !done(i, j) {
+        let bits = bits_at(i, j);
+        match state {
+            State::H => match bits & 0b11 {
+                FROM_DIAG => {
+                    ops.push(AlignOp::Match);
+                    i -= 1;
+                    j -= 1;
+                }
+                FROM_E => state = State::E,
+                FROM_F => state = State::F,
+                _ => break,
+            },
+            State::E => {
+                ops.push(AlignOp::Deletion);
+                if bits & E_EXTENDED == 0 {
+                    state = State::H;
+                }
+                j -= 1;
+            }
+            State::F => {
+                ops.push(AlignOp::Insertion);
+                if bits & F_EXTENDED == 0 {
+                    state = State::H;
+                }
+                i -= 1;
+            }
+        }
+    }
+
+    ops.reverse();
+    ops
+}
+
+/// Global alignment of the stretch between two anchors, picking the cheapest exact-enough DP
+fn align_piece(query: &[u8], target: &[u8], scoring: &ScoringScheme) -> Vec<AlignOp> {
+    let (n, m) = (query.len(), target.len());
+    if n == 0 || m == 0 {
+        return hirschberg_global(query, target, scoring);
+    }
+    if (n + 1).saturating_mul(m + 1) <= AUTO_FULL_CELLS {
+        return gotoh(query, target, AlignmentAlgorithm::Global, scoring).ops;
+    }
+
+    let band = 64 + n.max(m) / 100;
+    if (n + 1).saturating_mul(2 * band + 1) <= AUTO_FULL_CELLS {
+        banded_global(query, target, scoring, band).1
+    } else {
+        hirschberg_global(query, target, scoring)
+    }
+}
+
+/// Forward-strand minimizer anchors shared by query and target, in co-linear order
+///
+/// For global alignment every forward chain that fits between the chains
+/// already taken is kept, best first, so large rearrangement-free indels do
+/// not cut the alignment down to a single chain. Otherwise only the best
+/// chain is used. Overlapping anchors are dropped.
+fn colinear_anchors(query: &[u8], target: &[u8], all_chains: bool) -> (Vec<Anchor>, usize) {
+    let index = MinimizerIndex::build(&[("target".to_string(), target.to_vec())], 10, 15);
+    let k = index.k();
+    let chains: Vec<_> = index
+        .map_query(query, &ChainParams::default())
+        .into_iter()
+        .filter(|c| !c.reverse)
+        .collect();
+
+    let mut taken: Vec<(u32, u32, u32, u32)> = Vec::new();
+    let mut anchors = Vec::new();
+    for chain in chains {
+        let span = (chain.query_start, chain.query_end, chain.target_start, chain.target_end);
+        let colinear = taken.iter().all(|&(qs, qe, ts, te)| {
+            (span.1 <= qs && span.3 <= ts) || (span.0 >= qe && span.2 >= te)
+        });
+        if !colinear {
+            continue;
+        }
+        taken.push(span);
+        anchors.extend(chain.anchors);
+        if !all_chains {
+            break;
+        }
+    }
+
+    anchors.sort_unstable_by_key(|a| (a.target_pos, a.query_pos));
+    let mut kept: Vec<Anchor> = Vec::with_capacity(anchors.len());
+    let (mut query_next, mut target_next) = (0u32, 0u32);
+    for anchor in anchors {
+        if anchor.query_pos >= query_next && anchor.target_pos >= target_next {
+            query_next = anchor.query_pos + k as u32;
+            target_next = anchor.target_pos + k as u32;
+            kept.push(anchor);
+        }
+    }
+    (kept, k)
+}
+
+/// Alignment ops through a list of anchors, globally aligning the gaps between them
+fn stitch(query: &[u8], target: &[u8], anchors: &[Anchor], k: usize, scoring: &ScoringScheme) -> Vec<AlignOp> {
+    let mut ops = Vec::new();
+    let (first, last) = (anchors[0], anchors[anchors.len() - 1]);
+    let (mut q, mut t) = (first.query_pos as usize, first.target_pos as usize);
+    for anchor in anchors {
+        let (aq, at) = (anchor.query_pos as usize, anchor.target_pos as usize);
+        ops.extend(align_piece(&query[q..aq], &target[t..at], scoring));
+        ops.extend(std::iter::repeat_n(AlignOp::Match, k));
+        q = aq + k;
+        t = at + k;
+    }
+    debug_assert_eq!((q, t), (last.query_pos as usize + k, last.target_pos as usize + k));
+    ops
+}
+
+/// Chain-guided alignment of long sequences without a quadratic matrix
+///
+/// Minimizer chains fix exact k-mer matches on the main diagonal path; the
+/// stretches between them are aligned independently. Global alignments also
+/// align the flanks end to end, while local ones grow the anchored core
+/// outwards with X-drop extension. Returns `None` when no forward-strand chain
+/// is found.
+///
+/// X-drop extension may stop short of both sequence ends, so semi-global
+/// alignment is rejected rather than quietly scored as a local one.
+pub fn anchored_align(
+    query: &[u8],
+    target: &[u8],
+    algorithm: AlignmentAlgorithm,
+    scoring: &ScoringScheme,
+    x_drop: i32,
+) -> Result<Option<PairwiseAlignment>> {
+    if algorithm == AlignmentAlgorithm::SemiGlobal {
+        bail!("Anchored alignment only supports global and local alignment");
+    }
+    let global = algorithm == AlignmentAlgorithm::Global;
+    let (anchors, k) = colinear_anchors(query, target, global);
+    if anchors.is_empty() {
+        return Ok(None);
+    }
+
+    let (first, last) = (anchors[0], anchors[anchors.len() - 1]);
+    let (core_q0, core_t0) = (first.query_pos as usize, first.target_pos as usize);
+    let (core_q1, core_t1) = (last.query_pos as usize + k, last.target_pos as usize + k);
+    let core = stitch(query, target, &anchors, k, scoring);
+
+    let (ops, query_start, target_start) = if global {
+        let mut ops = align_piece(&query[..core_q0], &target[..core_t0], scoring);
+        ops.extend(core);
+        ops.extend(align_piece(&query[core_q1..], &target[core_t1..], scoring));
+        (ops, 0, 0)
+    } else {
+        let reversed = |s: &[u8]| s.iter().rev().copied().collect::<Vec<u8>>();
+        let left = xdrop_extend(&reversed(&query[..core_q0]), &reversed(&target[..core_t0]), scoring, x_drop);
+        let right = xdrop_extend(&query[core_q1..], &target[core_t1..], scoring, x_drop);
+
+        let mut ops: Vec<AlignOp> = left.ops.into_iter().rev().collect();
+        ops.extend(core);
+        ops.extend(right.ops);
+        (ops, core_q0 - left.query_len, core_t0 - left.target_len)
+    };
+
+    let score = score_ops(&ops, &query[query_start..], &target[target_start..], scoring);
+    Ok(Some(PairwiseAlignment::from_ops(
+        algorithm,
+        score,
+        scoring,
+        query,
+        target,
+        (query_start, target_start),
+        ops,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_sequence(state: &mut u64, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                b"ACGT"[(*state >> 33) as usize % 4]
+            })
+            .collect()
+    }
+
+    /// Copy of `source` with roughly one edit per `every` bases
+    fn mutate(state: &mut u64, source: &[u8], every: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(source.len());
+        for &base in source {
+            *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let roll = (*state >> 33) as usize;
+            match roll % (3 * every) {
+                0 => out.push(if base == b'A' { b'C' } else { b'A' }),
+                1 => {}
+                2 => out.extend_from_slice(&[base, b'G']),
+                _ => out.push(base),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn linear_space_global_scores_match_gotoh() {
+        let scoring = ScoringScheme::default();
+        let mut state = 7u64;
+        for round in 0..200 {
+            let query = random_sequence(&mut state, round % 23);
+            let source = random_sequence(&mut state, round % 19);
+            let target = mutate(&mut state, &source, 3);
+            let expected = gotoh(&query, &target, AlignmentAlgorithm::Global, &scoring).score;
+
+            let ops = hirschberg_global(&query, &target, &scoring);
+            assert_eq!(score_ops(&ops, &query, &target, &scoring), expected, "hirschberg round {}", round);
+
+            let (score, ops) = banded_global(&query, &target, &scoring, 32);
+            assert_eq!(score, expected, "banded round {}", round);
+            assert_eq!(score_ops(&ops, &query, &target, &scoring), score, "banded ops round {}", round);
+        }
+    }
+
+    #[test]
+    fn xdrop_extension_without_pruning_finds_the_best_prefix_alignment() {
+        let scoring = ScoringScheme::default();
+        let mut state = 11u64;
+        for round in 0..40 {
+            let query = random_sequence(&mut state, 1 + round % 9);
+            let target = mutate(&mut state, &query, 2);
+            let mut expected = 0;
+            for i in 0..=query.len() {
+                for j in 0..=target.len() {
+                    let (_, ops) = banded_global(&query[..i], &target[..j], &scoring, 16);
+                    expected = expected.max(score_ops(&ops, &query[..i], &target[..j], &scoring));
+                }
+            }
+
+            let extension = xdrop_extend(&query, &target, &scoring, 1000);
+            assert_eq!(extension.score, expected, "round {}", round);
+            let (q, t) = (&query[..extension.query_len], &target[..extension.target_len]);
+            assert_eq!(score_ops(&extension.ops, q, t, &scoring), extension.score, "round {}", round);
+        }
+    }
+
+    #[test]
+    fn xdrop_extension_stops_where_the_sequences_diverge() {
+        let scoring = ScoringScheme::default();
+        let mut state = 13u64;
+        let shared = random_sequence(&mut state, 50);
+        let (mut query, mut target) = (shared.clone(), shared);
+        query.extend(std::iter::repeat_n(b'A', 200));
+        target.extend(std::iter::repeat_n(b'C', 200));
+
+        let extension = xdrop_extend(&query, &target, &scoring, 20);
+        assert_eq!(extension.score, 100);
+        assert_eq!((extension.query_len, extension.target_len), (50, 50));
+        assert!(extension.ops.iter().all(|&op| op == AlignOp::Match));
+    }
+
+    #[test]
+    fn anchored_alignment_follows_the_full_matrix() {
+        let scoring = ScoringScheme::default();
+        let mut state = 17u64;
+        let target = random_sequence(&mut state, 3000);
+        let query = mutate(&mut state, &target, 100);
+
+        let full = gotoh(&query, &target, AlignmentAlgorithm::Global, &scoring);
+        let anchored = anchored_align(&query, &target, AlignmentAlgorithm::Global, &scoring, DEFAULT_X_DROP)
+            .unwrap()
+            .expect("shared seeds");
+        assert_eq!(anchored.score, full.score);
+        assert_eq!((anchored.query_end, anchored.target_end), (query.len(), target.len()));
+
+        // Unrelated flanks are trimmed by the local X-drop extension
+        let (left, right) = (random_sequence(&mut state, 500), random_sequence(&mut state, 500));
+        let flanked: Vec<u8> = left.iter().chain(&query).chain(&right).copied().collect();
+        let local = anchored_align(&flanked, &target, AlignmentAlgorithm::Local, &scoring, DEFAULT_X_DROP)
+            .unwrap()
+            .expect("shared seeds");
+        let best = gotoh(&flanked, &target, AlignmentAlgorithm::Local, &scoring);
+        assert_eq!(local.score, best.score);
+        assert!(local.query_start >= 490 && local.query_end <= 510 + query.len());
+
+        assert!(anchored_align(&query, &target, AlignmentAlgorithm::SemiGlobal, &scoring, DEFAULT_X_DROP).is_err());
+    }
+}
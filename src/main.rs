@@ -19,6 +19,27 @@ mod vcf_processor;
 mod benchmark;
 mod raw_converter;
 mod diy_dna;
+mod seq_container;
+mod kmer_filter;
+mod minimizer;
+mod striped_sw;
+mod long_align;
+mod msa;
+mod mapper;
+mod identity_matrix;
+mod phylo;
+mod alignment_format;
+mod dotplot;
+mod substitution;
+mod gvcf;
+mod vcf_filter;
+mod vcf_norm;
+mod structural_variants;
+mod copy_number;
+mod somatic;
+mod coverage;
+mod consensus;
+mod vcf_eval;
 
 use dna_engine::DnaEngine;
 use binary_optimizer::BinaryOptimizer;
@@ -85,6 +106,54 @@ enum Commands {
     /// DIY DNA extraction and manual genotyping
     Diy(DiyArgs),
     
+    /// Pack a FASTA file into an indexed 2-bit container
+    Pack(PackArgs),
+    
+    /// Extract sequences or regions from a packed container
+    Extract(ExtractArgs),
+    
+    /// Build a k-mer Bloom filter from a reference genome
+    BuildFilter(BuildFilterArgs),
+    
+    /// Screen reads for host and contaminant sequences
+    Screen(ScreenArgs),
+    
+    /// FASTQ quality report: base composition, Phred distribution and per-position quality
+    Qc(QcArgs),
+    
+    /// Progressive multiple sequence alignment of a multi-FASTA file
+    Msa(MsaArgs),
+    
+    /// Map single- or paired-end FASTQ reads to a reference (SAM/BAM output)
+    Map(MapArgs),
+    
+    /// All-vs-all identity matrix of a multi-FASTA file (CSV, PHYLIP, heatmap SVG)
+    Matrix(MatrixArgs),
+    
+    /// Build a phylogenetic tree (NJ/UPGMA) from sequences, an alignment or a distance matrix
+    Tree(TreeArgs),
+    
+    /// Jointly genotype per-sample gVCFs into a cohort VCF
+    Joint(JointArgs),
+    
+    /// Filter a VCF with an expression over QUAL, INFO and FORMAT fields
+    Filter(FilterArgs),
+    
+    /// Normalize a VCF: left-align and trim indels, split or join multiallelics, remove duplicates
+    Norm(NormArgs),
+    
+    /// Call copy-number gains and losses from binned read depth (SEG, BED and plot)
+    Cnv(CnvArgs),
+    
+    /// Depth statistics from a BAM: per-base bedGraph, per-target TSV and summary JSON
+    Coverage(CoverageArgs),
+    
+    /// Apply a sample's VCF variants to a reference: consensus FASTA with IUPAC or per-haplotype sequences and a chain file
+    Consensus(ConsensusArgs),
+    
+    /// Benchmark a query VCF against a truth set: haplotype-aware TP/FP/FN, precision, recall and F1
+    Eval(EvalArgs),
+    
     /// Show system status and capabilities
     Status,
 }
@@ -149,13 +218,69 @@ struct CompareArgs {
     #[arg(short = 'a', long, default_value = "global")]
     algorithm: String,
     
-    /// Similarity threshold
+    /// DP layout: auto, full, banded[:width], hirschberg, xdrop[:x] (semi-global needs auto or full)
+    #[arg(long, default_value = "auto")]
+    mode: String,
+    
+    /// Similarity threshold, applied to the chosen metric
     #[arg(short = 's', long, default_value = "0.8")]
     similarity: f64,
     
-    /// Enable binary-optimized alignment
-    #[arg(long, default_value = "true")]
+    /// Similarity metric: identity, gap-compressed, edit, hamming, jaccard[:k] (edit and identity need a band under 4.3e9 DP cells)
+    #[arg(short = 'm', long, default_value = "identity")]
+    metric: String,
+    
+    /// Enable binary-optimized (striped SIMD) alignment
+    #[arg(long, default_value = "true", action = clap::ArgAction::Set)]
     binary_align: bool,
+    
+    /// Score for a matching base
+    #[arg(long, default_value = "2")]
+    match_score: i32,
+    
+    /// Score for a mismatching base
+    #[arg(long, default_value = "-3", allow_hyphen_values = true)]
+    mismatch: i32,
+    
+    /// Gap opening penalty
+    #[arg(long, default_value = "5")]
+    gap_open: i32,
+    
+    /// Gap extension penalty (per base)
+    #[arg(long, default_value = "2")]
+    gap_extend: i32,
+    
+    /// Substitution matrix: BLOSUM45/62/80, PAM40/120/200/250, IUPAC, or an NCBI-format matrix file
+    #[arg(long)]
+    matrix: Option<String>,
+    
+    /// Write the full pairwise alignment (or the batch hit table) to this file
+    #[arg(long)]
+    alignment_output: Option<String>,
+    
+    /// Format of the alignment output: text, sam, bam, paf, blast6, blast7 (queries are aligned on the forward strand only)
+    #[arg(long, default_value = "text")]
+    format: String,
+    
+    /// Locally align every sequence in seq1 against every sequence in seq2
+    #[arg(long)]
+    batch: bool,
+    
+    /// Minimum local alignment score reported in batch mode
+    #[arg(long, default_value = "30")]
+    min_score: i32,
+    
+    /// Draw a both-strand dot plot (.svg or .png) instead of aligning
+    #[arg(long)]
+    dotplot: Option<String>,
+    
+    /// Dot-plot window length
+    #[arg(long, default_value = "20")]
+    dot_window: usize,
+    
+    /// Identical bases a dot-plot window needs (default: the whole window)
+    #[arg(long)]
+    dot_stringency: Option<usize>,
 }
 
 #[derive(Args)]
@@ -168,13 +293,57 @@ struct VariantArgs {
     #[arg(short = 'r', long)]
     reference: String,
     
-    /// Minimum coverage
-    #[arg(short = 'c', long, default_value = "10")]
-    coverage: u32,
+    /// Minimum coverage for SNP and indel calls [default: 10]
+    #[arg(short = 'c', long)]
+    coverage: Option<u32>,
     
-    /// Variant type filter: snp, indel, all
+    /// Variant type filter: snp, indel, all (SNPs and indels), sv (structural variants)
     #[arg(short = 't', long, default_value = "all")]
     variant_type: String,
+    
+    /// Output VCF file
+    #[arg(short, long, default_value = "variants.vcf")]
+    output: String,
+    
+    /// Minimum base quality for a base to enter the pileup
+    #[arg(long, default_value = "13")]
+    min_base_quality: u8,
+    
+    /// Minimum mapping quality for a read to be used
+    #[arg(long, default_value = "20")]
+    min_mapping_quality: u8,
+    
+    /// Minimum QUAL for a variant to be reported
+    #[arg(long, default_value = "20")]
+    min_quality: f64,
+    
+    /// Write a gVCF with reference-confidence blocks instead of a variants-only VCF
+    #[arg(long)]
+    gvcf: bool,
+    
+    /// Minimum structural variant length in bases (with -t sv)
+    #[arg(long, default_value = "50")]
+    min_sv_length: u64,
+    
+    /// Minimum split reads plus discordant pairs supporting a structural variant (with -t sv)
+    #[arg(long, default_value = "3")]
+    min_sv_support: u32,
+    
+    /// Matched normal BAM/SAM: call somatic variants in the input (tumor) against it
+    #[arg(long)]
+    normal: Option<String>,
+    
+    /// Minimum tumor log-odds (TLOD) for a somatic call (with --normal)
+    #[arg(long, default_value = "6.3")]
+    min_tumor_lod: f64,
+    
+    /// Minimum normal log-odds (NLOD) for a somatic call to pass (with --normal)
+    #[arg(long, default_value = "2.2")]
+    min_normal_lod: f64,
+    
+    /// Minimum tumor allele fraction for a somatic candidate (with --normal)
+    #[arg(long, default_value = "0.02")]
+    min_vaf: f64,
 }
 
 #[derive(Args)]
@@ -345,803 +514,2670 @@ struct DiyArgs {
     interactive: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct AnalysisResult {
-    timestamp: chrono::DateTime<chrono::Utc>,
-    sequence_length: usize,
-    processing_time_ms: u128,
-    sequences_per_second: f64,
-    quality_scores: Vec<f64>,
-    findings: Vec<String>,
-    binary_optimizations_used: Vec<String>,
+#[derive(Args)]
+struct PackArgs {
+    /// Input FASTA file
+    #[arg(short, long)]
+    input: String,
+    
+    /// Output container file (.idna)
+    #[arg(short, long)]
+    output: String,
 }
 
-#[derive(Debug)]
-struct DnaStats {
-    length: usize,
-    gc_content: f64,
-    at_content: f64,
-    base_composition: (f64, f64, f64, f64), // A, T, G, C percentages
-    complexity: f64,
-    repeat_count: usize,
+#[derive(Args)]
+struct ExtractArgs {
+    /// Packed container file
+    #[arg(short, long)]
+    input: String,
+    
+    /// Regions to extract, e.g. chr1:100000-200000 (default: all records)
+    #[arg(short, long)]
+    region: Vec<String>,
+    
+    /// Output FASTA file
+    #[arg(short, long)]
+    output: String,
 }
 
-fn parse_fasta(content: &str) -> Vec<(String, String)> {
-    let mut sequences = Vec::new();
-    let mut current_name = String::new();
-    let mut current_seq = String::new();
+#[derive(Args)]
+struct MsaArgs {
+    /// Input multi-FASTA file
+    #[arg(short, long)]
+    input: String,
     
-    for line in content.lines() {
-        let line = line.trim();
-        if line.starts_with('>') {
-            // Save previous sequence if exists
-            if !current_name.is_empty() && !current_seq.is_empty() {
-                sequences.push((current_name.clone(), current_seq.clone()));
-            }
-            // Start new sequence
-            current_name = line[1..].to_string();
-            current_seq.clear();
-        } else if !line.is_empty() {
-            // Add to current sequence (uppercase and filter valid bases)
-            let clean_seq: String = line.chars()
-                .filter(|c| matches!(c.to_ascii_uppercase(), 'A' | 'T' | 'G' | 'C'))
-                .map(|c| c.to_ascii_uppercase())
-                .collect();
-            current_seq.push_str(&clean_seq);
-        }
-    }
+    /// Output alignment file
+    #[arg(short, long)]
+    output: String,
     
-    // Don't forget the last sequence
-    if !current_name.is_empty() && !current_seq.is_empty() {
-        sequences.push((current_name, current_seq));
-    }
+    /// Output format: fasta, clustal, stockholm
+    #[arg(short, long, default_value = "fasta")]
+    format: String,
     
-    sequences
+    /// K-mer size for guide-tree distances
+    #[arg(short = 'k', long, default_value = "6")]
+    kmer_size: usize,
+    
+    /// Score for a matching base
+    #[arg(long, default_value = "2")]
+    match_score: i32,
+    
+    /// Score for a mismatching base
+    #[arg(long, default_value = "-3", allow_hyphen_values = true)]
+    mismatch: i32,
+    
+    /// Gap opening penalty
+    #[arg(long, default_value = "5")]
+    gap_open: i32,
+    
+    /// Gap extension penalty (per base)
+    #[arg(long, default_value = "2")]
+    gap_extend: i32,
+    
+    /// Write the consensus sequence as FASTA to this file
+    #[arg(long)]
+    consensus: Option<String>,
+    
+    /// Write per-column consensus and conservation scores (TSV) to this file
+    #[arg(long)]
+    conservation: Option<String>,
+    
+    /// Write the guide tree in Newick format to this file
+    #[arg(long)]
+    guide_tree: Option<String>,
 }
 
-fn analyze_dna_sequence(sequence: &str) -> DnaStats {
-    let length = sequence.len();
-    if length == 0 {
-        return DnaStats {
-            length: 0,
-            gc_content: 0.0,
-            at_content: 0.0,
-            base_composition: (0.0, 0.0, 0.0, 0.0),
-            complexity: 0.0,
-            repeat_count: 0,
-        };
-    }
+#[derive(Args)]
+struct MapArgs {
+    /// Reference FASTA, optionally gzipped
+    #[arg(short, long)]
+    reference: String,
     
-    let mut a_count = 0;
-    let mut t_count = 0;
-    let mut g_count = 0;
-    let mut c_count = 0;
+    /// FASTQ reads (first mate for paired-end), optionally gzipped
+    #[arg(short = '1', long)]
+    reads1: String,
     
-    for base in sequence.chars() {
-        match base.to_ascii_uppercase() {
-            'A' => a_count += 1,
-            'T' => t_count += 1,
-            'G' => g_count += 1,
-            'C' => c_count += 1,
-            _ => {} // Ignore invalid bases
-        }
-    }
+    /// Second-mate FASTQ for paired-end mapping
+    #[arg(short = '2', long)]
+    reads2: Option<String>,
     
-    let total = (a_count + t_count + g_count + c_count) as f64;
-    let a_percent = a_count as f64 / total;
-    let t_percent = t_count as f64 / total;
-    let g_percent = g_count as f64 / total;
-    let c_percent = c_count as f64 / total;
+    /// Output file; .sam writes SAM, anything else BAM
+    #[arg(short, long)]
+    output: String,
     
-    let gc_content = (g_count + c_count) as f64 / total;
-    let at_content = (a_count + t_count) as f64 / total;
+    /// Minimizer k-mer size
+    #[arg(short = 'k', long, default_value = "15")]
+    kmer_size: usize,
     
-    // Simple complexity calculation based on base diversity
-    let complexity = if total > 0.0 {
-        let mut entropy = 0.0;
-        for &count in &[a_count, t_count, g_count, c_count] {
-            if count > 0 {
-                let p = count as f64 / total;
-                entropy -= p * p.log2();
-            }
-        }
-        entropy / 2.0 // Normalize to 0-1 range
-    } else {
-        0.0
-    };
+    /// Minimizer window size
+    #[arg(short = 'w', long, default_value = "10")]
+    window: usize,
     
-    // Count simple repeats (same base repeated 3+ times)
-    let repeat_count = count_simple_repeats(sequence);
+    /// Minimum alignment score to report a hit
+    #[arg(long, default_value = "30")]
+    min_score: i32,
     
-    DnaStats {
-        length,
-        gc_content,
-        at_content,
-        base_composition: (a_percent, t_percent, g_percent, c_percent),
-        complexity,
-        repeat_count,
-    }
+    /// Sort alignments by coordinate and index BAM output; every record is held in memory until
+    /// the end, about 150 bytes plus 1.5 bytes per read base, so sort large runs with samtools instead
+    #[arg(long)]
+    sort: bool,
 }
 
-fn count_simple_repeats(sequence: &str) -> usize {
-    let mut count = 0;
-    let chars: Vec<char> = sequence.chars().collect();
-    let mut i = 0;
+#[derive(Args)]
+struct MatrixArgs {
+    /// Input multi-FASTA file
+    #[arg(short, long)]
+    input: String,
     
-    while i < chars.len() {
-        let current_char = chars[i];
-        let mut repeat_length = 1;
-        
-        // Count consecutive identical characters
-        while i + repeat_length < chars.len() && chars[i + repeat_length] == current_char {
-            repeat_length += 1;
-        }
-        
-        if repeat_length >= 3 {
-            count += 1;
-        }
-        
-        i += repeat_length;
-    }
+    /// Output CSV file of pairwise similarities
+    #[arg(short, long)]
+    output: String,
     
-    count
+    /// Similarity metric: identity, gap-compressed, edit, hamming, jaccard[:k] (edit and identity need a band under 4.3e9 DP cells)
+    #[arg(short, long, default_value = "identity")]
+    metric: String,
+    
+    /// Write the distance matrix in PHYLIP format to this file
+    #[arg(long)]
+    phylip: Option<String>,
+    
+    /// Write a clustered heatmap SVG to this file
+    #[arg(long)]
+    svg: Option<String>,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Args)]
+struct TreeArgs {
+    /// Input: unaligned or aligned multi-FASTA, or a PHYLIP distance matrix
+    #[arg(short, long)]
+    input: String,
     
-    // Initialize logging
-    if cli.verbose {
-        env_logger::Builder::from_default_env()
-            .filter_level(log::LevelFilter::Debug)
-            .init();
-    } else {
-        env_logger::Builder::from_default_env()
-            .filter_level(log::LevelFilter::Info)
-            .init();
-    }
+    /// Output Newick file
+    #[arg(short, long)]
+    output: String,
     
-    println!("🧬 INSTANT DNA v2.0.0");
-    println!("🚀 Professional DNA Analysis System");
-    println!("👨‍💻 Created by Richie Looney");
-    println!("⚡ Ready for DNA analysis");
-    println!();
+    /// Tree method: nj, upgma
+    #[arg(long, default_value = "nj")]
+    method: String,
     
-    // Configure thread pool
-    let thread_count = cli.threads.unwrap_or_else(num_cpus::get);
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(thread_count)
-        .build_global()
-        .context("Failed to initialize thread pool")?;
+    /// Distance correction for sequence input: p, jc, k2p
+    #[arg(long, default_value = "k2p")]
+    model: String,
     
-    info!("🚀 Initialized with {} threads", thread_count);
+    /// Treat FASTA input as already aligned even when it has no gaps
+    #[arg(long)]
+    aligned: bool,
     
-    // Initialize core systems
-    let dna_engine = DnaEngine::new(cli.simd)?;
-    let binary_optimizer = BinaryOptimizer::new();
+    /// Number of bootstrap replicates (sequence input only)
+    #[arg(short, long, default_value = "0")]
+    bootstrap: usize,
     
-    match cli.command {
-        Commands::Sequence(args) => {
-            sequence_dna(args, &dna_engine, &binary_optimizer).await
+    /// Random seed for bootstrap resampling
+    #[arg(long, default_value = "1")]
+    seed: u64,
+    
+    /// Print an ASCII rendering of the tree
+    #[arg(long)]
+    ascii: bool,
+    
+    /// Write an SVG rendering of the tree to this file
+    #[arg(long)]
+    svg: Option<String>,
+}
+
+#[derive(Args)]
+struct JointArgs {
+    /// Per-sample gVCF files (plain or .gz)
+    #[arg(short, long, num_args = 1.., required = true)]
+    inputs: Vec<String>,
+    
+    /// Output cohort VCF file
+    #[arg(short, long, default_value = "cohort.vcf")]
+    output: String,
+    
+    /// Minimum QUAL for a site to be reported
+    #[arg(long, default_value = "20")]
+    min_quality: f64,
+}
+
+#[derive(Args)]
+struct FilterArgs {
+    /// Input VCF file (plain or .gz)
+    #[arg(short, long)]
+    input: String,
+    
+    /// Expression records must satisfy, e.g. 'QUAL>30 && INFO/DP>10 && FMT/GQ>20'
+    #[arg(short, long)]
+    expression: String,
+    
+    /// Output VCF file
+    #[arg(short, long, default_value = "filtered.vcf")]
+    output: String,
+    
+    /// Keep failing records and mark them with this FILTER name instead of removing them
+    #[arg(short = 's', long)]
+    soft_filter: Option<String>,
+}
+
+#[derive(Args)]
+struct NormArgs {
+    /// Input VCF file (plain or .gz)
+    #[arg(short, long)]
+    input: String,
+    
+    /// Indexed reference FASTA; indels are only left-aligned when given
+    #[arg(short = 'r', long)]
+    reference: Option<String>,
+    
+    /// Output VCF file
+    #[arg(short, long, default_value = "normalized.vcf")]
+    output: String,
+    
+    /// Multiallelic records: split into biallelic ones, or join records at one position
+    #[arg(short = 'm', long)]
+    multiallelics: Option<String>,
+    
+    /// Remove records with the same position, REF and ALT after normalization
+    #[arg(short = 'd', long)]
+    rm_dup: bool,
+}
+
+#[derive(Args)]
+struct CnvArgs {
+    /// Input BAM/SAM file
+    #[arg(short, long)]
+    input: String,
+    
+    /// Indexed reference FASTA, for GC content
+    #[arg(short = 'r', long)]
+    reference: String,
+    
+    /// Matched control BAM, or several forming a panel of normals (default: the sample's own median)
+    #[arg(short = 'c', long, num_args = 1..)]
+    controls: Vec<String>,
+    
+    /// Mappability track (BED or bedGraph with scores); estimated from mapping qualities when absent
+    #[arg(long)]
+    mappability: Option<String>,
+    
+    /// Output SEG file
+    #[arg(short, long, default_value = "cnv.seg")]
+    output: String,
+    
+    /// Output BED file of gains and losses
+    #[arg(long, default_value = "cnv.bed")]
+    bed: String,
+    
+    /// Genome-wide plot of log2 ratios and segments (SVG)
+    #[arg(long, default_value = "cnv.svg")]
+    plot: String,
+    
+    /// Bin size in bases
+    #[arg(short = 'b', long, default_value = "1000")]
+    bin_size: u64,
+    
+    /// Segmentation method: cbs (circular binary segmentation) or hmm
+    #[arg(short = 'm', long, default_value = "cbs")]
+    method: String,
+    
+    /// Minimum mapping quality for a read to count towards depth
+    #[arg(long, default_value = "20")]
+    min_mapping_quality: u8,
+    
+    /// Log2 ratio at or above which a CBS segment is a gain
+    #[arg(long, default_value = "0.25")]
+    gain_threshold: f64,
+    
+    /// Log2 ratio at or below which a CBS segment is a loss
+    #[arg(long, default_value = "-0.25", allow_hyphen_values = true)]
+    loss_threshold: f64,
+    
+    /// Copy number at a log2 ratio of zero (1 to 5)
+    #[arg(long, default_value = "2")]
+    ploidy: u32,
+}
+
+#[derive(Args)]
+struct CoverageArgs {
+    /// Input BAM/SAM file (coordinate-sorted)
+    #[arg(short, long)]
+    input: String,
+    
+    /// BED file of target regions (default: whole contigs)
+    #[arg(short = 'b', long)]
+    targets: Option<String>,
+    
+    /// Output summary JSON file
+    #[arg(short, long, default_value = "coverage.json")]
+    output: String,
+    
+    /// Output per-base depth as bedGraph
+    #[arg(long, default_value = "coverage.bedgraph")]
+    bedgraph: String,
+    
+    /// Output per-target statistics as TSV
+    #[arg(long, default_value = "coverage.tsv")]
+    per_target: String,
+    
+    /// Minimum mapping quality for a read to count
+    #[arg(long, default_value = "20")]
+    min_mapping_quality: u8,
+    
+    /// Depths to report the percentage of bases covered at, comma-separated
+    #[arg(long, value_delimiter = ',', default_value = "10,20,30")]
+    thresholds: Vec<u32>,
+}
+
+#[derive(Args)]
+struct ConsensusArgs {
+    /// Reference FASTA file, optionally gzipped
+    #[arg(short, long)]
+    reference: String,
+    
+    /// VCF with the variants to apply
+    #[arg(short, long)]
+    vcf: String,
+    
+    /// Sample whose genotypes are applied (default: the first; every record for a sites-only VCF)
+    #[arg(short, long)]
+    sample: Option<String>,
+    
+    /// Regions to build, e.g. chrM:1-16569 (default: every reference sequence)
+    #[arg(long)]
+    region: Vec<String>,
+    
+    /// Write two sequences per region from phased genotypes instead of IUPAC codes
+    #[arg(long)]
+    haplotypes: bool,
+    
+    /// Apply records that failed FILTER as well as PASS
+    #[arg(long)]
+    include_filtered: bool,
+    
+    /// BED file of regions to mask with N
+    #[arg(long)]
+    mask: Option<String>,
+    
+    /// Per-base depth bedGraph (e.g. from the coverage command); bases below --min-depth become N
+    #[arg(long)]
+    depth: Option<String>,
+    
+    /// Minimum depth for a base to be kept when --depth is given
+    #[arg(long, default_value = "10")]
+    min_depth: u32,
+    
+    /// Output consensus FASTA file
+    #[arg(short, long, default_value = "consensus.fa")]
+    output: String,
+    
+    /// Output chain file mapping reference coordinates to the consensus
+    #[arg(long, default_value = "consensus.chain")]
+    chain: String,
+}
+
+#[derive(Args)]
+struct EvalArgs {
+    /// Truth VCF (plain or .gz)
+    #[arg(short, long)]
+    truth: String,
+    
+    /// Query VCF to benchmark (plain or .gz)
+    #[arg(short, long)]
+    query: String,
+    
+    /// Indexed reference FASTA
+    #[arg(short = 'r', long)]
+    reference: String,
+    
+    /// Confident-region BED files; only calls inside them are evaluated (default: everywhere)
+    #[arg(short = 'b', long)]
+    confident: Vec<String>,
+    
+    /// BED files to stratify results by, each reported under its file name
+    #[arg(long)]
+    stratify: Vec<String>,
+    
+    /// Truth sample (default: the first)
+    #[arg(long)]
+    truth_sample: Option<String>,
+    
+    /// Query sample (default: the first)
+    #[arg(long)]
+    query_sample: Option<String>,
+    
+    /// Match alleles regardless of zygosity
+    #[arg(long)]
+    squash_ploidy: bool,
+    
+    /// Evaluate records that failed FILTER as well as PASS
+    #[arg(long)]
+    all_records: bool,
+    
+    /// Output TSV of counts and metrics by region and variant type
+    #[arg(short, long, default_value = "eval.tsv")]
+    output: String,
+}
+
+#[derive(Args)]
+struct BuildFilterArgs {
+    /// Reference FASTA (e.g. human genome or PhiX), optionally gzipped
+    #[arg(short, long)]
+    reference: String,
+    
+    /// Output filter file
+    #[arg(short, long)]
+    output: String,
+    
+    /// K-mer size (max 32)
+    #[arg(short = 'k', long, default_value = "31")]
+    kmer_size: usize,
+    
+    /// Target false-positive rate
+    #[arg(long, default_value = "0.001")]
+    fpr: f64,
+}
+
+#[derive(Args)]
+struct ScreenArgs {
+    /// Input reads (FASTA/FASTQ, optionally gzipped)
+    #[arg(short, long)]
+    input: String,
+    
+    /// Output file for clean reads
+    #[arg(short, long)]
+    output: String,
+    
+    /// Host k-mer filter (e.g. built from the human genome)
+    #[arg(long)]
+    host: Option<String>,
+    
+    /// Contaminant k-mer filter (e.g. built from PhiX)
+    #[arg(long)]
+    contaminant: Option<String>,
+    
+    /// Fraction of a read's k-mers that must match to classify it
+    #[arg(long, default_value = "0.5")]
+    min_fraction: f64,
+    
+    /// Optional output file for host reads
+    #[arg(long)]
+    host_output: Option<String>,
+    
+    /// Optional output file for contaminant reads
+    #[arg(long)]
+    contaminant_output: Option<String>,
+}
+
+#[derive(Args)]
+struct QcArgs {
+    /// Input FASTQ file, optionally gzipped
+    #[arg(short, long)]
+    input: String,
+    
+    /// Output per-position quality TSV
+    #[arg(short, long, default_value = "quality.tsv")]
+    output: String,
+    
+    /// Output Phred score histogram TSV
+    #[arg(long, default_value = "quality_histogram.tsv")]
+    histogram: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AnalysisResult {
+    timestamp: chrono::DateTime<chrono::Utc>,
+    sequence_length: usize,
+    processing_time_ms: u128,
+    sequences_per_second: f64,
+    quality_scores: Vec<f64>,
+    findings: Vec<String>,
+    binary_optimizations_used: Vec<String>,
+}
+
+#[derive(Debug)]
+struct DnaStats {
+    length: usize,
+    gc_content: f64,
+    at_content: f64,
+    base_composition: (f64, f64, f64, f64), // A, T, G, C percentages
+    complexity: f64,
+    repeat_count: usize,
+}
+
+fn parse_fasta(content: &str) -> Vec<(String, String)> {
+    parse_fasta_with(content, |c| matches!(c, 'A' | 'T' | 'G' | 'C'))
+}
+
+/// FASTA records keeping every residue letter, so N runs and IUPAC codes keep their positions
+fn parse_residue_fasta(content: &str) -> Vec<(String, String)> {
+    parse_fasta_with(content, |c| c.is_ascii_alphabetic() || c == '*')
+}
+
+/// FASTA records, uppercased, keeping the characters `keep` accepts
+fn parse_fasta_with(content: &str, keep: impl Fn(char) -> bool) -> Vec<(String, String)> {
+    let mut sequences = Vec::new();
+    let mut current_name = String::new();
+    let mut current_seq = String::new();
+    
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('>') {
+            // Save previous sequence if exists
+            if !current_name.is_empty() && !current_seq.is_empty() {
+                sequences.push((current_name.clone(), current_seq.clone()));
+            }
+            // Start new sequence
+            current_name = line[1..].to_string();
+            current_seq.clear();
+        } else if !line.is_empty() {
+            // Add to current sequence (uppercase and filter valid residues)
+            let clean_seq: String = line.chars()
+                .map(|c| c.to_ascii_uppercase())
+                .filter(|&c| keep(c))
+                .collect();
+            current_seq.push_str(&clean_seq);
+        }
+    }
+    
+    // Don't forget the last sequence
+    if !current_name.is_empty() && !current_seq.is_empty() {
+        sequences.push((current_name, current_seq));
+    }
+    
+    sequences
+}
+
+fn analyze_dna_sequence(sequence: &str) -> DnaStats {
+    let length = sequence.len();
+    if length == 0 {
+        return DnaStats {
+            length: 0,
+            gc_content: 0.0,
+            at_content: 0.0,
+            base_composition: (0.0, 0.0, 0.0, 0.0),
+            complexity: 0.0,
+            repeat_count: 0,
+        };
+    }
+    
+    let mut a_count = 0;
+    let mut t_count = 0;
+    let mut g_count = 0;
+    let mut c_count = 0;
+    
+    for base in sequence.chars() {
+        match base.to_ascii_uppercase() {
+            'A' => a_count += 1,
+            'T' => t_count += 1,
+            'G' => g_count += 1,
+            'C' => c_count += 1,
+            _ => {} // Ignore invalid bases
+        }
+    }
+    
+    let total = (a_count + t_count + g_count + c_count) as f64;
+    let a_percent = a_count as f64 / total;
+    let t_percent = t_count as f64 / total;
+    let g_percent = g_count as f64 / total;
+    let c_percent = c_count as f64 / total;
+    
+    let gc_content = (g_count + c_count) as f64 / total;
+    let at_content = (a_count + t_count) as f64 / total;
+    
+    // Simple complexity calculation based on base diversity
+    let complexity = if total > 0.0 {
+        let mut entropy = 0.0;
+        for &count in &[a_count, t_count, g_count, c_count] {
+            if count > 0 {
+                let p = count as f64 / total;
+                entropy -= p * p.log2();
+            }
+        }
+        entropy / 2.0 // Normalize to 0-1 range
+    } else {
+        0.0
+    };
+    
+    // Count simple repeats (same base repeated 3+ times)
+    let repeat_count = count_simple_repeats(sequence);
+    
+    DnaStats {
+        length,
+        gc_content,
+        at_content,
+        base_composition: (a_percent, t_percent, g_percent, c_percent),
+        complexity,
+        repeat_count,
+    }
+}
+
+fn count_simple_repeats(sequence: &str) -> usize {
+    let mut count = 0;
+    let chars: Vec<char> = sequence.chars().collect();
+    let mut i = 0;
+    
+    while i < chars.len() {
+        let current_char = chars[i];
+        let mut repeat_length = 1;
+        
+        // Count consecutive identical characters
+        while i + repeat_length < chars.len() && chars[i + repeat_length] == current_char {
+            repeat_length += 1;
+        }
+        
+        if repeat_length >= 3 {
+            count += 1;
+        }
+        
+        i += repeat_length;
+    }
+    
+    count
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    
+    // Initialize logging
+    if cli.verbose {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Debug)
+            .init();
+    } else {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Info)
+            .init();
+    }
+    
+    println!("🧬 INSTANT DNA v2.0.0");
+    println!("🚀 Professional DNA Analysis System");
+    println!("👨‍💻 Created by Richie Looney");
+    println!("⚡ Ready for DNA analysis");
+    println!();
+    
+    // Configure thread pool
+    let thread_count = cli.threads.unwrap_or_else(num_cpus::get);
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(thread_count)
+        .build_global()
+        .context("Failed to initialize thread pool")?;
+    
+    info!("🚀 Initialized with {} threads", thread_count);
+    
+    // Initialize core systems
+    let dna_engine = DnaEngine::new(cli.simd)?;
+    let binary_optimizer = BinaryOptimizer::new();
+    
+    match cli.command {
+        Commands::Sequence(args) => {
+            sequence_dna(args, &dna_engine, &binary_optimizer).await
+        }
+        Commands::Analyze(args) => {
+            analyze_sequences(args, &dna_engine, &binary_optimizer).await
+        }
+        Commands::Compare(args) => {
+            compare_sequences(args, &dna_engine, &binary_optimizer).await
+        }
+        Commands::Variants(args) => {
+            call_variants(args, &dna_engine, &binary_optimizer).await
+        }
+        Commands::Assemble(args) => {
+            assemble_genome(args, &dna_engine, &binary_optimizer).await
+        }
+        Commands::Rna(args) => {
+            analyze_rna(args, &dna_engine, &binary_optimizer).await
+        }
+        Commands::Monitor(args) => {
+            monitor_sequences(args, &dna_engine, &binary_optimizer).await
+        }
+        Commands::Benchmark(args) => {
+            run_benchmark(args, &dna_engine, &binary_optimizer).await
+        }
+        Commands::Vcf(args) => {
+            process_vcf(args).await
+        }
+        Commands::Ancestry(args) => {
+            analyze_ancestry(args).await
+        }
+        Commands::Convert(args) => {
+            convert_raw_dna(args).await
+        }
+        Commands::Diy(args) => {
+            diy_dna_analysis(args).await
+        }
+        Commands::Pack(args) => {
+            pack_sequences(args).await
+        }
+        Commands::Extract(args) => {
+            extract_sequences(args).await
+        }
+        Commands::BuildFilter(args) => {
+            build_kmer_filter(args).await
+        }
+        Commands::Screen(args) => {
+            screen_reads(args).await
+        }
+        Commands::Qc(args) => {
+            quality_report(args).await
+        }
+        Commands::Msa(args) => {
+            multiple_alignment(args).await
+        }
+        Commands::Map(args) => {
+            map_reads(args, thread_count).await
+        }
+        Commands::Matrix(args) => {
+            identity_matrix(args, &binary_optimizer).await
+        }
+        Commands::Tree(args) => {
+            build_tree(args).await
+        }
+        Commands::Joint(args) => {
+            joint_genotype(args).await
+        }
+        Commands::Filter(args) => {
+            filter_vcf(args).await
+        }
+        Commands::Norm(args) => {
+            normalize_vcf(args).await
+        }
+        Commands::Cnv(args) => {
+            call_copy_number(args).await
+        }
+        Commands::Coverage(args) => {
+            compute_coverage(args).await
+        }
+        Commands::Consensus(args) => {
+            build_consensus(args).await
         }
-        Commands::Analyze(args) => {
-            analyze_sequences(args, &dna_engine, &binary_optimizer).await
+        Commands::Eval(args) => {
+            evaluate_calls(args).await
         }
-        Commands::Compare(args) => {
-            compare_sequences(args, &dna_engine, &binary_optimizer).await
+        Commands::Status => {
+            show_status(&dna_engine).await
         }
-        Commands::Variants(args) => {
-            call_variants(args, &dna_engine, &binary_optimizer).await
+    }
+}
+
+async fn sequence_dna(
+    args: SequenceArgs,
+    _engine: &DnaEngine,
+    _optimizer: &BinaryOptimizer,
+) -> Result<()> {
+    let start_time = Instant::now();
+    
+    println!("🔬 DNA SEQUENCING WITH INSTANT DNA");
+    println!("==================================");
+    println!("📊 Input: {}", args.input);
+    println!("📤 Output: {}", args.output);
+    println!("🎯 Quality threshold: {}", args.quality);
+    println!("⚡ Real-time mode: {}", args.realtime);
+    println!();
+    
+    // Simulate revolutionary DNA sequencing
+    println!("🧬 Applying binary optimizations...");
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    
+    let sequences = vec![
+        "ATCGATCGATCGTAGCTAGCTAGCTACGATCGATCGATCGATCGATCG",
+        "GCTAGCTAGCTACGATCGATCGATCGATCGATCGATCGATCGATCGAT",
+        "TTAACCGGTTAACCGGTTAACCGGTTAACCGGTTAACCGGTTAACCGG"
+    ];
+    
+    let processing_time = start_time.elapsed();
+    let sequences_per_second = sequences.len() as f64 / processing_time.as_secs_f64();
+    
+    println!("🎉 SEQUENCING COMPLETE!");
+    println!("✅ Sequenced {} sequences in {}ms", sequences.len(), processing_time.as_millis());
+    println!("🚀 Processing rate: {:.0} sequences/second", sequences_per_second);
+    
+    // Save results
+    let result_data = format!(
+        "Instant DNA Results\nSequences: {}\nTime: {}ms\nRate: {:.0} seq/sec\n",
+        sequences.len(),
+        processing_time.as_millis(),
+        sequences_per_second
+    );
+    
+    std::fs::write(&args.output, result_data)?;
+    println!("💾 Results saved to: {}", args.output);
+    
+    Ok(())
+}
+
+async fn analyze_sequences(
+    args: AnalyzeArgs,
+    _engine: &DnaEngine,
+    _optimizer: &BinaryOptimizer,
+) -> Result<()> {
+    let start_time = Instant::now();
+    
+    println!("🔍 DNA SEQUENCE ANALYSIS");
+    println!("========================");
+    println!("📊 Input file: {}", args.input);
+    println!("🧬 Analysis type: {}", args.analysis_type);
+    println!("🔬 Deep analysis: {}", args.deep);
+    println!();
+    
+    // Read and parse FASTA file
+    match std::fs::read_to_string(&args.input) {
+        Ok(content) => {
+            let sequences = parse_fasta(&content);
+            
+            if sequences.is_empty() {
+                println!("❌ No valid DNA sequences found in file");
+                return Ok(());
+            }
+            
+            println!("📊 Found {} sequences", sequences.len());
+            println!();
+            
+            for (i, (name, seq)) in sequences.iter().enumerate() {
+                let stats = analyze_dna_sequence(seq);
+                
+                println!("🧬 Sequence {} ({})", i + 1, name);
+                println!("   Length: {} bases", stats.length);
+                println!("   GC content: {:.1}%", stats.gc_content * 100.0);
+                println!("   AT content: {:.1}%", stats.at_content * 100.0);
+                println!("   A: {:.1}%, T: {:.1}%, G: {:.1}%, C: {:.1}%", 
+                    stats.base_composition.0 * 100.0,
+                    stats.base_composition.1 * 100.0,
+                    stats.base_composition.2 * 100.0,
+                    stats.base_composition.3 * 100.0);
+                
+                if args.deep {
+                    println!("   Complexity score: {:.2}", stats.complexity);
+                    println!("   Repeat regions: {}", stats.repeat_count);
+                }
+                println!();
+            }
+            
+            let processing_time = start_time.elapsed();
+            println!("✅ Analysis completed in {}ms", processing_time.as_millis());
+        }
+        Err(_) => {
+            println!("❌ Could not read file: {}", args.input);
+            println!("💡 Try using one of the sample files:");
+            println!("   sample_data/sample_dna.fasta");
+            println!("   sample_data/test_sequences.fasta");
+        }
+    }
+    
+    Ok(())
+}
+
+async fn compare_sequences(
+    args: CompareArgs,
+    _engine: &DnaEngine,
+    optimizer: &BinaryOptimizer,
+) -> Result<()> {
+    use alignment::{AlignmentEngine, ScoringScheme};
+    use alignment_format::{AlignmentHit, AlignmentWriter, KarlinAltschul, OutputFormat};
+    use binary_optimizer::SimilarityMetric;
+    use long_align::AlignmentMode;
+    use substitution::SubstitutionMatrix;
+    
+    let start_time = Instant::now();
+    
+    println!("⚖️ SEQUENCE COMPARISON WITH INSTANT DNA");
+    println!("======================================");
+    println!("📊 Sequence 1: {}", args.seq1);
+    println!("📊 Sequence 2: {}", args.seq2);
+    println!("🧮 Algorithm: {}", args.algorithm);
+    println!("🧭 Mode: {}", args.mode);
+    println!();
+    
+    let metric: SimilarityMetric = args.metric.parse()?;
+    let mode: AlignmentMode = args.mode.parse()?;
+    let format: OutputFormat = args.format.parse()?;
+    if format != OutputFormat::Text && args.alignment_output.is_none() {
+        return Err(anyhow::anyhow!("--format {} needs --alignment-output", args.format));
+    }
+    let matrix = match &args.matrix {
+        Some(spec) => {
+            let matrix = SubstitutionMatrix::load(spec, args.match_score, args.mismatch)?;
+            println!("🧪 Substitution matrix: {} ({} residues, {})", matrix.name, matrix.alphabet.len(),
+                if matrix.is_protein() { "protein" } else { "nucleotide" });
+            Some(Arc::new(matrix))
+        }
+        None => None,
+    };
+    let aligner = AlignmentEngine::new(args.binary_align)?
+        .with_scoring(ScoringScheme {
+            match_score: args.match_score,
+            mismatch: args.mismatch,
+            gap_open: args.gap_open,
+            gap_extend: args.gap_extend,
+            matrix,
+        })
+        .with_mode(mode);
+    
+    if args.batch {
+        return compare_batch(&args, &aligner, format);
+    }
+    if let Some(path) = &args.dotplot {
+        return compare_dotplot(&args, path);
+    }
+    
+    let algorithm: alignment::AlignmentAlgorithm = args.algorithm.parse()?;
+    let (query_name, query) = load_first_sequence(&args.seq1)?;
+    let (target_name, target) = load_first_sequence(&args.seq2)?;
+    let alignment = aligner.compare(query.as_bytes(), target.as_bytes(), algorithm)?;
+    
+    // Identity metrics come straight from the alignment; the rest are alignment-free
+    let (similarity, alignment_free) = match metric {
+        SimilarityMetric::BlastIdentity => (alignment.identity(), None),
+        SimilarityMetric::GapCompressedIdentity => (alignment.gap_compressed_identity(), None),
+        _ => {
+            let result = optimizer.compare_with_metric(query.as_bytes(), target.as_bytes(), metric)?;
+            (result.similarity, Some(result))
+        }
+    };
+    let processing_time = start_time.elapsed();
+    
+    println!("🎉 COMPARISON COMPLETE!");
+    println!("✅ Comparison complete in {}ms", processing_time.as_millis());
+    println!("🏆 Alignment score: {}", alignment.score);
+    println!("📍 Query {}-{}, subject {}-{}",
+        alignment.query_start + 1, alignment.query_end,
+        alignment.target_start + 1, alignment.target_end);
+    println!("🔍 Identities: {}/{} ({:.1}%), mismatches: {}, gaps: {} ({} opens)",
+        alignment.matches(), alignment.columns(), alignment.identity() * 100.0,
+        alignment.mismatches(), alignment.gap_bases(), alignment.gap_opens());
+    if aligner.scoring().matrix.is_some() {
+        let positives = alignment.positives(aligner.scoring());
+        println!("➕ Positives: {}/{} ({:.1}%)", positives, alignment.columns(),
+            positives as f64 * 100.0 / alignment.columns().max(1) as f64);
+    }
+    let cigar = alignment.cigar();
+    if cigar.len() <= 200 {
+        println!("🧾 CIGAR: {}", cigar);
+    }
+    match &alignment_free {
+        Some(result) => println!("📏 Metric: {} ({} differences over {})",
+            result.metric, result.differences, result.length),
+        None => println!("📏 Metric: {}", metric),
+    }
+    println!("📊 Similarity score: {:.4} ({:.1}%)", similarity, similarity * 100.0);
+    
+    if alignment.columns() <= 600 {
+        println!();
+        print!("{}", alignment.pretty(60));
+    }
+    let stats = KarlinAltschul::for_scoring(aligner.scoring());
+    if let Ok(stats) = &stats {
+        println!("📈 Bit score: {:.1}, E-value: {:.2e}", stats.bit_score(alignment.score),
+            stats.evalue(alignment.score, query.len(), target.len()));
+        println!("📐 Karlin-Altschul {}: λ = {:.3}, K = {:.3}, H = {:.3}",
+            if stats.gapped { "gapped" } else { "ungapped" }, stats.lambda, stats.k, stats.h);
+    }
+    
+    if let Some(path) = &args.alignment_output {
+        if format == OutputFormat::Text {
+            std::fs::write(path, format!("CIGAR: {}\n\n{}", cigar, alignment.pretty(60)))?;
+        } else {
+            let command_line: Vec<String> = std::env::args().collect();
+            let mut writer = AlignmentWriter::create(path, format, &[(target_name, target.len())],
+                &args.seq2, &command_line.join(" "))?;
+            let hit = AlignmentHit::new(0, alignment, &stats?, query.len(), target.len());
+            writer.write_query(&query_name, query.as_bytes(), &[hit])?;
+            writer.finish()?;
+        }
+        println!("💾 Alignment saved to: {} ({})", path, format);
+    }
+    
+    if similarity >= args.similarity {
+        println!("✨ Sequences meet the {:.1}% similarity threshold", args.similarity * 100.0);
+    } else {
+        println!("⚠️ Sequences fall below the {:.1}% similarity threshold", args.similarity * 100.0);
+    }
+    
+    Ok(())
+}
+
+/// All-queries-against-all-targets local alignment screen
+///
+/// Text output is a table of striped Smith-Waterman scores; the other formats
+/// re-align each reported hit with traceback to get coordinates and CIGARs.
+fn compare_batch(
+    args: &CompareArgs,
+    aligner: &alignment::AlignmentEngine,
+    format: alignment_format::OutputFormat,
+) -> Result<()> {
+    use alignment::AlignmentAlgorithm;
+    use alignment_format::{AlignmentHit, AlignmentWriter, KarlinAltschul, OutputFormat};
+    use std::io::Write;
+    
+    let start_time = Instant::now();
+    let queries = parse_residue_fasta(&std::fs::read_to_string(&args.seq1)?);
+    let targets = parse_residue_fasta(&std::fs::read_to_string(&args.seq2)?);
+    println!("📦 Batch mode: {} queries x {} targets ({})", queries.len(), targets.len(),
+        if args.binary_align { "striped SIMD Smith-Waterman" } else { "scalar Smith-Waterman" });
+    
+    let database_len: usize = targets.iter().map(|(_, sequence)| sequence.len()).sum();
+    let mut writer = match (&args.alignment_output, format) {
+        (Some(path), format) if format != OutputFormat::Text => {
+            let lengths: Vec<(String, usize)> = targets.iter().map(|(name, seq)| (name.clone(), seq.len())).collect();
+            let command_line: Vec<String> = std::env::args().collect();
+            Some((AlignmentWriter::create(path, format, &lengths, &args.seq2, &command_line.join(" "))?,
+                KarlinAltschul::for_scoring(aligner.scoring())?))
+        }
+        _ => None,
+    };
+    
+    let mut table = String::from("query\ttarget\tscore\ttarget_end\tprecision\n");
+    let mut hits = 0;
+    for (query_name, query) in &queries {
+        let scores = aligner.local_align_batch(query.as_bytes(), &targets);
+        let mut ranked: Vec<_> = scores
+            .iter()
+            .zip(targets.iter().enumerate())
+            .filter(|(score, _)| score.score >= args.min_score)
+            .collect();
+        ranked.sort_by_key(|(score, _)| std::cmp::Reverse(score.score));
+        
+        match ranked.first() {
+            Some((best, (_, (target_name, _)))) => {
+                println!("🎯 {} → {} (score {}, {} hits)", query_name, target_name, best.score, ranked.len());
+            }
+            None => println!("❌ {}: no hits with score ≥ {}", query_name, args.min_score),
+        }
+        
+        if let Some((writer, stats)) = writer.as_mut() {
+            let alignments: Vec<AlignmentHit> = ranked
+                .par_iter()
+                .map(|(_, (index, (_, target)))| {
+                    let alignment = aligner.compare(query.as_bytes(), target.as_bytes(), AlignmentAlgorithm::Local)?;
+                    Ok(AlignmentHit::new(*index, alignment, stats, query.len(), database_len))
+                })
+                .collect::<Result<_>>()?;
+            writer.write_query(query_name, query.as_bytes(), &alignments)?;
+        }
+        
+        for (score, (_, (target_name, _))) in ranked {
+            table.push_str(&format!("{}\t{}\t{}\t{}\t{:?}\n",
+                query_name, target_name, score.score, score.target_end + 1, score.precision));
+            hits += 1;
+        }
+    }
+    
+    println!("🎉 BATCH COMPARISON COMPLETE!");
+    println!("✅ {} alignments, {} hits in {}ms",
+        queries.len() * targets.len(), hits, start_time.elapsed().as_millis());
+    if let Some(path) = &args.alignment_output {
+        match writer {
+            Some((writer, _)) => {
+                writer.finish()?;
+                println!("💾 Hits saved to: {} ({})", path, format);
+            }
+            None => {
+                std::fs::File::create(path)?.write_all(table.as_bytes())?;
+                println!("💾 Hit table saved to: {}", path);
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// Word-match dot plot of the first sequence of each file
+fn compare_dotplot(args: &CompareArgs, path: &str) -> Result<()> {
+    use dotplot::{DotPlot, DotPlotParams};
+    
+    let start_time = Instant::now();
+    let (name1, seq1) = load_first_sequence(&args.seq1)?;
+    let (name2, seq2) = load_first_sequence(&args.seq2)?;
+    let params = DotPlotParams {
+        window: args.dot_window,
+        stringency: args.dot_stringency.unwrap_or(args.dot_window),
+        ..DotPlotParams::default()
+    };
+    println!("🔬 Dot plot: {} bp x {} bp, window {}, stringency {}",
+        seq1.len(), seq2.len(), params.window, params.stringency);
+    
+    let plot = DotPlot::compute((&name1, seq1.as_bytes()), (&name2, seq2.as_bytes()), params)?;
+    if path.to_lowercase().ends_with(".png") {
+        std::fs::write(path, plot.to_png()?)?;
+    } else {
+        std::fs::write(path, plot.to_svg())?;
+    }
+    
+    let forward = plot.segments.iter().filter(|s| !s.reverse).count();
+    let (longest_forward, longest_reverse) = plot.longest();
+    println!("🎉 DOT PLOT COMPLETE!");
+    println!("✅ {} diagonal segments in {}ms", plot.segments.len(), start_time.elapsed().as_millis());
+    println!("➡️ Forward strand: {} segments, longest {} bp", forward, longest_forward);
+    println!("⬅️ Reverse strand: {} segments, longest {} bp", plot.segments.len() - forward, longest_reverse);
+    println!("💾 Dot plot saved to: {}", path);
+    
+    Ok(())
+}
+
+/// First record of a FASTA file, or the whole file as a bare sequence
+///
+/// Every letter is kept, so N runs and IUPAC codes hold their positions.
+fn load_first_sequence(path: &str) -> Result<(String, String)> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read sequence file: {}", path))?;
+    
+    let records = parse_residue_fasta(&content);
+    if let Some(first) = records.into_iter().next() {
+        return Ok(first);
+    }
+    
+    let sequence: String = content
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if sequence.is_empty() {
+        return Err(anyhow::anyhow!("No sequence found in {}", path));
+    }
+    Ok((path.to_string(), sequence))
+}
+
+async fn call_variants(
+    args: VariantArgs,
+    _engine: &DnaEngine,
+    _optimizer: &BinaryOptimizer,
+) -> Result<()> {
+    use variant_caller::{CallerParams, VariantCaller, VariantType};
+    
+    let start_time = Instant::now();
+    
+    println!("🎯 VARIANT CALLING WITH INSTANT DNA");
+    println!("==================================");
+    println!("📊 Input: {}", args.input);
+    println!("🧬 Reference: {}", args.reference);
+    let min_coverage = args.coverage.unwrap_or(10);
+    println!("📈 Min coverage: {}", min_coverage);
+    println!("🔎 Variant types: {}", args.variant_type);
+    if let Some(normal) = &args.normal {
+        println!("🧫 Matched normal: {} (somatic mode)", normal);
+    }
+    println!();
+    
+    let variant_type: VariantType = args.variant_type.parse()?;
+    let caller = VariantCaller::new(min_coverage)?.with_params(CallerParams {
+        min_base_quality: args.min_base_quality,
+        min_mapping_quality: args.min_mapping_quality,
+        min_quality: args.min_quality,
+        gvcf: args.gvcf,
+        min_sv_length: args.min_sv_length,
+        min_sv_support: args.min_sv_support,
+        min_tumor_lod: args.min_tumor_lod,
+        min_normal_lod: args.min_normal_lod,
+        min_somatic_fraction: args.min_vaf,
+        ..CallerParams::default()
+    });
+    if let Some(normal) = &args.normal {
+        return report_somatic_variants(&caller, &args, normal, variant_type, start_time);
+    }
+    if variant_type == VariantType::Sv {
+        // SVs come from split reads and discordant pairs, not per-base pileups
+        if args.gvcf {
+            return Err(anyhow::anyhow!("--gvcf applies to SNP and indel calling, not -t sv"));
+        }
+        if args.coverage.is_some() {
+            return Err(anyhow::anyhow!("--coverage applies to SNP and indel calling, not -t sv (use --min-sv-support)"));
+        }
+        return report_structural_variants(&caller, &args, start_time);
+    }
+    let call_set = caller.call_variants(&args.input, &args.reference, variant_type)?;
+    if args.gvcf {
+        call_set.write_gvcf(&args.output, &args.reference)?;
+    } else {
+        call_set.write_vcf(&args.output, &args.reference)?;
+    }
+    
+    let processing_time = start_time.elapsed();
+    println!("🎉 VARIANT CALLING COMPLETE!");
+    println!("✅ Found {} variants ({} SNPs, {} indels) in {}ms",
+        call_set.calls.len(), call_set.snp_count(), call_set.indel_count(), processing_time.as_millis());
+    println!("🧬 Sample {}: {} reads used, {} filtered",
+        call_set.sample, call_set.reads_used, call_set.reads_filtered);
+    
+    for call in call_set.calls.iter().take(20) {
+        println!("📍 {}", call);
+    }
+    if call_set.calls.len() > 20 {
+        println!("   ... and {} more", call_set.calls.len() - 20);
+    }
+    if args.gvcf {
+        println!("🧱 {} reference-confidence blocks", call_set.blocks.len());
+        println!("💾 gVCF saved to: {}", args.output);
+    } else {
+        println!("💾 VCF saved to: {}", args.output);
+    }
+    
+    Ok(())
+}
+
+fn report_somatic_variants(
+    caller: &variant_caller::VariantCaller,
+    args: &VariantArgs,
+    normal: &str,
+    variant_type: variant_caller::VariantType,
+    start_time: Instant,
+) -> Result<()> {
+    let somatic_set = caller.call_somatic(&args.input, normal, &args.reference, variant_type)?;
+    somatic_set.write_vcf(&args.output, &args.reference)?;
+    
+    let processing_time = start_time.elapsed();
+    println!("🎉 SOMATIC VARIANT CALLING COMPLETE!");
+    println!("✅ Found {} somatic candidates ({} SNVs, {} indels), {} passing filters in {}ms",
+        somatic_set.calls.len(), somatic_set.snv_count(), somatic_set.indel_count(), somatic_set.passed(),
+        processing_time.as_millis());
+    println!("🧬 Tumor {}: {} reads used; normal {}: {} reads used; {} filtered",
+        somatic_set.tumor_sample, somatic_set.tumor_reads, somatic_set.normal_sample, somatic_set.normal_reads,
+        somatic_set.reads_filtered);
+    for (filter, count) in somatic_set.filter_counts() {
+        if count > 0 {
+            println!("🚫 {}: {}", filter, count);
+        }
+    }
+    
+    for call in somatic_set.calls.iter().take(20) {
+        println!("📍 {}", call);
+    }
+    if somatic_set.calls.len() > 20 {
+        println!("   ... and {} more", somatic_set.calls.len() - 20);
+    }
+    println!("💾 Somatic VCF saved to: {}", args.output);
+    
+    Ok(())
+}
+
+fn report_structural_variants(
+    caller: &variant_caller::VariantCaller,
+    args: &VariantArgs,
+    start_time: Instant,
+) -> Result<()> {
+    use structural_variants::SvType;
+    
+    let sv_set = caller.call_structural_variants(&args.input, &args.reference)?;
+    sv_set.write_vcf(&args.output, &args.reference)?;
+    
+    let processing_time = start_time.elapsed();
+    println!("🎉 STRUCTURAL VARIANT CALLING COMPLETE!");
+    println!("✅ Found {} SVs ({} DEL, {} DUP, {} INV, {} BND) in {}ms",
+        sv_set.calls.len(), sv_set.count(SvType::Deletion), sv_set.count(SvType::Duplication),
+        sv_set.count(SvType::Inversion), sv_set.count(SvType::Breakend), processing_time.as_millis());
+    println!("📏 Insert size: {:.0} ± {:.0} bp (discordant beyond {} bp, from {} pairs)",
+        sv_set.insert_model.mean, sv_set.insert_model.std_dev, sv_set.insert_model.high, sv_set.insert_model.pairs);
+    println!("🧬 Sample {}: {} discordant pairs, {} split reads, median depth {:.1}x",
+        sv_set.sample, sv_set.discordant_pairs, sv_set.split_reads, sv_set.median_depth);
+    
+    for call in sv_set.calls.iter().take(20) {
+        println!("📍 {}", call);
+    }
+    if sv_set.calls.len() > 20 {
+        println!("   ... and {} more", sv_set.calls.len() - 20);
+    }
+    println!("💾 VCF saved to: {}", args.output);
+    
+    Ok(())
+}
+
+async fn assemble_genome(
+    args: AssembleArgs,
+    _engine: &DnaEngine,
+    _optimizer: &BinaryOptimizer,
+) -> Result<()> {
+    let start_time = Instant::now();
+    
+    println!("🧩 GENOME ASSEMBLY WITH INSTANT DNA");
+    println!("==================================");
+    println!("📊 Input: {}", args.input);
+    println!("🔢 K-mer size: {}", args.kmer_size);
+    println!("🧮 Algorithm: {}", args.algorithm);
+    println!();
+    
+    tokio::time::sleep(tokio::time::Duration::from_millis(30)).await;
+    
+    let processing_time = start_time.elapsed();
+    let contigs = vec!["contig_1", "contig_2", "contig_3"];
+    
+    println!("🎉 ASSEMBLY COMPLETE!");
+    println!("✅ Generated {} contigs in {}ms", contigs.len(), processing_time.as_millis());
+    println!("📏 Total assembled length: 1,234,567 bp");
+    
+    Ok(())
+}
+
+async fn analyze_rna(
+    args: RnaArgs,
+    _engine: &DnaEngine,
+    _optimizer: &BinaryOptimizer,
+) -> Result<()> {
+    let start_time = Instant::now();
+    
+    println!("🧬 RNA ANALYSIS WITH INSTANT DNA");
+    println!("===============================");
+    println!("📊 Input: {}", args.input);
+    println!("🔬 Analysis type: {}", args.analysis_type);
+    println!("🌡️ Temperature: {:.1}°C", args.temperature);
+    println!();
+    
+    tokio::time::sleep(tokio::time::Duration::from_millis(18)).await;
+    
+    let processing_time = start_time.elapsed();
+    
+    println!("🎉 RNA ANALYSIS COMPLETE!");
+    println!("✅ Analysis complete in {}ms", processing_time.as_millis());
+    println!("🧬 Structure: ((((....))))...((((....))))");
+    
+    Ok(())
+}
+
+async fn monitor_sequences(
+    args: MonitorArgs,
+    _engine: &DnaEngine,
+    _optimizer: &BinaryOptimizer,
+) -> Result<()> {
+    println!("👁️ REAL-TIME MONITORING WITH INSTANT DNA");
+    println!("========================================");
+    println!("📁 Directory: {}", args.directory);
+    println!("⏱️ Interval: {}ms", args.interval);
+    println!();
+    
+    // Simulation of monitoring
+    for i in 1..=5 {
+        tokio::time::sleep(tokio::time::Duration::from_millis(args.interval)).await;
+        println!("📊 Scan #{}: Monitoring directory...", i);
+    }
+    
+    Ok(())
+}
+
+async fn run_benchmark(
+    args: BenchmarkArgs,
+    _engine: &DnaEngine,
+    _optimizer: &BinaryOptimizer,
+) -> Result<()> {
+    println!("🏁 DNA ANALYSIS COMPETITIVE BENCHMARK");
+    println!("====================================");
+    println!("� Test data path: {}", args.data_path);
+    println!("🔄 Iterations per tool: {}", args.iterations);
+    println!("🔧 Compare competitors: {}", if args.competitors { "Yes" } else { "No" });
+    println!();
+
+    // Create benchmark data if it doesn't exist
+    create_benchmark_data(&args.data_path).await?;
+
+    // Initialize benchmark suite
+    let mut benchmark = benchmark::BenchmarkSuite::new(
+        args.data_path.clone(), 
+        args.iterations
+    );
+
+    println!("🚀 Starting comprehensive benchmarks...");
+    println!();
+
+    if let Err(e) = benchmark.run_all_benchmarks() {
+        eprintln!("❌ Benchmark error: {}", e);
+        return Ok(());
+    }
+
+    let report = benchmark.generate_report();
+    println!("{}", report);
+
+    if args.report {
+        if let Err(e) = benchmark.save_report(&args.output) {
+            eprintln!("❌ Failed to save report: {}", e);
+        } else {
+            println!("📄 Detailed report saved to: {}", args.output);
         }
-        Commands::Assemble(args) => {
-            assemble_genome(args, &dna_engine, &binary_optimizer).await
+    }
+
+    println!("✅ Benchmark completed successfully!");
+    Ok(())
+}
+
+async fn create_benchmark_data(data_path: &str) -> Result<()> {
+    use std::fs;
+    use std::path::Path;
+
+    let path = Path::new(data_path);
+    if !path.exists() {
+        fs::create_dir_all(path)?;
+        println!("📁 Created benchmark data directory: {}", data_path);
+    }
+
+    // Create test VCF file if it doesn't exist
+    let test_vcf_path = path.join("test_sample.vcf.gz");
+    if !test_vcf_path.exists() {
+        // Use the real 1000 Genomes data if available
+        let real_vcf = Path::new("real_snp_data/1000genomes/ALL.chr22.1000genomes.vcf.gz");
+        if real_vcf.exists() {
+            println!("🔗 Linking to real 1000 Genomes data for benchmarks");
+            std::os::unix::fs::symlink(real_vcf.canonicalize()?, &test_vcf_path)?;
+        } else {
+            println!("⚠️  Real VCF data not found. Download 1000 Genomes data first:");
+            println!("   Run: ./download_1000genomes.sh");
+            return Ok(());
         }
-        Commands::Rna(args) => {
-            analyze_rna(args, &dna_engine, &binary_optimizer).await
+    }
+
+    // Create reference panel if it doesn't exist
+    let panel_path = path.join("reference_panel.txt");
+    if !panel_path.exists() {
+        let real_panel = Path::new("real_snp_data/1000genomes/integrated_call_samples_v3.20130502.ALL.panel");
+        if real_panel.exists() {
+            std::os::unix::fs::symlink(real_panel.canonicalize()?, &panel_path)?;
         }
-        Commands::Monitor(args) => {
-            monitor_sequences(args, &dna_engine, &binary_optimizer).await
+    }
+
+    // Create test FASTA file
+    let fasta_path = path.join("benchmark_sequence.fasta");
+    if !fasta_path.exists() {
+        let test_fasta = ">test_sequence\nATGCGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTA\nCGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGT\n";
+        fs::write(&fasta_path, test_fasta)?;
+    }
+
+    Ok(())
+}
+
+async fn show_status(_engine: &DnaEngine) -> Result<()> {
+    println!("🧬 INSTANT DNA - SYSTEM STATUS");
+    println!("==============================");
+    println!("💻 CPU Cores: {}", num_cpus::get());
+    println!("🧵 Active Threads: {}", rayon::current_num_threads());
+    println!("⚡ SIMD Support: Available");
+    println!("🧬 DNA Engine: Ready");
+    println!("📊 Binary Optimizer: Active");
+    println!("🎯 Version: 2.0.0");
+    println!("👨‍💻 Creator: Richie Looney");
+    println!("� Status: Ready for DNA analysis");
+    println!();
+    println!("� Supported formats: FASTA");
+    println!("🧬 Analysis types: Basic sequence stats, GC content, composition");
+    println!("⚡ Performance: Optimized for multi-core processing");
+    println!("🗄️ Database Support: VCF files, 1000 Genomes, population panels");
+    
+    Ok(())
+}
+
+async fn process_vcf(args: VcfArgs) -> Result<()> {
+    println!("🧬 Processing VCF file: {}", args.input);
+    println!("{}", "=".repeat(50));
+    
+    let mut processor = VCFProcessor::new();
+    
+    // Parse VCF file
+    processor.parse_vcf(std::path::Path::new(&args.input))
+        .context("Failed to parse VCF file")?;
+    
+    // Load population panel if provided
+    if let Some(panel_path) = &args.panel {
+        processor.parse_population_panel(std::path::Path::new(panel_path))
+            .context("Failed to parse population panel")?;
+    }
+    
+    // Display statistics
+    processor.print_statistics();
+    
+    // Calculate allele frequencies if requested
+    if args.frequencies {
+        println!("\n📊 Calculating allele frequencies...");
+        
+        if let Some(pop) = &args.population {
+            println!("\n🧬 Allele frequencies for population: {}", pop);
+            for (i, variant) in processor.variants.iter().enumerate().take(10) {
+                if let Some(freq) = processor.calculate_allele_frequency(i, pop) {
+                    println!("   {}: {} → {} (freq: {:.3})", 
+                        variant.id, variant.reference, variant.alternative, freq);
+                }
+            }
+        } else {
+            println!("   ⚠️  Specify --population to calculate frequencies");
         }
-        Commands::Benchmark(args) => {
-            run_benchmark(args, &dna_engine, &binary_optimizer).await
+    }
+    
+    // Save results if output specified
+    if let Some(output_path) = args.output {
+        println!("\n💾 Saving results to: {}", output_path);
+        // Implementation for saving results would go here
+        println!("   ✅ Results saved");
+    }
+    
+    Ok(())
+}
+
+async fn analyze_ancestry(args: AncestryArgs) -> Result<()> {
+    println!("🧬 Ancestry Analysis for sample: {}", args.sample);
+    println!("{}", "=".repeat(50));
+    
+    let mut processor = VCFProcessor::new();
+    
+    // Load VCF and population data
+    println!("📂 Loading VCF file: {}", args.vcf);
+    processor.parse_vcf(std::path::Path::new(&args.vcf))
+        .context("Failed to parse VCF file")?;
+    
+    println!("🌍 Loading population panel: {}", args.panel);
+    processor.parse_population_panel(std::path::Path::new(&args.panel))
+        .context("Failed to parse population panel")?;
+    
+    // Verify sample exists
+    if !processor.samples.contains(&args.sample) {
+        return Err(anyhow::anyhow!("Sample '{}' not found in VCF file", args.sample));
+    }
+    
+    println!("\n🧬 Estimating ancestry for sample: {}", args.sample);
+    
+    // Calculate ancestry estimates
+    match processor.estimate_ancestry(&args.sample) {
+        Ok(ancestry_scores) => {
+            println!("\n🌍 Ancestry Estimates:");
+            
+            // Sort by score (highest first)
+            let mut sorted_ancestry: Vec<_> = ancestry_scores.iter().collect();
+            sorted_ancestry.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
+            
+            for (ancestry, score) in sorted_ancestry {
+                let percentage = (score * 100.0).round() as u32;
+                let bar = "█".repeat((percentage / 5) as usize);
+                println!("   {:>15}: {:>3}% |{}|", ancestry, percentage, bar);
+            }
+            
+            // Detailed comparison if requested
+            if args.detailed {
+                println!("\n🔍 Detailed Population Comparisons:");
+                for (pop_code, population) in &processor.populations {
+                    if let Some(sample_from_pop) = population.samples.first() {
+                        if let Ok(similarity) = processor.calculate_genetic_similarity(&args.sample, sample_from_pop) {
+                            let percentage = (similarity * 100.0).round() as u32;
+                            println!("   {:>15} ({}): {}% similarity", population.name, pop_code, percentage);
+                        }
+                    }
+                }
+            }
         }
-        Commands::Vcf(args) => {
-            process_vcf(args).await
+        Err(e) => {
+            println!("❌ Error calculating ancestry: {}", e);
         }
-        Commands::Ancestry(args) => {
-            analyze_ancestry(args).await
+    }
+    
+    // Save results if requested
+    if let Some(output_path) = args.output {
+        println!("\n💾 Saving ancestry results to: {}", output_path);
+        println!("   ✅ Results saved");
+    }
+    
+    Ok(())
+}
+
+async fn convert_raw_dna(args: ConvertArgs) -> Result<()> {
+    use raw_converter::{RawDnaConverter, InputFormat};
+    
+    println!("🧬 RAW DNA TO VCF CONVERTER");
+    println!("===========================");
+    println!("📂 Input file: {}", args.input);
+    println!("📁 Output file: {}", args.output);
+    println!("👤 Sample name: {}", args.sample);
+    println!();
+
+    // Parse input format
+    let input_format = match args.format.to_lowercase().as_str() {
+        "auto" => InputFormat::Auto,
+        "23andme" => InputFormat::TwentyThreeAndMe,
+        "ancestry" | "ancestrydna" => InputFormat::AncestryDna,
+        "myheritage" => InputFormat::MyHeritage,
+        "familytree" | "familytreedna" => InputFormat::FamilyTreeDna,
+        "csv" => InputFormat::Csv,
+        "tab" => InputFormat::Tab,
+        "plink" => InputFormat::Plink,
+        _ => {
+            eprintln!("❌ Unknown format: {}. Using auto-detect.", args.format);
+            InputFormat::Auto
         }
-        Commands::Convert(args) => {
-            convert_raw_dna(args).await
+    };
+
+    // Create converter
+    let converter = RawDnaConverter::new(args.sample.clone(), input_format);
+
+    // Show conversion statistics if requested
+    if args.stats {
+        println!("📊 Analyzing input file...");
+        match converter.get_conversion_stats(&args.input) {
+            Ok(stats) => {
+                stats.print_summary();
+                println!();
+            }
+            Err(e) => {
+                eprintln!("⚠️ Could not analyze file: {}", e);
+            }
         }
-        Commands::Diy(args) => {
-            diy_dna_analysis(args).await
+    }
+
+    // Perform conversion
+    println!("🔄 Converting raw DNA data to VCF format...");
+    let start_time = std::time::Instant::now();
+
+    match converter.convert_to_vcf(&args.input, &args.output) {
+        Ok(()) => {
+            let duration = start_time.elapsed();
+            println!("✅ Conversion completed successfully!");
+            println!("⏱️ Processing time: {:.3}s", duration.as_secs_f64());
+            
+            // Optional compression
+            if args.compress {
+                println!("🗜️ Compressing output file...");
+                let compressed_output = format!("{}.gz", args.output);
+                
+                match compress_vcf_file(&args.output, &compressed_output) {
+                    Ok(()) => {
+                        println!("✅ Compressed file saved: {}", compressed_output);
+                        if let Err(e) = std::fs::remove_file(&args.output) {
+                            eprintln!("⚠️ Could not remove uncompressed file: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Compression failed: {}", e);
+                    }
+                }
+            }
+            
+            println!();
+            println!("💡 Your VCF file is ready for:");
+            println!("   • Ancestry analysis with --ancestry command");
+            println!("   • Population genetics studies");
+            println!("   • Import into other genomics tools");
+            println!("   • Medical genetic analysis");
         }
-        Commands::Status => {
-            show_status(&dna_engine).await
+        Err(e) => {
+            eprintln!("❌ Conversion failed: {}", e);
+            return Err(e);
         }
     }
+
+    Ok(())
 }
 
-async fn sequence_dna(
-    args: SequenceArgs,
-    _engine: &DnaEngine,
-    _optimizer: &BinaryOptimizer,
-) -> Result<()> {
-    let start_time = Instant::now();
+async fn diy_dna_analysis(args: DiyArgs) -> Result<()> {
+    use crate::diy_dna::DiyDnaExtractor;
     
-    println!("🔬 DNA SEQUENCING WITH INSTANT DNA");
-    println!("==================================");
-    println!("📊 Input: {}", args.input);
-    println!("📤 Output: {}", args.output);
-    println!("🎯 Quality threshold: {}", args.quality);
-    println!("⚡ Real-time mode: {}", args.realtime);
+    println!("🧬 DIY DNA EXTRACTION & MANUAL GENOTYPING");
+    println!("==========================================");
+    println!("👤 Sample: {}", args.sample);
+    println!("📤 Output VCF: {}", args.output);
     println!();
+
+    let mut extractor = DiyDnaExtractor::new(args.sample.clone());
     
-    // Simulate revolutionary DNA sequencing
-    println!("🧬 Applying binary optimizations...");
-    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    if args.load_markers {
+        extractor.load_diy_kit_markers();
+        println!();
+    }
     
-    let sequences = vec![
-        "ATCGATCGATCGTAGCTAGCTAGCTACGATCGATCGATCGATCGATCG",
-        "GCTAGCTAGCTACGATCGATCGATCGATCGATCGATCGATCGATCGAT",
-        "TTAACCGGTTAACCGGTTAACCGGTTAACCGGTTAACCGGTTAACCGG"
-    ];
+    if args.interactive {
+        extractor.start_manual_entry_session()?;
+        println!();
+        
+        // Export to VCF
+        println!("💾 Exporting manual entries to VCF...");
+        extractor.export_to_vcf(&args.output)?;
+        
+        let file_size = std::fs::metadata(&args.output)?.len();
+        println!("✅ DIY DNA analysis complete!");
+        println!("📊 Output: {} ({} bytes)", args.output, file_size);
+        
+        println!();
+        println!("🎉 Your manually entered DNA data is now in professional VCF format!");
+        println!("    You can use it for ancestry analysis, health reports, and more.");
+        
+    } else {
+        extractor.load_diy_kit_markers();
+        println!("💡 Use --interactive flag to start manual SNP entry session");
+        println!("   Example: instant-dna diy --sample \"YourName\" --output diy_dna.vcf --interactive");
+    }
     
-    let processing_time = start_time.elapsed();
-    let sequences_per_second = sequences.len() as f64 / processing_time.as_secs_f64();
+    Ok(())
+}
+
+async fn pack_sequences(args: PackArgs) -> Result<()> {
+    use seq_container::PackedContainer;
     
-    println!("🎉 SEQUENCING COMPLETE!");
-    println!("✅ Sequenced {} sequences in {:.2}ms", sequences.len(), processing_time.as_millis());
-    println!("🚀 Processing rate: {:.0} sequences/second", sequences_per_second);
+    println!("🗜️ PACKING SEQUENCES");
+    println!("====================");
+    println!("📂 Input: {}", args.input);
+    println!("📁 Output: {}", args.output);
+    println!();
     
-    // Save results
-    let result_data = format!(
-        "Instant DNA Results\nSequences: {}\nTime: {:.2}ms\nRate: {:.0} seq/sec\n",
-        sequences.len(),
-        processing_time.as_millis(),
-        sequences_per_second
-    );
+    let start_time = Instant::now();
+    let container = PackedContainer::from_fasta(std::path::Path::new(&args.input))?;
+    container.save(std::path::Path::new(&args.output))?;
+    
+    let total_bases: u64 = container.records.iter().map(|r| r.length).sum();
+    let input_size = std::fs::metadata(&args.input)?.len();
+    let output_size = std::fs::metadata(&args.output)?.len();
+    
+    println!("✅ Packed {} sequences ({} bases) in {}ms",
+        container.records.len(), total_bases, start_time.elapsed().as_millis());
+    for record in &container.records {
+        println!("   🧬 {}: {} bp, {} N runs, {} masked runs",
+            record.name, record.length, record.n_runs.len(), record.mask_runs.len());
+    }
+    println!("📊 Size: {} → {} bytes ({:.1}%)",
+        input_size, output_size, output_size as f64 / input_size.max(1) as f64 * 100.0);
     
-    std::fs::write(&args.output, result_data)?;
-    println!("💾 Results saved to: {}", args.output);
+    Ok(())
+}
+
+async fn extract_sequences(args: ExtractArgs) -> Result<()> {
+    use seq_container::{ContainerReader, Region};
+    use std::io::Write;
+    
+    let mut reader = ContainerReader::open(std::path::Path::new(&args.input))?;
+    
+    let regions: Vec<Region> = if args.region.is_empty() {
+        reader.records()
+            .iter()
+            .map(|r| Region { name: r.name.clone(), start: None, end: None })
+            .collect()
+    } else {
+        args.region.iter().map(|r| r.parse()).collect::<Result<_>>()?
+    };
+    
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.output)?);
+    
+    for region in &regions {
+        let sequence = reader.fetch_region(region)?;
+        writeln!(out, ">{}", region)?;
+        for line in sequence.chunks(60) {
+            out.write_all(line)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+    
+    println!("✅ Extracted {} regions to: {}", regions.len(), args.output);
     
     Ok(())
 }
 
-async fn analyze_sequences(
-    args: AnalyzeArgs,
-    _engine: &DnaEngine,
-    _optimizer: &BinaryOptimizer,
-) -> Result<()> {
-    let start_time = Instant::now();
+async fn build_kmer_filter(args: BuildFilterArgs) -> Result<()> {
+    use kmer_filter::KmerBloomFilter;
     
-    println!("🔍 DNA SEQUENCE ANALYSIS");
+    println!("🧱 BUILDING K-MER FILTER");
     println!("========================");
-    println!("📊 Input file: {}", args.input);
-    println!("🧬 Analysis type: {}", args.analysis_type);
-    println!("🔬 Deep analysis: {}", args.deep);
+    println!("🧬 Reference: {}", args.reference);
+    println!("🔢 K-mer size: {}", args.kmer_size);
+    println!("🎯 Target false-positive rate: {}", args.fpr);
     println!();
     
-    // Read and parse FASTA file
-    match std::fs::read_to_string(&args.input) {
-        Ok(content) => {
-            let sequences = parse_fasta(&content);
-            
-            if sequences.is_empty() {
-                println!("❌ No valid DNA sequences found in file");
-                return Ok(());
+    let start_time = Instant::now();
+    let filter = KmerBloomFilter::from_fasta(
+        std::path::Path::new(&args.reference),
+        args.kmer_size,
+        args.fpr,
+    )?;
+    filter.save(std::path::Path::new(&args.output))?;
+    
+    println!("✅ Filter built in {:.2}s", start_time.elapsed().as_secs_f64());
+    println!("   🧬 K-mers inserted: {}", filter.inserted());
+    println!("   💾 Size: {:.1} MB ({} hash functions)",
+        filter.num_bits() as f64 / 8.0 / 1_048_576.0, filter.num_hashes());
+    println!("   🎯 Estimated false-positive rate: {:.2e}", filter.estimated_false_positive_rate());
+    println!("📁 Saved to: {}", args.output);
+    
+    Ok(())
+}
+
+async fn quality_report(args: QcArgs) -> Result<()> {
+    use binary_optimizer::{QualityAccumulator, SimdProcessor};
+    use sequencer::{FastqReader, FastqRecord};
+    use std::io::Write;
+    
+    let start_time = Instant::now();
+    
+    println!("🔬 READ QUALITY REPORT");
+    println!("======================");
+    println!("📂 Input: {}", args.input);
+    println!();
+    
+    let mut reader = FastqReader::open(&args.input)?;
+    let mut accumulator = QualityAccumulator::new();
+    let mut base_counts = [0u64; 4];
+    let mut other_bases = 0u64;
+    let mut reads = 0usize;
+    let mut batch: Vec<FastqRecord> = Vec::with_capacity(10_000);
+    
+    let mut flush = |batch: &mut Vec<FastqRecord>| {
+        let qualities: Vec<&[u8]> = batch.iter().map(|r| r.quality.as_slice()).collect();
+        accumulator.add_reads(&qualities);
+        let codes: Vec<u8> = batch
+            .iter()
+            .flat_map(|r| r.sequence.iter().copied())
+            .filter_map(kmer_filter::base_code)
+            .collect();
+        let bases: usize = batch.iter().map(|r| r.sequence.len()).sum();
+        for (total, count) in base_counts.iter_mut().zip(SimdProcessor::count_bases_simd(&codes)) {
+            *total += count as u64;
+        }
+        other_bases += (bases - codes.len()) as u64;
+        reads += batch.len();
+        batch.clear();
+    };
+    while let Some(record) = reader.next_record()? {
+        batch.push(record);
+        if batch.len() == batch.capacity() {
+            flush(&mut batch);
+        }
+    }
+    flush(&mut batch);
+    let stats = accumulator.finish();
+    
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.output)
+        .with_context(|| format!("Failed to create output file: {}", args.output))?);
+    writeln!(out, "position\treads\tmean\tmedian\tq1\tq3")?;
+    for position in &stats.per_position {
+        writeln!(out, "{}\t{}\t{:.2}\t{}\t{}\t{}",
+            position.position + 1, position.count, position.mean, position.median, position.q1, position.q3)?;
+    }
+    out.flush()?;
+    
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.histogram)
+        .with_context(|| format!("Failed to create output file: {}", args.histogram))?);
+    writeln!(out, "phred\tbases")?;
+    for (phred, count) in stats.histogram.iter().enumerate().filter(|(_, &count)| count > 0) {
+        writeln!(out, "{}\t{}", phred, count)?;
+    }
+    out.flush()?;
+    
+    // 2-bit codes: A=0, T=1, G=2, C=3
+    let total_bases = (base_counts.iter().sum::<u64>() + other_bases).max(1) as f64;
+    let percent = |count: u64| count as f64 / total_bases * 100.0;
+    let processing_time = start_time.elapsed();
+    println!("🎉 QUALITY REPORT COMPLETE!");
+    println!("✅ {} reads, {} bases in {}ms", reads, stats.total_bases, processing_time.as_millis());
+    println!("🧬 A: {:.1}%, T: {:.1}%, G: {:.1}%, C: {:.1}%, N/other: {:.2}%",
+        percent(base_counts[0]), percent(base_counts[1]), percent(base_counts[2]), percent(base_counts[3]),
+        percent(other_bases));
+    println!("📊 GC content: {:.1}%", percent(base_counts[2] + base_counts[3]));
+    println!("📈 Phred: mean {:.1}, median {}, quartiles {}-{}, range {}-{}",
+        stats.mean, stats.median, stats.q1, stats.q3, stats.min, stats.max);
+    println!("   ≥Q20: {:.2}%  ≥Q30: {:.2}%", stats.q20_fraction * 100.0, stats.q30_fraction * 100.0);
+    println!("💾 Per-position quality saved to: {}", args.output);
+    println!("💾 Phred histogram saved to: {}", args.histogram);
+    
+    Ok(())
+}
+
+async fn screen_reads(args: ScreenArgs) -> Result<()> {
+    use kmer_filter::{KmerBloomFilter, ReadClass, ReadScreener};
+    use sequencer::{SequenceReader, SequenceRecord};
+    use std::io::Write;
+    
+    println!("🧹 READ SCREENING");
+    println!("=================");
+    println!("📂 Input: {}", args.input);
+    
+    if args.host.is_none() && args.contaminant.is_none() {
+        return Err(anyhow::anyhow!("Provide at least one of --host or --contaminant"));
+    }
+    
+    let load = |path: &Option<String>, label: &str| -> Result<Option<KmerBloomFilter>> {
+        path.as_ref()
+            .map(|p| {
+                println!("🧱 {} filter: {}", label, p);
+                KmerBloomFilter::load(std::path::Path::new(p))
+            })
+            .transpose()
+    };
+    let screener = ReadScreener::new(
+        load(&args.host, "Host")?,
+        load(&args.contaminant, "Contaminant")?,
+        args.min_fraction,
+    );
+    println!();
+    
+    let create = |path: &str| -> Result<std::io::BufWriter<std::fs::File>> {
+        Ok(std::io::BufWriter::new(std::fs::File::create(path)?))
+    };
+    let mut clean_out = create(&args.output)?;
+    let mut host_out = args.host_output.as_deref().map(create).transpose()?;
+    let mut contaminant_out = args.contaminant_output.as_deref().map(create).transpose()?;
+    
+    // Each read is written back in the format it was read in, whatever the file is called
+    let write_record = |out: &mut dyn Write, read: &SequenceRecord| -> Result<()> {
+        let sequence = String::from_utf8_lossy(&read.sequence);
+        match &read.quality {
+            Some(quality) => {
+                let quality: String = quality.iter().map(|&q| (q + 33) as char).collect();
+                writeln!(out, "@{}\n{}\n+\n{}", read.name, sequence, quality)?;
             }
-            
-            println!("📊 Found {} sequences", sequences.len());
-            println!();
-            
-            for (i, (name, seq)) in sequences.iter().enumerate() {
-                let stats = analyze_dna_sequence(seq);
-                
-                println!("🧬 Sequence {} ({})", i + 1, name);
-                println!("   Length: {} bases", stats.length);
-                println!("   GC content: {:.1}%", stats.gc_content * 100.0);
-                println!("   AT content: {:.1}%", stats.at_content * 100.0);
-                println!("   A: {:.1}%, T: {:.1}%, G: {:.1}%, C: {:.1}%", 
-                    stats.base_composition.0 * 100.0,
-                    stats.base_composition.1 * 100.0,
-                    stats.base_composition.2 * 100.0,
-                    stats.base_composition.3 * 100.0);
-                
-                if args.deep {
-                    println!("   Complexity score: {:.2}", stats.complexity);
-                    println!("   Repeat regions: {}", stats.repeat_count);
-                }
-                println!();
+            None => writeln!(out, ">{}\n{}", read.name, sequence)?,
+        }
+        Ok(())
+    };
+    
+    let start_time = Instant::now();
+    let mut counts = [0usize; 3];
+    let mut batch: Vec<SequenceRecord> = Vec::with_capacity(10_000);
+    
+    let mut flush = |batch: &mut Vec<SequenceRecord>| -> Result<()> {
+        let classes: Vec<ReadClass> = batch
+            .par_iter()
+            .map(|read| screener.classify(&read.sequence))
+            .collect();
+        for (read, class) in batch.iter().zip(classes) {
+            let out: Option<&mut dyn Write> = match class {
+                ReadClass::Clean => Some(&mut clean_out),
+                ReadClass::Host => host_out.as_mut().map(|w| w as &mut dyn Write),
+                ReadClass::Contaminant => contaminant_out.as_mut().map(|w| w as &mut dyn Write),
+            };
+            counts[class as usize] += 1;
+            if let Some(out) = out {
+                write_record(out, read)?;
             }
-            
-            let processing_time = start_time.elapsed();
-            println!("✅ Analysis completed in {:.2}ms", processing_time.as_millis());
         }
-        Err(_) => {
-            println!("❌ Could not read file: {}", args.input);
-            println!("💡 Try using one of the sample files:");
-            println!("   sample_data/sample_dna.fasta");
-            println!("   sample_data/test_sequences.fasta");
+        batch.clear();
+        Ok(())
+    };
+    
+    // Quality and length filtering is left to upstream tools; every read is classified
+    let mut reader = SequenceReader::open(&args.input)?;
+    while let Some(read) = reader.next_record()? {
+        batch.push(read);
+        if batch.len() == batch.capacity() {
+            flush(&mut batch)?;
         }
     }
+    flush(&mut batch)?;
+    
+    clean_out.flush()?;
+    if let Some(out) = host_out.as_mut() {
+        out.flush()?;
+    }
+    if let Some(out) = contaminant_out.as_mut() {
+        out.flush()?;
+    }
+    
+    let total: usize = counts.iter().sum();
+    let percent = |n: usize| n as f64 / total.max(1) as f64 * 100.0;
+    println!("🎉 SCREENING COMPLETE!");
+    println!("✅ Classified {} reads in {:.2}s", total, start_time.elapsed().as_secs_f64());
+    println!("   🧑 Host: {} ({:.2}%)", counts[ReadClass::Host as usize], percent(counts[ReadClass::Host as usize]));
+    println!("   🦠 Contaminant: {} ({:.2}%)",
+        counts[ReadClass::Contaminant as usize], percent(counts[ReadClass::Contaminant as usize]));
+    println!("   ✨ Clean: {} ({:.2}%)", counts[ReadClass::Clean as usize], percent(counts[ReadClass::Clean as usize]));
+    println!("📁 Clean reads saved to: {}", args.output);
     
     Ok(())
 }
 
-async fn compare_sequences(
-    args: CompareArgs,
-    _engine: &DnaEngine,
-    _optimizer: &BinaryOptimizer,
-) -> Result<()> {
-    let start_time = Instant::now();
+async fn multiple_alignment(args: MsaArgs) -> Result<()> {
+    use alignment::ScoringScheme;
+    use msa::ProgressiveAligner;
     
-    println!("⚖️ SEQUENCE COMPARISON WITH INSTANT DNA");
-    println!("======================================");
-    println!("📊 Sequence 1: {}", args.seq1);
-    println!("📊 Sequence 2: {}", args.seq2);
-    println!("🧮 Algorithm: {}", args.algorithm);
+    println!("🧩 MULTIPLE SEQUENCE ALIGNMENT");
+    println!("==============================");
+    println!("📂 Input: {}", args.input);
+    println!("📁 Output: {} ({})", args.output, args.format);
     println!();
     
-    tokio::time::sleep(tokio::time::Duration::from_millis(15)).await;
+    let start_time = Instant::now();
+    let content = std::fs::read_to_string(&args.input)
+        .with_context(|| format!("Could not read sequence file: {}", args.input))?;
+    let sequences = parse_residue_fasta(&content);
+    if sequences.len() < 2 {
+        return Err(anyhow::anyhow!("Need at least two sequences to align, found {}", sequences.len()));
+    }
+    println!("🧬 Aligning {} sequences (k-mer guide tree, k = {})", sequences.len(), args.kmer_size);
+    
+    let aligner = ProgressiveAligner::new(
+        ScoringScheme {
+            match_score: args.match_score,
+            mismatch: args.mismatch,
+            gap_open: args.gap_open,
+            gap_extend: args.gap_extend,
+            matrix: None,
+        },
+        args.kmer_size,
+    );
+    let (alignment, tree) = aligner.align(&sequences)?;
     
-    let processing_time = start_time.elapsed();
-    let similarity = 0.9547;
+    let formatted = match args.format.to_lowercase().as_str() {
+        "fasta" | "afa" => alignment.to_fasta(),
+        "clustal" | "aln" => alignment.to_clustal(),
+        "stockholm" | "sto" => alignment.to_stockholm(),
+        other => return Err(anyhow::anyhow!("Unknown MSA format: {} (use fasta, clustal or stockholm)", other)),
+    };
+    std::fs::write(&args.output, formatted)?;
+    
+    let consensus = alignment.consensus();
+    let conservation = alignment.conservation();
+    let mean_conservation = conservation.iter().sum::<f64>() / conservation.len().max(1) as f64;
+    
+    println!("🎉 ALIGNMENT COMPLETE!");
+    println!("✅ {} sequences, {} columns in {}ms",
+        alignment.rows.len(), alignment.columns(), start_time.elapsed().as_millis());
+    println!("🔒 Identical columns: {} ({:.1}%)", alignment.identical_columns(),
+        alignment.identical_columns() as f64 / alignment.columns().max(1) as f64 * 100.0);
+    println!("📊 Mean conservation: {:.3}", mean_conservation);
+    println!("💾 Alignment saved to: {}", args.output);
+    
+    if let Some(path) = &args.consensus {
+        let ungapped: Vec<u8> = consensus.iter().copied().filter(|&b| b != b'-').collect();
+        let mut fasta = String::from(">consensus\n");
+        for line in ungapped.chunks(60) {
+            fasta.push_str(&String::from_utf8_lossy(line));
+            fasta.push('\n');
+        }
+        std::fs::write(path, fasta)?;
+        println!("💾 Consensus saved to: {}", path);
+    }
     
-    println!("🎉 COMPARISON COMPLETE!");
-    println!("✅ Comparison complete in {:.2}ms", processing_time.as_millis());
-    println!("📊 Similarity score: {:.4} ({:.1}%)", similarity, similarity * 100.0);
+    if let Some(path) = &args.conservation {
+        let mut table = String::from("column\tconsensus\tconservation\tgap_fraction\n");
+        for (column, (&residue, score)) in consensus.iter().zip(&conservation).enumerate() {
+            table.push_str(&format!("{}\t{}\t{:.4}\t{:.4}\n",
+                column + 1, residue as char, score, alignment.gap_fraction(column)));
+        }
+        std::fs::write(path, table)?;
+        println!("💾 Conservation scores saved to: {}", path);
+    }
     
-    if similarity >= args.similarity {
-        println!("✨ Sequences are HIGHLY similar!");
+    if let Some(path) = &args.guide_tree {
+        std::fs::write(path, tree.to_newick(&alignment.names) + "\n")?;
+        println!("🌳 Guide tree saved to: {}", path);
     }
     
     Ok(())
 }
 
-async fn call_variants(
-    args: VariantArgs,
-    _engine: &DnaEngine,
-    _optimizer: &BinaryOptimizer,
-) -> Result<()> {
-    let start_time = Instant::now();
+async fn map_reads(args: MapArgs, threads: usize) -> Result<()> {
+    use mapper::{InsertSizeModel, MapParams, ReadMapper, Reference};
+    use rust_htslib::bam;
+    use sequencer::{FastqReader, FastqRecord};
     
-    println!("🎯 VARIANT CALLING WITH INSTANT DNA");
-    println!("==================================");
-    println!("📊 Input: {}", args.input);
+    const BATCH_SIZE: usize = 20_000;
+    
+    println!("🧭 READ MAPPING");
+    println!("===============");
     println!("🧬 Reference: {}", args.reference);
-    println!("📈 Min coverage: {}", args.coverage);
+    match &args.reads2 {
+        Some(reads2) => println!("📂 Reads: {} + {} (paired-end)", args.reads1, reads2),
+        None => println!("📂 Reads: {} (single-end)", args.reads1),
+    }
+    println!("📁 Output: {}", args.output);
     println!();
     
-    tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+    let start_time = Instant::now();
+    let reference = Reference::from_fasta(std::path::Path::new(&args.reference))?;
+    let params = MapParams {
+        kmer_size: args.kmer_size,
+        window: args.window,
+        min_score: args.min_score,
+        ..MapParams::default()
+    };
+    let mapper = ReadMapper::new(reference, params);
+    println!("🗂️ Indexed {} sequences ({} bp) in {:.2}s",
+        mapper.reference().names.len(), mapper.reference().total_length(), start_time.elapsed().as_secs_f64());
+    
+    let command_line: Vec<String> = std::env::args().collect();
+    let header = mapper.reference().sam_header(&command_line.join(" "), args.sort);
+    let sam_output = args.output.ends_with(".sam");
+    let format = if sam_output { bam::Format::Sam } else { bam::Format::Bam };
+    let mut writer = bam::Writer::from_path(&args.output, &header, format)
+        .with_context(|| format!("Could not create output file: {}", args.output))?;
+    if !sam_output && threads > 1 {
+        writer.set_threads(threads - 1)?;
+    }
     
-    let processing_time = start_time.elapsed();
-    let variants = vec!["chr1:12345 A>G", "chr2:67890 T>C", "chr3:11111 INS:ATG"];
+    let mut reader1 = FastqReader::open(&args.reads1)?;
+    let mut reader2 = args.reads2.as_deref().map(FastqReader::open).transpose()?;
+    let (mut reads, mut mapped, mut proper) = (0usize, 0usize, 0usize);
+    let mut model: Option<InsertSizeModel> = None;
+    let mut sorted: Vec<bam::Record> = Vec::new();
+    
+    loop {
+        let mut batch: Vec<(FastqRecord, Option<FastqRecord>)> = Vec::with_capacity(BATCH_SIZE);
+        while batch.len() < BATCH_SIZE {
+            let Some(mut first) = reader1.next_record()? else { break };
+            // Matching is case-insensitive; soft-masked reads are aligned as plain bases
+            first.sequence.make_ascii_uppercase();
+            let second = match reader2.as_mut() {
+                Some(reader) => {
+                    let Some(mut second) = reader.next_record()? else {
+                        return Err(anyhow::anyhow!("{} has more reads than its mate file", args.reads1));
+                    };
+                    if mapper::read_name(&first.name) != mapper::read_name(&second.name) {
+                        return Err(anyhow::anyhow!("Mate names differ: {} vs {}", first.name, second.name));
+                    }
+                    second.sequence.make_ascii_uppercase();
+                    Some(second)
+                }
+                None => None,
+            };
+            batch.push((first, second));
+        }
+        if batch.is_empty() {
+            break;
+        }
+        
+        let hits: Vec<_> = batch
+            .par_iter()
+            .map(|(first, second)| {
+                (mapper.map_read(&first.sequence), second.as_ref().map(|s| mapper.map_read(&s.sequence)))
+            })
+            .collect();
+        
+        let records: Vec<bam::Record> = if reader2.is_some() {
+            // Estimate the insert-size distribution from uniquely placed pairs in the first batch
+            let model = *model.get_or_insert_with(|| {
+                let sizes: Vec<usize> = hits
+                    .iter()
+                    .filter_map(|(h1, h2)| mapper::unique_insert_size(h1, h2.as_deref().unwrap_or(&[])))
+                    .collect();
+                let estimate = InsertSizeModel::estimate(&sizes);
+                match &estimate {
+                    Some(m) => println!("📏 Insert size: {:.1} ± {:.1} bp (from {} pairs, proper range {}-{})",
+                        m.mean, m.std_dev, m.pairs, m.low, m.high),
+                    None => println!("⚠️ Too few unique pairs to estimate insert size; assuming 500 ± 150 bp"),
+                }
+                estimate.unwrap_or_default()
+            });
+            
+            let placed: Vec<_> = batch
+                .par_iter()
+                .zip(hits)
+                .map(|((first, second), (hits1, hits2))| {
+                    let second = second.as_ref().expect("paired batch");
+                    let placement = mapper.pair_hits(hits1, hits2.unwrap_or_default(),
+                        &first.sequence, &second.sequence, &model);
+                    (placement.proper, mapper.pair_records(first, second, &placement))
+                })
+                .collect();
+            let mut records = Vec::with_capacity(placed.len() * 2);
+            for (is_proper, pair) in placed {
+                if is_proper {
+                    proper += 2;
+                }
+                records.extend(pair?);
+            }
+            records
+        } else {
+            batch
+                .par_iter()
+                .zip(hits)
+                .map(|((read, _), (read_hits, _))| mapper.single_record(read, &read_hits))
+                .collect::<Result<_>>()?
+        };
+        
+        for record in &records {
+            reads += 1;
+            if !record.is_unmapped() {
+                mapped += 1;
+            }
+            if !args.sort {
+                writer.write(record)?;
+            }
+        }
+        if args.sort {
+            sorted.extend(records);
+        }
+        info!("Mapped {} reads", reads);
+    }
+    if let (Some(reader), Some(reads2)) = (reader2.as_mut(), &args.reads2) {
+        if reader.next_record()?.is_some() {
+            return Err(anyhow::anyhow!("{} has more reads than its mate file", reads2));
+        }
+    }
     
-    println!("🎉 VARIANT CALLING COMPLETE!");
-    println!("✅ Found {} variants in {:.2}ms", variants.len(), processing_time.as_millis());
+    if args.sort {
+        // Unmapped reads without a placed mate have tid -1 and sort last
+        sorted.sort_by_key(|record| (record.tid() as u32, record.pos()));
+        for record in &sorted {
+            writer.write(record)?;
+        }
+        drop(writer);
+        if !sam_output {
+            let longest = mapper.reference().sequences.iter().map(|s| s.len()).max().unwrap_or(0);
+            // BAI cannot address positions past 2^29; CSI covers longer contigs
+            let index_type = if longest < 1 << 29 { bam::index::Type::Bai } else { bam::index::Type::Csi(14) };
+            bam::index::build(&args.output, None, index_type, threads as u32)
+                .with_context(|| format!("Could not index {}", args.output))?;
+        }
+    }
     
-    for variant in &variants {
-        println!("📍 {}", variant);
+    println!("🎉 MAPPING COMPLETE!");
+    println!("✅ {} reads in {:.2}s", reads, start_time.elapsed().as_secs_f64());
+    println!("🎯 Mapped: {} ({:.1}%)", mapped, mapped as f64 / reads.max(1) as f64 * 100.0);
+    if reader2.is_some() {
+        println!("👫 Properly paired: {} ({:.1}%)", proper, proper as f64 / reads.max(1) as f64 * 100.0);
     }
+    println!("💾 Alignments saved to: {}{}", args.output,
+        if args.sort { " (coordinate-sorted)" } else { "" });
     
     Ok(())
 }
 
-async fn assemble_genome(
-    args: AssembleArgs,
-    _engine: &DnaEngine,
-    _optimizer: &BinaryOptimizer,
-) -> Result<()> {
-    let start_time = Instant::now();
+async fn identity_matrix(args: MatrixArgs, optimizer: &BinaryOptimizer) -> Result<()> {
+    use binary_optimizer::SimilarityMetric;
+    use identity_matrix::PairwiseMatrix;
     
-    println!("🧩 GENOME ASSEMBLY WITH INSTANT DNA");
-    println!("==================================");
-    println!("📊 Input: {}", args.input);
-    println!("🔢 K-mer size: {}", args.kmer_size);
-    println!("🧮 Algorithm: {}", args.algorithm);
-    println!();
+    let metric: SimilarityMetric = args.metric.parse()?;
     
-    tokio::time::sleep(tokio::time::Duration::from_millis(30)).await;
+    println!("🔢 ALL-VS-ALL IDENTITY MATRIX");
+    println!("=============================");
+    println!("📂 Input: {}", args.input);
+    println!("📏 Metric: {}", metric);
+    println!();
     
-    let processing_time = start_time.elapsed();
-    let contigs = vec!["contig_1", "contig_2", "contig_3"];
+    let start_time = Instant::now();
+    let content = std::fs::read_to_string(&args.input)
+        .with_context(|| format!("Could not read sequence file: {}", args.input))?;
+    let sequences = parse_residue_fasta(&content);
+    let pairs = sequences.len() * sequences.len().saturating_sub(1) / 2;
+    println!("🧬 Comparing {} sequences ({} pairs)", sequences.len(), pairs);
+    
+    let matrix = PairwiseMatrix::compute(&sequences, metric, optimizer)?;
+    std::fs::write(&args.output, matrix.to_csv())?;
+    
+    let n = matrix.names.len();
+    let off_diagonal: Vec<(usize, usize, f64)> = (0..n)
+        .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+        .map(|(i, j)| (i, j, matrix.similarity[i][j]))
+        .collect();
+    let mean = off_diagonal.iter().map(|&(_, _, s)| s).sum::<f64>() / off_diagonal.len() as f64;
+    let (min_i, min_j, min) = off_diagonal.iter().copied().fold((0, 0, f64::INFINITY), |a, b| if b.2 < a.2 { b } else { a });
+    let (max_i, max_j, max) = off_diagonal.iter().copied().fold((0, 0, f64::NEG_INFINITY), |a, b| if b.2 > a.2 { b } else { a });
+    
+    println!("🎉 MATRIX COMPLETE!");
+    println!("✅ {} pairs in {}ms", pairs, start_time.elapsed().as_millis());
+    println!("📊 Mean similarity: {:.4}", mean);
+    println!("🔼 Most similar: {} / {} ({:.4})", matrix.names[max_i], matrix.names[max_j], max);
+    println!("🔽 Least similar: {} / {} ({:.4})", matrix.names[min_i], matrix.names[min_j], min);
+    println!("💾 Matrix saved to: {}", args.output);
+    
+    if let Some(path) = &args.phylip {
+        std::fs::write(path, matrix.to_phylip())?;
+        println!("💾 PHYLIP distances saved to: {}", path);
+    }
     
-    println!("🎉 ASSEMBLY COMPLETE!");
-    println!("✅ Generated {} contigs in {:.2}ms", contigs.len(), processing_time.as_millis());
-    println!("📏 Total assembled length: 1,234,567 bp");
+    if let Some(path) = &args.svg {
+        std::fs::write(path, matrix.to_heatmap_svg())?;
+        println!("🎨 Heatmap saved to: {}", path);
+    }
     
     Ok(())
 }
 
-async fn analyze_rna(
-    args: RnaArgs,
-    _engine: &DnaEngine,
-    _optimizer: &BinaryOptimizer,
-) -> Result<()> {
+async fn joint_genotype(args: JointArgs) -> Result<()> {
     let start_time = Instant::now();
     
-    println!("🧬 RNA ANALYSIS WITH INSTANT DNA");
-    println!("===============================");
-    println!("📊 Input: {}", args.input);
-    println!("🔬 Analysis type: {}", args.analysis_type);
-    println!("🌡️ Temperature: {:.1}°C", args.temperature);
+    println!("👪 JOINT GENOTYPING WITH INSTANT DNA");
+    println!("===================================");
+    println!("📊 Inputs: {} gVCFs", args.inputs.len());
+    println!("🎯 Min QUAL: {}", args.min_quality);
     println!();
     
-    tokio::time::sleep(tokio::time::Duration::from_millis(18)).await;
+    let samples = args
+        .inputs
+        .iter()
+        .map(|path| gvcf::SampleGvcf::load(std::path::Path::new(path)))
+        .collect::<Result<Vec<_>>>()?;
+    for sample in &samples {
+        println!("🧬 {}: {} records", sample.sample, sample.records.len());
+    }
+    let cohort = gvcf::joint_genotype(&samples, args.min_quality)?;
+    cohort.write_vcf(&args.output, "joint")?;
     
     let processing_time = start_time.elapsed();
-    
-    println!("🎉 RNA ANALYSIS COMPLETE!");
-    println!("✅ Analysis complete in {:.2}ms", processing_time.as_millis());
-    println!("🧬 Structure: ((((....))))...((((....))))");
+    println!();
+    println!("🎉 JOINT GENOTYPING COMPLETE!");
+    println!("✅ Genotyped {} sites across {} samples in {}ms",
+        cohort.sites.len(), cohort.samples.len(), processing_time.as_millis());
+    println!("💾 Cohort VCF saved to: {}", args.output);
     
     Ok(())
 }
 
-async fn monitor_sequences(
-    args: MonitorArgs,
-    _engine: &DnaEngine,
-    _optimizer: &BinaryOptimizer,
-) -> Result<()> {
-    println!("👁️ REAL-TIME MONITORING WITH INSTANT DNA");
-    println!("========================================");
-    println!("📁 Directory: {}", args.directory);
-    println!("⏱️ Interval: {}ms", args.interval);
-    println!();
+async fn filter_vcf(args: FilterArgs) -> Result<()> {
+    let start_time = Instant::now();
     
-    // Simulation of monitoring
-    for i in 1..=5 {
-        tokio::time::sleep(tokio::time::Duration::from_millis(args.interval)).await;
-        println!("📊 Scan #{}: Monitoring directory...", i);
+    println!("🧹 VCF FILTERING WITH INSTANT DNA");
+    println!("================================");
+    println!("📊 Input: {}", args.input);
+    println!("🔎 Expression: {}", args.expression);
+    match &args.soft_filter {
+        Some(name) => println!("🏷️  Soft filter: failing records marked {}", name),
+        None => println!("✂️  Hard filter: failing records removed"),
     }
+    println!();
+    
+    let filter: vcf_filter::FilterExpression = args.expression.parse()?;
+    let mut processor = VCFProcessor::new();
+    processor.parse_vcf(std::path::Path::new(&args.input))
+        .context("Failed to parse VCF file")?;
+    let summary = vcf_filter::apply_filter(&mut processor, &filter, args.soft_filter.as_deref());
+    processor.write_vcf(std::path::Path::new(&args.output))?;
+    
+    let processing_time = start_time.elapsed();
+    println!();
+    println!("🎉 FILTERING COMPLETE!");
+    println!("✅ {} records passed, {} failed in {}ms",
+        summary.passed, summary.failed, processing_time.as_millis());
+    println!("💾 Filtered VCF saved to: {}", args.output);
     
     Ok(())
 }
 
-async fn run_benchmark(
-    args: BenchmarkArgs,
-    _engine: &DnaEngine,
-    _optimizer: &BinaryOptimizer,
-) -> Result<()> {
-    println!("🏁 DNA ANALYSIS COMPETITIVE BENCHMARK");
+async fn normalize_vcf(args: NormArgs) -> Result<()> {
+    use vcf_norm::{MultiallelicMode, NormParams, Normalizer};
+    
+    let start_time = Instant::now();
+    
+    println!("📐 VCF NORMALIZATION WITH INSTANT DNA");
     println!("====================================");
-    println!("� Test data path: {}", args.data_path);
-    println!("🔄 Iterations per tool: {}", args.iterations);
-    println!("🔧 Compare competitors: {}", if args.competitors { "Yes" } else { "No" });
+    println!("📊 Input: {}", args.input);
+    match &args.reference {
+        Some(reference) => println!("🧬 Reference: {}", reference),
+        None => println!("⚠️  No reference: indels will not be left-aligned"),
+    }
     println!();
-
-    // Create benchmark data if it doesn't exist
-    create_benchmark_data(&args.data_path).await?;
-
-    // Initialize benchmark suite
-    let mut benchmark = benchmark::BenchmarkSuite::new(
-        args.data_path.clone(), 
-        args.iterations
-    );
-
-    println!("🚀 Starting comprehensive benchmarks...");
+    
+    let multiallelics = args.multiallelics.as_deref().map(str::parse::<MultiallelicMode>).transpose()?;
+    let mut normalizer = Normalizer::new(args.reference.as_deref())?.with_params(NormParams {
+        multiallelics,
+        remove_duplicates: args.rm_dup,
+    });
+    let mut processor = VCFProcessor::new();
+    processor.parse_vcf(std::path::Path::new(&args.input))
+        .context("Failed to parse VCF file")?;
+    let summary = normalizer.normalize(&mut processor)?;
+    processor.write_vcf(std::path::Path::new(&args.output))?;
+    
+    let processing_time = start_time.elapsed();
     println!();
-
-    if let Err(e) = benchmark.run_all_benchmarks() {
-        eprintln!("❌ Benchmark error: {}", e);
-        return Ok(());
-    }
-
-    let report = benchmark.generate_report();
-    println!("{}", report);
-
-    if args.report {
-        if let Err(e) = benchmark.save_report(&args.output) {
-            eprintln!("❌ Failed to save report: {}", e);
-        } else {
-            println!("📄 Detailed report saved to: {}", args.output);
+    println!("🎉 NORMALIZATION COMPLETE!");
+    println!("✅ {} records in, {} out in {}ms",
+        summary.total, processor.variants.len(), processing_time.as_millis());
+    println!("↔️  Realigned: {}", summary.realigned);
+    if let Some(mode) = multiallelics {
+        match mode {
+            MultiallelicMode::Split => println!("✂️  Split: {} multiallelic records into {}", summary.split, summary.split_into),
+            MultiallelicMode::Join => println!("🔗 Joined: {} records merged into multiallelics", summary.joined),
         }
     }
-
-    println!("✅ Benchmark completed successfully!");
+    if args.rm_dup {
+        println!("🗑️  Duplicates removed: {}", summary.duplicates);
+    }
+    if summary.reference_mismatches > 0 {
+        println!("⚠️  REF mismatches left unchanged: {}", summary.reference_mismatches);
+    }
+    println!("💾 Normalized VCF saved to: {}", args.output);
+    
     Ok(())
 }
 
-async fn create_benchmark_data(data_path: &str) -> Result<()> {
-    use std::fs;
-    use std::path::Path;
-
-    let path = Path::new(data_path);
-    if !path.exists() {
-        fs::create_dir_all(path)?;
-        println!("📁 Created benchmark data directory: {}", data_path);
-    }
-
-    // Create test VCF file if it doesn't exist
-    let test_vcf_path = path.join("test_sample.vcf.gz");
-    if !test_vcf_path.exists() {
-        // Use the real 1000 Genomes data if available
-        let real_vcf = Path::new("real_snp_data/1000genomes/ALL.chr22.1000genomes.vcf.gz");
-        if real_vcf.exists() {
-            println!("🔗 Linking to real 1000 Genomes data for benchmarks");
-            std::os::unix::fs::symlink(real_vcf.canonicalize()?, &test_vcf_path)?;
-        } else {
-            println!("⚠️  Real VCF data not found. Download 1000 Genomes data first:");
-            println!("   Run: ./download_1000genomes.sh");
-            return Ok(());
-        }
+async fn call_copy_number(args: CnvArgs) -> Result<()> {
+    use copy_number::{CnvCall, CnvParams, CopyNumberCaller, MappabilityTrack};
+    
+    let start_time = Instant::now();
+    
+    println!("📈 COPY-NUMBER CALLING WITH INSTANT DNA");
+    println!("======================================");
+    println!("📊 Input: {}", args.input);
+    println!("🧬 Reference: {}", args.reference);
+    match args.controls.len() {
+        0 => println!("⚖️  Normalized against: the sample's own median depth"),
+        1 => println!("⚖️  Normalized against: matched control {}", args.controls[0]),
+        n => println!("⚖️  Normalized against: panel of {} normals", n),
     }
-
-    // Create reference panel if it doesn't exist
-    let panel_path = path.join("reference_panel.txt");
-    if !panel_path.exists() {
-        let real_panel = Path::new("real_snp_data/1000genomes/integrated_call_samples_v3.20130502.ALL.panel");
-        if real_panel.exists() {
-            std::os::unix::fs::symlink(real_panel.canonicalize()?, &panel_path)?;
-        }
+    println!();
+    
+    let params = CnvParams {
+        bin_size: args.bin_size,
+        min_mapping_quality: args.min_mapping_quality,
+        method: args.method.parse()?,
+        gain_threshold: args.gain_threshold,
+        loss_threshold: args.loss_threshold,
+        ploidy: args.ploidy,
+        ..CnvParams::default()
+    };
+    let mut caller = CopyNumberCaller::new(&args.reference).with_params(params);
+    if let Some(path) = &args.mappability {
+        caller = caller.with_mappability(MappabilityTrack::load(path)?);
     }
-
-    // Create test FASTA file
-    let fasta_path = path.join("benchmark_sequence.fasta");
-    if !fasta_path.exists() {
-        let test_fasta = ">test_sequence\nATGCGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTA\nCGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGT\n";
-        fs::write(&fasta_path, test_fasta)?;
+    let profile = caller.call(&args.input, &args.controls)?;
+    profile.write_seg(&args.output)?;
+    profile.write_bed(&args.bed)?;
+    std::fs::write(&args.plot, profile.to_svg())?;
+    
+    let processing_time = start_time.elapsed();
+    println!("🎉 COPY-NUMBER CALLING COMPLETE!");
+    println!("✅ {} segments ({} gains, {} losses) in {}ms",
+        profile.segments.len(), profile.count(CnvCall::Gain), profile.count(CnvCall::Loss), processing_time.as_millis());
+    println!("📦 Sample {}: {} bins of {} bp ({} masked), median {:.0} reads per bin",
+        profile.sample, profile.bin_count(), params.bin_size, profile.masked_bins, profile.median_reads);
+    println!("〰️  {} segmentation against the {}, log2 noise {:.3}", params.method, profile.normalization(), profile.noise);
+    for segment in profile.calls().take(20) {
+        println!("📍 {}", segment);
     }
-
+    let calls = profile.calls().count();
+    if calls > 20 {
+        println!("   ... and {} more", calls - 20);
+    }
+    println!("💾 Segments saved to: {}", args.output);
+    println!("💾 Gains and losses saved to: {}", args.bed);
+    println!("💾 Plot saved to: {}", args.plot);
+    
     Ok(())
 }
 
-async fn show_status(_engine: &DnaEngine) -> Result<()> {
-    println!("🧬 INSTANT DNA - SYSTEM STATUS");
-    println!("==============================");
-    println!("💻 CPU Cores: {}", num_cpus::get());
-    println!("🧵 Active Threads: {}", rayon::current_num_threads());
-    println!("⚡ SIMD Support: Available");
-    println!("🧬 DNA Engine: Ready");
-    println!("📊 Binary Optimizer: Active");
-    println!("🎯 Version: 2.0.0");
-    println!("👨‍💻 Creator: Richie Looney");
-    println!("� Status: Ready for DNA analysis");
+async fn compute_coverage(args: CoverageArgs) -> Result<()> {
+    use coverage::{load_targets, CoverageCalculator, CoverageParams};
+    
+    let start_time = Instant::now();
+    
+    println!("📏 COVERAGE STATISTICS WITH INSTANT DNA");
+    println!("======================================");
+    println!("📊 Input: {}", args.input);
+    match &args.targets {
+        Some(path) => println!("🎯 Targets: {}", path),
+        None => println!("🎯 Targets: whole contigs"),
+    }
     println!();
-    println!("� Supported formats: FASTA");
-    println!("🧬 Analysis types: Basic sequence stats, GC content, composition");
-    println!("⚡ Performance: Optimized for multi-core processing");
-    println!("🗄️ Database Support: VCF files, 1000 Genomes, population panels");
+    
+    let targets = match &args.targets {
+        Some(path) => Some((path.as_str(), load_targets(std::path::Path::new(path))?)),
+        None => None,
+    };
+    let calculator = CoverageCalculator::new().with_params(CoverageParams {
+        min_mapping_quality: args.min_mapping_quality,
+        thresholds: args.thresholds.clone(),
+    });
+    let report = calculator.compute(&args.input, targets, &args.bedgraph)?;
+    report.write_targets(&args.per_target)?;
+    report.write_summary(&args.output)?;
+    
+    let processing_time = start_time.elapsed();
+    println!("🎉 COVERAGE COMPLETE!");
+    println!("✅ {} targets, {} bases in {}ms",
+        report.targets.len(), report.overall.total(), processing_time.as_millis());
+    println!("🧬 Sample {}: {} reads used, {} filtered", report.sample, report.reads_used, report.reads_filtered);
+    println!("📈 Depth: mean {:.1}x, median {}x, max {}x",
+        report.overall.mean(), report.overall.median(), report.overall.max());
+    for &depth in &args.thresholds {
+        println!("   ≥{}x: {:.2}% of bases, {} targets with a lower mean",
+            depth, report.overall.percent_at_least(depth), report.targets_below(depth));
+    }
+    println!("💾 Per-base depth saved to: {}", args.bedgraph);
+    println!("💾 Per-target statistics saved to: {}", args.per_target);
+    println!("💾 Summary saved to: {}", args.output);
     
     Ok(())
 }
 
-async fn process_vcf(args: VcfArgs) -> Result<()> {
-    println!("🧬 Processing VCF file: {}", args.input);
-    println!("{}", "=".repeat(50));
+async fn build_consensus(args: ConsensusArgs) -> Result<()> {
+    use consensus::{ConsensusBuilder, ConsensusParams};
+    use mapper::Reference;
+    use seq_container::Region;
+    use vcf_processor::VCFProcessor;
     
-    let mut processor = VCFProcessor::new();
+    let start_time = Instant::now();
     
-    // Parse VCF file
-    processor.parse_vcf(std::path::Path::new(&args.input))
-        .context("Failed to parse VCF file")?;
+    println!("🧬 CONSENSUS SEQUENCE WITH INSTANT DNA");
+    println!("=====================================");
+    println!("📚 Reference: {}", args.reference);
+    println!("📄 Variants: {}", args.vcf);
+    println!("🧩 Mode: {}", if args.haplotypes { "per-haplotype" } else { "IUPAC" });
+    println!();
     
-    // Load population panel if provided
-    if let Some(panel_path) = &args.panel {
-        processor.parse_population_panel(std::path::Path::new(panel_path))
-            .context("Failed to parse population panel")?;
+    let regions: Vec<Region> = args.region.iter().map(|r| r.parse()).collect::<Result<_>>()?;
+    let reference = Reference::from_fasta(std::path::Path::new(&args.reference))?;
+    let mut vcf = VCFProcessor::new();
+    vcf.parse_vcf(std::path::Path::new(&args.vcf))?;
+    
+    let mut builder = ConsensusBuilder::new(reference).with_params(ConsensusParams {
+        haplotypes: args.haplotypes,
+        include_filtered: args.include_filtered,
+    });
+    if let Some(path) = &args.mask {
+        println!("🎭 Masking regions in {}", path);
+        builder = builder.with_mask(std::path::Path::new(path))?;
     }
-    
-    // Display statistics
-    processor.print_statistics();
-    
-    // Calculate allele frequencies if requested
-    if args.frequencies {
-        println!("\n📊 Calculating allele frequencies...");
-        
-        if let Some(pop) = &args.population {
-            println!("\n🧬 Allele frequencies for population: {}", pop);
-            for (i, variant) in processor.variants.iter().enumerate().take(10) {
-                if let Some(freq) = processor.calculate_allele_frequency(i, pop) {
-                    println!("   {}: {} → {} (freq: {:.3})", 
-                        variant.id, variant.reference, variant.alternative, freq);
-                }
-            }
-        } else {
-            println!("   ⚠️  Specify --population to calculate frequencies");
-        }
+    if let Some(path) = &args.depth {
+        println!("📉 Masking bases below {}x in {}", args.min_depth, path);
+        builder = builder.with_depth_mask(std::path::Path::new(path), args.min_depth)?;
     }
+    let consensus = builder.build(&vcf, args.sample.as_deref(), &regions)?;
+    consensus.write_fasta(&args.output)?;
+    consensus.write_chain(&args.chain)?;
     
-    // Save results if output specified
-    if let Some(output_path) = args.output {
-        println!("\n💾 Saving results to: {}", output_path);
-        // Implementation for saving results would go here
-        println!("   ✅ Results saved");
+    let stats = &consensus.stats;
+    let processing_time = start_time.elapsed();
+    println!("🎉 CONSENSUS COMPLETE!");
+    println!("✅ {} sequences, {} bases in {}ms",
+        consensus.sequences.len(),
+        consensus.sequences.iter().map(|s| s.sequence.len()).sum::<usize>(),
+        processing_time.as_millis());
+    if !consensus.sample.is_empty() {
+        println!("🧬 Sample: {}", consensus.sample);
     }
+    println!("🔁 Applied: {} substitutions, {} indels, {} IUPAC sites",
+        stats.substitutions, stats.indels, stats.iupac_sites);
+    println!("⏭️  Skipped {} records: {} filtered, {} missing GT, {} symbolic, {} het indels, {} overlapping, {} REF mismatch, {} masked",
+        stats.skipped(), stats.skipped_filtered, stats.skipped_missing, stats.skipped_symbolic,
+        stats.skipped_heterozygous_indels, stats.skipped_overlapping, stats.skipped_mismatched, stats.skipped_masked);
+    println!("🎭 Masked bases: {}", stats.masked_bases);
+    println!("💾 Consensus saved to: {}", args.output);
+    println!("💾 Chain saved to: {}", args.chain);
     
     Ok(())
 }
 
-async fn analyze_ancestry(args: AncestryArgs) -> Result<()> {
-    println!("🧬 Ancestry Analysis for sample: {}", args.sample);
-    println!("{}", "=".repeat(50));
-    
-    let mut processor = VCFProcessor::new();
+async fn evaluate_calls(args: EvalArgs) -> Result<()> {
+    use vcf_eval::{format_metric, EvalParams, RegionSet, VcfEvaluator};
+    use vcf_processor::VCFProcessor;
     
-    // Load VCF and population data
-    println!("📂 Loading VCF file: {}", args.vcf);
-    processor.parse_vcf(std::path::Path::new(&args.vcf))
-        .context("Failed to parse VCF file")?;
+    let start_time = Instant::now();
     
-    println!("🌍 Loading population panel: {}", args.panel);
-    processor.parse_population_panel(std::path::Path::new(&args.panel))
-        .context("Failed to parse population panel")?;
+    println!("🎯 VARIANT BENCHMARKING WITH INSTANT DNA");
+    println!("=======================================");
+    println!("🏆 Truth: {}", args.truth);
+    println!("📊 Query: {}", args.query);
+    println!("🧬 Reference: {}", args.reference);
+    println!("🧩 Matching: {}", if args.squash_ploidy { "alleles" } else { "genotypes" });
+    println!();
     
-    // Verify sample exists
-    if !processor.samples.contains(&args.sample) {
-        return Err(anyhow::anyhow!("Sample '{}' not found in VCF file", args.sample));
+    let mut evaluator = VcfEvaluator::new(&args.reference)?.with_params(EvalParams {
+        squash_ploidy: args.squash_ploidy,
+        include_filtered: args.all_records,
+    });
+    if !args.confident.is_empty() {
+        let confident = RegionSet::load(&args.confident)?;
+        println!("✅ Confident regions: {} bases", confident.total_length());
+        evaluator = evaluator.with_confident(confident);
+    }
+    for path in &args.stratify {
+        let name = std::path::Path::new(path.trim_end_matches(".gz"))
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.clone());
+        let regions = RegionSet::load(std::slice::from_ref(path))?;
+        println!("🗂️  Stratum {}: {} bases", name, regions.total_length());
+        evaluator = evaluator.with_stratum(&name, regions);
     }
     
-    println!("\n🧬 Estimating ancestry for sample: {}", args.sample);
+    let mut truth = VCFProcessor::new();
+    truth.parse_vcf(std::path::Path::new(&args.truth))
+        .context("Failed to parse truth VCF")?;
+    let mut query = VCFProcessor::new();
+    query.parse_vcf(std::path::Path::new(&args.query))
+        .context("Failed to parse query VCF")?;
+    let report = evaluator.evaluate(&truth, args.truth_sample.as_deref(), &query, args.query_sample.as_deref())?;
+    report.write_tsv(&args.output)?;
     
-    // Calculate ancestry estimates
-    match processor.estimate_ancestry(&args.sample) {
-        Ok(ancestry_scores) => {
-            println!("\n🌍 Ancestry Estimates:");
-            
-            // Sort by score (highest first)
-            let mut sorted_ancestry: Vec<_> = ancestry_scores.iter().collect();
-            sorted_ancestry.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
-            
-            for (ancestry, score) in sorted_ancestry {
-                let percentage = (score * 100.0).round() as u32;
-                let bar = "█".repeat((percentage / 5) as usize);
-                println!("   {:>15}: {:>3}% |{}|", ancestry, percentage, bar);
-            }
-            
-            // Detailed comparison if requested
-            if args.detailed {
-                println!("\n🔍 Detailed Population Comparisons:");
-                for (pop_code, population) in &processor.populations {
-                    if let Some(sample_from_pop) = population.samples.first() {
-                        if let Ok(similarity) = processor.calculate_genetic_similarity(&args.sample, sample_from_pop) {
-                            let percentage = (similarity * 100.0).round() as u32;
-                            println!("   {:>15} ({}): {}% similarity", population.name, pop_code, percentage);
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            println!("❌ Error calculating ancestry: {}", e);
-        }
+    let processing_time = start_time.elapsed();
+    println!();
+    println!("🎉 BENCHMARKING COMPLETE!");
+    println!("✅ {} clusters replayed, {} matched exactly in {}ms",
+        report.replayed_clusters, report.exact_clusters, processing_time.as_millis());
+    if !report.truth_sample.is_empty() || !report.query_sample.is_empty() {
+        println!("🧬 Samples: truth {}, query {}", report.truth_sample, report.query_sample);
     }
-    
-    // Save results if requested
-    if let Some(output_path) = args.output {
-        println!("\n💾 Saving ancestry results to: {}", output_path);
-        println!("   ✅ Results saved");
+    for variant_type in ["SNP", "INDEL", "ALL"] {
+        let counts = report.counts("all", variant_type);
+        println!("   {:<5} TP {:>7} (query {:>7})  FP {:>6}  FN {:>6}  precision {}  recall {}  F1 {}",
+            variant_type, counts.truth_tp, counts.query_tp, counts.false_positives, counts.false_negatives,
+            format_metric(counts.precision()), format_metric(counts.recall()), format_metric(counts.f1()));
+    }
+    if report.truth_skipped + report.query_skipped > 0 {
+        println!("⚠️  Unusable records skipped: {} truth, {} query", report.truth_skipped, report.query_skipped);
     }
+    println!("💾 Metrics saved to: {}", args.output);
     
     Ok(())
 }
 
-async fn convert_raw_dna(args: ConvertArgs) -> Result<()> {
-    use raw_converter::{RawDnaConverter, InputFormat};
+async fn build_tree(args: TreeArgs) -> Result<()> {
+    use phylo::{alignment_distances, DistanceModel, PhyloTree, TreeMethod};
     
-    println!("🧬 RAW DNA TO VCF CONVERTER");
-    println!("===========================");
-    println!("📂 Input file: {}", args.input);
-    println!("📁 Output file: {}", args.output);
-    println!("👤 Sample name: {}", args.sample);
+    let method: TreeMethod = args.method.parse()?;
+    let model: DistanceModel = args.model.parse()?;
+    
+    println!("🌳 PHYLOGENETIC TREE");
+    println!("====================");
+    println!("📂 Input: {}", args.input);
+    println!("🧭 Method: {}", method);
     println!();
-
-    // Parse input format
-    let input_format = match args.format.to_lowercase().as_str() {
-        "auto" => InputFormat::Auto,
-        "23andme" => InputFormat::TwentyThreeAndMe,
-        "ancestry" | "ancestrydna" => InputFormat::AncestryDna,
-        "myheritage" => InputFormat::MyHeritage,
-        "familytree" | "familytreedna" => InputFormat::FamilyTreeDna,
-        "csv" => InputFormat::Csv,
-        "tab" => InputFormat::Tab,
-        "plink" => InputFormat::Plink,
-        _ => {
-            eprintln!("❌ Unknown format: {}. Using auto-detect.", args.format);
-            InputFormat::Auto
-        }
-    };
-
-    // Create converter
-    let converter = RawDnaConverter::new(args.sample.clone(), input_format);
-
-    // Show conversion statistics if requested
-    if args.stats {
-        println!("📊 Analyzing input file...");
-        match converter.get_conversion_stats(&args.input) {
-            Ok(stats) => {
-                stats.print_summary();
-                println!();
+    
+    let start_time = Instant::now();
+    let content = std::fs::read_to_string(&args.input)
+        .with_context(|| format!("Could not read input file: {}", args.input))?;
+    
+    // FASTA input yields alignment rows; a PHYLIP matrix is used as-is.
+    // Equal lengths alone don't make an alignment: it needs gaps or --aligned.
+    let (names, rows) = if content.trim_start().starts_with('>') {
+        let records = phylo::parse_aligned_fasta(&content);
+        let aligned = args.aligned || records.iter().any(|(_, row)| row.contains(&b'-'));
+        if aligned {
+            if records.len() < 2 {
+                return Err(anyhow::anyhow!("Need at least two sequences to build a tree, found {}", records.len()));
             }
-            Err(e) => {
-                eprintln!("⚠️ Could not analyze file: {}", e);
+            if let Some((name, row)) = records.iter().find(|(_, row)| row.len() != records[0].1.len()) {
+                return Err(anyhow::anyhow!("Aligned row {} has {} columns, expected {}",
+                    name, row.len(), records[0].1.len()));
             }
-        }
-    }
-
-    // Perform conversion
-    println!("🔄 Converting raw DNA data to VCF format...");
-    let start_time = std::time::Instant::now();
-
-    match converter.convert_to_vcf(&args.input, &args.output) {
-        Ok(()) => {
-            let duration = start_time.elapsed();
-            println!("✅ Conversion completed successfully!");
-            println!("⏱️ Processing time: {:.3}s", duration.as_secs_f64());
-            
-            // Optional compression
-            if args.compress {
-                println!("🗜️ Compressing output file...");
-                let compressed_output = format!("{}.gz", args.output);
-                
-                match compress_vcf_file(&args.output, &compressed_output) {
-                    Ok(()) => {
-                        println!("✅ Compressed file saved: {}", compressed_output);
-                        if let Err(e) = std::fs::remove_file(&args.output) {
-                            eprintln!("⚠️ Could not remove uncompressed file: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("❌ Compression failed: {}", e);
-                    }
-                }
+            println!("🧩 Using {} aligned sequences ({} columns)", records.len(), records[0].1.len());
+            records.into_iter().unzip()
+        } else {
+            let sequences = parse_residue_fasta(&content);
+            if sequences.len() < 2 {
+                return Err(anyhow::anyhow!("Need at least two sequences to build a tree, found {}", sequences.len()));
             }
-            
-            println!();
-            println!("💡 Your VCF file is ready for:");
-            println!("   • Ancestry analysis with --ancestry command");
-            println!("   • Population genetics studies");
-            println!("   • Import into other genomics tools");
-            println!("   • Medical genetic analysis");
+            println!("🧩 Aligning {} sequences first", sequences.len());
+            let aligner = msa::ProgressiveAligner::new(alignment::ScoringScheme::default(), 6);
+            let (alignment, _) = aligner.align(&sequences)?;
+            (alignment.names, alignment.rows)
         }
-        Err(e) => {
-            eprintln!("❌ Conversion failed: {}", e);
-            return Err(e);
+    } else {
+        (Vec::new(), Vec::new())
+    };
+    
+    let (names, distances) = if rows.is_empty() {
+        if args.bootstrap > 0 {
+            return Err(anyhow::anyhow!("Bootstrap needs sequences, not a distance matrix"));
         }
+        println!("📏 Reading PHYLIP distance matrix");
+        phylo::parse_phylip_distances(&content)?
+    } else {
+        println!("📏 Distance model: {}", model);
+        let distances = alignment_distances(&rows, model, None);
+        (names, distances)
+    };
+    
+    let mut tree = PhyloTree::build(method, &distances)?;
+    if args.bootstrap > 0 {
+        println!("🔁 Running {} bootstrap replicates", args.bootstrap);
+        tree.annotate_bootstrap(&rows, model, method, args.bootstrap, args.seed)?;
     }
-
-    Ok(())
-}
-
-async fn diy_dna_analysis(args: DiyArgs) -> Result<()> {
-    use crate::diy_dna::DiyDnaExtractor;
     
-    println!("🧬 DIY DNA EXTRACTION & MANUAL GENOTYPING");
-    println!("==========================================");
-    println!("👤 Sample: {}", args.sample);
-    println!("📤 Output VCF: {}", args.output);
-    println!();
-
-    let mut extractor = DiyDnaExtractor::new(args.sample.clone());
+    std::fs::write(&args.output, tree.to_newick(&names) + "\n")?;
     
-    if args.load_markers {
-        extractor.load_diy_kit_markers();
-        println!();
+    println!("🎉 TREE COMPLETE!");
+    println!("✅ {} taxa in {}ms", names.len(), start_time.elapsed().as_millis());
+    println!("📐 Total branch length: {:.5}", tree.total_length());
+    let supports: Vec<f64> = tree.graph.node_weights().filter_map(|node| node.support).collect();
+    if !supports.is_empty() {
+        println!("📊 Mean bootstrap support: {:.1}%", supports.iter().sum::<f64>() / supports.len() as f64);
     }
+    println!("💾 Newick tree saved to: {}", args.output);
     
-    if args.interactive {
-        extractor.start_manual_entry_session()?;
-        println!();
-        
-        // Export to VCF
-        println!("💾 Exporting manual entries to VCF...");
-        extractor.export_to_vcf(&args.output)?;
-        
-        let file_size = std::fs::metadata(&args.output)?.len();
-        println!("✅ DIY DNA analysis complete!");
-        println!("📊 Output: {} ({} bytes)", args.output, file_size);
-        
+    if args.ascii {
         println!();
-        println!("🎉 Your manually entered DNA data is now in professional VCF format!");
-        println!("    You can use it for ancestry analysis, health reports, and more.");
-        
-    } else {
-        extractor.load_diy_kit_markers();
-        println!("💡 Use --interactive flag to start manual SNP entry session");
-        println!("   Example: instant-dna diy --sample \"YourName\" --output diy_dna.vcf --interactive");
+        print!("{}", tree.to_ascii(&names, 60));
+    }
+    
+    if let Some(path) = &args.svg {
+        std::fs::write(path, tree.to_svg(&names))?;
+        println!("🎨 Tree rendering saved to: {}", path);
     }
     
     Ok(())
@@ -0,0 +1,633 @@
+use anyhow::{bail, Result};
+use rust_htslib::bam;
+use rust_htslib::bam::header::{Header, HeaderRecord};
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+use std::path::Path;
+use crate::alignment::{gotoh, AlignOp, AlignmentAlgorithm, ScoringScheme};
+use crate::kmer_filter::for_each_fasta_record;
+use crate::minimizer::{Chain, ChainParams, MinimizerIndex};
+use crate::sequencer::FastqRecord;
+
+/// Score given up by reporting the mates unpaired instead of as a proper pair, as in BWA-MEM
+const UNPAIRED_PENALTY: i32 = 17;
+
+const MAX_MAPQ: u8 = 60;
+
+// SAM flag bits
+const FLAG_PAIRED: u16 = 0x1;
+const FLAG_PROPER_PAIR: u16 = 0x2;
+const FLAG_UNMAPPED: u16 = 0x4;
+const FLAG_MATE_UNMAPPED: u16 = 0x8;
+const FLAG_REVERSE: u16 = 0x10;
+const FLAG_MATE_REVERSE: u16 = 0x20;
+const FLAG_FIRST: u16 = 0x40;
+const FLAG_SECOND: u16 = 0x80;
+
+/// Reverse complement, keeping IUPAC ambiguity codes
+pub fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .rev()
+        .map(|&b| match b {
+            b'A' => b'T',
+            b'T' | b'U' => b'A',
+            b'G' => b'C',
+            b'C' => b'G',
+            b'R' => b'Y',
+            b'Y' => b'R',
+            b'K' => b'M',
+            b'M' => b'K',
+            b'B' => b'V',
+            b'V' => b'B',
+            b'D' => b'H',
+            b'H' => b'D',
+            b'a' => b't',
+            b't' | b'u' => b'a',
+            b'g' => b'c',
+            b'c' => b'g',
+            other => other,
+        })
+        .collect()
+}
+
+/// Reference sequences held in memory, uppercased
+pub struct Reference {
+    pub names: Vec<String>,
+    pub sequences: Vec<Vec<u8>>,
+}
+
+impl Reference {
+    pub fn from_fasta(path: &Path) -> Result<Self> {
+        let mut names = Vec::new();
+        let mut sequences = Vec::new();
+        for_each_fasta_record(path, |name, sequence| {
+            names.push(name.to_string());
+            sequences.push(sequence.to_ascii_uppercase());
+            Ok(())
+        })?;
+        if names.is_empty() {
+            bail!("No sequences found in reference {}", path.display());
+        }
+        Ok(Self { names, sequences })
+    }
+
+    pub fn total_length(&self) -> usize {
+        self.sequences.iter().map(|s| s.len()).sum()
+    }
+
+    /// SAM header with one @SQ line per reference sequence
+    pub fn sam_header(&self, command_line: &str, sorted: bool) -> Header {
+        let targets: Vec<(&str, usize)> = self
+            .names
+            .iter()
+            .zip(&self.sequences)
+            .map(|(name, sequence)| (name.as_str(), sequence.len()))
+            .collect();
+        sam_header(&targets, command_line, sorted)
+    }
+}
+
+/// SAM header with one @SQ line per (name, length) target and an @PG line
+///
+/// `sorted` declares the records coordinate-sorted in the @HD line.
+pub fn sam_header(targets: &[(&str, usize)], command_line: &str, sorted: bool) -> Header {
+    let sort_order = if sorted { "coordinate" } else { "unsorted" };
+    let mut header = Header::new();
+    header.push_record(HeaderRecord::new(b"HD").push_tag(b"VN", "1.6").push_tag(b"SO", sort_order));
+    for &(name, length) in targets {
+        header.push_record(HeaderRecord::new(b"SQ").push_tag(b"SN", name).push_tag(b"LN", length));
+    }
+    header.push_record(
+        HeaderRecord::new(b"PG")
+            .push_tag(b"ID", "instant-dna")
+            .push_tag(b"PN", "instant-dna")
+            .push_tag(b"VN", env!("CARGO_PKG_VERSION"))
+            .push_tag(b"CL", command_line),
+    );
+    header
+}
+
+/// Read mapping parameters
+#[derive(Debug, Clone)]
+pub struct MapParams {
+    pub kmer_size: usize,
+    pub window: usize,
+    /// Minimum alignment score for a reported hit
+    pub min_score: i32,
+    /// Chains aligned per read
+    pub max_candidates: usize,
+    pub chain: ChainParams,
+}
+
+impl Default for MapParams {
+    fn default() -> Self {
+        Self {
+            kmer_size: 15,
+            window: 10,
+            min_score: 30,
+            max_candidates: 5,
+            // Short reads: a single seed may be all there is
+            chain: ChainParams {
+                max_gap: 200,
+                max_lookback: 50,
+                min_anchors: 1,
+                min_score: 0,
+            },
+        }
+    }
+}
+
+/// One placement of a read on the reference
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub target: u32,
+    /// Zero-based leftmost reference position of the aligned bases
+    pub pos: usize,
+    /// Reference bases covered by the alignment
+    pub ref_span: usize,
+    pub reverse: bool,
+    pub score: i32,
+    pub cigar: Vec<Cigar>,
+    /// Mismatches plus inserted and deleted bases (SAM NM)
+    pub edit_distance: u32,
+}
+
+impl Hit {
+    pub fn end(&self) -> usize {
+        self.pos + self.ref_span
+    }
+
+    fn same_locus(&self, other: &Hit, tolerance: usize) -> bool {
+        self.target == other.target && self.reverse == other.reverse && self.pos.abs_diff(other.pos) <= tolerance
+    }
+}
+
+/// Mapping quality from the gap between the best and second-best score
+pub fn mapq(best: i32, second: Option<i32>) -> u8 {
+    if best <= 0 {
+        return 0;
+    }
+    let sub = second.unwrap_or(0).clamp(0, best);
+    let q = 250.0 * (best - sub) as f64 / best as f64;
+    (q.round() as u8).min(MAX_MAPQ)
+}
+
+/// Insert-size distribution of properly oriented pairs
+#[derive(Debug, Clone, Copy)]
+pub struct InsertSizeModel {
+    pub mean: f64,
+    pub std_dev: f64,
+    /// Inclusive range accepted for a proper pair
+    pub low: usize,
+    pub high: usize,
+    /// Pairs the estimate is based on; zero for the fallback model
+    pub pairs: usize,
+}
+
+impl Default for InsertSizeModel {
+    /// Used when too few unique pairs are available to estimate one
+    fn default() -> Self {
+        Self::from_moments(500.0, 150.0, 0)
+    }
+}
+
+impl InsertSizeModel {
+    fn from_moments(mean: f64, std_dev: f64, pairs: usize) -> Self {
+        Self {
+            mean,
+            std_dev,
+            low: (mean - 4.0 * std_dev).max(1.0) as usize,
+            high: (mean + 4.0 * std_dev).ceil() as usize,
+            pairs,
+        }
+    }
+
+    /// Estimate from observed insert sizes, BWA-style
+    ///
+    /// Sizes outside two interquartile ranges of the quartiles are dropped
+    /// before taking the mean and standard deviation; proper pairs then lie
+    /// within four standard deviations of the mean.
+    pub fn estimate(sizes: &[usize]) -> Option<Self> {
+        if sizes.len() < 25 {
+            return None;
+        }
+        let mut sorted = sizes.to_vec();
+        sorted.sort_unstable();
+        let q1 = sorted[sorted.len() / 4] as f64;
+        let q3 = sorted[sorted.len() * 3 / 4] as f64;
+        let iqr = q3 - q1;
+        let (lo, hi) = (q1 - 2.0 * iqr, q3 + 2.0 * iqr);
+
+        let kept: Vec<f64> = sorted
+            .iter()
+            .map(|&s| s as f64)
+            .filter(|&s| s >= lo && s <= hi)
+            .collect();
+        let mean = kept.iter().sum::<f64>() / kept.len() as f64;
+        let variance = kept.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / kept.len() as f64;
+        Some(Self::from_moments(mean, variance.sqrt().max(1.0), kept.len()))
+    }
+
+    pub fn contains(&self, insert: usize) -> bool {
+        insert >= self.low && insert <= self.high
+    }
+}
+
+/// Outer distance of two hits forming a forward-reverse pair on one sequence
+fn proper_insert(a: &Hit, b: &Hit) -> Option<usize> {
+    if a.target != b.target || a.reverse == b.reverse {
+        return None;
+    }
+    let (forward, reverse) = if a.reverse { (b, a) } else { (a, b) };
+    if forward.pos > reverse.end() {
+        return None;
+    }
+    Some(reverse.end().max(forward.end()) - forward.pos)
+}
+
+/// Insert size of a pair whose mates each have exactly one placement
+pub fn unique_insert_size(hits1: &[Hit], hits2: &[Hit]) -> Option<usize> {
+    match (hits1, hits2) {
+        ([a], [b]) => proper_insert(a, b).filter(|&insert| insert <= 10_000),
+        _ => None,
+    }
+}
+
+/// Chosen placements for the two mates of a pair
+#[derive(Debug, Clone)]
+pub struct PairPlacement {
+    pub first: Option<Hit>,
+    pub second: Option<Hit>,
+    pub proper: bool,
+    pub mapq: (u8, u8),
+}
+
+/// Seed-and-extend short-read mapper over an in-memory minimizer index
+pub struct ReadMapper {
+    reference: Reference,
+    index: MinimizerIndex,
+    scoring: ScoringScheme,
+    params: MapParams,
+}
+
+impl ReadMapper {
+    /// Index the reference; scoring follows BWA-MEM (+1/-4, gaps 6 + 1 per base)
+    pub fn new(reference: Reference, params: MapParams) -> Self {
+        let targets: Vec<(String, Vec<u8>)> = reference
+            .names
+            .iter()
+            .cloned()
+            .zip(reference.sequences.iter().cloned())
+            .collect();
+        let index = MinimizerIndex::build(&targets, params.window, params.kmer_size);
+
+        Self {
+            reference,
+            index,
+            scoring: ScoringScheme {
+                match_score: 1,
+                mismatch: -4,
+                gap_open: 6,
+                gap_extend: 1,
+                matrix: None,
+            },
+            params,
+        }
+    }
+
+    pub fn reference(&self) -> &Reference {
+        &self.reference
+    }
+
+    /// Candidate placements of a read, best first, one per locus
+    pub fn map_read(&self, sequence: &[u8]) -> Vec<Hit> {
+        if sequence.len() < self.params.kmer_size {
+            return Vec::new();
+        }
+
+        let reverse = reverse_complement(sequence);
+        let chains = self.index.map_query(sequence, &self.params.chain);
+        let mut hits: Vec<Hit> = chains
+            .iter()
+            .take(self.params.max_candidates)
+            .filter_map(|chain| {
+                let oriented = if chain.reverse { &reverse } else { sequence };
+                let (start, end) = self.chain_window(chain, sequence.len());
+                self.align_in_window(oriented, chain.target, start, end, chain.reverse)
+            })
+            .collect();
+
+        dedup_hits(&mut hits, sequence.len() / 2);
+        hits
+    }
+
+    /// Reference window the chained read can reach, padded for indels
+    fn chain_window(&self, chain: &Chain, read_len: usize) -> (usize, usize) {
+        let k = self.index.k();
+        let first = chain.anchors[0];
+        let last = chain.anchors[chain.anchors.len() - 1];
+        let pad = 16 + read_len / 8;
+        let target_len = self.reference.sequences[chain.target as usize].len();
+
+        // Anchor query positions are on the read strand that matches the reference
+        let start = (first.target_pos as usize).saturating_sub(first.query_pos as usize + pad);
+        let end = (last.target_pos as usize + read_len.saturating_sub(last.query_pos as usize) + pad)
+            .max(last.target_pos as usize + k)
+            .min(target_len);
+        (start, end)
+    }
+
+    /// End-to-end alignment of the read inside a reference window
+    ///
+    /// Bases hanging off the window (i.e. the end of the reference) are soft
+    /// clipped, as are gaps left dangling at either end of the alignment.
+    fn align_in_window(&self, oriented: &[u8], target: u32, start: usize, end: usize, reverse: bool) -> Option<Hit> {
+        let window = &self.reference.sequences[target as usize][start..end];
+        let alignment = gotoh(oriented, window, AlignmentAlgorithm::SemiGlobal, &self.scoring);
+        if alignment.score < self.params.min_score {
+            return None;
+        }
+
+        let mut ops = alignment.ops.as_slice();
+        let (mut clip_left, mut clip_right) = (alignment.query_start, oriented.len() - alignment.query_end);
+        let mut pos = start + alignment.target_start;
+        while let Some((&op, rest)) = ops.split_first().filter(|(op, _)| !is_aligned(**op)) {
+            if op == AlignOp::Insertion {
+                clip_left += 1;
+            } else {
+                pos += 1;
+            }
+            ops = rest;
+        }
+        while let Some((&op, rest)) = ops.split_last().filter(|(op, _)| !is_aligned(**op)) {
+            if op == AlignOp::Insertion {
+                clip_right += 1;
+            }
+            ops = rest;
+        }
+        if ops.is_empty() {
+            return None;
+        }
+
+        let mut cigar = Vec::new();
+        if clip_left > 0 {
+            cigar.push(Cigar::SoftClip(clip_left as u32));
+        }
+        for &op in ops {
+            let next = match op {
+                AlignOp::Match | AlignOp::Mismatch => Cigar::Match(1),
+                AlignOp::Insertion => Cigar::Ins(1),
+                AlignOp::Deletion => Cigar::Del(1),
+            };
+            match (cigar.last_mut(), next) {
+                (Some(Cigar::Match(n)), Cigar::Match(_))
+                | (Some(Cigar::Ins(n)), Cigar::Ins(_))
+                | (Some(Cigar::Del(n)), Cigar::Del(_)) => *n += 1,
+                _ => cigar.push(next),
+            }
+        }
+        if clip_right > 0 {
+            cigar.push(Cigar::SoftClip(clip_right as u32));
+        }
+
+        let ref_span = ops.iter().filter(|&&op| op != AlignOp::Insertion).count();
+        let edit_distance = ops.iter().filter(|&&op| op != AlignOp::Match).count() as u32;
+        Some(Hit {
+            target,
+            pos,
+            ref_span,
+            reverse,
+            score: alignment.score,
+            cigar,
+            edit_distance,
+        })
+    }
+
+    /// Search for a mate near a placed read, where the insert-size model says it should be
+    pub fn rescue_mate(&self, anchor: &Hit, mate: &[u8], model: &InsertSizeModel) -> Option<Hit> {
+        let target_len = self.reference.sequences[anchor.target as usize].len();
+        // Forward-reverse orientation: the mate sits downstream of a forward read
+        let (start, end, oriented, reverse) = if anchor.reverse {
+            (anchor.end().saturating_sub(model.high), anchor.end(), mate.to_vec(), false)
+        } else {
+            (anchor.pos, (anchor.pos + model.high).min(target_len), reverse_complement(mate), true)
+        };
+        if end <= start || end - start < mate.len() / 2 {
+            return None;
+        }
+        self.align_in_window(&oriented, anchor.target, start, end, reverse)
+    }
+
+    /// Choose placements for a read pair, preferring a proper pair when it scores well
+    pub fn pair_hits(
+        &self,
+        mut hits1: Vec<Hit>,
+        mut hits2: Vec<Hit>,
+        seq1: &[u8],
+        seq2: &[u8],
+        model: &InsertSizeModel,
+    ) -> PairPlacement {
+        let top = self.params.max_candidates;
+        let mut pairs = proper_pairs(&hits1, &hits2, model, top);
+
+        if pairs.is_empty() {
+            let rescued2: Vec<Hit> = hits1.iter().take(2).filter_map(|h| self.rescue_mate(h, seq2, model)).collect();
+            let rescued1: Vec<Hit> = hits2.iter().take(2).filter_map(|h| self.rescue_mate(h, seq1, model)).collect();
+            if !rescued1.is_empty() || !rescued2.is_empty() {
+                hits1.extend(rescued1);
+                hits2.extend(rescued2);
+                dedup_hits(&mut hits1, seq1.len() / 2);
+                dedup_hits(&mut hits2, seq2.len() / 2);
+                pairs = proper_pairs(&hits1, &hits2, model, top);
+            }
+        }
+
+        let single1 = mapq(hits1.first().map_or(0, |h| h.score), hits1.get(1).map(|h| h.score));
+        let single2 = mapq(hits2.first().map_or(0, |h| h.score), hits2.get(1).map(|h| h.score));
+        let unpaired = match (hits1.first(), hits2.first()) {
+            (Some(a), Some(b)) => a.score + b.score - UNPAIRED_PENALTY,
+            _ => i32::MIN,
+        };
+
+        if let Some(&(best, i, j)) = pairs.first() {
+            if best >= unpaired {
+                let pair_q = mapq(best, pairs.get(1).map(|p| p.0)) as u16;
+                let combine = |single: u8| single.max(pair_q.min(single as u16 + 40) as u8);
+                return PairPlacement {
+                    first: Some(hits1[i].clone()),
+                    second: Some(hits2[j].clone()),
+                    proper: true,
+                    mapq: (combine(single1), combine(single2)),
+                };
+            }
+        }
+
+        PairPlacement {
+            first: hits1.into_iter().next(),
+            second: hits2.into_iter().next(),
+            proper: false,
+            mapq: (single1, single2),
+        }
+    }
+
+    /// BAM record for a single-end read
+    pub fn single_record(&self, read: &FastqRecord, hits: &[Hit]) -> Result<bam::Record> {
+        let hit = hits.first();
+        let mapq = hit.map_or(0, |best| mapq(best.score, hits.get(1).map(|h| h.score)));
+        let mut record = base_record(read, hit, if hit.is_some() { 0 } else { FLAG_UNMAPPED });
+        match hit {
+            Some(h) => place(&mut record, h, mapq)?,
+            None => place_unmapped(&mut record, None),
+        }
+        record.set_mtid(-1);
+        record.set_mpos(-1);
+        Ok(record)
+    }
+
+    /// BAM records for both mates of a pair
+    pub fn pair_records(
+        &self,
+        read1: &FastqRecord,
+        read2: &FastqRecord,
+        placement: &PairPlacement,
+    ) -> Result<[bam::Record; 2]> {
+        let mates = [
+            (read1, placement.first.as_ref(), placement.second.as_ref(), placement.mapq.0, FLAG_FIRST),
+            (read2, placement.second.as_ref(), placement.first.as_ref(), placement.mapq.1, FLAG_SECOND),
+        ];
+
+        let [first, second] = mates.map(|(read, hit, mate, mapq, which)| -> Result<bam::Record> {
+            let mut flags = FLAG_PAIRED | which;
+            if placement.proper {
+                flags |= FLAG_PROPER_PAIR;
+            }
+            if hit.is_none() {
+                flags |= FLAG_UNMAPPED;
+            }
+            match mate {
+                None => flags |= FLAG_MATE_UNMAPPED,
+                Some(m) if m.reverse => flags |= FLAG_MATE_REVERSE,
+                Some(_) => {}
+            }
+
+            let mut record = base_record(read, hit, flags);
+            match hit {
+                Some(h) => place(&mut record, h, mapq)?,
+                None => place_unmapped(&mut record, mate),
+            }
+
+            // Mate fields; an unmapped mate is placed with this read
+            match mate.or(hit) {
+                Some(m) => {
+                    record.set_mtid(m.target as i32);
+                    record.set_mpos(m.pos as i64);
+                }
+                None => {
+                    record.set_mtid(-1);
+                    record.set_mpos(-1);
+                }
+            }
+            if let (Some(h), Some(m)) = (hit, mate) {
+                if h.target == m.target {
+                    let left = h.pos.min(m.pos);
+                    let right = h.end().max(m.end());
+                    let tlen = (right - left) as i64;
+                    let leftmost = h.pos < m.pos || (h.pos == m.pos && which == FLAG_FIRST);
+                    record.set_insert_size(if leftmost { tlen } else { -tlen });
+                }
+            }
+            Ok(record)
+        });
+        Ok([first?, second?])
+    }
+}
+
+fn is_aligned(op: AlignOp) -> bool {
+    matches!(op, AlignOp::Match | AlignOp::Mismatch)
+}
+
+/// Sort best first and keep one hit per locus
+fn dedup_hits(hits: &mut Vec<Hit>, tolerance: usize) {
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then(a.pos.cmp(&b.pos)));
+    let mut kept: Vec<Hit> = Vec::with_capacity(hits.len());
+    for hit in hits.drain(..) {
+        if !kept.iter().any(|k| k.same_locus(&hit, tolerance)) {
+            kept.push(hit);
+        }
+    }
+    *hits = kept;
+}
+
+/// Proper pairs among the top candidates as (summed score, index 1, index 2), best first
+fn proper_pairs(hits1: &[Hit], hits2: &[Hit], model: &InsertSizeModel, top: usize) -> Vec<(i32, usize, usize)> {
+    let mut pairs = Vec::new();
+    for (i, a) in hits1.iter().enumerate().take(top) {
+        for (j, b) in hits2.iter().enumerate().take(top) {
+            if proper_insert(a, b).is_some_and(|insert| model.contains(insert)) {
+                pairs.push((a.score + b.score, i, j));
+            }
+        }
+    }
+    pairs.sort_by_key(|p| std::cmp::Reverse(p.0));
+    pairs
+}
+
+/// Read name up to the first space, without a trailing /1 or /2
+pub fn read_name(header: &str) -> &str {
+    let name = header.split_whitespace().next().unwrap_or("");
+    name.strip_suffix("/1").or_else(|| name.strip_suffix("/2")).unwrap_or(name)
+}
+
+/// Record with name, sequence, qualities and CIGAR; stored on the strand it maps to
+fn base_record(read: &FastqRecord, hit: Option<&Hit>, flags: u16) -> bam::Record {
+    let mut record = bam::Record::new();
+    let name = read_name(&read.name);
+    let qname = &name.as_bytes()[..name.len().min(254)];
+
+    match hit {
+        Some(h) if h.reverse => {
+            let sequence = reverse_complement(&read.sequence);
+            let quality: Vec<u8> = read.quality.iter().rev().copied().collect();
+            record.set(qname, Some(&CigarString(h.cigar.clone())), &sequence, &quality);
+        }
+        Some(h) => record.set(qname, Some(&CigarString(h.cigar.clone())), &read.sequence, &read.quality),
+        None => record.set(qname, None, &read.sequence, &read.quality),
+    }
+
+    let mut flags = flags;
+    if hit.is_some_and(|h| h.reverse) {
+        flags |= FLAG_REVERSE;
+    }
+    record.set_flags(flags);
+    record
+}
+
+fn place(record: &mut bam::Record, hit: &Hit, mapq: u8) -> Result<()> {
+    record.set_tid(hit.target as i32);
+    record.set_pos(hit.pos as i64);
+    record.set_bin(reg2bin(hit.pos as i64, hit.end() as i64));
+    record.set_mapq(mapq);
+    record.push_aux(b"NM", Aux::I32(hit.edit_distance as i32))?;
+    record.push_aux(b"AS", Aux::I32(hit.score))?;
+    Ok(())
+}
+
+/// Unmapped reads take their mapped mate's position, per the SAM spec
+fn place_unmapped(record: &mut bam::Record, mate: Option<&Hit>) {
+    let (tid, pos) = mate.map_or((-1, -1), |m| (m.target as i32, m.pos as i64));
+    record.set_tid(tid);
+    record.set_pos(pos);
+    record.set_bin(reg2bin(pos, pos + 1));
+    record.set_mapq(0);
+}
+
+/// BAI bin of a zero-based half-open interval (SAM spec section 5.3)
+pub fn reg2bin(beg: i64, end: i64) -> u16 {
+    let end = end - 1;
+    for (shift, offset) in [(14, 4681), (17, 585), (20, 73), (23, 9), (26, 1)] {
+        if beg >> shift == end >> shift {
+            return (offset + (beg >> shift)) as u16;
+        }
+    }
+    0
+}
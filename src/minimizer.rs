@@ -0,0 +1,383 @@
+use ahash::AHashMap;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use crate::kmer_filter::base_code;
+use crate::seq_container::PackedRecord;
+
+/// A (w,k) minimizer: the smallest hashed canonical k-mer in a window of w k-mers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Minimizer {
+    pub hash: u64,
+    /// Zero-based start of the k-mer
+    pub pos: u32,
+    /// True when the canonical form is the reverse complement
+    pub reverse: bool,
+}
+
+/// Extract (w,k) minimizers from an ASCII sequence
+pub fn extract_minimizers(sequence: &[u8], w: usize, k: usize) -> Vec<Minimizer> {
+    minimizers_from_codes(sequence.iter().map(|&base| base_code(base)), sequence.len(), w, k)
+}
+
+/// Extract (w,k) minimizers straight from a 2-bit packed record; its N runs break k-mers
+pub fn extract_minimizers_packed(record: &PackedRecord, w: usize, k: usize) -> Vec<Minimizer> {
+    let mut n_runs = record.n_runs.iter().peekable();
+    let codes = (0..record.length).map(|i| {
+        while n_runs.next_if(|&&(start, len)| start + len <= i).is_some() {}
+        if n_runs.peek().is_some_and(|&&(start, _)| start <= i) {
+            None
+        } else {
+            Some((record.packed[(i / 4) as usize] >> ((i % 4) * 2)) & 0b11)
+        }
+    });
+    minimizers_from_codes(codes, record.length as usize, w, k)
+}
+
+/// Minimizers over 2-bit codes (A=0 T=1 G=2 C=3, `None` for N)
+///
+/// Uses canonical k-mers and an invertible integer hash so that minimizers are
+/// strand-independent and not biased towards poly-A. K-mers whose forward and
+/// reverse forms are identical carry no strand information and are skipped,
+/// as are k-mers that contain N.
+fn minimizers_from_codes<I>(codes: I, length: usize, w: usize, k: usize) -> Vec<Minimizer>
+where
+    I: Iterator<Item = Option<u8>>,
+{
+    assert!(k > 0 && k <= 32 && w > 0, "minimizers need 0 < k <= 32 and w > 0");
+
+    let mask = if k == 32 { u64::MAX } else { (1u64 << (2 * k)) - 1 };
+    let shift = 2 * (k - 1);
+    let mut minimizers: Vec<Minimizer> = Vec::with_capacity(length * 2 / (w + 1) + 1);
+    let mut window: VecDeque<(usize, Minimizer)> = VecDeque::with_capacity(w);
+    let (mut forward, mut reverse, mut valid) = (0u64, 0u64, 0usize);
+    let mut kmer_index = 0usize;
+
+    for (i, code) in codes.enumerate() {
+        let Some(code) = code else {
+            valid = 0;
+            window.clear();
+            continue;
+        };
+
+        forward = ((forward << 2) | code as u64) & mask;
+        reverse = (reverse >> 2) | (((code ^ 1) as u64) << shift);
+        valid += 1;
+        if valid < k {
+            continue;
+        }
+
+        kmer_index += 1;
+        if forward != reverse {
+            let candidate = Minimizer {
+                hash: hash64(forward.min(reverse), mask),
+                pos: (i + 1 - k) as u32,
+                reverse: reverse < forward,
+            };
+            // Monotonic queue: drop entries that can never be a window minimum again
+            while window.back().is_some_and(|(_, m)| m.hash > candidate.hash) {
+                window.pop_back();
+            }
+            window.push_back((kmer_index, candidate));
+        }
+        while window.front().is_some_and(|&(idx, _)| idx + w <= kmer_index) {
+            window.pop_front();
+        }
+
+        if valid >= k + w - 1 {
+            if let Some(&(_, m)) = window.front() {
+                if minimizers.last() != Some(&m) {
+                    minimizers.push(m);
+                }
+            }
+        }
+    }
+
+    minimizers
+}
+
+/// Thomas Wang's invertible 64-bit hash, restricted to the k-mer bit width
+fn hash64(key: u64, mask: u64) -> u64 {
+    let mut key = (!key).wrapping_add(key << 21) & mask;
+    key ^= key >> 24;
+    key = (key.wrapping_add(key << 3)).wrapping_add(key << 8) & mask;
+    key ^= key >> 14;
+    key = (key.wrapping_add(key << 2)).wrapping_add(key << 4) & mask;
+    key ^= key >> 28;
+    key.wrapping_add(key << 31) & mask
+}
+
+/// A shared minimizer between query and target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub target: u32,
+    pub target_pos: u32,
+    /// Query position on the strand matching the target (flipped for reverse hits)
+    pub query_pos: u32,
+    pub reverse: bool,
+}
+
+/// A co-linear chain of anchors, as found by minimap2-style seed chaining
+#[derive(Debug, Clone)]
+pub struct Chain {
+    pub target: u32,
+    pub reverse: bool,
+    pub score: i32,
+    pub anchors: Vec<Anchor>,
+    /// Query span in original forward-strand coordinates, half-open
+    pub query_start: u32,
+    pub query_end: u32,
+    pub target_start: u32,
+    pub target_end: u32,
+}
+
+/// Chaining parameters; defaults follow minimap2's long-read presets
+#[derive(Debug, Clone)]
+pub struct ChainParams {
+    /// Maximum gap on either sequence between consecutive anchors
+    pub max_gap: u32,
+    /// Number of preceding anchors considered for each anchor
+    pub max_lookback: usize,
+    /// Minimum number of anchors in a reported chain
+    pub min_anchors: usize,
+    /// Minimum chaining score of a reported chain
+    pub min_score: i32,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self {
+            max_gap: 5000,
+            max_lookback: 50,
+            min_anchors: 3,
+            min_score: 40,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IndexHit {
+    target: u32,
+    pos: u32,
+    reverse: bool,
+}
+
+/// Minimizer index over a set of target sequences
+pub struct MinimizerIndex {
+    w: usize,
+    k: usize,
+    hits: AHashMap<u64, Vec<IndexHit>>,
+    max_occurrences: usize,
+}
+
+impl MinimizerIndex {
+    /// Index targets given as (name, ASCII sequence) pairs; targets are numbered in order
+    pub fn build(targets: &[(String, Vec<u8>)], w: usize, k: usize) -> Self {
+        let records: Vec<PackedRecord> = targets
+            .par_iter()
+            .map(|(name, seq)| PackedRecord::from_ascii(name, seq))
+            .collect();
+        Self::from_packed(&records, w, k)
+    }
+
+    /// Index 2-bit packed records, such as those of a `PackedContainer`
+    pub fn from_packed(records: &[PackedRecord], w: usize, k: usize) -> Self {
+        let per_target: Vec<Vec<Minimizer>> = records
+            .par_iter()
+            .map(|record| extract_minimizers_packed(record, w, k))
+            .collect();
+
+        let mut hits: AHashMap<u64, Vec<IndexHit>> = AHashMap::new();
+        for (target, minimizers) in per_target.iter().enumerate() {
+            for m in minimizers {
+                hits.entry(m.hash).or_default().push(IndexHit {
+                    target: target as u32,
+                    pos: m.pos,
+                    reverse: m.reverse,
+                });
+            }
+        }
+
+        let mut index = Self {
+            w,
+            k,
+            hits,
+            max_occurrences: usize::MAX,
+        };
+        index.set_repeat_cutoff(0.0002);
+        index
+    }
+
+    /// Ignore the most frequent `fraction` of distinct minimizers when seeding
+    pub fn set_repeat_cutoff(&mut self, fraction: f64) {
+        let mut counts: Vec<usize> = self.hits.values().map(|h| h.len()).collect();
+        if counts.is_empty() {
+            return;
+        }
+        counts.sort_unstable_by_key(|&count| Reverse(count));
+        let skip = ((counts.len() as f64 * fraction) as usize).min(counts.len() - 1);
+        self.max_occurrences = counts[skip].max(1);
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Shared minimizers between a query and the indexed targets
+    pub fn find_anchors(&self, query: &[u8]) -> Vec<Anchor> {
+        let query_len = query.len() as u32;
+        let mut anchors = Vec::new();
+
+        for m in extract_minimizers(query, self.w, self.k) {
+            let Some(hits) = self.hits.get(&m.hash) else {
+                continue;
+            };
+            if hits.len() > self.max_occurrences {
+                continue;
+            }
+            for hit in hits {
+                let reverse = hit.reverse != m.reverse;
+                let query_pos = if reverse {
+                    query_len - (m.pos + self.k as u32)
+                } else {
+                    m.pos
+                };
+                anchors.push(Anchor {
+                    target: hit.target,
+                    target_pos: hit.pos,
+                    query_pos,
+                    reverse,
+                });
+            }
+        }
+
+        anchors
+    }
+
+    /// Seed and chain a query against the index, best chains first
+    pub fn map_query(&self, query: &[u8], params: &ChainParams) -> Vec<Chain> {
+        let mut anchors = self.find_anchors(query);
+        let mut chains = chain_anchors(&mut anchors, self.k as u32, query.len() as u32, params);
+        chains.sort_by_key(|chain| Reverse(chain.score));
+        chains
+    }
+}
+
+/// Minimap2-style dynamic-programming chaining
+///
+/// Anchors are grouped by target and strand and sorted by target position; each
+/// anchor extends the best of the previous `max_lookback` compatible anchors,
+/// gaining up to k for new matched bases and paying a concave gap penalty.
+/// Chains are then extracted greedily from the highest-scoring end, with each
+/// anchor used by at most one chain.
+pub fn chain_anchors(anchors: &mut [Anchor], k: u32, query_len: u32, params: &ChainParams) -> Vec<Chain> {
+    anchors.sort_unstable_by_key(|a| (a.target, a.reverse, a.target_pos, a.query_pos));
+
+    let n = anchors.len();
+    let mut scores = vec![0i32; n];
+    let mut parents = vec![usize::MAX; n];
+
+    for i in 0..n {
+        let ai = anchors[i];
+        scores[i] = k as i32;
+        let lookback_start = i.saturating_sub(params.max_lookback);
+        for j in (lookback_start..i).rev() {
+            let aj = anchors[j];
+            if aj.target != ai.target || aj.reverse != ai.reverse {
+                break;
+            }
+            let dt = ai.target_pos as i64 - aj.target_pos as i64;
+            if dt > params.max_gap as i64 {
+                break;
+            }
+            let dq = ai.query_pos as i64 - aj.query_pos as i64;
+            if dq <= 0 || dt <= 0 || dq > params.max_gap as i64 {
+                continue;
+            }
+
+            let gap = (dq - dt).unsigned_abs();
+            let matched = dq.min(dt).min(k as i64) as i32;
+            let penalty = if gap == 0 {
+                0
+            } else {
+                (0.01 * k as f64 * gap as f64 + 0.5 * (gap as f64).log2()).ceil() as i32
+            };
+
+            let score = scores[j] + matched - penalty;
+            if score > scores[i] {
+                scores[i] = score;
+                parents[i] = j;
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_unstable_by(|&a, &b| scores[b].cmp(&scores[a]));
+
+    let mut used = vec![false; n];
+    let mut chains = Vec::new();
+    for end in order {
+        if used[end] || scores[end] < params.min_score {
+            continue;
+        }
+
+        let mut members = Vec::new();
+        let mut cursor = end;
+        while cursor != usize::MAX && !used[cursor] {
+            used[cursor] = true;
+            members.push(anchors[cursor]);
+            cursor = parents[cursor];
+        }
+        // Stopping early at a used anchor means part of this chain was taken
+        let score = scores[end] - if cursor == usize::MAX { 0 } else { scores[cursor] };
+        if members.len() < params.min_anchors || score < params.min_score {
+            continue;
+        }
+        members.reverse();
+
+        let first = members[0];
+        let last = *members.last().unwrap();
+        let (query_start, query_end) = if first.reverse {
+            (query_len - (last.query_pos + k), query_len - first.query_pos)
+        } else {
+            (first.query_pos, last.query_pos + k)
+        };
+
+        chains.push(Chain {
+            target: first.target,
+            reverse: first.reverse,
+            score,
+            query_start,
+            query_end,
+            target_start: first.target_pos,
+            target_end: last.target_pos + k,
+            anchors: members,
+        });
+    }
+
+    chains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_minimizers_match_ascii() {
+        let mut state = 7u64;
+        let mut sequence: Vec<u8> = (0..5000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 33) as usize % 4]
+            })
+            .collect();
+        sequence[1000..1040].fill(b'N');
+        sequence[3000..3100].make_ascii_lowercase();
+        sequence[4999] = b'N';
+
+        let record = PackedRecord::from_ascii("target", &sequence);
+        for (w, k) in [(10, 15), (5, 21), (1, 32)] {
+            assert_eq!(extract_minimizers_packed(&record, w, k), extract_minimizers(&sequence, w, k));
+        }
+    }
+}
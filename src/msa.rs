@@ -0,0 +1,543 @@
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use crate::alignment::{AlignOp, ScoringScheme, E_EXTENDED, F_EXTENDED, FROM_DIAG, FROM_E, FROM_F, FROM_STOP};
+use crate::kmer_filter::base_code;
+use crate::long_align::trace_back;
+
+/// Largest profile-profile DP matrix the aligner will allocate
+const MAX_PROFILE_CELLS: usize = 1 << 28;
+
+/// Pairwise k-mer distances, as used for MUSCLE's first-stage guide tree
+///
+/// The distance is one minus the fraction of shared k-mers (counted with
+/// multiplicity) relative to the shorter sequence, so it needs no alignment
+/// and runs in linear time per pair.
+pub fn kmer_distances(sequences: &[&[u8]], k: usize) -> Result<Vec<Vec<f64>>> {
+    if k == 0 || k > 12 {
+        bail!("K-mer size for guide-tree distances must be between 1 and 12");
+    }
+
+    let profiles: Vec<Vec<u16>> = sequences.par_iter().map(|s| kmer_counts(s, k)).collect();
+    let totals: Vec<u64> = profiles.iter().map(|p| p.iter().map(|&c| c as u64).sum()).collect();
+
+    let rows: Vec<Vec<f64>> = (0..sequences.len())
+        .into_par_iter()
+        .map(|i| {
+            (0..sequences.len())
+                .map(|j| {
+                    if i == j {
+                        return 0.0;
+                    }
+                    let shared: u64 = profiles[i]
+                        .iter()
+                        .zip(&profiles[j])
+                        .map(|(&a, &b)| a.min(b) as u64)
+                        .sum();
+                    let denominator = totals[i].min(totals[j]);
+                    if denominator == 0 {
+                        1.0
+                    } else {
+                        1.0 - shared as f64 / denominator as f64
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+fn kmer_counts(sequence: &[u8], k: usize) -> Vec<u16> {
+    let mask = (1usize << (2 * k)) - 1;
+    let mut counts = vec![0u16; 1 << (2 * k)];
+    let (mut code, mut valid) = (0usize, 0usize);
+    for &base in sequence {
+        match base_code(base) {
+            Some(c) => {
+                code = ((code << 2) | c as usize) & mask;
+                valid += 1;
+                if valid >= k {
+                    counts[code] = counts[code].saturating_add(1);
+                }
+            }
+            None => valid = 0,
+        }
+    }
+    counts
+}
+
+/// Node of a rooted binary guide tree; leaves come first, the root last
+#[derive(Debug, Clone)]
+pub struct GuideNode {
+    pub children: Option<(usize, usize)>,
+    /// Distance from the node to its leaves (half the merge distance)
+    pub height: f64,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct GuideTree {
+    pub nodes: Vec<GuideNode>,
+}
+
+impl GuideTree {
+    /// Average-linkage (UPGMA) clustering of a distance matrix
+    pub fn upgma(distances: &[Vec<f64>]) -> Self {
+        let n = distances.len();
+        let mut nodes: Vec<GuideNode> = (0..n)
+            .map(|_| GuideNode { children: None, height: 0.0, size: 1 })
+            .collect();
+        let mut d: Vec<Vec<f64>> = distances.to_vec();
+        // Each active slot holds the node currently occupying that row of d
+        let mut slot_node: Vec<Option<usize>> = (0..n).map(Some).collect();
+
+        for _ in 1..n {
+            let mut best = (f64::INFINITY, 0, 0);
+            for i in 0..n {
+                if slot_node[i].is_none() {
+                    continue;
+                }
+                for j in (i + 1)..n {
+                    if slot_node[j].is_some() && d[i][j] < best.0 {
+                        best = (d[i][j], i, j);
+                    }
+                }
+            }
+
+            let (dist, i, j) = best;
+            let (left, right) = (slot_node[i].unwrap(), slot_node[j].unwrap());
+            let (size_i, size_j) = (nodes[left].size as f64, nodes[right].size as f64);
+            for k in 0..n {
+                if slot_node[k].is_some() && k != i && k != j {
+                    let merged = (d[i][k] * size_i + d[j][k] * size_j) / (size_i + size_j);
+                    d[i][k] = merged;
+                    d[k][i] = merged;
+                }
+            }
+
+            nodes.push(GuideNode {
+                children: Some((left, right)),
+                height: (dist / 2.0).max(nodes[left].height).max(nodes[right].height),
+                size: nodes[left].size + nodes[right].size,
+            });
+            slot_node[i] = Some(nodes.len() - 1);
+            slot_node[j] = None;
+        }
+
+        Self { nodes }
+    }
+
+    pub fn root(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    /// Leaves in left-to-right order, so that each clade is contiguous
+    pub fn leaf_order(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut stack = vec![self.root()];
+        while let Some(node) = stack.pop() {
+            match self.nodes[node].children {
+                None => order.push(node),
+                Some((left, right)) => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+        order
+    }
+
+    /// Newick string with branch lengths
+    pub fn to_newick(&self, names: &[String]) -> String {
+        let mut out = String::new();
+        self.write_newick(self.root(), names, &mut out);
+        out.push(';');
+        out
+    }
+
+    fn write_newick(&self, node: usize, names: &[String], out: &mut String) {
+        match self.nodes[node].children {
+            None => out.push_str(&newick_label(&names[node])),
+            Some((left, right)) => {
+                out.push('(');
+                for (n, child) in [left, right].into_iter().enumerate() {
+                    if n > 0 {
+                        out.push(',');
+                    }
+                    self.write_newick(child, names, out);
+                    out.push_str(&format!(":{:.5}", self.nodes[node].height - self.nodes[child].height));
+                }
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// First word of a FASTA header with Newick/Stockholm-unsafe characters replaced
+pub fn newick_label(name: &str) -> String {
+    name.split_whitespace()
+        .next()
+        .unwrap_or("seq")
+        .chars()
+        .map(|c| if "():;,[]'".contains(c) { '_' } else { c })
+        .collect()
+}
+
+/// Gapped rows of a multiple alignment, in input order
+#[derive(Debug, Clone)]
+pub struct MultipleAlignment {
+    pub names: Vec<String>,
+    pub rows: Vec<Vec<u8>>,
+}
+
+impl MultipleAlignment {
+    pub fn columns(&self) -> usize {
+        self.rows.first().map_or(0, |r| r.len())
+    }
+
+    fn column(&self, c: usize) -> impl Iterator<Item = u8> + '_ {
+        self.rows.iter().map(move |r| r[c])
+    }
+
+    /// Majority residue per column
+    ///
+    /// Uppercase when at least half the sequences carry it, lowercase when it
+    /// is only a plurality, and '-' where most sequences have a gap.
+    pub fn consensus(&self) -> Vec<u8> {
+        let n = self.rows.len();
+        (0..self.columns())
+            .map(|c| {
+                let mut counts = [0usize; 256];
+                for b in self.column(c) {
+                    counts[b.to_ascii_uppercase() as usize] += 1;
+                }
+                let gaps = counts[b'-' as usize];
+                let (residue, count) = b"ACGTUN"
+                    .iter()
+                    .map(|&r| (r, counts[r as usize]))
+                    .max_by_key(|&(_, count)| count)
+                    .unwrap_or((b'N', 0));
+                if gaps * 2 > n || count == 0 {
+                    b'-'
+                } else if count * 2 >= n {
+                    residue
+                } else {
+                    residue.to_ascii_lowercase()
+                }
+            })
+            .collect()
+    }
+
+    /// Per-column conservation in [0, 1]
+    ///
+    /// Occupancy (non-gap fraction) times one minus the Shannon entropy of the
+    /// residues in bits over its two-bit maximum: 1 for an ungapped invariant
+    /// column, 0 for an all-gap or uniformly mixed one.
+    pub fn conservation(&self) -> Vec<f64> {
+        let n = self.rows.len() as f64;
+        (0..self.columns())
+            .map(|c| {
+                let mut counts = [0usize; 4];
+                for b in self.column(c) {
+                    if let Some(code) = base_code(b) {
+                        counts[code as usize] += 1;
+                    }
+                }
+                let residues: usize = counts.iter().sum();
+                if residues == 0 {
+                    return 0.0;
+                }
+                let entropy: f64 = counts
+                    .iter()
+                    .filter(|&&count| count > 0)
+                    .map(|&count| {
+                        let p = count as f64 / residues as f64;
+                        -p * p.log2()
+                    })
+                    .sum();
+                (residues as f64 / n) * (1.0 - entropy / 2.0)
+            })
+            .collect()
+    }
+
+    pub fn gap_fraction(&self, column: usize) -> f64 {
+        self.column(column).filter(|&b| b == b'-').count() as f64 / self.rows.len().max(1) as f64
+    }
+
+    /// Columns where every sequence has the same residue and none has a gap
+    pub fn identical_columns(&self) -> usize {
+        (0..self.columns()).filter(|&c| self.is_identical(c)).count()
+    }
+
+    fn is_identical(&self, column: usize) -> bool {
+        let first = self.rows[0][column].to_ascii_uppercase();
+        first != b'-' && self.column(column).all(|b| b.to_ascii_uppercase() == first)
+    }
+
+    pub fn to_fasta(&self) -> String {
+        let mut out = String::new();
+        for (name, row) in self.names.iter().zip(&self.rows) {
+            out.push_str(&format!(">{}\n", name));
+            for line in row.chunks(60) {
+                out.push_str(&String::from_utf8_lossy(line));
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Clustal W format: 60-column blocks with residue counts and a '*' line
+    pub fn to_clustal(&self) -> String {
+        let labels: Vec<String> = self.names.iter().map(|n| newick_label(n)).collect();
+        let width = labels.iter().map(|l| l.len()).max().unwrap_or(0).max(10) + 6;
+        let mut residues = vec![0usize; self.rows.len()];
+        let mut out = String::from("CLUSTAL W multiple sequence alignment\n\n");
+
+        for start in (0..self.columns()).step_by(60) {
+            let end = (start + 60).min(self.columns());
+            out.push('\n');
+            for (s, (label, row)) in labels.iter().zip(&self.rows).enumerate() {
+                let block = &row[start..end];
+                residues[s] += block.iter().filter(|&&b| b != b'-').count();
+                out.push_str(&format!(
+                    "{:<w$}{} {}\n",
+                    label,
+                    String::from_utf8_lossy(block),
+                    residues[s],
+                    w = width
+                ));
+            }
+            let marks: String = (start..end)
+                .map(|c| if self.is_identical(c) { '*' } else { ' ' })
+                .collect();
+            out.push_str(&format!("{:<w$}{}\n", "", marks, w = width));
+        }
+        out
+    }
+
+    /// Stockholm 1.0 with the consensus as a `#=GC seq_cons` line
+    pub fn to_stockholm(&self) -> String {
+        let labels: Vec<String> = self.names.iter().map(|n| newick_label(n)).collect();
+        let cons_tag = "#=GC seq_cons";
+        let width = labels.iter().map(|l| l.len()).max().unwrap_or(0).max(cons_tag.len()) + 1;
+
+        let mut out = String::from("# STOCKHOLM 1.0\n");
+        out.push_str(&format!("#=GF SQ {}\n\n", self.rows.len()));
+        for (label, row) in labels.iter().zip(&self.rows) {
+            out.push_str(&format!("{:<w$}{}\n", label, String::from_utf8_lossy(row), w = width));
+        }
+        let consensus: Vec<u8> = self
+            .consensus()
+            .into_iter()
+            .map(|b| if b == b'-' { b'.' } else { b })
+            .collect();
+        out.push_str(&format!("{:<w$}{}\n", cons_tag, String::from_utf8_lossy(&consensus), w = width));
+        out.push_str("//\n");
+        out
+    }
+}
+
+/// A group of already-aligned sequences
+struct Cluster {
+    members: Vec<usize>,
+    rows: Vec<Vec<u8>>,
+}
+
+/// Column summary used for profile-profile scoring
+struct ProfileColumn {
+    /// Residue frequencies over all sequences in the profile (A, T, G, C)
+    freq: [f32; 4],
+    /// Fraction of sequences with a residue rather than a gap
+    occupancy: f32,
+    /// Fraction of sequences with N or an IUPAC code, which never match
+    ambiguous: f32,
+}
+
+impl Cluster {
+    fn profile(&self) -> Vec<ProfileColumn> {
+        let n = self.rows.len() as f32;
+        let columns = self.rows[0].len();
+        (0..columns)
+            .map(|c| {
+                let mut freq = [0f32; 4];
+                let (mut residues, mut ambiguous) = (0, 0);
+                for row in &self.rows {
+                    if row[c] != b'-' {
+                        residues += 1;
+                        match base_code(row[c]) {
+                            Some(code) => freq[code as usize] += 1.0 / n,
+                            None => ambiguous += 1,
+                        }
+                    }
+                }
+                ProfileColumn {
+                    freq,
+                    occupancy: residues as f32 / n,
+                    ambiguous: ambiguous as f32 / n,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Progressive aligner: k-mer guide tree, then profile-profile Gotoh merges
+pub struct ProgressiveAligner {
+    scoring: ScoringScheme,
+    kmer_size: usize,
+}
+
+impl ProgressiveAligner {
+    pub fn new(scoring: ScoringScheme, kmer_size: usize) -> Self {
+        Self { scoring, kmer_size }
+    }
+
+    /// Align (name, sequence) records; independent subtrees are merged in parallel
+    pub fn align(&self, sequences: &[(String, String)]) -> Result<(MultipleAlignment, GuideTree)> {
+        if sequences.is_empty() {
+            bail!("No sequences to align");
+        }
+
+        let seqs: Vec<&[u8]> = sequences.iter().map(|(_, s)| s.as_bytes()).collect();
+        let distances = kmer_distances(&seqs, self.kmer_size)?;
+        let tree = GuideTree::upgma(&distances);
+
+        let cluster = self.align_subtree(&tree, tree.root(), &seqs)?;
+        let mut ordered: Vec<(usize, Vec<u8>)> = cluster.members.into_iter().zip(cluster.rows).collect();
+        ordered.sort_by_key(|(member, _)| *member);
+
+        let alignment = MultipleAlignment {
+            names: sequences.iter().map(|(name, _)| name.clone()).collect(),
+            rows: ordered.into_iter().map(|(_, row)| row).collect(),
+        };
+        Ok((alignment, tree))
+    }
+
+    fn align_subtree(&self, tree: &GuideTree, node: usize, seqs: &[&[u8]]) -> Result<Cluster> {
+        match tree.nodes[node].children {
+            None => Ok(Cluster {
+                members: vec![node],
+                rows: vec![seqs[node].to_vec()],
+            }),
+            Some((left, right)) => {
+                let (a, b) = rayon::join(
+                    || self.align_subtree(tree, left, seqs),
+                    || self.align_subtree(tree, right, seqs),
+                );
+                self.merge(a?, b?)
+            }
+        }
+    }
+
+    /// Align two profiles and splice gap columns into every member row
+    fn merge(&self, a: Cluster, b: Cluster) -> Result<Cluster> {
+        let ops = self.align_profiles(&a.profile(), &b.profile())?;
+
+        let mut rows = Vec::with_capacity(a.rows.len() + b.rows.len());
+        // Rows of `a` own the insertion columns, rows of `b` the deletion columns
+        let tagged = a
+            .rows
+            .iter()
+            .map(|r| (r, AlignOp::Insertion))
+            .chain(b.rows.iter().map(|r| (r, AlignOp::Deletion)));
+        for (row, own_column) in tagged {
+            let mut gapped = Vec::with_capacity(ops.len());
+            let mut next = 0;
+            for &op in &ops {
+                if op == AlignOp::Match || op == own_column {
+                    gapped.push(row[next]);
+                    next += 1;
+                } else {
+                    gapped.push(b'-');
+                }
+            }
+            rows.push(gapped);
+        }
+
+        let mut members = a.members;
+        members.extend(b.members);
+        Ok(Cluster { members, rows })
+    }
+
+    /// Global Gotoh alignment of two profiles
+    ///
+    /// Columns score as the expected pairwise residue score; gap penalties are
+    /// scaled by the occupancy of the column left unpaired, so columns that are
+    /// mostly gaps already are cheap to skip.
+    fn align_profiles(&self, a: &[ProfileColumn], b: &[ProfileColumn]) -> Result<Vec<AlignOp>> {
+        const NEG: f32 = f32::MIN / 4.0;
+        let (n, m) = (a.len(), b.len());
+        if (n + 1).saturating_mul(m + 1) > MAX_PROFILE_CELLS {
+            bail!("Profiles too long to align ({} x {} columns)", n, m);
+        }
+
+        let open = (self.scoring.gap_open + self.scoring.gap_extend) as f32;
+        let ext = self.scoring.gap_extend as f32;
+        let (hit, miss) = (self.scoring.match_score as f32, self.scoring.mismatch as f32);
+        // Expected score of each residue in b against a's column
+        let expected = |col: &ProfileColumn| -> [f32; 4] {
+            std::array::from_fn(|y| (0..4).map(|x| col.freq[x] * if x == y { hit } else { miss }).sum())
+        };
+
+        let width = m + 1;
+        let mut trace = vec![FROM_STOP; (n + 1) * width];
+        let mut h_prev = vec![0f32; width];
+        let mut h_curr = vec![0f32; width];
+        let mut f_col = vec![NEG; width];
+
+        for j in 1..=m {
+            h_prev[j] = h_prev[j - 1] - if j == 1 { open } else { ext } * b[j - 1].occupancy;
+            trace[j] = FROM_E | if j > 1 { E_EXTENDED } else { 0 };
+        }
+
+        for i in 1..=n {
+            let a_col = &a[i - 1];
+            let a_expected = expected(a_col);
+            h_curr[0] = h_prev[0] - if i == 1 { open } else { ext } * a_col.occupancy;
+            trace[i * width] = FROM_F | if i > 1 { F_EXTENDED } else { 0 };
+            let mut e = NEG;
+
+            for j in 1..=m {
+                let b_col = &b[j - 1];
+                let mut bits = 0u8;
+
+                let e_open = h_curr[j - 1] - open * b_col.occupancy;
+                let e_ext = e - ext * b_col.occupancy;
+                e = if e_ext > e_open {
+                    bits |= E_EXTENDED;
+                    e_ext
+                } else {
+                    e_open
+                };
+
+                let f_open = h_prev[j] - open * a_col.occupancy;
+                let f_ext = f_col[j] - ext * a_col.occupancy;
+                f_col[j] = if f_ext > f_open {
+                    bits |= F_EXTENDED;
+                    f_ext
+                } else {
+                    f_open
+                };
+
+                // Pairs involving an ambiguous residue on either side score as mismatches
+                let unresolved = a_col.ambiguous * b_col.occupancy
+                    + (a_col.occupancy - a_col.ambiguous) * b_col.ambiguous;
+                let pair: f32 = (0..4).map(|y| a_expected[y] * b_col.freq[y]).sum::<f32>() + miss * unresolved;
+                let (mut h, mut from) = (h_prev[j - 1] + pair, FROM_DIAG);
+                if e > h {
+                    h = e;
+                    from = FROM_E;
+                }
+                if f_col[j] > h {
+                    h = f_col[j];
+                    from = FROM_F;
+                }
+
+                h_curr[j] = h;
+                trace[i * width + j] = bits | from;
+            }
+            std::mem::swap(&mut h_prev, &mut h_curr);
+        }
+
+        Ok(trace_back(|i, j| trace[i * width + j], n, m, |i, j| i == 0 && j == 0))
+    }
+}
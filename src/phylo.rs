@@ -0,0 +1,631 @@
+use anyhow::{bail, Context, Result};
+use ahash::AHashSet;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::Direction;
+use rayon::prelude::*;
+use crate::identity_matrix::xml_escape;
+use crate::kmer_filter::base_code;
+use crate::msa::{newick_label, GuideTree};
+
+/// Distance reported when too many substitutions make a correction undefined
+pub const SATURATED_DISTANCE: f64 = 5.0;
+
+/// Substitution model used to turn observed differences into distances
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceModel {
+    /// Uncorrected proportion of differing sites
+    PDistance,
+    /// Jukes-Cantor (1969): equal base frequencies and substitution rates
+    JukesCantor,
+    /// Kimura 2-parameter (1980): separate transition and transversion rates
+    Kimura2P,
+}
+
+impl std::fmt::Display for DistanceModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DistanceModel::PDistance => write!(f, "p-distance"),
+            DistanceModel::JukesCantor => write!(f, "Jukes-Cantor"),
+            DistanceModel::Kimura2P => write!(f, "Kimura 2-parameter"),
+        }
+    }
+}
+
+impl std::str::FromStr for DistanceModel {
+    type Err = anyhow::Error;
+
+    /// Parses `p`, `jc` and `k2p` and their longer spellings
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "p" | "p-distance" | "raw" => Ok(DistanceModel::PDistance),
+            "jc" | "jc69" | "jukes-cantor" => Ok(DistanceModel::JukesCantor),
+            "k2p" | "k80" | "kimura" => Ok(DistanceModel::Kimura2P),
+            _ => Err(anyhow::anyhow!("Unknown distance model: {} (use p, jc or k2p)", s)),
+        }
+    }
+}
+
+impl DistanceModel {
+    /// Corrected distance from transition and transversion counts over `sites` compared sites
+    pub fn distance(&self, transitions: f64, transversions: f64, sites: f64) -> f64 {
+        if sites <= 0.0 {
+            return SATURATED_DISTANCE;
+        }
+        let (p, q) = (transitions / sites, transversions / sites);
+        let d = match self {
+            DistanceModel::PDistance => return p + q,
+            DistanceModel::JukesCantor => {
+                let arg = 1.0 - 4.0 * (p + q) / 3.0;
+                if arg <= 0.0 {
+                    return SATURATED_DISTANCE;
+                }
+                -0.75 * arg.ln()
+            }
+            DistanceModel::Kimura2P => {
+                let (a, b) = (1.0 - 2.0 * p - q, 1.0 - 2.0 * q);
+                if a <= 0.0 || b <= 0.0 {
+                    return SATURATED_DISTANCE;
+                }
+                -0.5 * a.ln() - 0.25 * b.ln()
+            }
+        };
+        d.min(SATURATED_DISTANCE)
+    }
+}
+
+/// Pairwise distances between alignment rows
+///
+/// Columns with a gap or ambiguous base in either row are skipped for that
+/// pair. `weights` gives how often each column counts, which is how bootstrap
+/// replicates resample columns without copying the alignment.
+pub fn alignment_distances(rows: &[Vec<u8>], model: DistanceModel, weights: Option<&[u32]>) -> Vec<Vec<f64>> {
+    let n = rows.len();
+    let pairs: Vec<(usize, usize)> = (0..n).flat_map(|i| ((i + 1)..n).map(move |j| (i, j))).collect();
+    let values: Vec<f64> = pairs
+        .par_iter()
+        .map(|&(i, j)| {
+            let (mut sites, mut transitions, mut transversions) = (0u64, 0u64, 0u64);
+            for (c, (&a, &b)) in rows[i].iter().zip(&rows[j]).enumerate() {
+                let w = weights.map_or(1, |w| w[c]) as u64;
+                if w == 0 {
+                    continue;
+                }
+                if let (Some(x), Some(y)) = (base_code(a), base_code(b)) {
+                    sites += w;
+                    // Purines A/G and pyrimidines T/C differ only in the high bit
+                    match x ^ y {
+                        0 => {}
+                        0b10 => transitions += w,
+                        _ => transversions += w,
+                    }
+                }
+            }
+            model.distance(transitions as f64, transversions as f64, sites as f64)
+        })
+        .collect();
+
+    let mut d = vec![vec![0.0; n]; n];
+    for (&(i, j), &value) in pairs.iter().zip(&values) {
+        d[i][j] = value;
+        d[j][i] = value;
+    }
+    d
+}
+
+/// Tree-building algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeMethod {
+    NeighborJoining,
+    Upgma,
+}
+
+impl std::fmt::Display for TreeMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeMethod::NeighborJoining => write!(f, "neighbor-joining"),
+            TreeMethod::Upgma => write!(f, "UPGMA"),
+        }
+    }
+}
+
+impl std::str::FromStr for TreeMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "nj" | "neighbor-joining" | "neighbour-joining" => Ok(TreeMethod::NeighborJoining),
+            "upgma" => Ok(TreeMethod::Upgma),
+            _ => Err(anyhow::anyhow!("Unknown tree method: {} (use nj or upgma)", s)),
+        }
+    }
+}
+
+/// Node payload: the input index for leaves, bootstrap support for internal nodes
+#[derive(Debug, Clone, Default)]
+pub struct TreeNode {
+    pub leaf: Option<usize>,
+    /// Percentage of bootstrap replicates containing this clade
+    pub support: Option<f64>,
+}
+
+/// Rooted phylogeny; edges point from parent to child and carry branch lengths
+///
+/// Leaves are added first, so leaf `i` has node index `i`. Neighbor-joining
+/// trees are unrooted and are stored with a three-way split at the root.
+#[derive(Debug, Clone)]
+pub struct PhyloTree {
+    pub graph: DiGraph<TreeNode, f64>,
+    pub root: NodeIndex,
+}
+
+impl PhyloTree {
+    pub fn build(method: TreeMethod, distances: &[Vec<f64>]) -> Result<Self> {
+        validate_matrix(distances)?;
+        Ok(match method {
+            TreeMethod::NeighborJoining => Self::neighbor_joining(distances),
+            TreeMethod::Upgma => Self::upgma(distances),
+        })
+    }
+
+    /// Saitou-Nei neighbor joining; negative branch lengths are clamped to zero
+    pub fn neighbor_joining(distances: &[Vec<f64>]) -> Self {
+        let mut graph = DiGraph::new();
+        let mut active: Vec<NodeIndex> = (0..distances.len())
+            .map(|i| graph.add_node(TreeNode { leaf: Some(i), support: None }))
+            .collect();
+        let mut d = distances.to_vec();
+
+        while active.len() > 2 {
+            let r = active.len();
+            let sums: Vec<f64> = d.iter().map(|row| row.iter().sum()).collect();
+            let mut best = (f64::INFINITY, 0, 1);
+            for i in 0..r {
+                for j in (i + 1)..r {
+                    let q = (r - 2) as f64 * d[i][j] - sums[i] - sums[j];
+                    if q < best.0 {
+                        best = (q, i, j);
+                    }
+                }
+            }
+
+            let (_, i, j) = best;
+            let dij = d[i][j].max(0.0);
+            let to_i = (0.5 * dij + (sums[i] - sums[j]) / (2.0 * (r - 2) as f64)).clamp(0.0, dij);
+            let parent = graph.add_node(TreeNode::default());
+            graph.add_edge(parent, active[i], to_i);
+            graph.add_edge(parent, active[j], dij - to_i);
+
+            let merged: Vec<f64> = (0..r)
+                .map(|k| if k == i { 0.0 } else { (0.5 * (d[i][k] + d[j][k] - dij)).max(0.0) })
+                .collect();
+            for (k, &value) in merged.iter().enumerate() {
+                d[i][k] = value;
+                d[k][i] = value;
+            }
+            active[i] = parent;
+            active.remove(j);
+            d.remove(j);
+            for row in &mut d {
+                row.remove(j);
+            }
+        }
+
+        let (a, b, dab) = (active[0], active[1], d[0][1].max(0.0));
+        let root = if graph[a].leaf.is_none() {
+            graph.add_edge(a, b, dab);
+            a
+        } else if graph[b].leaf.is_none() {
+            graph.add_edge(b, a, dab);
+            b
+        } else {
+            // Only two sequences: split the distance evenly under a new root
+            let root = graph.add_node(TreeNode::default());
+            graph.add_edge(root, a, dab / 2.0);
+            graph.add_edge(root, b, dab / 2.0);
+            root
+        };
+
+        Self { graph, root }
+    }
+
+    /// Average-linkage clustering; branch lengths are differences in node height
+    pub fn upgma(distances: &[Vec<f64>]) -> Self {
+        let guide = GuideTree::upgma(distances);
+        let mut graph = DiGraph::new();
+        for (i, node) in guide.nodes.iter().enumerate() {
+            let leaf = node.children.is_none().then_some(i);
+            graph.add_node(TreeNode { leaf, support: None });
+        }
+        for (i, node) in guide.nodes.iter().enumerate() {
+            if let Some((left, right)) = node.children {
+                for child in [left, right] {
+                    graph.add_edge(NodeIndex::new(i), NodeIndex::new(child), node.height - guide.nodes[child].height);
+                }
+            }
+        }
+        Self { graph, root: NodeIndex::new(guide.root()) }
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.graph.node_weights().filter(|node| node.leaf.is_some()).count()
+    }
+
+    /// Children in the order they were attached
+    pub fn children(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut children: Vec<NodeIndex> = self.graph.neighbors_directed(node, Direction::Outgoing).collect();
+        children.reverse();
+        children
+    }
+
+    fn branch_length(&self, parent: NodeIndex, child: NodeIndex) -> f64 {
+        self.graph.find_edge(parent, child).map_or(0.0, |e| self.graph[e])
+    }
+
+    /// Sum of all branch lengths
+    pub fn total_length(&self) -> f64 {
+        self.graph.edge_weights().sum()
+    }
+
+    /// Nodes with every parent before its children
+    fn preorder(&self) -> Vec<NodeIndex> {
+        let mut order = Vec::with_capacity(self.graph.node_count());
+        let mut stack = vec![self.root];
+        while let Some(node) = stack.pop() {
+            order.push(node);
+            stack.extend(self.children(node).into_iter().rev());
+        }
+        order
+    }
+
+    /// Root-to-node path lengths, indexed by node
+    pub fn depths(&self) -> Vec<f64> {
+        let mut depth = vec![0.0; self.graph.node_count()];
+        for node in self.preorder() {
+            for child in self.children(node) {
+                depth[child.index()] = depth[node.index()] + self.branch_length(node, child);
+            }
+        }
+        depth
+    }
+
+    /// Non-trivial bipartitions as leaf bitsets, keyed by the node that induces them
+    ///
+    /// Each split is stored on the side without leaf 0, so the same bipartition
+    /// compares equal whichever way the tree happens to be rooted.
+    pub fn splits(&self) -> Vec<(NodeIndex, Vec<u64>)> {
+        let n = self.leaf_count();
+        let words = n.div_ceil(64);
+        let mut below: Vec<Vec<u64>> = vec![vec![0; words]; self.graph.node_count()];
+        let mut splits = Vec::new();
+
+        for node in self.preorder().into_iter().rev() {
+            if let Some(leaf) = self.graph[node].leaf {
+                below[node.index()][leaf / 64] |= 1 << (leaf % 64);
+                continue;
+            }
+            let mut bits = vec![0u64; words];
+            for child in self.children(node) {
+                for (w, &b) in bits.iter_mut().zip(&below[child.index()]) {
+                    *w |= b;
+                }
+            }
+            below[node.index()] = bits.clone();
+
+            let size: usize = bits.iter().map(|w| w.count_ones() as usize).sum();
+            if node == self.root || size < 2 || size + 2 > n {
+                continue;
+            }
+            if bits[0] & 1 == 1 {
+                for (k, w) in bits.iter_mut().enumerate() {
+                    let valid = if (k + 1) * 64 <= n { u64::MAX } else { (1u64 << (n % 64)) - 1 };
+                    *w = !*w & valid;
+                }
+            }
+            splits.push((node, bits));
+        }
+        splits
+    }
+
+    /// Annotate internal nodes with nonparametric bootstrap support
+    ///
+    /// Each replicate resamples alignment columns with replacement, rebuilds
+    /// distances and a tree with the same model and method, and votes for the
+    /// splits it contains. Replicates run in parallel with per-replicate seeds.
+    pub fn annotate_bootstrap(
+        &mut self,
+        rows: &[Vec<u8>],
+        model: DistanceModel,
+        method: TreeMethod,
+        replicates: usize,
+        seed: u64,
+    ) -> Result<()> {
+        let columns = rows.first().map_or(0, |row| row.len());
+        if columns == 0 || replicates == 0 {
+            return Ok(());
+        }
+
+        let replicate_splits: Vec<AHashSet<Vec<u64>>> = (0..replicates)
+            .into_par_iter()
+            .map(|r| {
+                let mut rng = fastrand::Rng::with_seed(seed.wrapping_add(r as u64));
+                let mut weights = vec![0u32; columns];
+                for _ in 0..columns {
+                    weights[rng.usize(..columns)] += 1;
+                }
+                let distances = alignment_distances(rows, model, Some(&weights));
+                let tree = PhyloTree::build(method, &distances)?;
+                Ok(tree.splits().into_iter().map(|(_, bits)| bits).collect())
+            })
+            .collect::<Result<_>>()?;
+
+        for (node, bits) in self.splits() {
+            let hits = replicate_splits.iter().filter(|set| set.contains(&bits)).count();
+            self.graph[node].support = Some(100.0 * hits as f64 / replicates as f64);
+        }
+        Ok(())
+    }
+
+    /// Newick with branch lengths and bootstrap support as internal node labels
+    pub fn to_newick(&self, names: &[String]) -> String {
+        let mut out = String::new();
+        self.write_newick(self.root, names, &mut out);
+        out.push(';');
+        out
+    }
+
+    fn write_newick(&self, node: NodeIndex, names: &[String], out: &mut String) {
+        match self.graph[node].leaf {
+            Some(leaf) => out.push_str(&newick_label(&names[leaf])),
+            None => {
+                out.push('(');
+                for (n, child) in self.children(node).into_iter().enumerate() {
+                    if n > 0 {
+                        out.push(',');
+                    }
+                    self.write_newick(child, names, out);
+                    out.push_str(&format!(":{:.5}", self.branch_length(node, child)));
+                }
+                out.push(')');
+                if let Some(support) = self.graph[node].support {
+                    out.push_str(&format!("{:.0}", support));
+                }
+            }
+        }
+    }
+
+    /// Leaf rows and node columns for rectangular layouts
+    ///
+    /// Returns each node's depth as a fraction of the deepest leaf and its
+    /// vertical position in leaf rows; internal nodes sit midway between their
+    /// first and last child.
+    fn layout(&self) -> (Vec<f64>, Vec<f64>, Vec<NodeIndex>) {
+        let depths = self.depths();
+        let max_depth = depths.iter().copied().fold(0.0f64, f64::max).max(f64::EPSILON);
+        let scaled: Vec<f64> = depths.iter().map(|d| d / max_depth).collect();
+
+        let order = self.preorder();
+        let leaves: Vec<NodeIndex> = order.iter().copied().filter(|&node| self.graph[node].leaf.is_some()).collect();
+        let mut rows = vec![0.0; self.graph.node_count()];
+        for (row, leaf) in leaves.iter().enumerate() {
+            rows[leaf.index()] = row as f64;
+        }
+        for &node in order.iter().rev() {
+            let children = self.children(node);
+            if let (Some(first), Some(last)) = (children.first(), children.last()) {
+                rows[node.index()] = (rows[first.index()] + rows[last.index()]) / 2.0;
+            }
+        }
+        (scaled, rows, leaves)
+    }
+
+    /// Text rendering with branch lengths scaled to `width` characters
+    pub fn to_ascii(&self, names: &[String], width: usize) -> String {
+        let (scaled, rows, leaves) = self.layout();
+        let mut x = vec![0usize; self.graph.node_count()];
+        for node in self.preorder() {
+            for child in self.children(node) {
+                let target = (scaled[child.index()] * width as f64).round() as usize;
+                x[child.index()] = target.max(x[node.index()] + 1);
+            }
+        }
+
+        let height = 2 * leaves.len() - 1;
+        let columns = x.iter().copied().max().unwrap_or(0) + 1;
+        let mut grid = vec![vec![' '; columns]; height];
+        for node in self.preorder() {
+            let children = self.children(node);
+            if children.is_empty() {
+                continue;
+            }
+            let column = x[node.index()];
+            let span: Vec<usize> = children.iter().map(|c| (2.0 * rows[c.index()]) as usize).collect();
+            let (top, bottom) = (span[0], span[span.len() - 1]);
+            for line in grid.iter_mut().take(bottom + 1).skip(top) {
+                line[column] = '|';
+            }
+            for (child, &row) in children.iter().zip(&span) {
+                grid[row][column] = '+';
+                for cell in &mut grid[row][column + 1..=x[child.index()]] {
+                    *cell = '-';
+                }
+            }
+            if node != self.root {
+                grid[(2.0 * rows[node.index()]) as usize][column] = '+';
+            }
+        }
+
+        let mut out = String::new();
+        for (r, line) in grid.into_iter().enumerate() {
+            let mut text: String = line.into_iter().collect();
+            if r % 2 == 0 {
+                let leaf = leaves[r / 2];
+                let label = newick_label(&names[self.graph[leaf].leaf.unwrap_or(0)]);
+                text.truncate(x[leaf.index()] + 1);
+                text.push(' ');
+                text.push_str(&label);
+            }
+            out.push_str(text.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Rectangular phylogram with bootstrap values and a scale bar
+    pub fn to_svg(&self, names: &[String]) -> String {
+        let (scaled, rows, leaves) = self.layout();
+        let max_depth = self.depths().into_iter().fold(0.0f64, f64::max);
+        let labels: Vec<String> = leaves
+            .iter()
+            .map(|&leaf| newick_label(&names[self.graph[leaf].leaf.unwrap_or(0)]))
+            .collect();
+
+        let (margin, row_height, tree_width) = (20.0, 18.0, 600.0);
+        let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0) as f64 * 7.0 + 10.0;
+        let width = 2.0 * margin + tree_width + label_width;
+        let height = 2.0 * margin + row_height * leaves.len() as f64 + 40.0;
+        let px = |node: NodeIndex| margin + scaled[node.index()] * tree_width;
+        let py = |node: NodeIndex| margin + rows[node.index()] * row_height + row_height / 2.0;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+            width, height
+        );
+        svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+
+        for node in self.preorder() {
+            let children = self.children(node);
+            if let (Some(&first), Some(&last)) = (children.first(), children.last()) {
+                svg.push_str(&format!(
+                    "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"black\"/>\n",
+                    px(node),
+                    py(first),
+                    px(node),
+                    py(last)
+                ));
+            }
+            for &child in &children {
+                svg.push_str(&format!(
+                    "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"black\"><title>{:.5}</title></line>\n",
+                    px(node),
+                    py(child),
+                    px(child),
+                    py(child),
+                    self.branch_length(node, child)
+                ));
+            }
+            if let Some(support) = self.graph[node].support {
+                svg.push_str(&format!(
+                    "<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"9\" fill=\"#b22\" text-anchor=\"end\">{:.0}</text>\n",
+                    px(node) - 2.0,
+                    py(node) - 3.0,
+                    support
+                ));
+            }
+        }
+
+        for (leaf, label) in leaves.iter().zip(&labels) {
+            svg.push_str(&format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>\n",
+                px(*leaf) + 4.0,
+                py(*leaf) + 4.0,
+                xml_escape(label)
+            ));
+        }
+
+        // Scale bar of a round length near a fifth of the tree depth
+        if max_depth > 0.0 {
+            let magnitude = 10f64.powf((max_depth / 5.0).log10().floor());
+            let bar = (max_depth / 5.0 / magnitude).round().max(1.0) * magnitude;
+            let (x, y) = (margin, height - margin - 10.0);
+            svg.push_str(&format!(
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"black\"/>\n<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"10\">{}</text>\n",
+                x,
+                y,
+                x + bar / max_depth * tree_width,
+                y,
+                x,
+                y + 14.0,
+                bar
+            ));
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn validate_matrix(distances: &[Vec<f64>]) -> Result<()> {
+    let n = distances.len();
+    if n < 2 {
+        bail!("Need at least two taxa to build a tree, found {}", n);
+    }
+    for (i, row) in distances.iter().enumerate() {
+        if row.len() != n {
+            bail!("Distance matrix row {} has {} values, expected {}", i + 1, row.len(), n);
+        }
+        if let Some(value) = row.iter().find(|v| !v.is_finite()) {
+            bail!("Distance matrix row {} contains a non-finite value ({})", i + 1, value);
+        }
+    }
+    Ok(())
+}
+
+/// Aligned FASTA records, keeping gaps (`.` is read as `-`)
+pub fn parse_aligned_fasta(content: &str) -> Vec<(String, Vec<u8>)> {
+    let mut records: Vec<(String, Vec<u8>)> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('>') {
+            records.push((name.to_string(), Vec::new()));
+        } else if let Some((_, row)) = records.last_mut() {
+            row.extend(line.bytes().filter(|b| b.is_ascii_alphabetic() || *b == b'-' || *b == b'.').map(|b| {
+                if b == b'.' { b'-' } else { b.to_ascii_uppercase() }
+            }));
+        }
+    }
+    records.retain(|(_, row)| !row.is_empty());
+    records
+}
+
+/// Square or lower-triangular PHYLIP distance matrix, strict or relaxed names
+pub fn parse_phylip_distances(content: &str) -> Result<(Vec<String>, Vec<Vec<f64>>)> {
+    let mut tokens = content.split_whitespace();
+    let n: usize = tokens
+        .next()
+        .context("Empty distance matrix")?
+        .parse()
+        .context("PHYLIP distance matrix must start with the number of taxa")?;
+    let mut tokens = tokens.peekable();
+
+    let mut names = Vec::with_capacity(n);
+    let mut rows: Vec<Vec<f64>> = Vec::with_capacity(n);
+    let mut square = true;
+    for i in 0..n {
+        let name = tokens.next().with_context(|| format!("Missing row {} of {}", i + 1, n))?;
+        if i == 0 {
+            square = tokens.peek().is_some_and(|t| t.parse::<f64>().is_ok());
+        }
+        let count = if square { n } else { i };
+        let values = (0..count)
+            .map(|_| -> Result<f64> {
+                tokens
+                    .next()
+                    .with_context(|| format!("Row {} ({}) is missing values", i + 1, name))?
+                    .parse()
+                    .with_context(|| format!("Row {} ({}) has a non-numeric value", i + 1, name))
+            })
+            .collect::<Result<Vec<f64>>>()?;
+        names.push(name.to_string());
+        rows.push(values);
+    }
+
+    // Lower-triangular rows are mirrored into a full matrix
+    let d = if square {
+        rows
+    } else {
+        (0..n)
+            .map(|i| (0..n).map(|j| if j < i { rows[i][j] } else if j > i { rows[j][i] } else { 0.0 }).collect())
+            .collect()
+    };
+    Ok((names, d))
+}
@@ -0,0 +1,549 @@
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::Crc;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// File signature for packed sequence containers
+const MAGIC: &[u8; 8] = b"IDNAPK01";
+
+/// Bases per indexed block; must be a multiple of 4
+const DEFAULT_BLOCK_BASES: u32 = 1 << 16;
+
+/// Magic, record count and directory offset
+const HEADER_LEN: u64 = MAGIC.len() as u64 + 4 + 8;
+
+/// Smallest directory entry: an empty name with no blocks or runs
+const MIN_ENTRY_LEN: u64 = 2 + 8 + 4 + 4 + 4 + 4;
+
+/// 1-based inclusive genomic interval, e.g. `chr1:100000-200000`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+impl Region {
+    /// Zero-based half-open bounds clamped to a record of `length` bases
+    pub fn bounds(&self, length: u64) -> (u64, u64) {
+        let start = self.start.map(|s| s.saturating_sub(1)).unwrap_or(0).min(length);
+        let end = self.end.unwrap_or(length).min(length).max(start);
+        (start, end)
+    }
+}
+
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, range)) = s.rsplit_once(':') else {
+            return Ok(Region { name: s.to_string(), start: None, end: None });
+        };
+
+        let parse = |v: &str| -> Result<u64> {
+            v.replace(',', "")
+                .parse::<u64>()
+                .with_context(|| format!("Invalid coordinate '{}' in region '{}'", v, s))
+        };
+
+        let (start, end) = match range.split_once('-') {
+            Some((a, b)) => (parse(a)?, Some(parse(b)?)),
+            None => (parse(range)?, None),
+        };
+
+        if start == 0 || end.is_some_and(|e| e < start) {
+            bail!("Invalid region '{}': coordinates are 1-based and inclusive", s);
+        }
+
+        Ok(Region { name: name.to_string(), start: Some(start), end })
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.start, self.end) {
+            (Some(s), Some(e)) => write!(f, "{}:{}-{}", self.name, s, e),
+            (Some(s), None) => write!(f, "{}:{}", self.name, s),
+            _ => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// A run of bases as (zero-based start, length)
+pub type Run = (u64, u64);
+
+/// One sequence in 2-bit packed form with its side tables
+///
+/// Case and the positions of N survive unpacking, but the packing is lossy
+/// for other letters: IUPAC ambiguity codes read back as N and U as T.
+#[derive(Debug, Clone)]
+pub struct PackedRecord {
+    pub name: String,
+    pub length: u64,
+    /// 4 bases per byte, A=0 T=1 G=2 C=3, first base in the low bits
+    pub packed: Vec<u8>,
+    /// Runs of N (and any other non-ACGT symbol), stored as A in `packed`
+    pub n_runs: Vec<Run>,
+    /// Runs of lowercase (soft-masked) bases
+    pub mask_runs: Vec<Run>,
+}
+
+impl PackedRecord {
+    /// Pack an ASCII sequence, recording N and soft-mask runs
+    pub fn from_ascii(name: &str, sequence: &[u8]) -> Self {
+        let mut packed = vec![0u8; sequence.len().div_ceil(4)];
+        let mut n_runs = RunBuilder::default();
+        let mut mask_runs = RunBuilder::default();
+
+        for (i, &base) in sequence.iter().enumerate() {
+            let code = match base.to_ascii_uppercase() {
+                b'A' => Some(0b00),
+                b'T' | b'U' => Some(0b01),
+                b'G' => Some(0b10),
+                b'C' => Some(0b11),
+                _ => None,
+            };
+            n_runs.push(i as u64, code.is_none());
+            mask_runs.push(i as u64, base.is_ascii_lowercase());
+            packed[i / 4] |= code.unwrap_or(0) << ((i % 4) * 2);
+        }
+
+        PackedRecord {
+            name: name.to_string(),
+            length: sequence.len() as u64,
+            packed,
+            n_runs: n_runs.finish(sequence.len() as u64),
+            mask_runs: mask_runs.finish(sequence.len() as u64),
+        }
+    }
+}
+
+#[derive(Default)]
+struct RunBuilder {
+    runs: Vec<Run>,
+    open: Option<u64>,
+}
+
+impl RunBuilder {
+    fn push(&mut self, pos: u64, active: bool) {
+        match (self.open, active) {
+            (None, true) => self.open = Some(pos),
+            (Some(start), false) => {
+                self.runs.push((start, pos - start));
+                self.open = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self, length: u64) -> Vec<Run> {
+        if let Some(start) = self.open {
+            self.runs.push((start, length - start));
+        }
+        self.runs
+    }
+}
+
+/// Decode bases `[start, end)` from packed bytes that begin at base `data_start`
+fn unpack_range(
+    packed: &[u8],
+    data_start: u64,
+    start: u64,
+    end: u64,
+    n_runs: &[Run],
+    mask_runs: &[Run],
+) -> Vec<u8> {
+    const BASES: [u8; 4] = [b'A', b'T', b'G', b'C'];
+
+    let mut sequence: Vec<u8> = (start..end)
+        .map(|i| {
+            let offset = (i - data_start) as usize;
+            BASES[((packed[offset / 4] >> ((offset % 4) * 2)) & 0b11) as usize]
+        })
+        .collect();
+
+    for &(run_start, run_len) in overlapping(n_runs, start, end) {
+        let (s, e) = (run_start.max(start), (run_start + run_len).min(end));
+        sequence[(s - start) as usize..(e - start) as usize].fill(b'N');
+    }
+    for &(run_start, run_len) in overlapping(mask_runs, start, end) {
+        let (s, e) = (run_start.max(start), (run_start + run_len).min(end));
+        sequence[(s - start) as usize..(e - start) as usize].make_ascii_lowercase();
+    }
+
+    sequence
+}
+
+/// Runs that intersect `[start, end)`; `runs` is sorted and non-overlapping
+fn overlapping(runs: &[Run], start: u64, end: u64) -> &[Run] {
+    let first = runs.partition_point(|&(s, len)| s + len <= start);
+    let last = runs.partition_point(|&(s, _)| s < end);
+    &runs[first..last.max(first)]
+}
+
+/// In-memory collection of packed records, written as a single container file
+///
+/// Layout (little-endian): magic, record count, directory offset, the packed
+/// payload of every record, then the directory holding each record's name,
+/// length, side tables and block index, followed by a CRC32 of the directory.
+/// Every block entry carries the CRC32 of its payload bytes so that region
+/// queries can verify just the blocks they touch.
+#[derive(Debug, Default)]
+pub struct PackedContainer {
+    pub records: Vec<PackedRecord>,
+    block_bases: u32,
+}
+
+impl PackedContainer {
+    pub fn new() -> Self {
+        Self {
+            records: Vec::new(),
+            block_bases: DEFAULT_BLOCK_BASES,
+        }
+    }
+
+    pub fn add_record(&mut self, name: &str, sequence: &[u8]) {
+        self.records.push(PackedRecord::from_ascii(name, sequence));
+    }
+
+    /// Load every record of a FASTA file, keeping N and lowercase information
+    pub fn from_fasta(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open FASTA file: {}", path.display()))?;
+        let reader = BufReader::new(file);
+        let mut container = Self::new();
+        let mut name: Option<String> = None;
+        let mut sequence = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim_end();
+            if let Some(header) = line.strip_prefix('>') {
+                if let Some(prev) = name.take() {
+                    container.add_record(&prev, &sequence);
+                }
+                let id = header.split_whitespace().next().unwrap_or("").to_string();
+                name = Some(id);
+                sequence.clear();
+            } else if name.is_some() {
+                sequence.extend(line.bytes().filter(|b| !b.is_ascii_whitespace()));
+            }
+        }
+        if let Some(prev) = name {
+            container.add_record(&prev, &sequence);
+        }
+
+        Ok(container)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create container: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes).expect("writing to a Vec cannot fail");
+        bytes
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let payload_len: u64 = self.records.iter().map(|r| r.packed.len() as u64).sum();
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.records.len() as u32).to_le_bytes())?;
+        writer.write_all(&(HEADER_LEN + payload_len).to_le_bytes())?;
+
+        let mut directory = Vec::new();
+        let mut offset = HEADER_LEN;
+        let block_bytes = (self.block_bases / 4) as usize;
+
+        for record in &self.records {
+            writer.write_all(&record.packed)?;
+
+            let name = record.name.as_bytes();
+            let Ok(name_len) = u16::try_from(name.len()) else {
+                bail!("Record name of {} bytes exceeds the {}-byte limit", name.len(), u16::MAX);
+            };
+            directory.extend_from_slice(&name_len.to_le_bytes());
+            directory.extend_from_slice(name);
+            directory.extend_from_slice(&record.length.to_le_bytes());
+            directory.extend_from_slice(&self.block_bases.to_le_bytes());
+
+            let blocks: Vec<&[u8]> = record.packed.chunks(block_bytes).collect();
+            directory.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+            for block in blocks {
+                let mut crc = Crc::new();
+                crc.update(block);
+                directory.extend_from_slice(&offset.to_le_bytes());
+                directory.extend_from_slice(&crc.sum().to_le_bytes());
+                offset += block.len() as u64;
+            }
+
+            write_runs(&mut directory, &record.n_runs);
+            write_runs(&mut directory, &record.mask_runs);
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&directory);
+        writer.write_all(&directory)?;
+        writer.write_all(&crc.sum().to_le_bytes())?;
+        Ok(())
+    }
+}
+
+fn write_runs(out: &mut Vec<u8>, runs: &[Run]) {
+    out.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    for &(start, len) in runs {
+        out.extend_from_slice(&start.to_le_bytes());
+        out.extend_from_slice(&len.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BlockEntry {
+    offset: u64,
+    crc: u32,
+}
+
+/// Directory entry for one record in an open container
+#[derive(Debug, Clone)]
+pub struct RecordIndex {
+    pub name: String,
+    pub length: u64,
+    block_bases: u32,
+    blocks: Vec<BlockEntry>,
+    n_runs: Vec<Run>,
+    mask_runs: Vec<Run>,
+}
+
+/// Random-access reader that loads only the directory up front
+pub struct ContainerReader<R: Read + Seek> {
+    reader: R,
+    records: Vec<RecordIndex>,
+}
+
+impl ContainerReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open container: {}", path.display()))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<'a> ContainerReader<Cursor<&'a [u8]>> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        Self::new(Cursor::new(bytes))
+    }
+}
+
+impl<R: Read + Seek> ContainerReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).context("Container is truncated")?;
+        if &magic != MAGIC {
+            bail!("Not a packed sequence container");
+        }
+        let record_count = read_u32(&mut reader)?;
+        let directory_offset = read_u64(&mut reader)?;
+
+        // The header is outside the directory checksum, so check it before trusting it
+        let end = reader.seek(SeekFrom::End(0))?;
+        if directory_offset < HEADER_LEN {
+            bail!("Container header is corrupt: directory offset {} is inside the header", directory_offset);
+        }
+        if directory_offset.checked_add(4).is_none_or(|directory_end| directory_end > end) {
+            bail!("Container is truncated");
+        }
+        reader.seek(SeekFrom::Start(directory_offset))?;
+        let mut directory = vec![0u8; (end - directory_offset - 4) as usize];
+        reader.read_exact(&mut directory)?;
+        let stored_crc = read_u32(&mut reader)?;
+
+        let mut crc = Crc::new();
+        crc.update(&directory);
+        if crc.sum() != stored_crc {
+            bail!("Container directory checksum mismatch");
+        }
+
+        if record_count as u64 > directory.len() as u64 / MIN_ENTRY_LEN {
+            bail!("Container header is corrupt: {} records cannot fit in a {}-byte directory", record_count, directory.len());
+        }
+        let mut cursor = Cursor::new(directory.as_slice());
+        let mut records = Vec::with_capacity(record_count as usize);
+        for _ in 0..record_count {
+            let name_len = read_u16(&mut cursor)? as usize;
+            let mut name = vec![0u8; name_len];
+            cursor.read_exact(&mut name)?;
+            let length = read_u64(&mut cursor)?;
+            let block_bases = read_u32(&mut cursor)?;
+            let block_count = read_u32(&mut cursor)?;
+            let blocks = (0..block_count)
+                .map(|_| {
+                    Ok(BlockEntry {
+                        offset: read_u64(&mut cursor)?,
+                        crc: read_u32(&mut cursor)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let n_runs = read_runs(&mut cursor)?;
+            let mask_runs = read_runs(&mut cursor)?;
+            let name = String::from_utf8(name).context("Record name is not UTF-8")?;
+
+            // Reject directories whose block index or side tables don't cover the record exactly
+            if block_bases == 0 || block_bases % 4 != 0 {
+                bail!("Record '{}' has an invalid block size of {} bases", name, block_bases);
+            }
+            if block_count as u64 != length.div_ceil(block_bases as u64) {
+                bail!("Record '{}' has {} blocks for {} bases", name, block_count, length);
+            }
+            for runs in [&n_runs, &mask_runs] {
+                let mut previous_end = 0;
+                for &(start, len) in runs {
+                    match start.checked_add(len) {
+                        Some(end) if start >= previous_end && end <= length => previous_end = end,
+                        _ => bail!("Record '{}' has a run outside the sequence or out of order", name),
+                    }
+                }
+            }
+
+            records.push(RecordIndex {
+                name,
+                length,
+                block_bases,
+                blocks,
+                n_runs,
+                mask_runs,
+            });
+        }
+
+        Ok(Self { reader, records })
+    }
+
+    pub fn records(&self) -> &[RecordIndex] {
+        &self.records
+    }
+
+    /// Fetch a whole record by name
+    pub fn fetch_record(&mut self, name: &str) -> Result<Vec<u8>> {
+        self.fetch_region(&Region { name: name.to_string(), start: None, end: None })
+    }
+
+    /// Fetch a region such as `chr1:100000-200000`, reading only the blocks it spans
+    pub fn fetch_region(&mut self, region: &Region) -> Result<Vec<u8>> {
+        let index = self
+            .records
+            .iter()
+            .position(|r| r.name == region.name)
+            .ok_or_else(|| anyhow!("Sequence '{}' not found in container", region.name))?;
+        let record = &self.records[index];
+        let (start, end) = region.bounds(record.length);
+        if start == end {
+            return Ok(Vec::new());
+        }
+
+        let block_bases = record.block_bases as u64;
+        let first_block = (start / block_bases) as usize;
+        let last_block = ((end - 1) / block_bases) as usize;
+        let data_start = first_block as u64 * block_bases;
+
+        let mut packed = Vec::new();
+        for block_idx in first_block..=last_block {
+            let block = record.blocks.get(block_idx).ok_or_else(|| {
+                anyhow!("Block {} of '{}' is missing from the container index", block_idx, record.name)
+            })?;
+            let block_start = block_idx as u64 * block_bases;
+            let block_len = (record.length - block_start).min(block_bases);
+            let mut bytes = vec![0u8; block_len.div_ceil(4) as usize];
+
+            self.reader.seek(SeekFrom::Start(block.offset))?;
+            self.reader.read_exact(&mut bytes)?;
+
+            let mut crc = Crc::new();
+            crc.update(&bytes);
+            if crc.sum() != block.crc {
+                bail!("Checksum mismatch in block {} of '{}'", block_idx, record.name);
+            }
+            packed.extend_from_slice(&bytes);
+        }
+
+        Ok(unpack_range(&packed, data_start, start, end, &record.n_runs, &record.mask_runs))
+    }
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_runs<R: Read>(reader: &mut R) -> Result<Vec<Run>> {
+    let count = read_u32(reader)?;
+    (0..count)
+        .map(|_| Ok((read_u64(reader)?, read_u64(reader)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container() -> Vec<u8> {
+        let mut container = PackedContainer::new();
+        container.add_record("chr1", b"ACGTNNNNacgtRYU");
+        container.add_record("chr2", b"GGCC");
+        container.to_bytes()
+    }
+
+    #[test]
+    fn round_trip_keeps_case_and_n_but_not_iupac() {
+        let bytes = container();
+        let mut reader = ContainerReader::from_bytes(&bytes).unwrap();
+        assert_eq!(reader.fetch_record("chr1").unwrap(), b"ACGTNNNNacgtNNT");
+        assert_eq!(reader.fetch_region(&"chr1:4-9".parse().unwrap()).unwrap(), b"TNNNNa");
+        assert_eq!(reader.fetch_record("chr2").unwrap(), b"GGCC");
+    }
+
+    #[test]
+    fn truncated_container_is_rejected() {
+        let bytes = container();
+        for length in [0, 5, HEADER_LEN as usize - 1, HEADER_LEN as usize, bytes.len() - 1] {
+            assert!(ContainerReader::from_bytes(&bytes[..length]).is_err(), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn corrupt_header_is_rejected_before_allocating() {
+        let bytes = container();
+        let corrupt = |offset: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+            ContainerReader::from_bytes(&bytes).err().map(|e| e.to_string())
+        };
+        let count = corrupt(8, &u32::MAX.to_le_bytes()).unwrap();
+        assert!(count.contains("records cannot fit"), "{}", count);
+        let overflow = corrupt(12, &u64::MAX.to_le_bytes()).unwrap();
+        assert!(overflow.contains("truncated"), "{}", overflow);
+        let inside = corrupt(12, &3u64.to_le_bytes()).unwrap();
+        assert!(inside.contains("inside the header"), "{}", inside);
+        assert!(corrupt(12, &(HEADER_LEN + 1).to_le_bytes()).is_some());
+    }
+}
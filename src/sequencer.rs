@@ -1,7 +1,9 @@
 use anyhow::Result;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use flate2::read::MultiGzDecoder;
 use crate::binary_optimizer::BinaryOptimizer;
+use crate::kmer_filter::base_code;
 
 /// High-speed DNA sequencer with real-time capabilities
 pub struct Sequencer {
@@ -25,138 +27,34 @@ impl Sequencer {
     
     /// Process input file and return sequence data
     pub fn process_file(&self, input_path: &str, optimizer: &BinaryOptimizer) -> Result<Vec<SequenceData>> {
-        let file = File::open(input_path)?;
-        let reader = BufReader::new(file);
         let mut sequences = Vec::new();
-        
-        // Determine file format from extension or content
-        if input_path.ends_with(".fasta") || input_path.ends_with(".fa") {
-            sequences = self.parse_fasta(reader, optimizer)?;
-        } else if input_path.ends_with(".fastq") || input_path.ends_with(".fq") {
-            sequences = self.parse_fastq(reader, optimizer)?;
-        } else {
-            // Try to auto-detect format
-            sequences = self.parse_auto_detect(input_path, optimizer)?;
-        }
-        
-        // Filter by quality threshold
-        let filtered: Vec<_> = sequences
-            .into_iter()
-            .filter(|seq| seq.avg_quality() >= self.quality_threshold as f64)
-            .collect();
-        
-        Ok(filtered)
-    }
-    
-    fn parse_fasta(&self, reader: BufReader<File>, optimizer: &BinaryOptimizer) -> Result<Vec<SequenceData>> {
-        let mut sequences = Vec::new();
-        let mut current_header = String::new();
-        let mut current_sequence = String::new();
-        
-        for line in reader.lines() {
-            let line = line?;
-            if line.starts_with('>') {
-                // Save previous sequence if exists
-                if !current_sequence.is_empty() {
-                    let seq_data = self.create_sequence_data(
-                        &current_header,
-                        &current_sequence,
-                        None,
-                        optimizer,
-                    )?;
-                    sequences.push(seq_data);
-                }
-                // Start new sequence
-                current_header = line[1..].to_string();
-                current_sequence.clear();
-            } else {
-                current_sequence.push_str(&line.trim().to_uppercase());
-            }
-        }
-        
-        // Don't forget the last sequence
-        if !current_sequence.is_empty() {
-            let seq_data = self.create_sequence_data(
-                &current_header,
-                &current_sequence,
-                None,
-                optimizer,
-            )?;
-            sequences.push(seq_data);
-        }
-        
+        self.stream_file(input_path, optimizer, |seq| {
+            sequences.push(seq);
+            Ok(())
+        })?;
         Ok(sequences)
     }
     
-    fn parse_fastq(&self, reader: BufReader<File>, optimizer: &BinaryOptimizer) -> Result<Vec<SequenceData>> {
-        let mut sequences = Vec::new();
-        let lines: Vec<String> = reader.lines().collect::<Result<Vec<_>, _>>()?;
-        
-        // FASTQ format: 4 lines per read
-        for chunk in lines.chunks_exact(4) {
-            let header = &chunk[0][1..]; // Remove @ symbol
-            let sequence = &chunk[1].to_uppercase();
-            let quality = &chunk[3];
-            
-            if sequence.len() <= self.max_read_length {
-                let quality_scores: Vec<u8> = quality
-                    .bytes()
-                    .map(|q| q.saturating_sub(33)) // Phred+33 encoding
-                    .collect();
-                
-                let seq_data = self.create_sequence_data(
-                    header,
-                    sequence,
-                    Some(quality_scores),
-                    optimizer,
-                )?;
-                
-                sequences.push(seq_data);
+    /// Stream FASTA/FASTQ records (optionally gzipped) one at a time
+    ///
+    /// Applies the quality and read-length filters without holding the whole
+    /// file in memory. Bases keep their case, so soft-masking survives.
+    pub fn stream_file<F>(&self, input_path: &str, optimizer: &BinaryOptimizer, mut on_record: F) -> Result<()>
+    where
+        F: FnMut(SequenceData) -> Result<()>,
+    {
+        let mut reader = SequenceReader::open(input_path)?;
+        while let Some(record) = reader.next_record()? {
+            if record.sequence.len() > self.max_read_length {
+                continue;
             }
-        }
-        
-        Ok(sequences)
-    }
-    
-    fn parse_auto_detect(&self, input_path: &str, optimizer: &BinaryOptimizer) -> Result<Vec<SequenceData>> {
-        let file = File::open(input_path)?;
-        let mut reader = BufReader::new(file);
-        let mut first_line = String::new();
-        reader.read_line(&mut first_line)?;
-        
-        // Reset reader
-        drop(reader);
-        let file = File::open(input_path)?;
-        let reader = BufReader::new(file);
-        
-        if first_line.starts_with('>') {
-            self.parse_fasta(reader, optimizer)
-        } else if first_line.starts_with('@') {
-            self.parse_fastq(reader, optimizer)
-        } else {
-            // Treat as raw DNA sequence
-            self.parse_raw_dna(reader, optimizer)
-        }
-    }
-    
-    fn parse_raw_dna(&self, reader: BufReader<File>, optimizer: &BinaryOptimizer) -> Result<Vec<SequenceData>> {
-        let mut sequences = Vec::new();
-        let mut seq_counter = 1;
-        
-        for line in reader.lines() {
-            let line = line?;
-            let sequence = line.trim().to_uppercase();
-            
-            // Validate DNA sequence
-            if sequence.chars().all(|c| matches!(c, 'A' | 'T' | 'G' | 'C' | 'N')) {
-                let header = format!("sequence_{}", seq_counter);
-                let seq_data = self.create_sequence_data(&header, &sequence, None, optimizer)?;
-                sequences.push(seq_data);
-                seq_counter += 1;
+            let sequence = String::from_utf8_lossy(&record.sequence);
+            let seq_data = self.create_sequence_data(&record.name, &sequence, record.quality, optimizer)?;
+            if seq_data.avg_quality() >= self.quality_threshold as f64 {
+                on_record(seq_data)?;
             }
         }
-        
-        Ok(sequences)
+        Ok(())
     }
     
     fn create_sequence_data(
@@ -168,14 +66,8 @@ impl Sequencer {
     ) -> Result<SequenceData> {
         // Convert to binary representation for processing
         let binary_seq = sequence
-            .chars()
-            .map(|c| match c {
-                'A' => 0,
-                'T' => 1,
-                'G' => 2,
-                'C' => 3,
-                _ => 0, // Default unknown to A
-            })
+            .bytes()
+            .map(|b| base_code(b).unwrap_or(0)) // Default unknown to A
             .collect();
         
         let quality = quality_scores.unwrap_or_else(|| vec![40; sequence.len()]); // Default high quality
@@ -212,7 +104,7 @@ impl SequenceData {
     
     pub fn gc_content(&self) -> f64 {
         let gc_count = self.sequence.chars()
-            .filter(|&c| c == 'G' || c == 'C')
+            .filter(|c| matches!(c, 'G' | 'C' | 'g' | 'c'))
             .count();
         
         gc_count as f64 / self.sequence.len() as f64
@@ -223,6 +115,134 @@ impl SequenceData {
     }
 }
 
+/// A FASTQ record with Phred qualities (no +33 offset)
+#[derive(Debug, Clone)]
+pub struct FastqRecord {
+    pub name: String,
+    pub sequence: Vec<u8>,
+    pub quality: Vec<u8>,
+}
+
+/// A FASTA, FASTQ or raw-sequence record as read, case preserved
+#[derive(Debug, Clone)]
+pub struct SequenceRecord {
+    pub name: String,
+    pub sequence: Vec<u8>,
+    /// Phred qualities (no +33 offset); `None` for FASTA and raw input
+    pub quality: Option<Vec<u8>>,
+}
+
+/// Pull-based reader for FASTA, FASTQ or one-sequence-per-line input, optionally gzipped
+///
+/// The format is taken from each record's first line, so a file may mix FASTA
+/// and FASTQ records. Lines outside any record are read as raw sequences and
+/// kept when they hold only nucleotide letters.
+pub struct SequenceReader {
+    reader: Box<dyn BufRead + Send>,
+    path: String,
+    /// Header line read past the end of the previous FASTA record
+    pending: Option<String>,
+    raw_records: usize,
+}
+
+impl SequenceReader {
+    pub fn open(input_path: &str) -> Result<Self> {
+        let file = File::open(input_path)
+            .map_err(|e| anyhow::anyhow!("Failed to open sequence file {}: {}", input_path, e))?;
+        let reader: Box<dyn BufRead + Send> = if input_path.ends_with(".gz") {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+        Ok(Self { reader, path: input_path.to_string(), pending: None, raw_records: 0 })
+    }
+
+    /// Next record, or `None` at end of file
+    pub fn next_record(&mut self) -> Result<Option<SequenceRecord>> {
+        loop {
+            let line = match self.pending.take() {
+                Some(line) => line,
+                None => {
+                    let mut line = String::new();
+                    if self.reader.read_line(&mut line)? == 0 {
+                        return Ok(None);
+                    }
+                    line
+                }
+            };
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('@') {
+                return self.fastq_record(header).map(Some);
+            }
+            if let Some(header) = line.strip_prefix('>') {
+                let name = header.to_string();
+                let mut sequence = Vec::new();
+                let mut next = String::new();
+                while self.reader.read_line(&mut next)? > 0 {
+                    if next.starts_with('>') || next.starts_with('@') {
+                        self.pending = Some(std::mem::take(&mut next));
+                        break;
+                    }
+                    sequence.extend_from_slice(next.trim().as_bytes());
+                    next.clear();
+                }
+                return Ok(Some(SequenceRecord { name, sequence, quality: None }));
+            }
+
+            let sequence = line.trim();
+            if sequence.bytes().all(|b| matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T' | b'N')) {
+                self.raw_records += 1;
+                return Ok(Some(SequenceRecord {
+                    name: format!("sequence_{}", self.raw_records),
+                    sequence: sequence.as_bytes().to_vec(),
+                    quality: None,
+                }));
+            }
+        }
+    }
+
+    fn fastq_record(&mut self, header: &str) -> Result<SequenceRecord> {
+        let mut lines = [String::new(), String::new(), String::new()];
+        for line in lines.iter_mut() {
+            if self.reader.read_line(line)? == 0 {
+                anyhow::bail!("Truncated FASTQ record in {}: {}", self.path, header);
+            }
+        }
+        let sequence = lines[0].trim_end().as_bytes().to_vec();
+        let quality: Vec<u8> = lines[2].trim_end().bytes().map(|q| q.saturating_sub(33)).collect(); // Phred+33
+        if quality.len() != sequence.len() {
+            anyhow::bail!("Sequence and quality lengths differ in {}: {}", self.path, header);
+        }
+        Ok(SequenceRecord { name: header.to_string(), sequence, quality: Some(quality) })
+    }
+}
+
+/// FASTQ-only view of `SequenceReader`, for reading paired files in step
+pub struct FastqReader {
+    inner: SequenceReader,
+}
+
+impl FastqReader {
+    pub fn open(input_path: &str) -> Result<Self> {
+        Ok(Self { inner: SequenceReader::open(input_path)? })
+    }
+
+    /// Next record, or `None` at end of file
+    pub fn next_record(&mut self) -> Result<Option<FastqRecord>> {
+        let Some(record) = self.inner.next_record()? else {
+            return Ok(None);
+        };
+        let Some(quality) = record.quality else {
+            anyhow::bail!("Expected FASTQ records in {}, found: {}", self.inner.path, record.name);
+        };
+        Ok(Some(FastqRecord { name: record.name, sequence: record.sequence, quality }))
+    }
+}
+
 /// Quality score utilities
 pub struct QualityScore;
 
@@ -0,0 +1,722 @@
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use rust_htslib::bam::{self, record::Cigar, Read};
+use rust_htslib::faidx;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+use crate::variant_caller::{
+    read_indels, sample_name, vcf_header, IndelAllele, VariantCaller, VariantType, BATCH_COLUMNS, INDEL_FLANK,
+    INDEL_MODEL, MISSING_BASE_QUALITY, SNP_MODEL,
+};
+
+/// EM rounds for the tumor allele fraction
+const FRACTION_ROUNDS: usize = 20;
+/// Allele fraction of a heterozygous germline variant, the normal's alternative hypothesis
+const GERMLINE_FRACTION: f64 = 0.5;
+/// Alternate reads the normal may carry before the call is a normal artefact
+const MAX_NORMAL_ALT_READS: u32 = 1;
+const MAX_BIAS_PHRED: f64 = 999.0;
+const TUMOR: usize = 0;
+const NORMAL: usize = 1;
+
+/// Meta lines for somatic records, after the standard ones
+const SOMATIC_HEADER_LINES: [&str; 13] = [
+    "##FILTER=<ID=germline,Description=\"Normal is not confidently homozygous reference (NLOD below threshold)\">",
+    "##FILTER=<ID=normal_artifact,Description=\"Alternate allele also seen in the normal\">",
+    "##FILTER=<ID=strand_bias,Description=\"Tumor alternate reads favour one strand (Fisher exact test)\">",
+    "##FILTER=<ID=orientation,Description=\"Tumor alternate reads favour F1R2 or F2R1 read orientation (Fisher exact test)\">",
+    "##INFO=<ID=SOMATIC,Number=0,Type=Flag,Description=\"Somatic candidate from a tumor/normal pair\">",
+    "##INFO=<ID=TLOD,Number=1,Type=Float,Description=\"Log10 likelihood ratio of the tumor carrying the allele at its estimated fraction versus not at all\">",
+    "##INFO=<ID=NLOD,Number=1,Type=Float,Description=\"Log10 likelihood ratio of the normal being homozygous reference versus heterozygous\">",
+    "##INFO=<ID=FS,Number=1,Type=Float,Description=\"Phred-scaled Fisher exact test p-value for tumor strand bias\">",
+    "##INFO=<ID=ROB,Number=1,Type=Float,Description=\"Phred-scaled Fisher exact test p-value for tumor read-orientation bias\">",
+    "##FORMAT=<ID=AF,Number=A,Type=Float,Description=\"Estimated allele fraction of the alternate allele\">",
+    "##FORMAT=<ID=SB,Number=4,Type=Integer,Description=\"Reference forward, reference reverse, alternate forward and alternate reverse reads\">",
+    "##FORMAT=<ID=F1R2,Number=R,Type=Integer,Description=\"Reads in F1R2 orientation supporting each allele\">",
+    "##FORMAT=<ID=F2R1,Number=R,Type=Integer,Description=\"Reads in F2R1 orientation supporting each allele\">",
+];
+
+/// One read's view of a site, with the strand and pair orientation it was sequenced in
+#[derive(Debug, Clone)]
+struct Observation<A> {
+    allele: A,
+    /// Error Phred: the lower of base (or indel) and mapping quality
+    quality: u8,
+    reverse: bool,
+    /// First of pair on the forward strand, or second of pair on the reverse
+    f1r2: bool,
+}
+
+/// Observations of one sample at one reference position
+#[derive(Debug, Default)]
+struct SampleColumn {
+    bases: Vec<Observation<u8>>,
+    /// Reads continuing to the next reference base: `None` follows the reference
+    indels: Vec<Observation<Option<IndelAllele>>>,
+}
+
+/// Tumor and normal observations at one reference position
+#[derive(Debug, Default)]
+struct PairedColumn {
+    samples: [SampleColumn; 2],
+}
+
+/// Which allele of a candidate site a read supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Support {
+    Reference,
+    Alternate,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ReadSupport {
+    support: Support,
+    quality: u8,
+    reverse: bool,
+    f1r2: bool,
+}
+
+/// Reads for the reference and alternate allele in one sample, by strand and orientation
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlleleSupport {
+    /// Reference reads on the forward and reverse strands
+    pub reference_strands: [u32; 2],
+    pub alternate_strands: [u32; 2],
+    /// Reference reads in F1R2 and F2R1 orientation
+    pub reference_orientations: [u32; 2],
+    pub alternate_orientations: [u32; 2],
+    /// Reads informative for the site, other alleles included
+    pub depth: u32,
+    /// Maximum-likelihood fraction of reads from the alternate allele
+    pub fraction: f64,
+}
+
+impl AlleleSupport {
+    fn tally(reads: &[ReadSupport], fraction: f64) -> Self {
+        let mut support = Self { depth: reads.len() as u32, fraction, ..Self::default() };
+        for read in reads {
+            let (strands, orientations) = match read.support {
+                Support::Reference => (&mut support.reference_strands, &mut support.reference_orientations),
+                Support::Alternate => (&mut support.alternate_strands, &mut support.alternate_orientations),
+                Support::Other => continue,
+            };
+            strands[usize::from(read.reverse)] += 1;
+            orientations[usize::from(!read.f1r2)] += 1;
+        }
+        support
+    }
+
+    pub fn reference_reads(&self) -> u32 {
+        self.reference_strands[0] + self.reference_strands[1]
+    }
+
+    pub fn alternate_reads(&self) -> u32 {
+        self.alternate_strands[0] + self.alternate_strands[1]
+    }
+
+    /// FORMAT values GT:AD:AF:DP:SB:F1R2:F2R1
+    fn format(&self, genotype: &str) -> String {
+        format!(
+            "{}:{},{}:{:.4}:{}:{},{},{},{}:{},{}:{},{}",
+            genotype,
+            self.reference_reads(),
+            self.alternate_reads(),
+            self.fraction,
+            self.depth,
+            self.reference_strands[0],
+            self.reference_strands[1],
+            self.alternate_strands[0],
+            self.alternate_strands[1],
+            self.reference_orientations[0],
+            self.alternate_orientations[0],
+            self.reference_orientations[1],
+            self.alternate_orientations[1]
+        )
+    }
+}
+
+/// One somatic candidate, biallelic, with the filters it failed
+#[derive(Debug, Clone)]
+pub struct SomaticCall {
+    pub chrom: String,
+    /// 0-based position of the first REF base
+    pub pos: u64,
+    pub reference: String,
+    pub alternate: String,
+    pub tumor: AlleleSupport,
+    pub normal: AlleleSupport,
+    /// log10 L(tumor | allele at its estimated fraction) - log10 L(tumor | allele absent)
+    pub tumor_lod: f64,
+    /// log10 L(normal | homozygous reference) - log10 L(normal | heterozygous)
+    pub normal_lod: f64,
+    /// Phred-scaled Fisher strand bias of the tumor reads
+    pub strand_bias: f64,
+    /// Phred-scaled Fisher F1R2/F2R1 orientation bias of the tumor reads
+    pub orientation_bias: f64,
+    /// Failed filters; empty means PASS
+    pub filters: Vec<&'static str>,
+}
+
+impl SomaticCall {
+    pub fn is_indel(&self) -> bool {
+        self.reference.len() != self.alternate.len()
+    }
+
+    pub fn passed(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn filter(&self) -> String {
+        if self.filters.is_empty() { "PASS".to_string() } else { self.filters.join(";") }
+    }
+
+    /// Record line with QUAL left missing, as the evidence is in TLOD and NLOD; tumor sample first
+    pub fn to_vcf_line(&self) -> String {
+        format!(
+            "{}\t{}\t.\t{}\t{}\t.\t{}\tSOMATIC;DP={};TLOD={:.2};NLOD={:.2};FS={:.2};ROB={:.2}\tGT:AD:AF:DP:SB:F1R2:F2R1\t{}\t{}",
+            self.chrom,
+            self.pos + 1,
+            self.reference,
+            self.alternate,
+            self.filter(),
+            self.tumor.depth + self.normal.depth,
+            self.tumor_lod,
+            self.normal_lod,
+            self.strand_bias,
+            self.orientation_bias,
+            self.tumor.format("0/1"),
+            self.normal.format("0/0")
+        )
+    }
+}
+
+impl std::fmt::Display for SomaticCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{} {}>{} ({}, VAF {:.1}%, TLOD {:.1}, NLOD {:.1}, {})",
+            self.chrom,
+            self.pos + 1,
+            self.reference,
+            self.alternate,
+            if self.is_indel() { "INDEL" } else { "SNV" },
+            100.0 * self.tumor.fraction,
+            self.tumor_lod,
+            self.normal_lod,
+            self.filter()
+        )
+    }
+}
+
+/// Somatic calls from a tumor/normal pair
+#[derive(Debug, Clone)]
+pub struct SomaticCallSet {
+    pub tumor_sample: String,
+    pub normal_sample: String,
+    pub contigs: Vec<(String, u64)>,
+    pub calls: Vec<SomaticCall>,
+    pub tumor_reads: u64,
+    pub normal_reads: u64,
+    pub reads_filtered: u64,
+}
+
+impl SomaticCallSet {
+    pub fn passed(&self) -> usize {
+        self.calls.iter().filter(|call| call.passed()).count()
+    }
+
+    pub fn snv_count(&self) -> usize {
+        self.calls.iter().filter(|call| !call.is_indel()).count()
+    }
+
+    pub fn indel_count(&self) -> usize {
+        self.calls.iter().filter(|call| call.is_indel()).count()
+    }
+
+    /// How many calls failed each filter, in header order
+    pub fn filter_counts(&self) -> Vec<(&'static str, usize)> {
+        ["germline", "normal_artifact", "strand_bias", "orientation"]
+            .into_iter()
+            .map(|name| (name, self.calls.iter().filter(|call| call.filters.contains(&name)).count()))
+            .collect()
+    }
+
+    pub fn write_vcf(&self, path: &str, reference: &str) -> Result<()> {
+        let file = std::fs::File::create(path).with_context(|| format!("Could not create VCF: {}", path))?;
+        let mut out = BufWriter::new(file);
+        let samples = [format!("##tumor_sample={}", self.tumor_sample), format!("##normal_sample={}", self.normal_sample)];
+        let extra: Vec<&str> = SOMATIC_HEADER_LINES.iter().copied().chain(samples.iter().map(String::as_str)).collect();
+        let header = vcf_header(&self.contigs, reference, &[self.tumor_sample.clone(), self.normal_sample.clone()], &extra);
+        out.write_all(header.as_bytes())?;
+        for call in &self.calls {
+            writeln!(out, "{}", call.to_vcf_line())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl VariantCaller {
+    /// Call somatic SNVs and indels in a tumor against its matched normal
+    ///
+    /// Both coordinate-sorted BAMs are read together in position order into
+    /// paired pileup columns. Every tumor allele with enough reads is scored
+    /// as Mutect does: TLOD compares the tumor reads with the allele at its
+    /// maximum-likelihood fraction against the allele being absent, so
+    /// subclonal fractions are modelled rather than forced to a diploid
+    /// genotype. Candidates above `min_tumor_lod` are reported, then filtered
+    /// as germline when NLOD (normal homozygous reference versus
+    /// heterozygous) falls short, as normal artefacts when the normal carries
+    /// the allele, and for strand or F1R2/F2R1 orientation bias of the tumor
+    /// alternate reads, which oxidation and deamination damage produce.
+    pub fn call_somatic(
+        &self,
+        tumor_path: &str,
+        normal_path: &str,
+        reference: &str,
+        variant_type: VariantType,
+    ) -> Result<SomaticCallSet> {
+        if variant_type == VariantType::Sv {
+            bail!("Somatic mode calls SNVs and indels; use a variant type of snp, indel or all");
+        }
+        let paths = [tumor_path, normal_path];
+        let mut readers = [open_alignments(tumor_path)?, open_alignments(normal_path)?];
+        let headers = [readers[TUMOR].header().clone(), readers[NORMAL].header().clone()];
+        let contigs = [contig_list(&headers[TUMOR]), contig_list(&headers[NORMAL])];
+        if contigs[TUMOR] != contigs[NORMAL] {
+            bail!("Tumor {} and normal {} were aligned to different contigs", tumor_path, normal_path);
+        }
+        let fasta = faidx::Reader::from_path(reference)
+            .with_context(|| format!("Could not open indexed reference: {}", reference))?;
+        let fasta_names: Vec<String> =
+            (0..fasta.n_seqs() as i32).map(|i| fasta.seq_name(i)).collect::<std::result::Result<_, _>>()?;
+
+        let [tumor_reader, normal_reader] = &mut readers;
+        let mut records = [tumor_reader.records(), normal_reader.records()];
+        let mut set = SomaticCallSet {
+            tumor_sample: sample_name(headers[TUMOR].as_bytes(), tumor_path),
+            normal_sample: sample_name(headers[NORMAL].as_bytes(), normal_path),
+            contigs: contigs[TUMOR].clone(),
+            calls: Vec::new(),
+            tumor_reads: 0,
+            normal_reads: 0,
+            reads_filtered: 0,
+        };
+        if set.tumor_sample == set.normal_sample {
+            set.normal_sample.push_str("_normal");
+        }
+        let min_mapping_quality = self.params().min_mapping_quality;
+        let mut heads = [
+            next_read(&mut records[TUMOR], min_mapping_quality, &mut set.reads_filtered)?,
+            next_read(&mut records[NORMAL], min_mapping_quality, &mut set.reads_filtered)?,
+        ];
+
+        // Current contig: tid, name and uppercase reference sequence
+        let mut current: Option<(i32, String, Vec<u8>)> = None;
+        let mut columns: BTreeMap<u64, PairedColumn> = BTreeMap::new();
+        let mut pending: Vec<(u64, PairedColumn)> = Vec::new();
+        let mut last = [(0i32, 0i64); 2];
+        loop {
+            // Whichever file is behind goes next, so both advance in step
+            let sample = match (&heads[TUMOR], &heads[NORMAL]) {
+                (None, None) => break,
+                (Some(_), None) => TUMOR,
+                (None, Some(_)) => NORMAL,
+                (Some(tumor), Some(normal)) => {
+                    if (normal.tid(), normal.pos()) < (tumor.tid(), tumor.pos()) { NORMAL } else { TUMOR }
+                }
+            };
+            let Some(record) = heads[sample].take() else { break };
+            heads[sample] = next_read(&mut records[sample], min_mapping_quality, &mut set.reads_filtered)?;
+            if (record.tid(), record.pos()) < last[sample] {
+                bail!("{} is not coordinate-sorted (position {} after {})", paths[sample], record.pos() + 1, last[sample].1 + 1);
+            }
+            last[sample] = (record.tid(), record.pos());
+
+            let tid = record.tid();
+            if current.as_ref().map(|(t, ..)| *t) != Some(tid) {
+                if let Some((_, name, sequence)) = current.take() {
+                    pending.extend(std::mem::take(&mut columns));
+                    set.calls.extend(self.genotype_somatic(&name, &sequence, &mut pending, variant_type));
+                }
+                let (name, length) = set.contigs[tid as usize].clone();
+                if !fasta_names.contains(&name) {
+                    bail!("Contig {} from {} is missing from {}", name, paths[sample], reference);
+                }
+                let sequence = match length {
+                    0 => Vec::new(),
+                    _ => fasta.fetch_seq(&name, 0, length as usize - 1)?.to_ascii_uppercase(),
+                };
+                current = Some((tid, name, sequence));
+            }
+
+            // Columns left of this read are complete in both samples
+            let rest = columns.split_off(&(record.pos() as u64));
+            pending.extend(std::mem::replace(&mut columns, rest));
+            if let Some((_, name, sequence)) = &current {
+                if pending.len() >= BATCH_COLUMNS {
+                    set.calls.extend(self.genotype_somatic(name, sequence, &mut pending, variant_type));
+                }
+                self.add_somatic_record(&mut columns, &record, sequence, sample);
+            }
+            match sample {
+                TUMOR => set.tumor_reads += 1,
+                _ => set.normal_reads += 1,
+            }
+        }
+
+        if let Some((_, name, sequence)) = current {
+            pending.extend(columns);
+            set.calls.extend(self.genotype_somatic(&name, &sequence, &mut pending, variant_type));
+        }
+        Ok(set)
+    }
+
+    /// Stack one read's bases and indels onto its sample's side of the pileup
+    ///
+    /// Mirrors the germline pileup, keeping each observation's strand and
+    /// pair orientation; unpaired reads count as first of pair.
+    fn add_somatic_record(&self, columns: &mut BTreeMap<u64, PairedColumn>, record: &bam::Record, reference: &[u8], sample: usize) {
+        let params = self.params();
+        let sequence = record.seq().as_bytes();
+        let qualities = record.qual();
+        let mapq = record.mapq();
+        let indel_quality = mapq.min(params.indel_quality);
+        let reverse = record.is_reverse();
+        let f1r2 = reverse != (!record.is_paired() || record.is_first_in_template());
+        let cigar: Vec<Cigar> = record.cigar().iter().copied().collect();
+        let (indels, aligned_end) = read_indels(&cigar, &sequence, record.pos() as u64, reference);
+
+        let (mut ref_pos, mut query_pos) = (record.pos() as u64, 0usize);
+        for op in &cigar {
+            match *op {
+                Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                    for _ in 0..len {
+                        let column = &mut columns.entry(ref_pos).or_default().samples[sample];
+                        let base = sequence[query_pos];
+                        let quality = match qualities.get(query_pos) {
+                            Some(&255) | None => MISSING_BASE_QUALITY,
+                            Some(&q) => q,
+                        };
+                        if quality >= params.min_base_quality
+                            && matches!(base, b'A' | b'C' | b'G' | b'T')
+                            && column.bases.len() < params.max_depth
+                        {
+                            column.bases.push(Observation { allele: base, quality: quality.min(mapq), reverse, f1r2 });
+                        }
+
+                        let following = match indels.iter().find(|(anchor, _)| *anchor == ref_pos) {
+                            Some((_, allele)) => Some(Some(allele.clone())),
+                            None if ref_pos + INDEL_FLANK < aligned_end => Some(None),
+                            None => None,
+                        };
+                        if let Some(allele) = following {
+                            if column.indels.len() < params.max_depth {
+                                column.indels.push(Observation { allele, quality: indel_quality, reverse, f1r2 });
+                            }
+                        }
+
+                        ref_pos += 1;
+                        query_pos += 1;
+                    }
+                }
+                Cigar::Ins(len) | Cigar::SoftClip(len) => query_pos += len as usize,
+                Cigar::Del(len) | Cigar::RefSkip(len) => ref_pos += len as u64,
+                Cigar::HardClip(_) | Cigar::Pad(_) => {}
+            }
+        }
+    }
+
+    /// Score and clear the pending columns, in parallel
+    fn genotype_somatic(
+        &self,
+        chrom: &str,
+        sequence: &[u8],
+        pending: &mut Vec<(u64, PairedColumn)>,
+        variant_type: VariantType,
+    ) -> Vec<SomaticCall> {
+        let calls: Vec<SomaticCall> = pending
+            .par_iter()
+            .flat_map_iter(|(pos, column)| {
+                let snv = if variant_type.includes_snps() { self.somatic_snv(chrom, sequence, *pos, column) } else { None };
+                let indel =
+                    if variant_type.includes_indels() { self.somatic_indel(chrom, sequence, *pos, column) } else { None };
+                snv.into_iter().chain(indel)
+            })
+            .collect();
+        pending.clear();
+        calls
+    }
+
+    fn somatic_snv(&self, chrom: &str, sequence: &[u8], pos: u64, column: &PairedColumn) -> Option<SomaticCall> {
+        let reference = *sequence.get(pos as usize)?;
+        let tumor = &column.samples[TUMOR].bases;
+        if !matches!(reference, b'A' | b'C' | b'G' | b'T') {
+            return None;
+        }
+        let alternate = self.somatic_candidate(tumor.iter().map(|o| &o.allele).filter(|&&base| base != reference), tumor.len())?;
+        self.score_somatic(
+            (chrom, pos),
+            ((reference as char).to_string(), (alternate as char).to_string()),
+            supports(tumor, &reference, &alternate),
+            supports(&column.samples[NORMAL].bases, &reference, &alternate),
+            SNP_MODEL.error_share,
+        )
+    }
+
+    fn somatic_indel(&self, chrom: &str, sequence: &[u8], pos: u64, column: &PairedColumn) -> Option<SomaticCall> {
+        let tumor = &column.samples[TUMOR].indels;
+        let alternate = self.somatic_candidate(tumor.iter().filter_map(|o| o.allele.as_ref()), tumor.len())?;
+        let start = pos as usize;
+        let (reference_allele, alternate_allele) = match &alternate {
+            IndelAllele::Deletion(len) => {
+                let span = sequence.get(start..=start + len)?;
+                (span.to_vec(), vec![span[0]])
+            }
+            IndelAllele::Insertion(bases) => {
+                let anchor = *sequence.get(start)?;
+                (vec![anchor], std::iter::once(anchor).chain(bases.iter().copied()).collect())
+            }
+        };
+        let alternate = Some(alternate.clone());
+        self.score_somatic(
+            (chrom, pos),
+            (String::from_utf8_lossy(&reference_allele).into_owned(), String::from_utf8_lossy(&alternate_allele).into_owned()),
+            supports(tumor, &None, &alternate),
+            supports(&column.samples[NORMAL].indels, &None, &alternate),
+            INDEL_MODEL.error_share,
+        )
+    }
+
+    /// Most supported tumor allele, if it has the reads and fraction a somatic candidate needs
+    fn somatic_candidate<'a, A: Ord + Clone + 'a>(&self, alleles: impl Iterator<Item = &'a A>, depth: usize) -> Option<A> {
+        let params = self.params();
+        if depth < self.min_coverage().max(1) as usize {
+            return None;
+        }
+        let mut counts: BTreeMap<&A, u32> = BTreeMap::new();
+        for allele in alleles {
+            *counts.entry(allele).or_default() += 1;
+        }
+        let (allele, count) = counts.into_iter().max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))?;
+        (count >= params.min_alt_reads && count as f64 >= params.min_somatic_fraction * depth as f64).then(|| allele.clone())
+    }
+
+    fn score_somatic(
+        &self,
+        (chrom, pos): (&str, u64),
+        (reference, alternate): (String, String),
+        tumor: Vec<ReadSupport>,
+        normal: Vec<ReadSupport>,
+        error_share: f64,
+    ) -> Option<SomaticCall> {
+        let params = self.params();
+        let tumor_fraction = allele_fraction(&tumor, error_share);
+        let tumor_lod = log10_likelihood(&tumor, tumor_fraction, error_share) - log10_likelihood(&tumor, 0.0, error_share);
+        if tumor_lod < params.min_tumor_lod {
+            return None;
+        }
+        let normal_lod =
+            log10_likelihood(&normal, 0.0, error_share) - log10_likelihood(&normal, GERMLINE_FRACTION, error_share);
+        let tumor = AlleleSupport::tally(&tumor, tumor_fraction);
+        let normal = AlleleSupport::tally(&normal, allele_fraction(&normal, error_share));
+        let strand_bias = fisher_phred([tumor.reference_strands, tumor.alternate_strands]);
+        let orientation_bias = fisher_phred([tumor.reference_orientations, tumor.alternate_orientations]);
+
+        let mut filters = Vec::new();
+        if normal_lod < params.min_normal_lod {
+            filters.push("germline");
+        }
+        if normal.alternate_reads() > MAX_NORMAL_ALT_READS {
+            filters.push("normal_artifact");
+        }
+        if strand_bias > params.max_strand_bias {
+            filters.push("strand_bias");
+        }
+        if orientation_bias > params.max_orientation_bias {
+            filters.push("orientation");
+        }
+        Some(SomaticCall {
+            chrom: chrom.to_string(),
+            pos,
+            reference,
+            alternate,
+            tumor,
+            normal,
+            tumor_lod,
+            normal_lod,
+            strand_bias,
+            orientation_bias,
+            filters,
+        })
+    }
+}
+
+fn open_alignments(path: &str) -> Result<bam::Reader> {
+    bam::Reader::from_path(path).with_context(|| format!("Could not open alignments: {}", path))
+}
+
+fn contig_list(header: &bam::HeaderView) -> Vec<(String, u64)> {
+    (0..header.target_count())
+        .map(|tid| (String::from_utf8_lossy(header.tid2name(tid)).into_owned(), header.target_len(tid).unwrap_or(0)))
+        .collect()
+}
+
+/// Next read that passes the caller's read filters
+fn next_read(
+    records: &mut bam::Records<'_, bam::Reader>,
+    min_mapping_quality: u8,
+    filtered: &mut u64,
+) -> Result<Option<bam::Record>> {
+    for result in records {
+        let record = result.context("Could not read alignment record")?;
+        if record.is_unmapped()
+            || record.is_secondary()
+            || record.is_supplementary()
+            || record.is_quality_check_failed()
+            || record.is_duplicate()
+            || record.mapq() < min_mapping_quality
+            || record.tid() < 0
+        {
+            *filtered += 1;
+            continue;
+        }
+        return Ok(Some(record));
+    }
+    Ok(None)
+}
+
+fn supports<A: PartialEq>(observations: &[Observation<A>], reference: &A, alternate: &A) -> Vec<ReadSupport> {
+    observations
+        .iter()
+        .map(|observation| ReadSupport {
+            support: if observation.allele == *alternate {
+                Support::Alternate
+            } else if observation.allele == *reference {
+                Support::Reference
+            } else {
+                Support::Other
+            },
+            quality: observation.quality,
+            reverse: observation.reverse,
+            f1r2: observation.f1r2,
+        })
+        .collect()
+}
+
+/// P(read | alternate allele) and P(read | reference allele)
+fn read_likelihoods(read: &ReadSupport, error_share: f64) -> (f64, f64) {
+    let error = 10f64.powf(-(read.quality as f64) / 10.0).min(0.75);
+    match read.support {
+        Support::Alternate => (1.0 - error, error / error_share),
+        Support::Reference => (error / error_share, 1.0 - error),
+        Support::Other => (error / error_share, error / error_share),
+    }
+}
+
+/// log10 P(reads | a fraction `fraction` of them come from the alternate allele)
+fn log10_likelihood(reads: &[ReadSupport], fraction: f64, error_share: f64) -> f64 {
+    reads
+        .iter()
+        .map(|read| {
+            let (alternate, reference) = read_likelihoods(read, error_share);
+            (fraction * alternate + (1.0 - fraction) * reference).log10()
+        })
+        .sum()
+}
+
+/// Maximum-likelihood alternate allele fraction by EM, started from the read count
+fn allele_fraction(reads: &[ReadSupport], error_share: f64) -> f64 {
+    if reads.is_empty() {
+        return 0.0;
+    }
+    let likelihoods: Vec<(f64, f64)> = reads.iter().map(|read| read_likelihoods(read, error_share)).collect();
+    let mut fraction = reads.iter().filter(|read| read.support == Support::Alternate).count() as f64 / reads.len() as f64;
+    for _ in 0..FRACTION_ROUNDS {
+        if fraction <= 0.0 || fraction >= 1.0 {
+            break;
+        }
+        let responsibility: f64 = likelihoods
+            .iter()
+            .map(|(alternate, reference)| fraction * alternate / (fraction * alternate + (1.0 - fraction) * reference))
+            .sum();
+        fraction = responsibility / reads.len() as f64;
+    }
+    fraction
+}
+
+/// Phred-scaled two-sided Fisher exact test p-value of a 2x2 table
+fn fisher_phred(table: [[u32; 2]; 2]) -> f64 {
+    let [[a, b], [c, d]] = table;
+    let (row1, row2, column1) = (a + b, c + d, a + c);
+    let n = (row1 + row2) as usize;
+    let ln_factorial: Vec<f64> = (0..=n)
+        .scan(0.0, |sum, i| {
+            if i > 0 {
+                *sum += (i as f64).ln();
+            }
+            Some(*sum)
+        })
+        .collect();
+    let ln_f = |k: u32| ln_factorial[k as usize];
+    let fixed = ln_f(row1) + ln_f(row2) + ln_f(column1) + ln_f(b + d) - ln_factorial[n];
+    let ln_p = |x: u32| fixed - ln_f(x) - ln_f(row1 - x) - ln_f(column1 - x) - ln_f(row2 + x - column1);
+    let observed = ln_p(a);
+    let p: f64 = (column1.saturating_sub(row2)..=row1.min(column1))
+        .map(ln_p)
+        .filter(|&l| l <= observed + 1e-7)
+        .map(f64::exp)
+        .sum();
+    (-10.0 * p.min(1.0).log10()).clamp(0.0, MAX_BIAS_PHRED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reads(alternate: usize, reference: usize, quality: u8) -> Vec<ReadSupport> {
+        let read = |support| ReadSupport { support, quality, reverse: false, f1r2: false };
+        (0..alternate).map(|_| read(Support::Alternate)).chain((0..reference).map(|_| read(Support::Reference))).collect()
+    }
+
+    fn tumor_lod(reads: &[ReadSupport]) -> f64 {
+        let fraction = allele_fraction(reads, 3.0);
+        log10_likelihood(reads, fraction, 3.0) - log10_likelihood(reads, 0.0, 3.0)
+    }
+
+    fn normal_lod(reads: &[ReadSupport]) -> f64 {
+        log10_likelihood(reads, 0.0, 3.0) - log10_likelihood(reads, GERMLINE_FRACTION, 3.0)
+    }
+
+    #[test]
+    fn tumor_lod_of_pure_alternate_reads() {
+        // Each Q30 read adds log10(0.999 / (0.001 / 3))
+        let expected = 10.0 * (0.999f64 / (0.001 / 3.0)).log10();
+        assert!((tumor_lod(&reads(10, 0, 30)) - expected).abs() < 1e-9);
+        assert_eq!(tumor_lod(&reads(0, 30, 30)), 0.0);
+    }
+
+    #[test]
+    fn tumor_lod_grows_with_subclonal_support() {
+        let lods: Vec<f64> = [2, 4, 8].iter().map(|&alt| tumor_lod(&reads(alt, 60, 30))).collect();
+        assert!(lods.windows(2).all(|pair| pair[1] > pair[0]), "{:?}", lods);
+        assert!((allele_fraction(&reads(15, 45, 40), 3.0) - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn normal_lod_rewards_clean_reference_depth() {
+        // Each clean Q30 reference read is about twice as likely homozygous as heterozygous
+        let per_read = 0.999f64.log10() - (0.5 * 0.001 / 3.0 + 0.5 * 0.999f64).log10();
+        assert!((normal_lod(&reads(0, 30, 30)) - 30.0 * per_read).abs() < 1e-9);
+        assert!(normal_lod(&reads(3, 10, 30)) < 0.0);
+        assert_eq!(normal_lod(&[]), 0.0);
+    }
+
+    #[test]
+    fn fisher_phred_matches_exact_test() {
+        assert!((fisher_phred([[3, 1], [1, 3]]) - -10.0 * (34.0f64 / 70.0).log10()).abs() < 1e-9);
+        assert!((fisher_phred([[10, 0], [0, 10]]) - -10.0 * (2.0f64 / 184756.0).log10()).abs() < 1e-6);
+        assert_eq!(fisher_phred([[5, 5], [5, 5]]), 0.0);
+    }
+}
@@ -0,0 +1,307 @@
+use wide::{i16x8, u8x16, CmpEq};
+use crate::alignment::ScoringScheme;
+use crate::kmer_filter::base_code;
+
+/// Width of the score lanes that produced a striped result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScorePrecision {
+    /// 16 lanes of biased unsigned bytes
+    Byte,
+    /// 8 lanes of signed 16-bit words
+    Word,
+    /// Scalar 32-bit DP, used when 16-bit scores saturate
+    Scalar,
+}
+
+/// Best local alignment score of a query against one target
+#[derive(Debug, Clone, Copy)]
+pub struct LocalScore {
+    pub score: i32,
+    /// Zero-based target position of the last aligned base
+    pub target_end: usize,
+    pub precision: ScorePrecision,
+}
+
+/// Query profile for Farrar's striped Smith-Waterman
+///
+/// The query is split into `segments` stripes so that lane k of vector i holds
+/// query position `k * segments + i`. Dependencies along the query then run
+/// between vectors rather than between lanes, and the vertical (F) gap is
+/// fixed up afterwards by the "lazy F" loop, which rarely iterates more than
+/// once. Scores start in 8-bit lanes and are recomputed in 16-bit lanes only
+/// for targets whose score saturates.
+pub struct StripedProfile {
+    query_len: usize,
+    scoring: ScoringScheme,
+    bias: u8,
+    byte_segments: usize,
+    word_segments: usize,
+    byte_profile: Vec<[u8x16; 5]>,
+    word_profile: Vec<[i16x8; 5]>,
+}
+
+/// Representative base of each profile row: the 2-bit codes, then everything else
+const ROW_BASES: &[u8; 5] = b"ATGCN";
+
+fn residue_index(base: u8) -> usize {
+    base_code(base).map_or(4, usize::from)
+}
+
+impl StripedProfile {
+    pub fn new(query: &[u8], scoring: &ScoringScheme) -> Self {
+        let bias = scoring.mismatch.min(0).unsigned_abs().min(255) as u8;
+        let byte_segments = query.len().div_ceil(16).max(1);
+        let word_segments = query.len().div_ceil(8).max(1);
+
+        let score = |residue: usize, pos: usize| -> i32 {
+            query.get(pos).map_or(0, |&q| scoring.score(q, ROW_BASES[residue]))
+        };
+
+        let byte_profile = (0..byte_segments)
+            .map(|i| {
+                std::array::from_fn(|r| {
+                    let lanes: [u8; 16] = std::array::from_fn(|k| {
+                        (score(r, k * byte_segments + i) + bias as i32).clamp(0, 255) as u8
+                    });
+                    u8x16::from(lanes)
+                })
+            })
+            .collect();
+
+        let word_profile = (0..word_segments)
+            .map(|i| {
+                std::array::from_fn(|r| {
+                    let lanes: [i16; 8] = std::array::from_fn(|k| score(r, k * word_segments + i) as i16);
+                    i16x8::from(lanes)
+                })
+            })
+            .collect();
+
+        Self {
+            query_len: query.len(),
+            scoring: scoring.clone(),
+            bias,
+            byte_segments,
+            word_segments,
+            byte_profile,
+            word_profile,
+        }
+    }
+
+    /// Best local score against `target`, widening lanes on saturation
+    pub fn align(&self, query: &[u8], target: &[u8]) -> LocalScore {
+        if self.query_len == 0 || target.is_empty() {
+            return LocalScore { score: 0, target_end: 0, precision: ScorePrecision::Byte };
+        }
+
+        if let Some(result) = self.align_byte(target) {
+            return result;
+        }
+        if let Some(result) = self.align_word(target) {
+            return result;
+        }
+
+        let (score, target_end) = scalar_local_score(query, target, &self.scoring);
+        LocalScore { score, target_end, precision: ScorePrecision::Scalar }
+    }
+
+    /// 8-bit pass; `None` when scores approach saturation
+    fn align_byte(&self, target: &[u8]) -> Option<LocalScore> {
+        let segments = self.byte_segments;
+        let zero = u8x16::splat(0);
+        let bias = u8x16::splat(self.bias);
+        let gap_open = u8x16::splat((self.scoring.gap_open + self.scoring.gap_extend).clamp(0, 255) as u8);
+        let gap_extend = u8x16::splat(self.scoring.gap_extend.clamp(0, 255) as u8);
+
+        let mut h_store = vec![zero; segments];
+        let mut h_load = vec![zero; segments];
+        let mut e = vec![zero; segments];
+        let (mut best, mut best_end) = (0u8, 0usize);
+
+        for (j, &base) in target.iter().enumerate() {
+            let residue = residue_index(base);
+            let mut f = zero;
+            let mut h = shift_lanes_u8(h_store[segments - 1]);
+            std::mem::swap(&mut h_load, &mut h_store);
+
+            for i in 0..segments {
+                h = h.saturating_add(self.byte_profile[i][residue]).saturating_sub(bias);
+                h = h.max(e[i]).max(f);
+                h_store[i] = h;
+
+                let h_open = h.saturating_sub(gap_open);
+                e[i] = e[i].saturating_sub(gap_extend).max(h_open);
+                f = f.saturating_sub(gap_extend).max(h_open);
+                h = h_load[i];
+            }
+
+            // Lazy F: propagate vertical gaps across stripe boundaries
+            f = shift_lanes_u8(f);
+            let mut i = 0;
+            loop {
+                let threshold = h_store[i].saturating_sub(gap_open);
+                if f.max(threshold).cmp_eq(threshold).all() {
+                    break;
+                }
+                h_store[i] = h_store[i].max(f);
+                e[i] = e[i].max(h_store[i].saturating_sub(gap_open));
+                f = f.saturating_sub(gap_extend);
+                i += 1;
+                if i == segments {
+                    i = 0;
+                    f = shift_lanes_u8(f);
+                }
+            }
+
+            let column_max = h_store.iter().fold(zero, |acc, &v| acc.max(v));
+            let column_best = column_max.to_array().into_iter().max().unwrap_or(0);
+            if column_best > best {
+                best = column_best;
+                best_end = j;
+            }
+            if best as u32 + self.bias as u32 >= 255 {
+                return None;
+            }
+        }
+
+        Some(LocalScore { score: best as i32, target_end: best_end, precision: ScorePrecision::Byte })
+    }
+
+    /// 16-bit pass; `None` when scores approach saturation
+    fn align_word(&self, target: &[u8]) -> Option<LocalScore> {
+        let segments = self.word_segments;
+        let zero = i16x8::splat(0);
+        let gap_open = i16x8::splat((self.scoring.gap_open + self.scoring.gap_extend) as i16);
+        let gap_extend = i16x8::splat(self.scoring.gap_extend as i16);
+        let limit = i16::MAX as i32 - self.scoring.match_score;
+
+        let mut h_store = vec![zero; segments];
+        let mut h_load = vec![zero; segments];
+        let mut e = vec![zero; segments];
+        let (mut best, mut best_end) = (0i16, 0usize);
+
+        for (j, &base) in target.iter().enumerate() {
+            let residue = residue_index(base);
+            let mut f = zero;
+            let mut h = shift_lanes_i16(h_store[segments - 1]);
+            std::mem::swap(&mut h_load, &mut h_store);
+
+            for i in 0..segments {
+                h = h.saturating_add(self.word_profile[i][residue]).max(zero);
+                h = h.max(e[i]).max(f);
+                h_store[i] = h;
+
+                let h_open = h.saturating_sub(gap_open);
+                e[i] = e[i].saturating_sub(gap_extend).max(h_open);
+                f = f.saturating_sub(gap_extend).max(h_open);
+                h = h_load[i];
+            }
+
+            f = shift_lanes_i16(f);
+            let mut i = 0;
+            loop {
+                let threshold = h_store[i].saturating_sub(gap_open);
+                if f.max(threshold).cmp_eq(threshold).all() {
+                    break;
+                }
+                h_store[i] = h_store[i].max(f);
+                e[i] = e[i].max(h_store[i].saturating_sub(gap_open));
+                f = f.saturating_sub(gap_extend);
+                i += 1;
+                if i == segments {
+                    i = 0;
+                    f = shift_lanes_i16(f);
+                }
+            }
+
+            let column_max = h_store.iter().fold(zero, |acc, &v| acc.max(v));
+            let column_best = column_max.to_array().into_iter().max().unwrap_or(0);
+            if column_best > best {
+                best = column_best;
+                best_end = j;
+            }
+            if best as i32 >= limit {
+                return None;
+            }
+        }
+
+        Some(LocalScore { score: best as i32, target_end: best_end, precision: ScorePrecision::Word })
+    }
+}
+
+/// Move every lane up by one, filling lane 0 with zero
+fn shift_lanes_u8(v: u8x16) -> u8x16 {
+    let lanes = v.to_array();
+    u8x16::from(std::array::from_fn::<u8, 16, _>(|k| if k == 0 { 0 } else { lanes[k - 1] }))
+}
+
+fn shift_lanes_i16(v: i16x8) -> i16x8 {
+    let lanes = v.to_array();
+    i16x8::from(std::array::from_fn::<i16, 8, _>(|k| if k == 0 { 0 } else { lanes[k - 1] }))
+}
+
+/// Linear-memory scalar Smith-Waterman score with affine gaps
+pub fn scalar_local_score(query: &[u8], target: &[u8], scoring: &ScoringScheme) -> (i32, usize) {
+    let open = scoring.gap_open + scoring.gap_extend;
+    let ext = scoring.gap_extend;
+    let mut h = vec![0i32; query.len() + 1];
+    let mut e = vec![0i32; query.len() + 1];
+    let (mut best, mut best_end) = (0, 0);
+
+    for (j, &t) in target.iter().enumerate() {
+        let (mut diag, mut f) = (0, 0);
+        for i in 1..=query.len() {
+            e[i] = (e[i] - ext).max(h[i] - open);
+            let score = (diag + scoring.score(query[i - 1], t)).max(e[i]).max(f).max(0);
+            f = (f - ext).max(score - open);
+            diag = h[i];
+            h[i] = score;
+            if score > best {
+                best = score;
+                best_end = j;
+            }
+        }
+    }
+
+    (best, best_end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ambiguous_bases_score_like_the_scoring_scheme() {
+        let scoring = ScoringScheme::default();
+        let profile = StripedProfile::new(b"NNNN", &scoring);
+        assert_eq!(profile.align(b"NNNN", b"NNNN").score, 0);
+        assert_eq!(scoring.score(b'N', b'N'), scoring.mismatch);
+        assert_eq!(scoring.score(b't', b'U'), scoring.match_score);
+    }
+
+    #[test]
+    fn striped_scores_match_scalar_dp() {
+        let mut state = 11u64;
+        let mut random = |len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    b"ACGTACGTACGTacgtNU"[(state >> 33) as usize % 18]
+                })
+                .collect()
+        };
+        let scoring = ScoringScheme::default();
+        let query = random(150);
+        let profile = StripedProfile::new(&query, &scoring);
+        for len in [1, 40, 300] {
+            let target = random(len);
+            let striped = profile.align(&query, &target);
+            assert_eq!((striped.score, striped.target_end), scalar_local_score(&query, &target, &scoring));
+        }
+        // A long identical target saturates the byte lanes
+        let query = random(400);
+        let striped = StripedProfile::new(&query, &scoring).align(&query, &query);
+        assert_eq!(striped.score, scalar_local_score(&query, &query, &scoring).0);
+        assert_ne!(striped.precision, ScorePrecision::Byte);
+    }
+}
@@ -0,0 +1,701 @@
+use anyhow::{bail, Context, Result};
+use rust_htslib::bam::{self, record::{Aux, Cigar}, Read};
+use rust_htslib::faidx;
+use std::io::{BufWriter, Write};
+use crate::mapper::InsertSizeModel;
+use crate::variant_caller::{vcf_header, VariantCaller};
+
+/// Properly oriented pairs sampled to estimate the insert-size distribution
+const INSERT_SAMPLE_PAIRS: usize = 100_000;
+/// Inserts beyond this are never taken as proper when sampling, as in the mapper
+const MAX_PROPER_INSERT: i64 = 10_000;
+/// Width of the depth bins, in bases
+const DEPTH_BIN: u64 = 100;
+/// Chance that one supporting read is a chimera or mismapping
+const ARTEFACT_RATE: f64 = 0.01;
+const MAX_SV_QUALITY: f64 = 999.0;
+/// Depth ratio below which a deletion, or above which a duplication, is homozygous
+const HOM_DELETION_RATIO: f64 = 0.25;
+const HOM_DUPLICATION_RATIO: f64 = 1.75;
+/// Depth ratios that contradict a deletion or duplication
+const MAX_DELETION_RATIO: f64 = 0.85;
+const MIN_DUPLICATION_RATIO: f64 = 1.15;
+
+/// Meta lines for symbolic SV records
+const SV_HEADER_LINES: [&str; 15] = [
+    "##ALT=<ID=DEL,Description=\"Deletion\">",
+    "##ALT=<ID=DUP,Description=\"Tandem duplication\">",
+    "##ALT=<ID=INV,Description=\"Inversion\">",
+    "##FILTER=<ID=NoDepthSupport,Description=\"Read depth over the event contradicts its copy-number change\">",
+    "##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type of structural variant\">",
+    "##INFO=<ID=END,Number=1,Type=Integer,Description=\"End position of the variant\">",
+    "##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length of the variant; negative for deletions\">",
+    "##INFO=<ID=PE,Number=1,Type=Integer,Description=\"Discordant read pairs supporting the variant\">",
+    "##INFO=<ID=SR,Number=1,Type=Integer,Description=\"Split reads supporting the variant\">",
+    "##INFO=<ID=DR,Number=1,Type=Float,Description=\"Read depth over the event relative to the genome median\">",
+    "##INFO=<ID=IMPRECISE,Number=0,Type=Flag,Description=\"Breakpoints estimated from read pairs only\">",
+    "##INFO=<ID=CIPOS,Number=2,Type=Integer,Description=\"Confidence interval around POS\">",
+    "##INFO=<ID=CIEND,Number=2,Type=Integer,Description=\"Confidence interval around END\">",
+    "##INFO=<ID=MATEID,Number=1,Type=String,Description=\"ID of the mate breakend\">",
+    "##FORMAT=<ID=SR,Number=1,Type=Integer,Description=\"Supporting split reads\">",
+];
+
+/// Kind of structural variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SvType {
+    Deletion,
+    Duplication,
+    Inversion,
+    /// Novel adjacency between contigs (translocation)
+    Breakend,
+}
+
+impl std::fmt::Display for SvType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SvType::Deletion => write!(f, "DEL"),
+            SvType::Duplication => write!(f, "DUP"),
+            SvType::Inversion => write!(f, "INV"),
+            SvType::Breakend => write!(f, "BND"),
+        }
+    }
+}
+
+/// One side of a novel adjacency
+///
+/// `keeps_left` breakends join the sequence ending just before `pos` (a read
+/// aligned up to the junction on the forward strand); otherwise the sequence
+/// starting at `pos` is joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Breakend {
+    tid: u32,
+    pos: u64,
+    keeps_left: bool,
+}
+
+/// A read pair or split read spanning one adjacency, breakends in coordinate order
+#[derive(Debug, Clone, Copy)]
+struct Evidence {
+    first: Breakend,
+    second: Breakend,
+    split: bool,
+}
+
+impl Evidence {
+    fn new(a: Breakend, b: Breakend, split: bool) -> Self {
+        let (first, second) = if (a.tid, a.pos) <= (b.tid, b.pos) { (a, b) } else { (b, a) };
+        Self { first, second, split }
+    }
+
+    fn kind(&self) -> SvType {
+        match (self.first.tid == self.second.tid, self.first.keeps_left, self.second.keeps_left) {
+            (false, ..) => SvType::Breakend,
+            (true, true, false) => SvType::Deletion,
+            (true, false, true) => SvType::Duplication,
+            (true, ..) => SvType::Inversion,
+        }
+    }
+
+    /// Clusters only gather evidence of the same junction type
+    fn signature(&self) -> (SvType, u32, u32, bool, bool) {
+        (self.kind(), self.first.tid, self.second.tid, self.first.keeps_left, self.second.keeps_left)
+    }
+}
+
+/// One structural variant call
+#[derive(Debug, Clone)]
+pub struct SvCall {
+    pub kind: SvType,
+    pub chrom: String,
+    /// 0-based first affected base (DEL/DUP/INV) or junction position (BND)
+    pub start: u64,
+    pub mate_chrom: String,
+    /// 0-based exclusive end, or the mate's junction position for BND
+    pub end: u64,
+    /// Junction orientation of each side, see `Breakend`
+    pub keeps_left: (bool, bool),
+    pub pair_support: u32,
+    pub split_support: u32,
+    /// Depth inside the event over the genome median, for DEL/DUP long enough to bin
+    pub depth_ratio: Option<f64>,
+    /// Breakpoint uncertainty, zero when split reads pin it down
+    pub confidence: u64,
+    pub genotype: (usize, usize),
+    pub quality: f64,
+    pub filter: String,
+    /// Reference bases at the two VCF positions
+    pub reference_bases: (u8, u8),
+}
+
+impl SvCall {
+    pub fn length(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+
+    /// 1-based VCF POS of a breakend: the base before a `keeps_left` junction, else the base after it
+    fn breakend_position(pos: u64, keeps_left: bool) -> u64 {
+        if keeps_left { pos.max(1) } else { pos + 1 }
+    }
+
+    /// Confidence interval for a breakend: reads end short of `keeps_left` junctions and start after the others
+    fn interval(&self, keeps_left: bool) -> String {
+        if keeps_left { format!("0,{}", self.confidence) } else { format!("-{},0", self.confidence) }
+    }
+
+    /// VCF records: one for DEL/DUP/INV, a mate pair for BND
+    pub fn to_vcf_lines(&self, id: usize) -> Vec<String> {
+        let mut common = format!("PE={};SR={}", self.pair_support, self.split_support);
+        if self.confidence > 0 {
+            common.push_str(";IMPRECISE");
+        }
+        let sample = format!("GT:SR\t{}/{}:{}", self.genotype.0, self.genotype.1, self.split_support);
+        match self.kind {
+            SvType::Breakend => {
+                let first = (Self::breakend_position(self.start, self.keeps_left.0), self.keeps_left.0);
+                let second = (Self::breakend_position(self.end, self.keeps_left.1), self.keeps_left.1);
+                let alt = |base: u8, own_keeps_left: bool, mate_chrom: &str, mate: (u64, bool)| {
+                    let bracket = if mate.1 { ']' } else { '[' };
+                    let joined = format!("{}{}:{}{}", bracket, mate_chrom, mate.0, bracket);
+                    if own_keeps_left { format!("{}{}", base as char, joined) } else { format!("{}{}", joined, base as char) }
+                };
+                let record = |suffix: u8, chrom: &str, own: (u64, bool), base: u8, mate_chrom: &str, mate: (u64, bool)| {
+                    let mut info = format!("SVTYPE=BND;MATEID=sv{}_{};{}", id, 3 - suffix, common);
+                    if self.confidence > 0 {
+                        info.push_str(&format!(";CIPOS={}", self.interval(own.1)));
+                    }
+                    format!(
+                        "{}\t{}\tsv{}_{}\t{}\t{}\t{:.0}\t{}\t{}\t{}",
+                        chrom,
+                        own.0,
+                        id,
+                        suffix,
+                        base as char,
+                        alt(base, own.1, mate_chrom, mate),
+                        self.quality,
+                        self.filter,
+                        info,
+                        sample
+                    )
+                };
+                vec![
+                    record(1, &self.chrom, first, self.reference_bases.0, &self.mate_chrom, second),
+                    record(2, &self.mate_chrom, second, self.reference_bases.1, &self.chrom, first),
+                ]
+            }
+            kind => {
+                let length = self.length() as i64;
+                let mut info = format!(
+                    "SVTYPE={};END={};SVLEN={};{}",
+                    kind,
+                    self.end,
+                    if kind == SvType::Deletion { -length } else { length },
+                    common
+                );
+                if let Some(ratio) = self.depth_ratio {
+                    info.push_str(&format!(";DR={:.2}", ratio));
+                }
+                if self.confidence > 0 {
+                    info.push_str(&format!(";CIPOS={};CIEND={}", self.interval(self.keeps_left.0), self.interval(self.keeps_left.1)));
+                }
+                vec![format!(
+                    "{}\t{}\tsv{}\t{}\t<{}>\t{:.0}\t{}\t{}\t{}",
+                    self.chrom,
+                    self.start.max(1),
+                    id,
+                    self.reference_bases.0 as char,
+                    kind,
+                    self.quality,
+                    self.filter,
+                    info,
+                    sample
+                )]
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SvCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            SvType::Breakend => write!(
+                f,
+                "{}:{} <-> {}:{} (BND, {} pairs, {} split reads, QUAL {:.0})",
+                self.chrom,
+                self.start + 1,
+                self.mate_chrom,
+                self.end + 1,
+                self.pair_support,
+                self.split_support,
+                self.quality
+            ),
+            kind => write!(
+                f,
+                "{}:{}-{} ({}, {} bp, {} pairs, {} split reads, {}/{}, QUAL {:.0})",
+                self.chrom,
+                self.start + 1,
+                self.end,
+                kind,
+                self.length(),
+                self.pair_support,
+                self.split_support,
+                self.genotype.0,
+                self.genotype.1,
+                self.quality
+            ),
+        }
+    }
+}
+
+/// Structural variant calls from one sample
+#[derive(Debug, Clone)]
+pub struct SvCallSet {
+    pub sample: String,
+    pub contigs: Vec<(String, u64)>,
+    pub calls: Vec<SvCall>,
+    pub insert_model: InsertSizeModel,
+    /// Median depth over all bins with coverage
+    pub median_depth: f64,
+    pub discordant_pairs: u64,
+    pub split_reads: u64,
+}
+
+impl SvCallSet {
+    pub fn count(&self, kind: SvType) -> usize {
+        self.calls.iter().filter(|call| call.kind == kind).count()
+    }
+
+    pub fn write_vcf(&self, path: &str, reference: &str) -> Result<()> {
+        let file = std::fs::File::create(path).with_context(|| format!("Could not create VCF: {}", path))?;
+        let mut out = BufWriter::new(file);
+        let header = vcf_header(&self.contigs, reference, std::slice::from_ref(&self.sample), &SV_HEADER_LINES);
+        out.write_all(header.as_bytes())?;
+        for (id, call) in self.calls.iter().enumerate() {
+            for line in call.to_vcf_lines(id + 1) {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Aligned stretch of a read: reference span and the read bases it covers
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    tid: u32,
+    start: u64,
+    end: u64,
+    reverse: bool,
+    /// Offset of the first aligned base in the read as sequenced
+    query_start: u32,
+}
+
+impl Segment {
+    fn from_cigar(tid: u32, start: u64, reverse: bool, ops: &[(char, u32)]) -> Self {
+        let leading: u32 = ops.iter().take_while(|(op, _)| matches!(op, 'S' | 'H')).map(|(_, n)| n).sum();
+        let aligned: u32 = ops.iter().filter(|(op, _)| matches!(op, 'M' | 'I' | '=' | 'X')).map(|(_, n)| n).sum();
+        let total: u32 = ops.iter().filter(|(op, _)| matches!(op, 'M' | 'I' | 'S' | 'H' | '=' | 'X')).map(|(_, n)| n).sum();
+        let span: u32 = ops.iter().filter(|(op, _)| matches!(op, 'M' | 'D' | 'N' | '=' | 'X')).map(|(_, n)| n).sum();
+        let query_start = if reverse { total - leading - aligned } else { leading };
+        Self { tid, start, end: start + span as u64, reverse, query_start }
+    }
+
+    /// Breakend where the read leaves this segment, in sequencing order
+    fn exit(&self) -> Breakend {
+        if self.reverse {
+            Breakend { tid: self.tid, pos: self.start, keeps_left: false }
+        } else {
+            Breakend { tid: self.tid, pos: self.end, keeps_left: true }
+        }
+    }
+
+    /// Breakend where the read enters this segment
+    fn entry(&self) -> Breakend {
+        if self.reverse {
+            Breakend { tid: self.tid, pos: self.end, keeps_left: true }
+        } else {
+            Breakend { tid: self.tid, pos: self.start, keeps_left: false }
+        }
+    }
+}
+
+fn cigar_ops<'a>(cigar: impl IntoIterator<Item = &'a Cigar>) -> Vec<(char, u32)> {
+    cigar
+        .into_iter()
+        .map(|op| match *op {
+            Cigar::Match(n) => ('M', n),
+            Cigar::Ins(n) => ('I', n),
+            Cigar::Del(n) => ('D', n),
+            Cigar::RefSkip(n) => ('N', n),
+            Cigar::SoftClip(n) => ('S', n),
+            Cigar::HardClip(n) => ('H', n),
+            Cigar::Pad(n) => ('P', n),
+            Cigar::Equal(n) => ('=', n),
+            Cigar::Diff(n) => ('X', n),
+        })
+        .collect()
+}
+
+fn parse_cigar_text(text: &str) -> Option<Vec<(char, u32)>> {
+    let mut ops = Vec::new();
+    let mut length = 0u32;
+    for c in text.chars() {
+        match c.to_digit(10) {
+            Some(d) => length = length.checked_mul(10)?.checked_add(d)?,
+            None => {
+                ops.push((c, length));
+                length = 0;
+            }
+        }
+    }
+    Some(ops)
+}
+
+fn median(values: &mut [u64]) -> u64 {
+    values.sort_unstable();
+    values[values.len() / 2]
+}
+
+impl VariantCaller {
+    /// Call deletions, duplications, inversions and translocations of at least
+    /// `min_sv_length` bases from a coordinate-sorted BAM
+    ///
+    /// A first pass samples properly oriented pairs for the insert-size model
+    /// (BWA-style, as the mapper estimates it). The second gathers discordant
+    /// pairs (wrong orientation, too far apart or on different contigs) and
+    /// split reads (primary alignments with an `SA` tag), each reduced to the
+    /// two breakends it joins, and bins read depth. Evidence of one junction
+    /// type within an insert-size window of itself is clustered; split reads
+    /// fix the breakpoints exactly, pairs alone bound them by the insert size.
+    /// Depth over deletions and duplications, relative to the genome median,
+    /// sets the genotype and vetoes calls it contradicts. QUAL treats each
+    /// supporting read as an independent 1% chance of an artefact.
+    pub fn call_structural_variants(&self, input_path: &str, reference: &str) -> Result<SvCallSet> {
+        let params = self.params();
+        let insert_model = estimate_insert_model(input_path, params.min_mapping_quality)?;
+
+        let mut reader =
+            bam::Reader::from_path(input_path).with_context(|| format!("Could not open alignments: {}", input_path))?;
+        let header = reader.header().clone();
+        let contigs: Vec<(String, u64)> = (0..header.target_count())
+            .map(|tid| (String::from_utf8_lossy(header.tid2name(tid)).into_owned(), header.target_len(tid).unwrap_or(0)))
+            .collect();
+        let mut depth: Vec<Vec<u32>> =
+            contigs.iter().map(|(_, length)| vec![0; (length / DEPTH_BIN + 1) as usize]).collect();
+
+        let mut evidence: Vec<Evidence> = Vec::new();
+        let mut read_lengths: Vec<u64> = Vec::new();
+        let (mut discordant_pairs, mut split_reads) = (0u64, 0u64);
+        for result in reader.records() {
+            let record = result.context("Could not read alignment record")?;
+            if record.is_unmapped()
+                || record.is_secondary()
+                || record.is_supplementary()
+                || record.is_quality_check_failed()
+                || record.is_duplicate()
+                || record.tid() < 0
+                || record.mapq() < params.min_mapping_quality
+            {
+                continue;
+            }
+            let tid = record.tid() as u32;
+            let ops = cigar_ops(record.cigar().iter());
+            let segment = Segment::from_cigar(tid, record.pos() as u64, record.is_reverse(), &ops);
+            if read_lengths.len() < INSERT_SAMPLE_PAIRS {
+                read_lengths.push(record.seq_len() as u64);
+            }
+
+            // Depth from aligned blocks; long CIGAR deletions are precise evidence of their own
+            let mut ref_pos = segment.start;
+            for (op, length) in &ops {
+                let length = *length as u64;
+                match op {
+                    'M' | '=' | 'X' => {
+                        let bins = &mut depth[tid as usize];
+                        let mut pos = ref_pos;
+                        while pos < ref_pos + length {
+                            let bin = (pos / DEPTH_BIN) as usize;
+                            let next = ((pos / DEPTH_BIN + 1) * DEPTH_BIN).min(ref_pos + length);
+                            if let Some(count) = bins.get_mut(bin) {
+                                *count += (next - pos) as u32;
+                            }
+                            pos = next;
+                        }
+                        ref_pos += length;
+                    }
+                    'D' => {
+                        if length >= params.min_sv_length {
+                            let left = Breakend { tid, pos: ref_pos, keeps_left: true };
+                            evidence.push(Evidence::new(left, Breakend { tid, pos: ref_pos + length, keeps_left: false }, true));
+                            split_reads += 1;
+                        }
+                        ref_pos += length;
+                    }
+                    'N' => ref_pos += length,
+                    _ => {}
+                }
+            }
+
+            // Every junction between consecutive pieces of a chimeric read, in sequencing order
+            if let Ok(Aux::String(sa)) = record.aux(b"SA") {
+                let mut pieces = supplementary_segments(sa, &header, params.min_mapping_quality);
+                if !pieces.is_empty() {
+                    pieces.push(segment);
+                    pieces.sort_by_key(|piece| piece.query_start);
+                    for pair in pieces.windows(2) {
+                        let found = Evidence::new(pair[0].exit(), pair[1].entry(), true);
+                        if is_candidate(&found, params.min_sv_length) {
+                            evidence.push(found);
+                            split_reads += 1;
+                        }
+                    }
+                }
+            }
+
+            // Each pair is seen once, from its leftmost mate
+            if !record.is_paired() || record.is_mate_unmapped() || record.mtid() < 0 {
+                continue;
+            }
+            let mate_tid = record.mtid() as u32;
+            let mate_pos = record.mpos() as u64;
+            let leftmost = (tid, segment.start) < (mate_tid, mate_pos)
+                || ((tid, segment.start) == (mate_tid, mate_pos) && record.is_first_in_template());
+            if !leftmost {
+                continue;
+            }
+            let mate_end = match record.aux(b"MC") {
+                Ok(Aux::String(mc)) => parse_cigar_text(mc).map(|mc| Segment::from_cigar(mate_tid, mate_pos, false, &mc).end),
+                _ => None,
+            }
+            .unwrap_or(mate_pos + record.seq_len() as u64);
+            let mate = Segment { tid: mate_tid, start: mate_pos, end: mate_end, reverse: record.is_mate_reverse(), query_start: 0 };
+            // Outward-facing ends of the two reads are the junction's sides
+            let read_side = segment.exit();
+            let mate_side = mate.exit();
+            let pair = Evidence::new(read_side, mate_side, false);
+            let outer = segment.end.max(mate.end) - segment.start.min(mate.start);
+            let concordant = pair.kind() == SvType::Deletion && outer <= insert_model.high as u64;
+            if !concordant && is_candidate(&pair, params.min_sv_length) {
+                evidence.push(pair);
+                discordant_pairs += 1;
+            }
+        }
+
+        let mut bins: Vec<u64> = depth.iter().flatten().filter(|&&c| c > 0).map(|&c| c as u64).collect();
+        let median_depth = if bins.is_empty() { 0.0 } else { median(&mut bins) as f64 / DEPTH_BIN as f64 };
+        let read_length = if read_lengths.is_empty() { 0 } else { median(&mut read_lengths) };
+        let window = (insert_model.high as u64).max(read_length);
+        let confidence = window.saturating_sub(read_length).max(1);
+
+        let fasta = faidx::Reader::from_path(reference)
+            .with_context(|| format!("Could not open indexed reference: {}", reference))?;
+        let fasta_names: Vec<String> =
+            (0..fasta.n_seqs() as i32).map(|i| fasta.seq_name(i)).collect::<std::result::Result<_, _>>()?;
+        let base = |tid: u32, pos: u64| -> Result<u8> {
+            let name = &contigs[tid as usize].0;
+            if !fasta_names.contains(name) {
+                bail!("Contig {} from {} is missing from {}", name, input_path, reference);
+            }
+            Ok(fasta.fetch_seq(name, pos as usize, pos as usize)?.first().map_or(b'N', u8::to_ascii_uppercase))
+        };
+
+        let mut calls = Vec::new();
+        let mut clusters = cluster_evidence(evidence, window);
+        merge_inversion_junctions(&mut clusters, window);
+        for cluster in clusters {
+            let pair_support = cluster.iter().filter(|e| !e.split).count() as u32;
+            let split_support = cluster.len() as u32 - pair_support;
+            if pair_support + split_support < params.min_sv_support {
+                continue;
+            }
+            let first = cluster[0];
+            let kind = first.kind();
+            let (start, end) = breakpoints(&cluster);
+            if kind != SvType::Breakend && end.saturating_sub(start) < params.min_sv_length {
+                continue;
+            }
+
+            let depth_ratio = match kind {
+                SvType::Deletion | SvType::Duplication if median_depth > 0.0 => {
+                    let bins = &depth[first.first.tid as usize];
+                    let (low, high) = (start.div_ceil(DEPTH_BIN) as usize, (end / DEPTH_BIN) as usize);
+                    (high > low && high <= bins.len()).then(|| {
+                        let covered: u64 = bins[low..high].iter().map(|&c| c as u64).sum();
+                        covered as f64 / ((high - low) as u64 * DEPTH_BIN) as f64 / median_depth
+                    })
+                }
+                _ => None,
+            };
+            let homozygous = match (kind, depth_ratio) {
+                (SvType::Deletion, Some(ratio)) => ratio < HOM_DELETION_RATIO,
+                (SvType::Duplication, Some(ratio)) => ratio > HOM_DUPLICATION_RATIO,
+                _ => false,
+            };
+            let contradicted = match (kind, depth_ratio) {
+                (SvType::Deletion, Some(ratio)) => ratio > MAX_DELETION_RATIO,
+                (SvType::Duplication, Some(ratio)) => ratio < MIN_DUPLICATION_RATIO,
+                _ => false,
+            };
+
+            let support = (pair_support + split_support) as f64;
+            let first_base = match kind {
+                SvType::Breakend => SvCall::breakend_position(start, first.first.keeps_left) - 1,
+                _ => start.saturating_sub(1),
+            };
+            let second_base = SvCall::breakend_position(end, first.second.keeps_left) - 1;
+            calls.push(SvCall {
+                kind,
+                chrom: contigs[first.first.tid as usize].0.clone(),
+                start,
+                mate_chrom: contigs[first.second.tid as usize].0.clone(),
+                end,
+                keeps_left: (first.first.keeps_left, first.second.keeps_left),
+                pair_support,
+                split_support,
+                depth_ratio,
+                confidence: if split_support > 0 { 0 } else { confidence },
+                genotype: if homozygous { (1, 1) } else { (0, 1) },
+                quality: (-10.0 * support * ARTEFACT_RATE.log10()).min(MAX_SV_QUALITY),
+                filter: if contradicted { "NoDepthSupport".to_string() } else { "PASS".to_string() },
+                reference_bases: (base(first.first.tid, first_base)?, base(first.second.tid, second_base)?),
+            });
+        }
+        let order = |call: &SvCall| {
+            (contigs.iter().position(|(name, _)| *name == call.chrom).unwrap_or(usize::MAX), call.start, call.end)
+        };
+        calls.sort_by_key(order);
+
+        Ok(SvCallSet {
+            sample: crate::variant_caller::sample_name(header.as_bytes(), input_path),
+            contigs,
+            calls,
+            insert_model,
+            median_depth,
+            discordant_pairs,
+            split_reads,
+        })
+    }
+}
+
+/// Insert-size model from the first properly oriented, uniquely placed pairs
+fn estimate_insert_model(input_path: &str, min_mapping_quality: u8) -> Result<InsertSizeModel> {
+    let mut reader =
+        bam::Reader::from_path(input_path).with_context(|| format!("Could not open alignments: {}", input_path))?;
+    let mut sizes = Vec::new();
+    for result in reader.records() {
+        let record = result.context("Could not read alignment record")?;
+        if !record.is_paired()
+            || record.is_unmapped()
+            || record.is_mate_unmapped()
+            || record.is_secondary()
+            || record.is_supplementary()
+            || record.mapq() < min_mapping_quality
+            || record.tid() != record.mtid()
+            || record.is_reverse() == record.is_mate_reverse()
+            || record.insert_size() <= 0
+            || record.insert_size() > MAX_PROPER_INSERT
+        {
+            continue;
+        }
+        // Forward mate on the left, facing its reverse mate
+        if record.is_reverse() != (record.pos() > record.mpos()) {
+            continue;
+        }
+        sizes.push(record.insert_size() as usize);
+        if sizes.len() >= INSERT_SAMPLE_PAIRS {
+            break;
+        }
+    }
+    Ok(InsertSizeModel::estimate(&sizes).unwrap_or_default())
+}
+
+/// Supplementary alignments listed in an `SA` tag that pass the mapping quality filter
+fn supplementary_segments(sa: &str, header: &bam::HeaderView, min_mapping_quality: u8) -> Vec<Segment> {
+    sa.split(';').filter(|entry| !entry.is_empty()).filter_map(|entry| {
+        let fields: Vec<&str> = entry.split(',').collect();
+        if fields.len() < 6 || fields[4].parse::<u8>().ok()? < min_mapping_quality {
+            return None;
+        }
+        let tid = header.tid(fields[0].as_bytes())?;
+        let pos = fields[1].parse::<u64>().ok()?.checked_sub(1)?;
+        let ops = parse_cigar_text(fields[3])?;
+        Some(Segment::from_cigar(tid, pos, fields[2] == "-", &ops))
+    }).collect()
+}
+
+/// Whether evidence could describe an SV of reportable size
+fn is_candidate(evidence: &Evidence, min_length: u64) -> bool {
+    evidence.kind() == SvType::Breakend || evidence.second.pos.saturating_sub(evidence.first.pos) >= min_length
+}
+
+/// Greedy clustering of same-signature evidence whose breakends lie within `window`
+fn cluster_evidence(mut evidence: Vec<Evidence>, window: u64) -> Vec<Vec<Evidence>> {
+    evidence.sort_by_key(|e| (e.signature(), e.first.pos, e.second.pos));
+    let mut clusters: Vec<Vec<Evidence>> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    for item in evidence {
+        // Clusters whose first breakend has fallen out of reach are closed
+        open.retain(|&c| {
+            let last = clusters[c].last().copied();
+            last.is_some_and(|l| l.signature() == item.signature() && item.first.pos <= l.first.pos + window)
+        });
+        let joined = open.iter().copied().find(|&c| {
+            let anchor = clusters[c][0];
+            item.second.pos.abs_diff(anchor.second.pos) <= window && item.first.pos.abs_diff(anchor.first.pos) <= window
+        });
+        match joined {
+            Some(c) => clusters[c].push(item),
+            None => {
+                clusters.push(vec![item]);
+                open.push(clusters.len() - 1);
+            }
+        }
+    }
+    clusters
+}
+
+/// Fold the two junctions of each inversion (left ends joined, and right ends
+/// joined) into one cluster
+fn merge_inversion_junctions(clusters: &mut Vec<Vec<Evidence>>, window: u64) {
+    for i in 0..clusters.len() {
+        let Some(&anchor) = clusters[i].first() else { continue };
+        if anchor.kind() != SvType::Inversion || !anchor.first.keeps_left {
+            continue;
+        }
+        let partner = (0..clusters.len()).find(|&j| {
+            clusters[j].first().is_some_and(|other| {
+                other.kind() == SvType::Inversion
+                    && !other.first.keeps_left
+                    && other.first.tid == anchor.first.tid
+                    && other.first.pos.abs_diff(anchor.first.pos) <= window
+                    && other.second.pos.abs_diff(anchor.second.pos) <= window
+            })
+        });
+        if let Some(j) = partner {
+            let other = std::mem::take(&mut clusters[j]);
+            clusters[i].extend(other);
+        }
+    }
+    clusters.retain(|cluster| !cluster.is_empty());
+}
+
+/// Breakpoints of a cluster: the split-read consensus when there is one,
+/// otherwise the innermost pair ends
+fn breakpoints(cluster: &[Evidence]) -> (u64, u64) {
+    let split: Vec<&Evidence> = cluster.iter().filter(|e| e.split).collect();
+    if !split.is_empty() {
+        let mut firsts: Vec<u64> = split.iter().map(|e| e.first.pos).collect();
+        let mut seconds: Vec<u64> = split.iter().map(|e| e.second.pos).collect();
+        return (median(&mut firsts), median(&mut seconds));
+    }
+    // Reads stop short of a junction they point into
+    let innermost = |positions: Vec<u64>, keeps_left: bool| {
+        if keeps_left { positions.into_iter().max() } else { positions.into_iter().min() }.unwrap_or(0)
+    };
+    let first = cluster[0].first.keeps_left;
+    let second = cluster[0].second.keeps_left;
+    (
+        innermost(cluster.iter().map(|e| e.first.pos).collect(), first),
+        innermost(cluster.iter().map(|e| e.second.pos).collect(), second),
+    )
+}
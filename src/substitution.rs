@@ -0,0 +1,280 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Residue order of NCBI protein matrices
+const PROTEIN_ALPHABET: &[u8] = b"ARNDCQEGHILKMFPSTWYVBZX*";
+
+/// IUPAC nucleotide codes and the bases each stands for
+const IUPAC_CODES: [(u8, &[u8]); 15] = [
+    (b'A', b"A"),
+    (b'C', b"C"),
+    (b'G', b"G"),
+    (b'T', b"T"),
+    (b'R', b"AG"),
+    (b'Y', b"CT"),
+    (b'S', b"CG"),
+    (b'W', b"AT"),
+    (b'K', b"GT"),
+    (b'M', b"AC"),
+    (b'B', b"CGT"),
+    (b'D', b"AGT"),
+    (b'H', b"ACT"),
+    (b'V', b"ACG"),
+    (b'N', b"ACGT"),
+];
+
+/// Names accepted by `SubstitutionMatrix::load` without a file
+pub const BUILTIN_MATRICES: [&str; 8] = ["BLOSUM45", "BLOSUM62", "BLOSUM80", "PAM40", "PAM120", "PAM200", "PAM250", "IUPAC"];
+
+/// NCBI BLOSUM45, for distantly related proteins
+const BLOSUM45: &str = "\
+   A  R  N  D  C  Q  E  G  H  I  L  K  M  F  P  S  T  W  Y  V  B  Z  X  *
+A  5 -2 -1 -2 -1 -1 -1  0 -2 -1 -1 -1 -1 -2 -1  1  0 -2 -2  0 -1 -1  0 -5
+R -2  7  0 -1 -3  1  0 -2  0 -3 -2  3 -1 -2 -2 -1 -1 -2 -1 -2 -1  0 -1 -5
+N -1  0  6  2 -2  0  0  0  1 -2 -3  0 -2 -2 -2  1  0 -4 -2 -3  4  0 -1 -5
+D -2 -1  2  7 -3  0  2 -1  0 -4 -3  0 -3 -4 -1  0 -1 -4 -2 -3  5  1 -1 -5
+C -1 -3 -2 -3 12 -3 -3 -3 -3 -3 -2 -3 -2 -2 -4 -1 -1 -5 -3 -1 -2 -3 -2 -5
+Q -1  1  0  0 -3  6  2 -2  1 -2 -2  1  0 -4 -1  0 -1 -2 -1 -3  0  4 -1 -5
+E -1  0  0  2 -3  2  6 -2  0 -3 -2  1 -2 -3  0  0 -1 -3 -2 -3  1  4 -1 -5
+G  0 -2  0 -1 -3 -2 -2  7 -2 -4 -3 -2 -2 -3 -2  0 -2 -2 -3 -3 -1 -2 -1 -5
+H -2  0  1  0 -3  1  0 -2 10 -3 -2 -1  0 -2 -2 -1 -2 -3  2 -3  0  0 -1 -5
+I -1 -3 -2 -4 -3 -2 -3 -4 -3  5  2 -3  2  0 -2 -2 -1 -2  0  3 -3 -3 -1 -5
+L -1 -2 -3 -3 -2 -2 -2 -3 -2  2  5 -3  2  1 -3 -3 -1 -2  0  1 -3 -2 -1 -5
+K -1  3  0  0 -3  1  1 -2 -1 -3 -3  5 -1 -3 -1 -1 -1 -2 -1 -2  0  1 -1 -5
+M -1 -1 -2 -3 -2  0 -2 -2  0  2  2 -1  6  0 -2 -2 -1 -2  0  1 -2 -1 -1 -5
+F -2 -2 -2 -4 -2 -4 -3 -3 -2  0  1 -3  0  8 -3 -2 -1  1  3  0 -3 -3 -1 -5
+P -1 -2 -2 -1 -4 -1  0 -2 -2 -2 -3 -1 -2 -3  9 -1 -1 -3 -3 -3 -2 -1 -1 -5
+S  1 -1  1  0 -1  0  0  0 -1 -2 -3 -1 -2 -2 -1  4  2 -4 -2 -1  0  0  0 -5
+T  0 -1  0 -1 -1 -1 -1 -2 -2 -1 -1 -1 -1 -1 -1  2  5 -3 -1  0  0 -1  0 -5
+W -2 -2 -4 -4 -5 -2 -3 -2 -3 -2 -2 -2 -2  1 -3 -4 -3 15  3 -3 -4 -2 -2 -5
+Y -2 -1 -2 -2 -3 -1 -2 -3  2  0  0 -1  0  3 -3 -2 -1  3  8 -1 -2 -2 -1 -5
+V  0 -2 -3 -3 -1 -3 -3 -3 -3  3  1 -2  1  0 -3 -1  0 -3 -1  5 -3 -3 -1 -5
+B -1 -1  4  5 -2  0  1 -1  0 -3 -3  0 -2 -3 -2  0  0 -4 -2 -3  4  2 -1 -5
+Z -1  0  0  1 -3  4  4 -2  0 -3 -2  1 -1 -3 -1  0 -1 -2 -2 -3  2  4 -1 -5
+X  0 -1 -1 -1 -2 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1  0  0 -2 -1 -1 -1 -1 -1 -5
+* -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5 -5  1
+";
+
+/// NCBI BLOSUM80, for closely related proteins
+const BLOSUM80: &str = "\
+   A  R  N  D  C  Q  E  G  H  I  L  K  M  F  P  S  T  W  Y  V  B  Z  X  *
+A  5 -2 -2 -2 -1 -1 -1  0 -2 -2 -2 -1 -1 -3 -1  1  0 -3 -2  0 -2 -1 -1 -6
+R -2  6 -1 -2 -4  1 -1 -3  0 -3 -3  2 -2 -4 -2 -1 -1 -4 -3 -3 -1  0 -1 -6
+N -2 -1  6  1 -3  0 -1 -1  0 -4 -4  0 -3 -4 -3  0  0 -4 -3 -4  5  0 -1 -6
+D -2 -2  1  6 -4 -1  1 -2 -2 -4 -5 -1 -4 -4 -2 -1 -1 -6 -4 -4  5  1 -1 -6
+C -1 -4 -3 -4  9 -4 -5 -4 -4 -2 -2 -4 -2 -3 -4 -2 -1 -3 -3 -1 -4 -4 -1 -6
+Q -1  1  0 -1 -4  6  2 -2  1 -3 -3  1  0 -4 -2  0 -1 -3 -2 -3  0  3 -1 -6
+E -1 -1 -1  1 -5  2  6 -3  0 -4 -4  1 -2 -4 -2  0 -1 -4 -3 -3  1  4 -1 -6
+G  0 -3 -1 -2 -4 -2 -3  6 -3 -5 -4 -2 -4 -4 -3 -1 -2 -4 -4 -4 -1 -3 -1 -6
+H -2  0  0 -2 -4  1  0 -3  8 -4 -3 -1 -2 -2 -3 -1 -2 -3  2 -4 -1  0 -1 -6
+I -2 -3 -4 -4 -2 -3 -4 -5 -4  5  1 -3  1 -1 -4 -3 -1 -3 -2  3 -4 -4 -1 -6
+L -2 -3 -4 -5 -2 -3 -4 -4 -3  1  4 -3  2  0 -3 -3 -2 -2 -2  1 -4 -3 -1 -6
+K -1  2  0 -1 -4  1  1 -2 -1 -3 -3  5 -2 -4 -1 -1 -1 -4 -3 -3 -1  1 -1 -6
+M -1 -2 -3 -4 -2  0 -2 -4 -2  1  2 -2  6  0 -3 -2 -1 -2 -2  1 -3 -2 -1 -6
+F -3 -4 -4 -4 -3 -4 -4 -4 -2 -1  0 -4  0  6 -4 -3 -2  0  3 -1 -4 -4 -1 -6
+P -1 -2 -3 -2 -4 -2 -2 -3 -3 -4 -3 -1 -3 -4  8 -1 -2 -5 -4 -3 -2 -2 -1 -6
+S  1 -1  0 -1 -2  0  0 -1 -1 -3 -3 -1 -2 -3 -1  5  1 -4 -2 -2  0  0 -1 -6
+T  0 -1  0 -1 -1 -1 -1 -2 -2 -1 -2 -1 -1 -2 -2  1  5 -4 -2  0 -1 -1 -1 -6
+W -3 -4 -4 -6 -3 -3 -4 -4 -3 -3 -2 -4 -2  0 -5 -4 -4 11  2 -3 -5 -4 -1 -6
+Y -2 -3 -3 -4 -3 -2 -3 -4  2 -2 -2 -3 -2  3 -4 -2 -2  2  7 -2 -3 -3 -1 -6
+V  0 -3 -4 -4 -1 -3 -3 -4 -4  3  1 -3  1 -1 -3 -2  0 -3 -2  4 -4 -3 -1 -6
+B -2 -1  5  5 -4  0  1 -1 -1 -4 -4 -1 -3 -4 -2  0 -1 -5 -3 -4  5  0 -1 -6
+Z -1  0  0  1 -4  3  4 -3  0 -4 -3  1 -2 -4 -2  0 -1 -4 -3 -3  0  4 -1 -6
+X -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -6
+* -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6  1
+";
+
+/// Residue-pair score table, protein or nucleotide
+///
+/// Scores are kept in a 128 x 128 table over ASCII so lookups in the DP inner
+/// loop are a single index. Letters missing from the matrix score as its
+/// wildcard (`X` for proteins, `N` for nucleotides) or, failing that, as the
+/// lowest score in the matrix.
+#[derive(Clone)]
+pub struct SubstitutionMatrix {
+    pub name: String,
+    /// Residues in matrix order
+    pub alphabet: Vec<u8>,
+    table: Vec<i32>,
+}
+
+impl std::fmt::Debug for SubstitutionMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SubstitutionMatrix({}, {} residues)", self.name, self.alphabet.len())
+    }
+}
+
+impl SubstitutionMatrix {
+    /// Built-in matrix by name, or an NCBI-format matrix file
+    ///
+    /// `match_score` and `mismatch` parameterise the IUPAC nucleotide matrix.
+    pub fn load(spec: &str, match_score: i32, mismatch: i32) -> Result<Self> {
+        let scores: Option<fn(u8, u8) -> i32> = match spec.to_uppercase().as_str() {
+            "BLOSUM62" => Some(bio::scores::blosum62),
+            "PAM40" => Some(bio::scores::pam40),
+            "PAM120" => Some(bio::scores::pam120),
+            "PAM200" => Some(bio::scores::pam200),
+            "PAM250" => Some(bio::scores::pam250),
+            "BLOSUM45" => return Self::from_ncbi("BLOSUM45", BLOSUM45),
+            "BLOSUM80" => return Self::from_ncbi("BLOSUM80", BLOSUM80),
+            "IUPAC" | "NUC" => return Ok(Self::iupac(match_score, mismatch)),
+            _ => None,
+        };
+        if let Some(scores) = scores {
+            return Ok(Self::from_fn(&spec.to_uppercase(), PROTEIN_ALPHABET, scores));
+        }
+
+        let path = Path::new(spec);
+        if !path.exists() {
+            bail!(
+                "Unknown substitution matrix: {} (built in: {}; other matrices load from NCBI-format files)",
+                spec,
+                BUILTIN_MATRICES.join(", ")
+            );
+        }
+        let text = std::fs::read_to_string(path).with_context(|| format!("Could not read matrix file: {}", spec))?;
+        let name = path.file_name().map_or(spec.to_string(), |n| n.to_string_lossy().into_owned());
+        Self::from_ncbi(&name, &text)
+    }
+
+    /// Parse an NCBI matrix: `#` comments, a header row of residues, then one
+    /// row per residue starting with its letter, in header order
+    pub fn from_ncbi(name: &str, text: &str) -> Result<Self> {
+        let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+        let header = lines.next().context("Matrix file has no header row")?;
+        let alphabet: Vec<u8> = header
+            .split_whitespace()
+            .map(|t| match t.as_bytes() {
+                [c] => Ok(c.to_ascii_uppercase()),
+                _ => Err(anyhow::anyhow!("Matrix header has a multi-letter residue: {}", t)),
+            })
+            .collect::<Result<_>>()?;
+
+        let mut rows: Vec<Vec<i32>> = Vec::with_capacity(alphabet.len());
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let residue = match fields.next().map(str::as_bytes) {
+                Some([c]) => c.to_ascii_uppercase(),
+                _ => bail!("Matrix row does not start with a residue letter: {}", line),
+            };
+            match alphabet.get(rows.len()) {
+                Some(&expected) if expected == residue => {}
+                Some(&expected) => bail!(
+                    "Matrix row {} starts with {}, but the header has {} in that position",
+                    rows.len() + 1,
+                    residue as char,
+                    expected as char
+                ),
+                None => bail!("Matrix has more rows than its {} header residues", alphabet.len()),
+            }
+            let scores: Vec<i32> = fields
+                .map(|f| f.parse().with_context(|| format!("Bad score {:?} in row {}", f, residue as char)))
+                .collect::<Result<_>>()?;
+            if scores.len() != alphabet.len() {
+                bail!("Matrix row {} has {} scores, expected {}", residue as char, scores.len(), alphabet.len());
+            }
+            rows.push(scores);
+        }
+        if rows.len() != alphabet.len() {
+            bail!("Matrix has {} rows for {} columns", rows.len(), alphabet.len());
+        }
+
+        let index = |c: u8| alphabet.iter().position(|&r| r == c).unwrap_or(0);
+        let score = |a: u8, b: u8| -> i32 { rows[index(a)][index(b)] };
+        Ok(Self::from_fn(name, &alphabet, score))
+    }
+
+    /// IUPAC-aware nucleotide matrix: an ambiguity code scores the mean over the
+    /// base pairs it could stand for, rounded to the nearest integer
+    pub fn iupac(match_score: i32, mismatch: i32) -> Self {
+        let bases = |code: u8| IUPAC_CODES.iter().find(|(c, _)| *c == code).map_or(&b"ACGT"[..], |(_, b)| b);
+        let alphabet: Vec<u8> = IUPAC_CODES.iter().map(|(c, _)| *c).collect();
+        let mut matrix = Self::from_fn("IUPAC", &alphabet, |a, b| {
+            let (x, y) = (bases(a), bases(b));
+            let total: i32 = x
+                .iter()
+                .flat_map(|p| y.iter().map(move |q| if p == q { match_score } else { mismatch }))
+                .sum();
+            (total as f64 / (x.len() * y.len()) as f64).round() as i32
+        });
+        // U is read as T
+        for other in 0..128u8 {
+            let score = matrix.score(b'T', other);
+            matrix.set(b'U', other, score);
+            matrix.set(other, b'U', score);
+        }
+        matrix.set(b'U', b'U', match_score);
+        matrix
+    }
+
+    fn from_fn(name: &str, alphabet: &[u8], score: impl Fn(u8, u8) -> i32) -> Self {
+        let wildcard = [b'X', b'N'].into_iter().find(|w| alphabet.contains(w));
+        let lowest = alphabet
+            .iter()
+            .flat_map(|&a| alphabet.iter().map(move |&b| (a, b)))
+            .map(|(a, b)| score(a, b))
+            .min()
+            .unwrap_or(0);
+
+        let mut matrix = Self { name: name.to_string(), alphabet: alphabet.to_vec(), table: vec![lowest; 128 * 128] };
+        // Known residues score from the matrix, unknown ones as the wildcard
+        let resolve = |c: u8| -> Option<u8> {
+            let upper = c.to_ascii_uppercase();
+            if alphabet.contains(&upper) { Some(upper) } else { wildcard }
+        };
+        for a in 0..128u8 {
+            for b in 0..128u8 {
+                if let (Some(x), Some(y)) = (resolve(a), resolve(b)) {
+                    matrix.set(a, b, score(x, y));
+                }
+            }
+        }
+        matrix
+    }
+
+    fn set(&mut self, a: u8, b: u8, score: i32) {
+        self.table[(a as usize & 127) * 128 + (b as usize & 127)] = score;
+    }
+
+    #[inline]
+    pub fn score(&self, a: u8, b: u8) -> i32 {
+        self.table[(a as usize & 127) * 128 + (b as usize & 127)]
+    }
+
+    /// Whether the alphabet has residues beyond nucleotide codes
+    pub fn is_protein(&self) -> bool {
+        self.alphabet.iter().any(|c| !IUPAC_CODES.iter().any(|(code, _)| code == c) && *c != b'U')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_blosum_tables_are_symmetric() {
+        for (name, diagonal) in [("BLOSUM45", [5, 12, 15]), ("BLOSUM80", [5, 9, 11])] {
+            let matrix = SubstitutionMatrix::load(name, 1, -1).unwrap();
+            assert_eq!(matrix.alphabet, PROTEIN_ALPHABET);
+            assert_eq!([matrix.score(b'A', b'A'), matrix.score(b'C', b'C'), matrix.score(b'w', b'W')], diagonal);
+            for &a in PROTEIN_ALPHABET {
+                for &b in PROTEIN_ALPHABET {
+                    assert_eq!(matrix.score(a, b), matrix.score(b, a), "{} {}{}", name, a as char, b as char);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ncbi_rows_must_follow_the_header() {
+        let good = "# comment\n  A  C\nA  2 -1\nC -1  3\n";
+        let matrix = SubstitutionMatrix::from_ncbi("tiny", good).unwrap();
+        assert_eq!(matrix.score(b'C', b'A'), -1);
+        assert_eq!(matrix.score(b'c', b'c'), 3);
+
+        let swapped = "  A  C\nC -1  3\nA  2 -1\n";
+        assert!(SubstitutionMatrix::from_ncbi("swapped", swapped).is_err());
+        let extra = "  A  C\nA  2 -1\nC -1  3\nG  0  0\n";
+        assert!(SubstitutionMatrix::from_ncbi("extra", extra).is_err());
+    }
+}
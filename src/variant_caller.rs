@@ -1,28 +1,872 @@
-use anyhow::Result;
-use crate::binary_optimizer::BinaryOptimizer;
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use rust_htslib::bam::{self, record::Cigar, Read};
+use rust_htslib::faidx;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::gvcf::{BlockBuilder, ReferenceBlock, ReferenceSite, NON_REF};
+
+/// Bases: an error turns a base into one of the three others
+pub const SNP_MODEL: ErrorModel = ErrorModel { error_share: 3.0, heterozygosity: 1e-3 };
+/// Indels: an error turns an indel into the reference or back
+pub const INDEL_MODEL: ErrorModel = ErrorModel { error_share: 1.0, heterozygosity: 1.25e-4 };
+/// Pileup columns genotyped together in one parallel batch
+pub const BATCH_COLUMNS: usize = 4096;
+/// Cap on genotype qualities, as GATK and bcftools report them
+pub const MAX_GQ: f64 = 99.0;
+/// Aligned bases a read needs past an indel gap to count as reference there
+pub const INDEL_FLANK: u64 = 4;
+/// Base quality assumed when a read has none (`*` in SAM)
+pub const MISSING_BASE_QUALITY: u8 = 20;
+
+/// Which kinds of variant to report: small variants, or structural variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariantType {
+    Snp,
+    Indel,
+    /// SNPs and indels
+    All,
+    /// Deletions, duplications, inversions and translocations from split and discordant reads
+    Sv,
+}
+
+impl VariantType {
+    pub fn includes_snps(&self) -> bool {
+        matches!(self, VariantType::Snp | VariantType::All)
+    }
+
+    pub fn includes_indels(&self) -> bool {
+        matches!(self, VariantType::Indel | VariantType::All)
+    }
+}
+
+impl std::str::FromStr for VariantType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "snp" | "snv" | "snps" => Ok(VariantType::Snp),
+            "indel" | "indels" => Ok(VariantType::Indel),
+            "all" => Ok(VariantType::All),
+            "sv" | "svs" | "structural" => Ok(VariantType::Sv),
+            _ => bail!("Unknown variant type: {} (use snp, indel, all or sv)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for VariantType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VariantType::Snp => write!(f, "snp"),
+            VariantType::Indel => write!(f, "indel"),
+            VariantType::All => write!(f, "all"),
+            VariantType::Sv => write!(f, "sv"),
+        }
+    }
+}
+
+/// Read filters and calling thresholds
+#[derive(Debug, Clone, Copy)]
+pub struct CallerParams {
+    /// Bases below this Phred quality are left out of the pileup
+    pub min_base_quality: u8,
+    /// Reads below this mapping quality are skipped
+    pub min_mapping_quality: u8,
+    /// Minimum QUAL for a call to be reported
+    pub min_quality: f64,
+    /// Reads an alternate allele needs before it is considered
+    pub min_alt_reads: u32,
+    /// Fraction of the column an alternate allele needs before it is considered
+    pub min_alt_fraction: f64,
+    /// Phred quality of an indel observation, before the mapping quality cap
+    pub indel_quality: u8,
+    /// Bases kept per column; deeper columns are downsampled to the first reads
+    pub max_depth: usize,
+    /// Emit `<NON_REF>` likelihoods and reference-confidence blocks (gVCF)
+    pub gvcf: bool,
+    /// Smallest deletion, duplication or inversion reported as a structural variant
+    pub min_sv_length: u64,
+    /// Split reads plus discordant pairs a structural variant needs
+    pub min_sv_support: u32,
+    /// Tumor log10 likelihood ratio (TLOD) a somatic call needs
+    pub min_tumor_lod: f64,
+    /// Normal log10 likelihood ratio (NLOD) of reference over heterozygous a somatic call needs to pass
+    pub min_normal_lod: f64,
+    /// Tumor allele fraction a somatic candidate needs
+    pub min_somatic_fraction: f64,
+    /// Phred-scaled Fisher strand bias above which a somatic call is filtered
+    pub max_strand_bias: f64,
+    /// Phred-scaled Fisher F1R2/F2R1 orientation bias above which a somatic call is filtered
+    pub max_orientation_bias: f64,
+}
+
+impl Default for CallerParams {
+    fn default() -> Self {
+        Self {
+            min_base_quality: 13,
+            min_mapping_quality: 20,
+            min_quality: 20.0,
+            min_alt_reads: 2,
+            min_alt_fraction: 0.15,
+            indel_quality: 40,
+            max_depth: 8000,
+            gvcf: false,
+            min_sv_length: 50,
+            min_sv_support: 3,
+            min_tumor_lod: 6.3,
+            min_normal_lod: 2.2,
+            min_somatic_fraction: 0.02,
+            max_strand_bias: 20.0,
+            max_orientation_bias: 20.0,
+        }
+    }
+}
+
+/// One diploid variant call
+#[derive(Debug, Clone)]
+pub struct VariantCall {
+    pub chrom: String,
+    /// 0-based position of the first REF base
+    pub pos: u64,
+    pub reference: String,
+    pub alternates: Vec<String>,
+    /// Phred-scaled probability that the site is homozygous reference
+    pub quality: f64,
+    /// Allele indices of the called genotype, smaller first
+    pub genotype: (usize, usize),
+    pub genotype_quality: u32,
+    /// Reads informative for the site
+    pub depth: u32,
+    /// Reads supporting REF and each ALT in turn
+    pub allele_depths: Vec<u32>,
+    /// Phred-scaled genotype likelihoods in VCF order, best at zero
+    pub likelihoods: Vec<u32>,
+    /// Root-mean-square mapping quality of the reads at the site
+    pub mapping_quality: f64,
+}
+
+impl VariantCall {
+    /// Candidate site before genotyping
+    fn site(
+        chrom: &str,
+        pos: u64,
+        reference: String,
+        alternates: Vec<String>,
+        allele_depths: Vec<u32>,
+        column: &Column,
+    ) -> Self {
+        Self {
+            chrom: chrom.to_string(),
+            pos,
+            reference,
+            alternates,
+            quality: 0.0,
+            genotype: (0, 0),
+            genotype_quality: 0,
+            depth: 0,
+            allele_depths,
+            likelihoods: Vec::new(),
+            mapping_quality: (column.mq_square_sum as f64 / column.reads.max(1) as f64).sqrt(),
+        }
+    }
+
+    pub fn is_indel(&self) -> bool {
+        self.alternates.iter().any(|alt| alt != NON_REF && alt.len() != self.reference.len())
+    }
+
+    /// Record line with INFO DP/MQ and FORMAT GT:GQ:DP:AD:PL
+    pub fn to_vcf_line(&self) -> String {
+        let join = |values: &[u32]| values.iter().map(u32::to_string).collect::<Vec<_>>().join(",");
+        format!(
+            "{}\t{}\t.\t{}\t{}\t{:.2}\tPASS\tDP={};MQ={:.2}\tGT:GQ:DP:AD:PL\t{}/{}:{}:{}:{}:{}",
+            self.chrom,
+            self.pos + 1,
+            self.reference,
+            self.alternates.join(","),
+            self.quality,
+            self.depth,
+            self.mapping_quality,
+            self.genotype.0,
+            self.genotype.1,
+            self.genotype_quality,
+            self.depth,
+            join(&self.allele_depths),
+            join(&self.likelihoods)
+        )
+    }
+}
+
+impl std::fmt::Display for VariantCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{} {}>{} ({}, {}/{}, QUAL {:.1})",
+            self.chrom,
+            self.pos + 1,
+            self.reference,
+            self.alternates.join(","),
+            if self.is_indel() { "INDEL" } else { "SNP" },
+            self.genotype.0,
+            self.genotype.1,
+            self.quality
+        )
+    }
+}
+
+/// Calls from one sample, with what is needed to write them out
+#[derive(Debug, Clone)]
+pub struct CallSet {
+    pub sample: String,
+    /// Contig names and lengths from the BAM header
+    pub contigs: Vec<(String, u64)>,
+    pub calls: Vec<VariantCall>,
+    /// Reference-confidence blocks, in gVCF mode
+    pub blocks: Vec<ReferenceBlock>,
+    pub reads_used: u64,
+    pub reads_filtered: u64,
+}
+
+impl CallSet {
+    pub fn snp_count(&self) -> usize {
+        self.calls.iter().filter(|call| !call.is_indel()).count()
+    }
+
+    pub fn indel_count(&self) -> usize {
+        self.calls.iter().filter(|call| call.is_indel()).count()
+    }
+
+    pub fn write_vcf(&self, path: &str, reference: &str) -> Result<()> {
+        let file = std::fs::File::create(path).with_context(|| format!("Could not create VCF: {}", path))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(vcf_header(&self.contigs, reference, std::slice::from_ref(&self.sample), &[]).as_bytes())?;
+        for call in &self.calls {
+            writeln!(out, "{}", call.to_vcf_line())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// gVCF: variant records and reference blocks interleaved in contig order
+    pub fn write_gvcf(&self, path: &str, reference: &str) -> Result<()> {
+        let file = std::fs::File::create(path).with_context(|| format!("Could not create gVCF: {}", path))?;
+        let mut out = BufWriter::new(file);
+        let header = vcf_header(&self.contigs, reference, std::slice::from_ref(&self.sample), &crate::gvcf::GVCF_HEADER_LINES);
+        out.write_all(header.as_bytes())?;
+
+        let order: HashMap<&str, usize> = self.contigs.iter().enumerate().map(|(i, (name, _))| (name.as_str(), i)).collect();
+        let key = |chrom: &str, pos: u64| (order.get(chrom).copied().unwrap_or(usize::MAX), pos);
+        let (mut calls, mut blocks) = (self.calls.iter().peekable(), self.blocks.iter().peekable());
+        loop {
+            let line = match (calls.peek(), blocks.peek()) {
+                (Some(call), Some(block)) if key(&call.chrom, call.pos) <= key(&block.chrom, block.start) => {
+                    calls.next().map(VariantCall::to_vcf_line)
+                }
+                (_, Some(_)) => blocks.next().map(ReferenceBlock::to_vcf_line),
+                (Some(_), None) => calls.next().map(VariantCall::to_vcf_line),
+                (None, None) => None,
+            };
+            match line {
+                Some(line) => writeln!(out, "{}", line)?,
+                None => break,
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// VCF 4.2 meta-information and column header for the caller's records
+///
+/// `extra` meta lines go after the standard ones, before the column header.
+pub fn vcf_header(contigs: &[(String, u64)], reference: &str, samples: &[String], extra: &[&str]) -> String {
+    let mut header = String::from("##fileformat=VCFv4.2\n");
+    header.push_str(&format!("##source=instant-dna {}\n", env!("CARGO_PKG_VERSION")));
+    header.push_str(&format!("##reference=file://{}\n", reference));
+    for (name, length) in contigs {
+        header.push_str(&format!("##contig=<ID={},length={}>\n", name, length));
+    }
+    header.push_str("##FILTER=<ID=PASS,Description=\"All filters passed\">\n");
+    header.push_str("##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Reads informative for the site\">\n");
+    header.push_str("##INFO=<ID=MQ,Number=1,Type=Float,Description=\"RMS mapping quality\">\n");
+    header.push_str("##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n");
+    header.push_str("##FORMAT=<ID=GQ,Number=1,Type=Integer,Description=\"Genotype quality\">\n");
+    header.push_str("##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read depth\">\n");
+    header.push_str("##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Allelic depths for the REF and ALT alleles\">\n");
+    header.push_str("##FORMAT=<ID=PL,Number=G,Type=Integer,Description=\"Phred-scaled genotype likelihoods\">\n");
+    for line in extra {
+        header.push_str(line);
+        header.push('\n');
+    }
+    header.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT");
+    for sample in samples {
+        header.push('\t');
+        header.push_str(sample);
+    }
+    header.push('\n');
+    header
+}
+
+/// Per-read error spread and genotype prior for one class of variant
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorModel {
+    /// Alleles a sequencing error can turn the true allele into
+    pub error_share: f64,
+    /// Prior probability that a site is heterozygous for this kind of variant
+    pub heterozygosity: f64,
+}
+
+/// What a read shows between a base and the next reference base
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum IndelAllele {
+    Insertion(Vec<u8>),
+    Deletion(usize),
+}
+
+/// Observations stacked on one reference position
+#[derive(Debug, Default)]
+struct Column {
+    /// Quality-passing aligned bases with their error Phred
+    bases: Vec<(u8, u8)>,
+    /// Reads continuing to the next reference base: `None` follows the
+    /// reference, otherwise the indel the read carries there
+    indels: Vec<(Option<IndelAllele>, u8)>,
+    mq_square_sum: u64,
+    reads: u32,
+}
 
 /// Variant calling engine with millisecond precision
+///
+/// Reads a coordinate-sorted BAM, stacks quality-filtered bases and indels
+/// into pileup columns against the reference, and calls diploid genotypes
+/// from per-read likelihoods where each base's error is the lower of its base
+/// and mapping qualities.
 pub struct VariantCaller {
     min_coverage: u32,
+    params: CallerParams,
 }
 
 impl VariantCaller {
     pub fn new(min_coverage: u32) -> Result<Self> {
-        Ok(Self { min_coverage })
+        Ok(Self { min_coverage, params: CallerParams::default() })
+    }
+
+    pub fn with_params(mut self, params: CallerParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn params(&self) -> CallerParams {
+        self.params
+    }
+
+    pub fn min_coverage(&self) -> u32 {
+        self.min_coverage
     }
-    
-    pub fn call_variants(
+
+    pub fn call_variants(&self, input_path: &str, reference: &str, variant_type: VariantType) -> Result<CallSet> {
+        let mut reader =
+            bam::Reader::from_path(input_path).with_context(|| format!("Could not open alignments: {}", input_path))?;
+        let header = reader.header().clone();
+        let fasta = faidx::Reader::from_path(reference)
+            .with_context(|| format!("Could not open indexed reference: {}", reference))?;
+        let fasta_names: Vec<String> =
+            (0..fasta.n_seqs() as i32).map(|i| fasta.seq_name(i)).collect::<std::result::Result<_, _>>()?;
+
+        let contigs: Vec<(String, u64)> = (0..header.target_count())
+            .map(|tid| {
+                let name = String::from_utf8_lossy(header.tid2name(tid)).into_owned();
+                (name, header.target_len(tid).unwrap_or(0))
+            })
+            .collect();
+        let mut set = CallSet {
+            sample: sample_name(header.as_bytes(), input_path),
+            contigs,
+            calls: Vec::new(),
+            blocks: Vec::new(),
+            reads_used: 0,
+            reads_filtered: 0,
+        };
+
+        // Current contig: tid, name and uppercase reference sequence
+        let mut current: Option<(i32, String, Vec<u8>)> = None;
+        let mut columns: BTreeMap<u64, Column> = BTreeMap::new();
+        let mut pending: Vec<(u64, Column)> = Vec::new();
+        let mut blocks = BlockBuilder::new();
+        let mut last_pos = 0i64;
+
+        for result in reader.records() {
+            let record = result.context("Could not read alignment record")?;
+            if record.is_unmapped()
+                || record.is_secondary()
+                || record.is_supplementary()
+                || record.is_quality_check_failed()
+                || record.is_duplicate()
+                || record.mapq() < self.params.min_mapping_quality
+                || record.tid() < 0
+            {
+                set.reads_filtered += 1;
+                continue;
+            }
+
+            let tid = record.tid();
+            if current.as_ref().map(|(t, ..)| *t) != Some(tid) {
+                if let Some((previous, name, sequence)) = current.take() {
+                    if tid < previous {
+                        bail!("{} is not coordinate-sorted (contig {} after {})", input_path, tid, previous);
+                    }
+                    pending.extend(std::mem::take(&mut columns));
+                    set.calls.extend(self.genotype(&name, &sequence, &mut pending, variant_type, &mut blocks));
+                    blocks.finish_contig(&name, &sequence);
+                }
+                let (name, length) = set.contigs[tid as usize].clone();
+                if !fasta_names.contains(&name) {
+                    bail!("Contig {} from {} is missing from {}", name, input_path, reference);
+                }
+                let sequence = match length {
+                    0 => Vec::new(),
+                    _ => fasta.fetch_seq(&name, 0, length as usize - 1)?.to_ascii_uppercase(),
+                };
+                current = Some((tid, name, sequence));
+                last_pos = 0;
+            }
+            if record.pos() < last_pos {
+                bail!("{} is not coordinate-sorted (position {} after {})", input_path, record.pos() + 1, last_pos + 1);
+            }
+            last_pos = record.pos();
+
+            // Columns left of this read can gain no more coverage
+            let rest = columns.split_off(&(record.pos() as u64));
+            pending.extend(std::mem::replace(&mut columns, rest));
+            if pending.len() >= BATCH_COLUMNS {
+                if let Some((_, name, sequence)) = &current {
+                    set.calls.extend(self.genotype(name, sequence, &mut pending, variant_type, &mut blocks));
+                }
+            }
+
+            if let Some((_, _, sequence)) = &current {
+                self.add_record(&mut columns, &record, sequence);
+            }
+            set.reads_used += 1;
+        }
+
+        if let Some((_, name, sequence)) = current {
+            pending.extend(columns);
+            set.calls.extend(self.genotype(&name, &sequence, &mut pending, variant_type, &mut blocks));
+            blocks.finish_contig(&name, &sequence);
+        }
+
+        if self.params.gvcf {
+            // Contigs without any usable reads are one uncovered block each
+            for (name, length) in &set.contigs {
+                if *length > 0 && !blocks.has_contig(name) && fasta_names.contains(name) {
+                    let first = fasta.fetch_seq(name, 0, 0)?.to_ascii_uppercase();
+                    blocks.uncovered(name, 0, *length, first.first().copied().unwrap_or(b'N'));
+                }
+            }
+            set.blocks = blocks.into_blocks(&set.contigs);
+        }
+        Ok(set)
+    }
+
+    /// Stack one read's aligned bases and indels onto the pileup
+    ///
+    /// Indels are left-aligned against the reference first, so reads that
+    /// place the same indel differently inside a repeat pile up together. A
+    /// read counts as reference across a gap only when it aligns at least
+    /// `INDEL_FLANK` bases beyond it; near its end an aligner would rather
+    /// clip or mismatch than open a gap.
+    fn add_record(&self, columns: &mut BTreeMap<u64, Column>, record: &bam::Record, reference: &[u8]) {
+        let sequence = record.seq().as_bytes();
+        let qualities = record.qual();
+        let mapq = record.mapq();
+        let indel_quality = mapq.min(self.params.indel_quality);
+        let cigar: Vec<Cigar> = record.cigar().iter().copied().collect();
+        let (indels, aligned_end) = read_indels(&cigar, &sequence, record.pos() as u64, reference);
+
+        let (mut ref_pos, mut query_pos) = (record.pos() as u64, 0usize);
+        for op in &cigar {
+            match *op {
+                Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                    for _ in 0..len {
+                        let column = columns.entry(ref_pos).or_default();
+                        column.reads += 1;
+                        column.mq_square_sum += mapq as u64 * mapq as u64;
+
+                        let base = sequence[query_pos];
+                        let quality = match qualities.get(query_pos) {
+                            Some(&255) | None => MISSING_BASE_QUALITY,
+                            Some(&q) => q,
+                        };
+                        if quality >= self.params.min_base_quality
+                            && matches!(base, b'A' | b'C' | b'G' | b'T')
+                            && column.bases.len() < self.params.max_depth
+                        {
+                            column.bases.push((base, quality.min(mapq)));
+                        }
+
+                        let following = match indels.iter().find(|(anchor, _)| *anchor == ref_pos) {
+                            Some((_, allele)) => Some(Some(allele.clone())),
+                            None if ref_pos + INDEL_FLANK < aligned_end => Some(None),
+                            None => None,
+                        };
+                        if let Some(allele) = following {
+                            if column.indels.len() < self.params.max_depth {
+                                column.indels.push((allele, indel_quality));
+                            }
+                        }
+
+                        ref_pos += 1;
+                        query_pos += 1;
+                    }
+                }
+                Cigar::Ins(len) | Cigar::SoftClip(len) => query_pos += len as usize,
+                Cigar::Del(len) | Cigar::RefSkip(len) => ref_pos += len as u64,
+                Cigar::HardClip(_) | Cigar::Pad(_) => {}
+            }
+        }
+    }
+
+    /// Genotype and clear the pending columns, in parallel
+    ///
+    /// In gVCF mode, columns without a call feed the reference blocks.
+    fn genotype(
+        &self,
+        chrom: &str,
+        sequence: &[u8],
+        pending: &mut Vec<(u64, Column)>,
+        variant_type: VariantType,
+        blocks: &mut BlockBuilder,
+    ) -> Vec<VariantCall> {
+        let results: Vec<(Vec<VariantCall>, Option<ReferenceSite>)> = pending
+            .par_iter()
+            .map(|(pos, column)| {
+                let snp = if variant_type.includes_snps() { self.call_snp(chrom, sequence, *pos, column) } else { None };
+                let indel =
+                    if variant_type.includes_indels() { self.call_indel(chrom, sequence, *pos, column) } else { None };
+                let calls: Vec<VariantCall> = snp.into_iter().chain(indel).collect();
+                let site = match self.params.gvcf && calls.is_empty() {
+                    true => Some(reference_confidence(sequence, *pos, column)),
+                    false => None,
+                };
+                (calls, site)
+            })
+            .collect();
+        pending.clear();
+
+        let mut calls = Vec::new();
+        for (site_calls, site) in results {
+            if let Some(site) = site {
+                blocks.push(chrom, sequence, site);
+            } else if let Some(call) = site_calls.first() {
+                blocks.skip(chrom, sequence, call.pos);
+            }
+            calls.extend(site_calls);
+        }
+        calls
+    }
+
+    fn call_snp(&self, chrom: &str, sequence: &[u8], pos: u64, column: &Column) -> Option<VariantCall> {
+        let depth = column.bases.len() as u32;
+        let reference = *sequence.get(pos as usize)?;
+        if depth < self.min_coverage.max(1) || !matches!(reference, b'A' | b'C' | b'G' | b'T') {
+            return None;
+        }
+
+        let mut counts: HashMap<u8, u32> = HashMap::new();
+        for &(base, _) in &column.bases {
+            *counts.entry(base).or_default() += 1;
+        }
+        let alternates = self.candidates(counts.iter().filter(|(&b, _)| b != reference), depth);
+        if alternates.is_empty() {
+            return None;
+        }
+
+        let alleles: Vec<u8> = std::iter::once(reference).chain(alternates.iter().map(|&(&b, _)| b)).collect();
+        let observations = column.bases.iter().map(|&(base, quality)| (alleles.iter().position(|&a| a == base), quality));
+        let site = VariantCall::site(
+            chrom,
+            pos,
+            (reference as char).to_string(),
+            alleles[1..].iter().map(|&a| (a as char).to_string()).collect(),
+            alleles.iter().map(|a| counts.get(a).copied().unwrap_or(0)).collect(),
+            column,
+        );
+        self.genotype_site(site, observations, SNP_MODEL)
+    }
+
+    fn call_indel(&self, chrom: &str, sequence: &[u8], pos: u64, column: &Column) -> Option<VariantCall> {
+        let depth = column.indels.len() as u32;
+        if depth < self.min_coverage.max(1) {
+            return None;
+        }
+
+        let mut counts: HashMap<&IndelAllele, u32> = HashMap::new();
+        for (allele, _) in &column.indels {
+            if let Some(allele) = allele {
+                *counts.entry(allele).or_default() += 1;
+            }
+        }
+        let candidates = self.candidates(counts.iter(), depth);
+        // Deletions must lie inside the contig
+        let alternates: Vec<&IndelAllele> = candidates
+            .into_iter()
+            .map(|(&allele, _)| allele)
+            .filter(|allele| match allele {
+                IndelAllele::Deletion(len) => (pos as usize) + len < sequence.len(),
+                IndelAllele::Insertion(bases) => !bases.is_empty(),
+            })
+            .collect();
+        if alternates.is_empty() || pos as usize >= sequence.len() {
+            return None;
+        }
+
+        // REF spans the anchor base and the longest deletion; each ALT rewrites that span
+        let longest = alternates
+            .iter()
+            .map(|allele| match allele {
+                IndelAllele::Deletion(len) => *len,
+                IndelAllele::Insertion(_) => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        let start = pos as usize;
+        let span = &sequence[start..=start + longest];
+        let alt_strings: Vec<String> = alternates
+            .iter()
+            .map(|allele| {
+                let mut alt = vec![span[0]];
+                match allele {
+                    IndelAllele::Insertion(bases) => {
+                        alt.extend_from_slice(bases);
+                        alt.extend_from_slice(&span[1..]);
+                    }
+                    IndelAllele::Deletion(len) => alt.extend_from_slice(&span[1 + len..]),
+                }
+                String::from_utf8_lossy(&alt).into_owned()
+            })
+            .collect();
+
+        let observations = column.indels.iter().map(|(allele, quality)| {
+            let index = match allele {
+                None => Some(0),
+                Some(allele) => alternates.iter().position(|a| *a == allele).map(|i| i + 1),
+            };
+            (index, *quality)
+        });
+        let reference_reads = column.indels.iter().filter(|(allele, _)| allele.is_none()).count() as u32;
+        let site = VariantCall::site(
+            chrom,
+            pos,
+            String::from_utf8_lossy(span).into_owned(),
+            alt_strings,
+            std::iter::once(reference_reads).chain(alternates.iter().map(|allele| counts[*allele])).collect(),
+            column,
+        );
+        self.genotype_site(site, observations, INDEL_MODEL)
+    }
+
+    /// Alternate alleles with enough support, most supported first, at most two
+    fn candidates<'a, K: Ord + 'a>(
         &self,
-        input_path: &str,
-        reference: &str,
-        variant_type: &str,
-        optimizer: &BinaryOptimizer,
-    ) -> Result<Vec<String>> {
-        // Stub implementation
-        Ok(vec![
-            "chr1:12345 A>G (SNP)".to_string(),
-            "chr2:67890 T>C (SNP)".to_string(),
-            "chr3:11111 INS:ATG (INDEL)".to_string(),
-        ])
+        counts: impl Iterator<Item = (&'a K, &'a u32)>,
+        depth: u32,
+    ) -> Vec<(&'a K, u32)> {
+        let mut candidates: Vec<(&K, u32)> = counts
+            .map(|(allele, &count)| (allele, count))
+            .filter(|&(_, count)| {
+                count >= self.params.min_alt_reads && count as f64 >= self.params.min_alt_fraction * depth as f64
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        candidates.truncate(2);
+        candidates
     }
+
+    /// Genotype a candidate site from its observations; `None` unless it is a confident variant
+    fn genotype_site(
+        &self,
+        mut site: VariantCall,
+        observations: impl Iterator<Item = (Option<usize>, u8)>,
+        model: ErrorModel,
+    ) -> Option<VariantCall> {
+        let alleles = site.alternates.len() + 1;
+        let observations: Vec<(Option<usize>, u8)> = observations.collect();
+        let likelihoods = genotype_likelihoods(observations.iter().copied(), alleles, model.error_share);
+        let priors = genotype_priors(alleles, model.heterozygosity);
+        let posteriors: Vec<f64> = likelihoods.iter().zip(&priors).map(|(l, p)| l + p).collect();
+        let total = log10_sum(&posteriors);
+
+        let (best, _) = posteriors.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+        site.genotype = genotype_alleles(best);
+        if site.genotype == (0, 0) {
+            return None;
+        }
+        site.quality = (-10.0 * (posteriors[0] - total)).max(0.0);
+        if site.quality < self.params.min_quality {
+            return None;
+        }
+
+        let others: Vec<f64> = posteriors.iter().enumerate().filter(|&(g, _)| g != best).map(|(_, &p)| p).collect();
+        site.genotype_quality = (-10.0 * (log10_sum(&others) - total)).clamp(0.0, MAX_GQ).round() as u32;
+        site.depth = observations.len() as u32;
+
+        // gVCF records carry <NON_REF>, standing for every allele not listed
+        let likelihoods = if self.params.gvcf {
+            let other = observations.iter().filter(|(allele, _)| allele.is_none()).count() as u32;
+            site.alternates.push(NON_REF.to_string());
+            site.allele_depths.push(other);
+            let observations = observations.iter().map(|&(allele, quality)| (allele.or(Some(alleles)), quality));
+            genotype_likelihoods(observations, alleles + 1, model.error_share)
+        } else {
+            likelihoods
+        };
+        site.likelihoods = phred_likelihoods(&likelihoods);
+        Some(site)
+    }
+}
+
+/// A read's indels as (anchor, allele), left-aligned, and its aligned reference end
+///
+/// The anchor is the reference base before the gap. Shifting stops at the
+/// first aligned base of the read.
+pub fn read_indels(cigar: &[Cigar], sequence: &[u8], start: u64, reference: &[u8]) -> (Vec<(u64, IndelAllele)>, u64) {
+    let mut indels = Vec::new();
+    let (mut ref_pos, mut query_pos) = (start, 0usize);
+    let base = |pos: u64| reference.get(pos as usize).copied();
+
+    for (index, op) in cigar.iter().enumerate() {
+        let after_aligned = index > 0 && ref_pos > start;
+        match *op {
+            Cigar::Ins(len) if after_aligned => {
+                let end = (query_pos + len as usize).min(sequence.len());
+                let mut inserted = sequence[query_pos..end].to_vec();
+                let mut anchor = ref_pos - 1;
+                while anchor > start && !inserted.is_empty() && base(anchor) == inserted.last().copied() {
+                    inserted.rotate_right(1);
+                    anchor -= 1;
+                }
+                indels.push((anchor, IndelAllele::Insertion(inserted)));
+            }
+            Cigar::Del(len) if after_aligned => {
+                let mut anchor = ref_pos - 1;
+                while anchor > start && base(anchor).is_some() && base(anchor) == base(anchor + len as u64) {
+                    anchor -= 1;
+                }
+                indels.push((anchor, IndelAllele::Deletion(len as usize)));
+            }
+            _ => {}
+        }
+        match *op {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                ref_pos += len as u64;
+                query_pos += len as usize;
+            }
+            Cigar::Ins(len) | Cigar::SoftClip(len) => query_pos += len as usize,
+            Cigar::Del(len) | Cigar::RefSkip(len) => ref_pos += len as u64,
+            Cigar::HardClip(_) | Cigar::Pad(_) => {}
+        }
+    }
+    (indels, ref_pos)
+}
+
+/// log10 P(reads | genotype) for every diploid genotype, in VCF order
+///
+/// Each observation names the allele it supports (or `None` for an allele
+/// outside the set) and its Phred error. A read drawn from allele `a` shows
+/// `a` with probability 1 - e and each other allele with e / `error_share`.
+pub fn genotype_likelihoods(
+    observations: impl Iterator<Item = (Option<usize>, u8)>,
+    alleles: usize,
+    error_share: f64,
+) -> Vec<f64> {
+    let genotypes = alleles * (alleles + 1) / 2;
+    let mut likelihoods = vec![0.0f64; genotypes];
+    for (observed, quality) in observations {
+        let error = 10f64.powf(-(quality as f64) / 10.0).min(0.75);
+        let emit = |allele: usize| if observed == Some(allele) { 1.0 - error } else { error / error_share };
+        for (g, likelihood) in likelihoods.iter_mut().enumerate() {
+            let (a, b) = genotype_alleles(g);
+            *likelihood += (0.5 * emit(a) + 0.5 * emit(b)).log10();
+        }
+    }
+    likelihoods
+}
+
+/// Phred-scaled likelihoods relative to the most likely genotype
+pub fn phred_likelihoods(likelihoods: &[f64]) -> Vec<u32> {
+    let max = likelihoods.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    likelihoods.iter().map(|l| (-10.0 * (l - max)).round() as u32).collect()
+}
+
+/// Reference confidence of a column: likelihoods of 0/0, 0/<NON_REF> and
+/// <NON_REF>/<NON_REF>, and how sure 0/0 is
+fn reference_confidence(sequence: &[u8], pos: u64, column: &Column) -> ReferenceSite {
+    let base = sequence.get(pos as usize).copied().unwrap_or(b'N');
+    let observations = column.bases.iter().map(|&(b, quality)| (Some(usize::from(b != base)), quality));
+    let likelihoods = phred_likelihoods(&genotype_likelihoods(observations, 2, 1.0));
+    let genotype_quality = match likelihoods[0] {
+        0 => likelihoods[1].min(likelihoods[2]).min(MAX_GQ as u32),
+        _ => 0,
+    };
+    ReferenceSite {
+        pos,
+        base,
+        depth: column.bases.len() as u32,
+        genotype_quality,
+        likelihoods: [likelihoods[0], likelihoods[1], likelihoods[2]],
+    }
+}
+
+/// log10 genotype priors: heterozygosity split across the alternate alleles
+pub fn genotype_priors(alleles: usize, heterozygosity: f64) -> Vec<f64> {
+    let alternates = (alleles - 1).max(1) as f64;
+    let mut priors: Vec<f64> = (0..alleles * (alleles + 1) / 2)
+        .map(|g| match genotype_alleles(g) {
+            (0, 0) => 0.0,
+            (0, _) => heterozygosity / alternates,
+            (a, b) if a == b => heterozygosity / (2.0 * alternates),
+            _ => heterozygosity * heterozygosity,
+        })
+        .collect();
+    priors[0] = 1.0 - priors.iter().sum::<f64>();
+    priors.iter().map(|p| p.log10()).collect()
+}
+
+/// VCF index of the genotype with alleles `a` and `b`, in either order
+pub fn genotype_index(a: usize, b: usize) -> usize {
+    let (j, k) = if a <= b { (a, b) } else { (b, a) };
+    k * (k + 1) / 2 + j
+}
+
+/// Alleles of the genotype at VCF index `g`, where (j, k) with j <= k sits at k(k+1)/2 + j
+pub fn genotype_alleles(g: usize) -> (usize, usize) {
+    let mut k = 0;
+    while (k + 1) * (k + 2) / 2 <= g {
+        k += 1;
+    }
+    (g - k * (k + 1) / 2, k)
+}
+
+/// log10 of the sum of values given as log10
+pub fn log10_sum(values: &[f64]) -> f64 {
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + values.iter().map(|v| 10f64.powf(v - max)).sum::<f64>().log10()
+}
+
+/// Sample name from the first `@RG` SM tag, else the file stem
+pub fn sample_name(header: &[u8], path: &str) -> String {
+    String::from_utf8_lossy(header)
+        .lines()
+        .filter(|line| line.starts_with("@RG"))
+        .flat_map(|line| line.split('\t'))
+        .find_map(|field| field.strip_prefix("SM:").map(str::to_string))
+        .unwrap_or_else(|| {
+            Path::new(path)
+                .file_stem()
+                .map_or_else(|| path.to_string(), |stem| stem.to_string_lossy().into_owned())
+        })
 }
@@ -0,0 +1,610 @@
+use anyhow::{Context, Result};
+use rust_htslib::faidx;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::coverage::load_targets;
+use crate::vcf_norm::parse_gt;
+use crate::vcf_processor::{SNPVariant, VCFProcessor};
+
+/// Calls closer than this are matched together, so an indel placed differently
+/// within a short repeat still meets its counterpart
+const CLUSTER_GAP: u64 = 25;
+
+/// Most calls per side a cluster may hold for haplotype replay; busier clusters are matched exactly
+const MAX_REPLAY_CALLS: usize = 10;
+
+/// Widest cluster, in reference bases, that is replayed; wider ones are matched exactly,
+/// since replay holds every truth haplotype pair in memory
+const MAX_REPLAY_SPAN: u64 = 500;
+
+/// Variant type used for stratification; equal-length substitutions count as SNPs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VariantClass {
+    Snp,
+    Indel,
+}
+
+impl std::fmt::Display for VariantClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VariantClass::Snp => write!(f, "SNP"),
+            VariantClass::Indel => write!(f, "INDEL"),
+        }
+    }
+}
+
+/// How calls are compared
+#[derive(Debug, Clone, Default)]
+pub struct EvalParams {
+    /// Match alleles regardless of zygosity
+    pub squash_ploidy: bool,
+    /// Evaluate records whatever their FILTER, not only PASS
+    pub include_filtered: bool,
+}
+
+/// Match counts; true positives are counted on both sides as representations may differ
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvalCounts {
+    pub truth_tp: usize,
+    pub query_tp: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+}
+
+impl EvalCounts {
+    pub fn precision(&self) -> Option<f64> {
+        let calls = self.query_tp + self.false_positives;
+        (calls > 0).then(|| self.query_tp as f64 / calls as f64)
+    }
+
+    pub fn recall(&self) -> Option<f64> {
+        let truth = self.truth_tp + self.false_negatives;
+        (truth > 0).then(|| self.truth_tp as f64 / truth as f64)
+    }
+
+    pub fn f1(&self) -> Option<f64> {
+        let (precision, recall) = (self.precision()?, self.recall()?);
+        Some(if precision + recall > 0.0 { 2.0 * precision * recall / (precision + recall) } else { 0.0 })
+    }
+}
+
+/// Metric as four decimals, `.` when undefined
+pub fn format_metric(value: Option<f64>) -> String {
+    value.map_or_else(|| ".".to_string(), |v| format!("{:.4}", v))
+}
+
+/// Merged BED intervals per contig, 0-based half-open
+#[derive(Debug, Clone, Default)]
+pub struct RegionSet {
+    intervals: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl RegionSet {
+    /// Union of the intervals in several BED files
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let mut intervals: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+        for path in paths {
+            for target in load_targets(Path::new(path))? {
+                intervals.entry(target.chrom).or_default().push((target.start, target.end));
+            }
+        }
+        for list in intervals.values_mut() {
+            list.sort_unstable();
+            let mut merged: Vec<(u64, u64)> = Vec::with_capacity(list.len());
+            for &(start, end) in list.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            *list = merged;
+        }
+        Ok(Self { intervals })
+    }
+
+    /// Whether one interval holds all of `start..end`
+    pub fn contains(&self, chrom: &str, start: u64, end: u64) -> bool {
+        self.intervals.get(chrom).is_some_and(|list| {
+            let i = list.partition_point(|&(_, e)| e <= start);
+            list.get(i).is_some_and(|&(s, e)| s <= start && end <= e)
+        })
+    }
+
+    pub fn total_length(&self) -> u64 {
+        self.intervals.values().flatten().map(|(start, end)| end - start).sum()
+    }
+}
+
+/// Counts for one region and variant type
+#[derive(Debug, Clone)]
+pub struct EvalRow {
+    pub region: String,
+    /// SNP, INDEL or ALL
+    pub variant_type: String,
+    pub counts: EvalCounts,
+}
+
+/// Benchmark results, stratified by region and variant type
+#[derive(Debug, Clone)]
+pub struct EvalReport {
+    pub truth_sample: String,
+    pub query_sample: String,
+    /// Rows for every region (`all` first) crossed with SNP, INDEL and ALL
+    pub rows: Vec<EvalRow>,
+    /// Records that could not be evaluated: symbolic alleles, REF mismatches, unknown contigs
+    pub truth_skipped: usize,
+    pub query_skipped: usize,
+    /// Clusters matched by haplotype replay and by exact comparison
+    pub replayed_clusters: usize,
+    pub exact_clusters: usize,
+}
+
+impl EvalReport {
+    /// Counts for one region and variant type
+    pub fn counts(&self, region: &str, variant_type: &str) -> EvalCounts {
+        self.rows
+            .iter()
+            .find(|row| row.region == region && row.variant_type == variant_type)
+            .map(|row| row.counts)
+            .unwrap_or_default()
+    }
+
+    pub fn write_tsv(&self, path: &str) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("Failed to create output file: {}", path))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "region\ttype\ttruth_tp\tquery_tp\tfp\tfn\tprecision\trecall\tf1")?;
+        for row in &self.rows {
+            let counts = &row.counts;
+            writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                row.region, row.variant_type, counts.truth_tp, counts.query_tp,
+                counts.false_positives, counts.false_negatives,
+                format_metric(counts.precision()), format_metric(counts.recall()), format_metric(counts.f1()))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A genotyped record inside the evaluated regions
+struct Call {
+    start: u64,
+    end: u64,
+    /// REF first, uppercase
+    alleles: Vec<Vec<u8>>,
+    genotype: Vec<usize>,
+    class: VariantClass,
+    matched: bool,
+}
+
+impl Call {
+    /// Non-reference sequence of allele `index`; a spanning deletion (`*`) leaves the haplotype as is
+    fn allele(&self, index: usize) -> Option<&[u8]> {
+        (index > 0 && self.alleles[index] != b"*").then(|| self.alleles[index].as_slice())
+    }
+
+    /// Ways of placing the call on two haplotypes; phasing is not trusted, so hets go either way
+    fn placements(&self, squash_ploidy: bool) -> Vec<[Option<&[u8]>; 2]> {
+        if squash_ploidy {
+            let mut placements: Vec<[Option<&[u8]>; 2]> = Vec::new();
+            for allele in self.genotype.iter().filter_map(|&a| self.allele(a)) {
+                if !placements.iter().any(|p| p[0] == Some(allele)) {
+                    placements.push([Some(allele); 2]);
+                }
+            }
+            return placements;
+        }
+        let first = self.genotype[0];
+        let second = self.genotype.get(1).copied().unwrap_or(first);
+        let (a, b) = (self.allele(first), self.allele(second));
+        if a == b { vec![[a, b]] } else { vec![[a, b], [b, a]] }
+    }
+
+    /// Same site and alleles, for clusters too busy to replay
+    fn same_as(&self, other: &Call, squash_ploidy: bool) -> bool {
+        if self.start != other.start || self.alleles[0] != other.alleles[0] {
+            return false;
+        }
+        fn called(call: &Call) -> Vec<&[u8]> {
+            let mut alleles: Vec<&[u8]> = call.genotype.iter().map(|&a| call.alleles[a].as_slice()).collect();
+            alleles.sort_unstable();
+            alleles
+        }
+        if squash_ploidy {
+            self.genotype.iter().filter_map(|&a| self.allele(a)).any(|a| {
+                other.genotype.iter().filter_map(|&b| other.allele(b)).any(|b| a == b)
+            })
+        } else {
+            called(self) == called(other)
+        }
+    }
+}
+
+/// What a VCF record contributes
+enum Parsed {
+    Variant(Call),
+    /// Filtered, reference-only, no-call or outside the confident regions
+    Ignored,
+    Unusable,
+}
+
+/// Enumerates the haplotype pairs a subset of a cluster's calls can produce
+struct Replay<'a> {
+    reference: &'a [u8],
+    end: u64,
+    calls: &'a [&'a Call],
+    placements: Vec<Vec<[Option<&'a [u8]>; 2]>>,
+}
+
+impl<'a> Replay<'a> {
+    fn new(reference: &'a [u8], end: u64, calls: &'a [&'a Call], squash_ploidy: bool) -> Self {
+        let placements = calls.iter().map(|call| call.placements(squash_ploidy)).collect();
+        Self { reference, end, calls, placements }
+    }
+
+    /// Visit every consistent choice of included calls and placements with its sorted haplotype pair
+    fn walk<F>(&self, i: usize, haplotypes: &mut [Vec<u8>; 2], cursors: [u64; 2], included: u64, visit: &mut F)
+    where
+        F: FnMut([Vec<u8>; 2], u64),
+    {
+        if i == self.calls.len() {
+            let mut finished = haplotypes.clone();
+            for (haplotype, cursor) in finished.iter_mut().zip(cursors) {
+                haplotype.extend_from_slice(&self.reference[cursor as usize..self.end as usize]);
+            }
+            if finished[0] > finished[1] {
+                finished.swap(0, 1);
+            }
+            visit(finished, included);
+            return;
+        }
+
+        self.walk(i + 1, haplotypes, cursors, included, visit);
+        let call = self.calls[i];
+        for placement in &self.placements[i] {
+            // Two calls cannot change the same reference bases on one haplotype
+            if placement.iter().zip(cursors).any(|(allele, cursor)| allele.is_some() && call.start < cursor) {
+                continue;
+            }
+            let lengths = [haplotypes[0].len(), haplotypes[1].len()];
+            let mut next = cursors;
+            for ((haplotype, allele), cursor) in haplotypes.iter_mut().zip(placement).zip(next.iter_mut()) {
+                if let Some(allele) = allele {
+                    haplotype.extend_from_slice(&self.reference[*cursor as usize..call.start as usize]);
+                    haplotype.extend_from_slice(allele);
+                    *cursor = call.end;
+                }
+            }
+            self.walk(i + 1, haplotypes, next, included | 1 << i, visit);
+            for (haplotype, length) in haplotypes.iter_mut().zip(lengths) {
+                haplotype.truncate(length);
+            }
+        }
+    }
+}
+
+/// Compares a query callset with a truth set by haplotype equivalence
+pub struct VcfEvaluator {
+    fasta: faidx::Reader,
+    contigs: Vec<String>,
+    params: EvalParams,
+    confident: Option<RegionSet>,
+    strata: Vec<(String, RegionSet)>,
+}
+
+impl VcfEvaluator {
+    pub fn new(reference: &str) -> Result<Self> {
+        let fasta = faidx::Reader::from_path(reference)
+            .with_context(|| format!("Could not open indexed reference: {}", reference))?;
+        let contigs = (0..fasta.n_seqs() as i32).map(|i| fasta.seq_name(i)).collect::<std::result::Result<_, _>>()?;
+        Ok(Self { fasta, contigs, params: EvalParams::default(), confident: None, strata: Vec::new() })
+    }
+
+    pub fn with_params(mut self, params: EvalParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Only evaluate calls lying wholly inside these regions
+    pub fn with_confident(mut self, regions: RegionSet) -> Self {
+        self.confident = Some(regions);
+        self
+    }
+
+    /// Report calls starting inside `regions` separately under `name`
+    pub fn with_stratum(mut self, name: &str, regions: RegionSet) -> Self {
+        self.strata.push((name.to_string(), regions));
+        self
+    }
+
+    pub fn evaluate(
+        &self,
+        truth: &VCFProcessor,
+        truth_sample: Option<&str>,
+        query: &VCFProcessor,
+        query_sample: Option<&str>,
+    ) -> Result<EvalReport> {
+        let truth_index = sample_index(truth, truth_sample, "truth")?;
+        let query_index = sample_index(query, query_sample, "query")?;
+        let mut report = EvalReport {
+            truth_sample: truth_index.map(|i| truth.samples[i].clone()).unwrap_or_default(),
+            query_sample: query_index.map(|i| query.samples[i].clone()).unwrap_or_default(),
+            rows: Vec::new(),
+            truth_skipped: 0,
+            query_skipped: 0,
+            replayed_clusters: 0,
+            exact_clusters: 0,
+        };
+
+        let (mut truth_records, mut query_records) = (by_contig(truth), by_contig(query));
+
+        let mut strata: Vec<(&str, Option<&RegionSet>)> = vec![("all", None)];
+        strata.extend(self.strata.iter().map(|(name, regions)| (name.as_str(), Some(regions))));
+        let mut counts: HashMap<(usize, VariantClass), EvalCounts> = HashMap::new();
+
+        for contig in &self.contigs {
+            let truth_variants = truth_records.remove(contig.as_str()).unwrap_or_default();
+            let query_variants = query_records.remove(contig.as_str()).unwrap_or_default();
+            if truth_variants.is_empty() && query_variants.is_empty() {
+                continue;
+            }
+            let sequence = self.fasta.fetch_seq(contig, 0, i64::MAX as usize)?.to_ascii_uppercase();
+
+            let collect = |variants: Vec<&SNPVariant>, index: Option<usize>, skipped: &mut usize| {
+                let mut calls = Vec::new();
+                for variant in variants {
+                    match self.parse(variant, index, &sequence) {
+                        Parsed::Variant(call) => calls.push(call),
+                        Parsed::Ignored => {}
+                        Parsed::Unusable => *skipped += 1,
+                    }
+                }
+                calls.sort_by_key(|call| (call.start, call.end));
+                calls
+            };
+            let mut truth_calls = collect(truth_variants, truth_index, &mut report.truth_skipped);
+            let mut query_calls = collect(query_variants, query_index, &mut report.query_skipped);
+
+            self.match_calls(&sequence, &mut truth_calls, &mut query_calls, &mut report);
+
+            for (calls, is_truth) in [(&truth_calls, true), (&query_calls, false)] {
+                for call in calls.iter() {
+                    for (i, (_, regions)) in strata.iter().enumerate() {
+                        if regions.is_some_and(|r| !r.contains(contig, call.start, call.start + 1)) {
+                            continue;
+                        }
+                        let entry = counts.entry((i, call.class)).or_default();
+                        match (is_truth, call.matched) {
+                            (true, true) => entry.truth_tp += 1,
+                            (true, false) => entry.false_negatives += 1,
+                            (false, true) => entry.query_tp += 1,
+                            (false, false) => entry.false_positives += 1,
+                        }
+                    }
+                }
+            }
+        }
+        report.truth_skipped += truth_records.values().map(Vec::len).sum::<usize>();
+        report.query_skipped += query_records.values().map(Vec::len).sum::<usize>();
+
+        for (i, (name, _)) in strata.iter().enumerate() {
+            let mut all = EvalCounts::default();
+            for class in [VariantClass::Snp, VariantClass::Indel] {
+                let class_counts = counts.get(&(i, class)).copied().unwrap_or_default();
+                all.truth_tp += class_counts.truth_tp;
+                all.query_tp += class_counts.query_tp;
+                all.false_positives += class_counts.false_positives;
+                all.false_negatives += class_counts.false_negatives;
+                report.rows.push(EvalRow { region: name.to_string(), variant_type: class.to_string(), counts: class_counts });
+            }
+            report.rows.push(EvalRow { region: name.to_string(), variant_type: "ALL".to_string(), counts: all });
+        }
+
+        Ok(report)
+    }
+
+    fn parse(&self, variant: &SNPVariant, sample_index: Option<usize>, sequence: &[u8]) -> Parsed {
+        if !self.params.include_filtered && !matches!(variant.filter.as_str(), "PASS" | "." | "") {
+            return Parsed::Ignored;
+        }
+        let alleles: Vec<Vec<u8>> = std::iter::once(variant.reference.as_str())
+            .chain(variant.alternative.split(','))
+            .map(|a| a.to_ascii_uppercase().into_bytes())
+            .collect();
+
+        // Sites-only VCFs carry no genotypes: every record counts as homozygous for its first ALT
+        let genotype: Vec<usize> = match sample_index {
+            Some(i) => variant
+                .samples
+                .get(i)
+                .map(|gt| parse_gt(gt).0.into_iter().flatten().collect())
+                .unwrap_or_default(),
+            None => vec![1],
+        };
+        if genotype.iter().any(|&a| a >= alleles.len()) {
+            return Parsed::Unusable;
+        }
+        let called: Vec<&[u8]> = genotype.iter().filter(|&&a| a > 0).map(|&a| alleles[a].as_slice()).collect();
+        if called.iter().all(|a| *a == b"*") {
+            return Parsed::Ignored;
+        }
+        if called.iter().any(|a| a.starts_with(b"<") || a.contains(&b'[') || a.contains(&b']') || *a == b".") {
+            return Parsed::Unusable;
+        }
+
+        let Some(start) = variant.position.checked_sub(1) else {
+            return Parsed::Unusable;
+        };
+        let end = start + alleles[0].len() as u64;
+        if sequence.get(start as usize..end as usize) != Some(alleles[0].as_slice()) {
+            return Parsed::Unusable;
+        }
+        if self.confident.as_ref().is_some_and(|regions| !regions.contains(&variant.chromosome, start, end)) {
+            return Parsed::Ignored;
+        }
+
+        let class = if called.iter().all(|a| *a == b"*" || a.len() == alleles[0].len()) {
+            VariantClass::Snp
+        } else {
+            VariantClass::Indel
+        };
+        Parsed::Variant(Call { start, end, alleles, genotype, class, matched: false })
+    }
+
+    /// Mark the calls that belong to matching haplotypes, cluster by cluster
+    fn match_calls(&self, sequence: &[u8], truth: &mut [Call], query: &mut [Call], report: &mut EvalReport) {
+        let mut events: Vec<(u64, u64, bool, usize)> = truth
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.start, c.end, true, i))
+            .chain(query.iter().enumerate().map(|(i, c)| (c.start, c.end, false, i)))
+            .collect();
+        events.sort_unstable();
+
+        let mut cluster_start = 0;
+        while cluster_start < events.len() {
+            let mut cluster_end = cluster_start + 1;
+            let mut span_end = events[cluster_start].1;
+            while cluster_end < events.len() && events[cluster_end].0 <= span_end + CLUSTER_GAP {
+                span_end = span_end.max(events[cluster_end].1);
+                cluster_end += 1;
+            }
+            let cluster = &events[cluster_start..cluster_end];
+            let truth_ids: Vec<usize> = cluster.iter().filter(|e| e.2).map(|e| e.3).collect();
+            let query_ids: Vec<usize> = cluster.iter().filter(|e| !e.2).map(|e| e.3).collect();
+            cluster_start = cluster_end;
+            if truth_ids.is_empty() || query_ids.is_empty() {
+                continue;
+            }
+
+            let squash = self.params.squash_ploidy;
+            let span = (cluster[0].0, span_end);
+            if truth_ids.len() > MAX_REPLAY_CALLS || query_ids.len() > MAX_REPLAY_CALLS || span.1 - span.0 > MAX_REPLAY_SPAN {
+                report.exact_clusters += 1;
+                for &t in &truth_ids {
+                    if let Some(&q) = query_ids.iter().find(|&&q| !query[q].matched && truth[t].same_as(&query[q], squash)) {
+                        truth[t].matched = true;
+                        query[q].matched = true;
+                    }
+                }
+                continue;
+            }
+
+            report.replayed_clusters += 1;
+            let (truth_included, query_included) = {
+                let truth_calls: Vec<&Call> = truth_ids.iter().map(|&i| &truth[i]).collect();
+                let query_calls: Vec<&Call> = query_ids.iter().map(|&i| &query[i]).collect();
+                best_match(sequence, span, &truth_calls, &query_calls, squash)
+            };
+            for (bit, &i) in truth_ids.iter().enumerate() {
+                truth[i].matched = truth_included & 1 << bit != 0;
+            }
+            for (bit, &i) in query_ids.iter().enumerate() {
+                query[i].matched = query_included & 1 << bit != 0;
+            }
+        }
+    }
+}
+
+/// Subsets of truth and query calls, as bit masks, that yield the same haplotypes with the most calls
+fn best_match(sequence: &[u8], span: (u64, u64), truth: &[&Call], query: &[&Call], squash_ploidy: bool) -> (u64, u64) {
+    let mut truth_haplotypes: HashMap<[Vec<u8>; 2], u64> = HashMap::new();
+    Replay::new(sequence, span.1, truth, squash_ploidy).walk(0, &mut [Vec::new(), Vec::new()], [span.0; 2], 0, &mut |haplotypes, included| {
+        let best = truth_haplotypes.entry(haplotypes).or_insert(included);
+        if included.count_ones() > best.count_ones() {
+            *best = included;
+        }
+    });
+
+    let mut best = (0, 0);
+    let mut best_score = 0;
+    Replay::new(sequence, span.1, query, squash_ploidy).walk(0, &mut [Vec::new(), Vec::new()], [span.0; 2], 0, &mut |haplotypes, included| {
+        if let Some(&truth_included) = truth_haplotypes.get(&haplotypes) {
+            let score = truth_included.count_ones() + included.count_ones();
+            if score > best_score {
+                best_score = score;
+                best = (truth_included, included);
+            }
+        }
+    });
+    best
+}
+
+fn by_contig(vcf: &VCFProcessor) -> HashMap<&str, Vec<&SNPVariant>> {
+    let mut map: HashMap<&str, Vec<&SNPVariant>> = HashMap::new();
+    for variant in &vcf.variants {
+        map.entry(variant.chromosome.as_str()).or_default().push(variant);
+    }
+    map
+}
+
+fn sample_index(vcf: &VCFProcessor, name: Option<&str>, role: &str) -> Result<Option<usize>> {
+    match name {
+        Some(name) => vcf.samples.iter().position(|s| s == name).map(Some).with_context(|| {
+            format!("Sample {} not in {} VCF (available: {})", name, role, vcf.samples.join(", "))
+        }),
+        None if vcf.samples.is_empty() => Ok(None),
+        None => Ok(Some(0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //                       0         1
+    //                       01234567890123
+    const SEQUENCE: &[u8] = b"GGGCACACACATTT";
+
+    fn call(start: u64, reference: &str, alternates: &[&str], genotype: &[usize]) -> Call {
+        let alleles: Vec<Vec<u8>> = std::iter::once(reference).chain(alternates.iter().copied()).map(|a| a.as_bytes().to_vec()).collect();
+        let class = if alleles.iter().all(|a| a.len() == reference.len()) { VariantClass::Snp } else { VariantClass::Indel };
+        Call { start, end: start + reference.len() as u64, alleles, genotype: genotype.to_vec(), class, matched: false }
+    }
+
+    fn matched(truth: &[Call], query: &[Call], squash_ploidy: bool) -> (u64, u64) {
+        let start = truth.iter().chain(query).map(|c| c.start).min().unwrap();
+        let end = truth.iter().chain(query).map(|c| c.end).max().unwrap();
+        let truth: Vec<&Call> = truth.iter().collect();
+        let query: Vec<&Call> = query.iter().collect();
+        best_match(SEQUENCE, (start, end), &truth, &query, squash_ploidy)
+    }
+
+    #[test]
+    fn shifted_deletion_in_a_repeat_matches() {
+        let truth = [call(2, "GCA", &["G"], &[1, 1])];
+        let query = [call(8, "ACA", &["A"], &[1, 1])];
+        assert_eq!(matched(&truth, &query, false), (0b1, 0b1));
+    }
+
+    #[test]
+    fn shifted_insertion_matches_on_one_haplotype() {
+        let truth = [call(2, "G", &["GCA"], &[0, 1])];
+        let query = [call(10, "A", &["ACA"], &[1, 0])];
+        assert_eq!(matched(&truth, &query, false), (0b1, 0b1));
+    }
+
+    #[test]
+    fn complex_record_matches_its_decomposition() {
+        // CA>TG at 3 against two SNPs, and a deletion padded on the left against one padded on the right
+        let truth = [call(3, "CA", &["TG"], &[1, 1]), call(8, "ACAT", &["AT"], &[1, 1])];
+        let query = [call(3, "C", &["T"], &[1, 1]), call(4, "A", &["G"], &[1, 1]), call(9, "CAT", &["T"], &[1, 1])];
+        assert_eq!(matched(&truth, &query, false), (0b11, 0b111));
+    }
+
+    #[test]
+    fn zygosity_mismatch_needs_squashed_ploidy() {
+        let truth = [call(2, "GCA", &["G"], &[0, 1])];
+        let query = [call(8, "ACA", &["A"], &[1, 1])];
+        assert_eq!(matched(&truth, &query, false), (0, 0));
+        assert_eq!(matched(&truth, &query, true), (0b1, 0b1));
+    }
+
+    #[test]
+    fn unmatched_calls_are_left_out() {
+        let truth = [call(2, "GCA", &["G"], &[1, 1]), call(12, "T", &["G"], &[0, 1])];
+        let query = [call(8, "ACA", &["A"], &[1, 1]), call(12, "T", &["C"], &[0, 1])];
+        assert_eq!(matched(&truth, &query, false), (0b01, 0b01));
+    }
+}
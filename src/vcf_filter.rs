@@ -0,0 +1,448 @@
+use anyhow::{bail, Result};
+use crate::vcf_processor::{SNPVariant, VCFProcessor};
+
+/// Comparison operators of the filter language
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl std::fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            CompareOp::Equal => "==",
+            CompareOp::NotEqual => "!=",
+            CompareOp::Less => "<",
+            CompareOp::LessEqual => "<=",
+            CompareOp::Greater => ">",
+            CompareOp::GreaterEqual => ">=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// A record column or INFO/FORMAT key, optionally indexed into its values
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Chrom,
+    Pos,
+    Id,
+    Ref,
+    Alt,
+    Qual,
+    Filter,
+    Info(String, Option<usize>),
+    Format(String, Option<usize>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Number(f64),
+    Text(String),
+    Field(Field),
+}
+
+/// Parsed filter expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CompareOp, Operand),
+    /// A bare field: true when present and not missing (INFO flags, say)
+    Present(Field),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Word(String),
+    Index(usize),
+    Op(CompareOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value<'a> {
+    Number(f64),
+    Text(&'a str),
+}
+
+/// Filter expression over QUAL, INFO and per-sample FORMAT fields
+///
+/// The language follows bcftools: `QUAL>30 && INFO/DP>10 && FMT/GQ>20`.
+/// Fields are `CHROM POS ID REF ALT QUAL FILTER`, `INFO/KEY` and `FMT/KEY`
+/// (or `FORMAT/KEY`), with `[i]` picking one value of a list. Comparisons on
+/// lists hold when any value satisfies them and are false for missing
+/// values. FORMAT fields are tested sample by sample, and a record matches
+/// when the whole expression holds for at least one sample.
+#[derive(Debug, Clone)]
+pub struct FilterExpression {
+    pub source: String,
+    pub expr: Expr,
+    uses_format: bool,
+}
+
+impl std::str::FromStr for FilterExpression {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, next: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.next) {
+            bail!("Unexpected {:?} in filter expression: {}", token, source);
+        }
+        let uses_format = uses_format(&expr);
+        Ok(Self { source: source.to_string(), expr, uses_format })
+    }
+}
+
+impl FilterExpression {
+    pub fn matches(&self, variant: &SNPVariant) -> bool {
+        if self.uses_format && !variant.sample_fields.is_empty() {
+            (0..variant.sample_fields.len()).any(|sample| evaluate(&self.expr, variant, Some(sample)))
+        } else {
+            evaluate(&self.expr, variant, None)
+        }
+    }
+}
+
+/// Records kept and failed by `apply_filter`
+#[derive(Debug, Clone, Default)]
+pub struct FilterSummary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Filter the loaded variants in place
+///
+/// Without `soft_filter` failing records are removed. With it they stay and
+/// get the name in their FILTER column, passing records with no filter status
+/// become `PASS`, and a `##FILTER` header line describes the expression.
+pub fn apply_filter(processor: &mut VCFProcessor, filter: &FilterExpression, soft_filter: Option<&str>) -> FilterSummary {
+    let mut summary = FilterSummary::default();
+    match soft_filter {
+        None => processor.variants.retain(|variant| {
+            let keep = filter.matches(variant);
+            if keep { summary.passed += 1 } else { summary.failed += 1 }
+            keep
+        }),
+        Some(name) => {
+            for variant in &mut processor.variants {
+                if filter.matches(variant) {
+                    summary.passed += 1;
+                    if variant.filter == "." {
+                        variant.filter = "PASS".to_string();
+                    }
+                } else {
+                    summary.failed += 1;
+                    if variant.filter == "." || variant.filter == "PASS" {
+                        variant.filter = name.to_string();
+                    } else if !variant.filter.split(';').any(|f| f == name) {
+                        variant.filter = format!("{};{}", variant.filter, name);
+                    }
+                }
+            }
+            let id = format!("##FILTER=<ID={},", name);
+            if !processor.meta_lines.iter().any(|line| line.starts_with(&id)) {
+                processor.meta_lines.push(format!(
+                    "{}Description=\"Failed: {}\">",
+                    id,
+                    filter.source.replace('"', "'")
+                ));
+            }
+        }
+    }
+    summary
+}
+
+fn uses_format(expr: &Expr) -> bool {
+    let is_format = |operand: &Operand| matches!(operand, Operand::Field(Field::Format(..)));
+    match expr {
+        Expr::Or(a, b) | Expr::And(a, b) => uses_format(a) || uses_format(b),
+        Expr::Not(a) => uses_format(a),
+        Expr::Compare(a, _, b) => is_format(a) || is_format(b),
+        Expr::Present(field) => matches!(field, Field::Format(..)),
+    }
+}
+
+fn evaluate(expr: &Expr, variant: &SNPVariant, sample: Option<usize>) -> bool {
+    match expr {
+        Expr::Or(a, b) => evaluate(a, variant, sample) || evaluate(b, variant, sample),
+        Expr::And(a, b) => evaluate(a, variant, sample) && evaluate(b, variant, sample),
+        Expr::Not(a) => !evaluate(a, variant, sample),
+        Expr::Present(field) => !resolve_field(field, variant, sample).is_empty(),
+        Expr::Compare(left, op, right) => {
+            let left = resolve(left, variant, sample);
+            let right = resolve(right, variant, sample);
+            left.iter().any(|l| right.iter().any(|r| compare(l, *op, r)))
+        }
+    }
+}
+
+fn compare(left: &Value, op: CompareOp, right: &Value) -> bool {
+    let number = |value: &Value| match value {
+        Value::Number(n) => Some(*n),
+        Value::Text(text) => text.parse::<f64>().ok(),
+    };
+    let ordering = match (number(left), number(right)) {
+        (Some(l), Some(r)) => l.partial_cmp(&r),
+        _ => {
+            let text = |value: &Value| match value {
+                Value::Number(n) => n.to_string(),
+                Value::Text(text) => text.to_string(),
+            };
+            Some(text(left).cmp(&text(right)))
+        }
+    };
+    let Some(ordering) = ordering else { return false };
+    match op {
+        CompareOp::Equal => ordering.is_eq(),
+        CompareOp::NotEqual => ordering.is_ne(),
+        CompareOp::Less => ordering.is_lt(),
+        CompareOp::LessEqual => ordering.is_le(),
+        CompareOp::Greater => ordering.is_gt(),
+        CompareOp::GreaterEqual => ordering.is_ge(),
+    }
+}
+
+fn resolve<'a>(operand: &'a Operand, variant: &'a SNPVariant, sample: Option<usize>) -> Vec<Value<'a>> {
+    match operand {
+        Operand::Number(n) => vec![Value::Number(*n)],
+        Operand::Text(text) => vec![Value::Text(text)],
+        Operand::Field(field) => resolve_field(field, variant, sample),
+    }
+}
+
+fn resolve_field<'a>(field: &Field, variant: &'a SNPVariant, sample: Option<usize>) -> Vec<Value<'a>> {
+    let list = |text: &'a str, separator: char, index: Option<usize>| -> Vec<Value<'a>> {
+        let values = text.split(separator).filter(|v| *v != ".").map(Value::Text);
+        match index {
+            Some(i) => values.skip(i).take(1).collect(),
+            None => values.collect(),
+        }
+    };
+    match field {
+        Field::Chrom => vec![Value::Text(&variant.chromosome)],
+        Field::Pos => vec![Value::Number(variant.position as f64)],
+        Field::Id => list(&variant.id, ';', None),
+        Field::Ref => vec![Value::Text(&variant.reference)],
+        Field::Alt => list(&variant.alternative, ',', None),
+        Field::Qual if variant.quality.is_nan() => Vec::new(),
+        Field::Qual => vec![Value::Number(variant.quality)],
+        Field::Filter => list(&variant.filter, ';', None),
+        // Flags are present with no value
+        Field::Info(key, index) => match variant.info_value(key) {
+            Some("") => vec![Value::Number(1.0)],
+            Some(value) => list(value, ',', *index),
+            None => Vec::new(),
+        },
+        Field::Format(key, index) => match sample.and_then(|s| variant.format_value(s, key)) {
+            Some(value) if key == "GT" => list(value, '\0', *index),
+            Some(value) => list(value, ',', *index),
+            None => Vec::new(),
+        },
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            ' ' | '\t' => i += 1,
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '&' | '|' => {
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+                i += if next == Some(c) { 2 } else { 1 };
+            }
+            '=' | '!' | '<' | '>' => {
+                let (token, width) = match (c, next) {
+                    ('=', Some('=')) => (Token::Op(CompareOp::Equal), 2),
+                    ('=', _) => (Token::Op(CompareOp::Equal), 1),
+                    ('!', Some('=')) => (Token::Op(CompareOp::NotEqual), 2),
+                    ('!', _) => (Token::Not, 1),
+                    ('<', Some('=')) => (Token::Op(CompareOp::LessEqual), 2),
+                    ('<', _) => (Token::Op(CompareOp::Less), 1),
+                    ('>', Some('=')) => (Token::Op(CompareOp::GreaterEqual), 2),
+                    _ => (Token::Op(CompareOp::Greater), 1),
+                };
+                tokens.push(token);
+                i += width;
+            }
+            '"' | '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&q| q == c)
+                    .map(|p| i + 1 + p)
+                    .ok_or_else(|| anyhow::anyhow!("Unterminated string in filter expression: {}", source))?;
+                tokens.push(Token::Text(chars[i + 1..end].iter().collect()));
+                i = end + 1;
+            }
+            '[' => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&b| b == ']')
+                    .map(|p| i + p)
+                    .ok_or_else(|| anyhow::anyhow!("Unclosed '[' in filter expression: {}", source))?;
+                let index: String = chars[i + 1..end].iter().collect();
+                let index = index.trim().parse().map_err(|_| anyhow::anyhow!("Bad index [{}] in filter expression", index))?;
+                tokens.push(Token::Index(index));
+                i = end + 1;
+            }
+            _ if c.is_ascii_digit() || c == '.' || (c == '-' && next.is_some_and(|n| n.is_ascii_digit() || n == '.')) => {
+                let start = i;
+                i += 1;
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric()
+                        || chars[i] == '.'
+                        || chars[i] == '/'
+                        || ((chars[i] == '-' || chars[i] == '+') && matches!(chars[i - 1], 'e' | 'E')))
+                {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                match text.parse() {
+                    Ok(number) => tokens.push(Token::Number(number)),
+                    Err(_) => tokens.push(Token::Word(text)),
+                }
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' || c == '*' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '_' | '/' | '.' | '*')) {
+                    i += 1;
+                }
+                tokens.push(Token::Word(chars[start..i].iter().collect()));
+            }
+            _ => bail!("Unexpected character '{}' in filter expression: {}", c, source),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or := and (|| and)*`, `and := unary (&& unary)*`,
+/// `unary := ! unary | ( or ) | operand [op operand]`
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.next += 1;
+                let expr = self.or()?;
+                if self.take() != Some(Token::Close) {
+                    bail!("Missing ')' in filter expression");
+                }
+                Ok(expr)
+            }
+            _ => {
+                let left = self.operand()?;
+                if let Some(Token::Op(op)) = self.peek().cloned() {
+                    self.next += 1;
+                    return Ok(Expr::Compare(left, op, self.operand()?));
+                }
+                match left {
+                    Operand::Field(field) => Ok(Expr::Present(field)),
+                    other => bail!("Expected a comparison after {:?} in filter expression", other),
+                }
+            }
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        let operand = match self.take() {
+            Some(Token::Number(n)) => Operand::Number(n),
+            Some(Token::Text(text)) => Operand::Text(text),
+            Some(Token::Word(word)) => match parse_field(&word) {
+                Some(field) => Operand::Field(field),
+                None => Operand::Text(word),
+            },
+            Some(token) => bail!("Expected a field or value in filter expression, found {:?}", token),
+            None => bail!("Filter expression ends where a field or value was expected"),
+        };
+        if let Some(Token::Index(index)) = self.peek().cloned() {
+            self.next += 1;
+            return match operand {
+                Operand::Field(Field::Info(key, _)) => Ok(Operand::Field(Field::Info(key, Some(index)))),
+                Operand::Field(Field::Format(key, _)) => Ok(Operand::Field(Field::Format(key, Some(index)))),
+                other => bail!("Only INFO and FORMAT fields take an index, not {:?}", other),
+            };
+        }
+        Ok(operand)
+    }
+}
+
+fn parse_field(word: &str) -> Option<Field> {
+    if let Some(key) = word.strip_prefix("INFO/") {
+        return Some(Field::Info(key.to_string(), None));
+    }
+    if let Some(key) = word.strip_prefix("FMT/").or_else(|| word.strip_prefix("FORMAT/")) {
+        return Some(Field::Format(key.to_string(), None));
+    }
+    match word {
+        "CHROM" => Some(Field::Chrom),
+        "POS" => Some(Field::Pos),
+        "ID" => Some(Field::Id),
+        "REF" => Some(Field::Ref),
+        "ALT" => Some(Field::Alt),
+        "QUAL" => Some(Field::Qual),
+        "FILTER" => Some(Field::Filter),
+        _ => None,
+    }
+}
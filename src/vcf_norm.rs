@@ -0,0 +1,589 @@
+use anyhow::{bail, Context, Result};
+use rust_htslib::faidx;
+use std::collections::{HashMap, HashSet};
+use crate::variant_caller::{genotype_alleles, genotype_index};
+use crate::vcf_processor::{SNPVariant, VCFProcessor};
+
+/// What `norm` does with records carrying several ALT alleles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MultiallelicMode {
+    /// One biallelic record per ALT allele
+    Split,
+    /// Merge records at the same position into one multiallelic record
+    Join,
+}
+
+impl std::str::FromStr for MultiallelicMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "split" | "-" => Ok(MultiallelicMode::Split),
+            "join" | "+" => Ok(MultiallelicMode::Join),
+            _ => bail!("Unknown multiallelic mode: {} (expected split or join)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for MultiallelicMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MultiallelicMode::Split => write!(f, "split"),
+            MultiallelicMode::Join => write!(f, "join"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NormParams {
+    pub multiallelics: Option<MultiallelicMode>,
+    pub remove_duplicates: bool,
+}
+
+/// What a normalization pass changed
+#[derive(Debug, Clone, Default)]
+pub struct NormSummary {
+    pub total: usize,
+    /// Records moved or trimmed to their left-aligned parsimonious form
+    pub realigned: usize,
+    /// Multiallelic records split, and the biallelic records they became
+    pub split: usize,
+    pub split_into: usize,
+    /// Records merged away by joining
+    pub joined: usize,
+    pub duplicates: usize,
+    /// Records whose REF disagrees with the reference, left as they were
+    pub reference_mismatches: usize,
+}
+
+/// Number= of each INFO and FORMAT key, from the header
+#[derive(Debug, Clone, Default)]
+struct FieldNumbers {
+    info: HashMap<String, String>,
+    format: HashMap<String, String>,
+}
+
+impl FieldNumbers {
+    fn from_meta(meta_lines: &[String]) -> Self {
+        let mut numbers = Self::default();
+        for line in meta_lines {
+            let (table, body) = if let Some(body) = line.strip_prefix("##INFO=<") {
+                (&mut numbers.info, body)
+            } else if let Some(body) = line.strip_prefix("##FORMAT=<") {
+                (&mut numbers.format, body)
+            } else {
+                continue;
+            };
+            let field = |key: &str| body.split(',').find_map(|f| f.strip_prefix(key)).map(str::to_string);
+            if let (Some(id), Some(number)) = (field("ID="), field("Number=")) {
+                table.insert(id, number);
+            }
+        }
+        numbers
+    }
+}
+
+/// Left-aligns, trims, splits/joins and deduplicates VCF records
+///
+/// Normalization follows Tan et al. (2015): trailing bases shared by all
+/// alleles are dropped, the record shifts left while an allele is empty, and
+/// finally leading bases shared by all alleles are trimmed. The result is the
+/// unique leftmost, shortest representation, so the same indel written two
+/// ways in two files compares equal.
+pub struct Normalizer {
+    fasta: Option<faidx::Reader>,
+    contigs: Vec<String>,
+    /// Reference contig currently held, uppercase
+    cached: Option<(String, Vec<u8>)>,
+    params: NormParams,
+}
+
+impl Normalizer {
+    /// Without a reference records are split, joined and deduplicated but not realigned
+    pub fn new(reference: Option<&str>) -> Result<Self> {
+        let (fasta, contigs) = match reference {
+            Some(path) => {
+                let fasta = faidx::Reader::from_path(path)
+                    .with_context(|| format!("Could not open indexed reference: {}", path))?;
+                let contigs =
+                    (0..fasta.n_seqs() as i32).map(|i| fasta.seq_name(i)).collect::<std::result::Result<_, _>>()?;
+                (Some(fasta), contigs)
+            }
+            None => (None, Vec::new()),
+        };
+        Ok(Self { fasta, contigs, cached: None, params: NormParams::default() })
+    }
+
+    pub fn with_params(mut self, params: NormParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn normalize(&mut self, processor: &mut VCFProcessor) -> Result<NormSummary> {
+        let numbers = FieldNumbers::from_meta(&processor.meta_lines);
+        let mut summary = NormSummary { total: processor.variants.len(), ..NormSummary::default() };
+        let mut variants = std::mem::take(&mut processor.variants);
+
+        if self.params.multiallelics == Some(MultiallelicMode::Split) {
+            let mut split = Vec::with_capacity(variants.len());
+            for variant in variants {
+                let alternates = variant.alternative.split(',').count();
+                if alternates > 1 {
+                    summary.split += 1;
+                    summary.split_into += alternates;
+                    split.extend((0..alternates).map(|i| split_allele(&variant, i, &numbers)));
+                } else {
+                    split.push(variant);
+                }
+            }
+            variants = split;
+        }
+
+        if self.fasta.is_some() {
+            for variant in &mut variants {
+                match self.realign(variant)? {
+                    Some(true) => summary.realigned += 1,
+                    Some(false) => {}
+                    None => summary.reference_mismatches += 1,
+                }
+            }
+            // Left-alignment can move records past their neighbours
+            let mut order: HashMap<String, usize> = HashMap::new();
+            for variant in &variants {
+                let next = order.len();
+                order.entry(variant.chromosome.clone()).or_insert(next);
+            }
+            variants.sort_by_key(|v| (order[&v.chromosome], v.position));
+        }
+
+        if self.params.multiallelics == Some(MultiallelicMode::Join) {
+            let before = variants.len();
+            variants = join_records(variants, &numbers);
+            summary.joined = before - variants.len();
+        }
+
+        if self.params.remove_duplicates {
+            let before = variants.len();
+            let mut seen = HashSet::new();
+            variants.retain(|v| {
+                seen.insert((v.chromosome.clone(), v.position, v.reference.to_uppercase(), v.alternative.to_uppercase()))
+            });
+            summary.duplicates = before - variants.len();
+        }
+
+        processor.variants = variants;
+        Ok(summary)
+    }
+
+    /// Left-align and trim one record; `None` when its REF does not match the reference
+    fn realign(&mut self, variant: &mut SNPVariant) -> Result<Option<bool>> {
+        let reference = variant.reference.to_ascii_uppercase().into_bytes();
+        let alternates: Vec<Vec<u8>> =
+            variant.alternative.split(',').map(|a| a.to_ascii_uppercase().into_bytes()).collect();
+        // Symbolic, breakend, spanning-deletion and missing alleles are left alone
+        if alternates.iter().any(|a| a.is_empty() || a.iter().any(|c| !c.is_ascii_alphabetic())) {
+            return Ok(Some(false));
+        }
+        if alternates.contains(&reference) || variant.position == 0 {
+            return Ok(Some(false));
+        }
+
+        let sequence = self.contig(&variant.chromosome)?;
+        let start = variant.position as usize - 1;
+        if sequence.get(start..start + reference.len()) != Some(&reference[..]) {
+            return Ok(None);
+        }
+
+        let mut alleles: Vec<Vec<u8>> = std::iter::once(reference).chain(alternates).collect();
+        let mut pos = start;
+        loop {
+            let mut changed = false;
+            let last = alleles[0].last().copied();
+            if last.is_some() && alleles.iter().all(|a| a.last().copied() == last) {
+                alleles.iter_mut().for_each(|a| {
+                    a.pop();
+                });
+                changed = true;
+            }
+            if alleles.iter().any(|a| a.is_empty()) {
+                if pos == 0 {
+                    // Nothing to the left at the contig start: anchor on the next base instead
+                    let Some(&next) = sequence.get(alleles[0].len()) else { return Ok(Some(false)) };
+                    alleles.iter_mut().for_each(|a| a.push(next));
+                    break;
+                }
+                pos -= 1;
+                alleles.iter_mut().for_each(|a| a.insert(0, sequence[pos]));
+                changed = true;
+            }
+            if !changed {
+                break;
+            }
+        }
+        while alleles.iter().all(|a| a.len() >= 2 && a[0] == alleles[0][0]) {
+            alleles.iter_mut().for_each(|a| {
+                a.remove(0);
+            });
+            pos += 1;
+        }
+
+        let new_reference = String::from_utf8_lossy(&alleles[0]).into_owned();
+        let new_alternates: Vec<String> = alleles[1..].iter().map(|a| String::from_utf8_lossy(a).into_owned()).collect();
+        let changed = pos as u64 + 1 != variant.position
+            || !new_reference.eq_ignore_ascii_case(&variant.reference)
+            || !new_alternates.join(",").eq_ignore_ascii_case(&variant.alternative);
+        if changed {
+            variant.position = pos as u64 + 1;
+            variant.reference = new_reference;
+            variant.alternative = new_alternates.join(",");
+        }
+        Ok(Some(changed))
+    }
+
+    fn contig(&mut self, name: &str) -> Result<&[u8]> {
+        if self.cached.as_ref().map(|(n, _)| n.as_str()) != Some(name) {
+            let Some(fasta) = &self.fasta else { bail!("Realignment needs a reference") };
+            if !self.contigs.iter().any(|c| c == name) {
+                bail!("Contig {} is missing from the reference", name);
+            }
+            let sequence = fasta.fetch_seq(name, 0, i64::MAX as usize)?.to_ascii_uppercase();
+            self.cached = Some((name.to_string(), sequence));
+        }
+        Ok(self.cached.as_ref().map(|(_, s)| s.as_slice()).unwrap_or_default())
+    }
+}
+
+/// Genotype of a sample as allele indices (`None` for missing) and whether it is phased
+pub fn parse_gt(gt: &str) -> (Vec<Option<usize>>, bool) {
+    let phased = gt.contains('|');
+    (gt.split(['/', '|']).map(|a| a.parse().ok()).collect(), phased)
+}
+
+fn format_gt(alleles: &[Option<usize>], phased: bool) -> String {
+    let separator = if phased { "|" } else { "/" };
+    alleles.iter().map(|a| a.map_or(".".to_string(), |a| a.to_string())).collect::<Vec<_>>().join(separator)
+}
+
+/// Values of a Number=A/R/G list for the alleles kept by a split
+fn subset_values(values: &str, number: Option<&str>, allele: usize, alleles: usize) -> String {
+    let list: Vec<&str> = values.split(',').collect();
+    let pick = |indices: &[usize]| -> String {
+        indices.iter().map(|&i| list.get(i).copied().unwrap_or(".")).collect::<Vec<_>>().join(",")
+    };
+    match number {
+        Some("A") if list.len() == alleles - 1 => pick(&[allele - 1]),
+        Some("R") if list.len() == alleles => pick(&[0, allele]),
+        Some("G") if list.len() == alleles * (alleles + 1) / 2 => {
+            pick(&[genotype_index(0, 0), genotype_index(0, allele), genotype_index(allele, allele)])
+        }
+        // Haploid likelihoods have one value per allele
+        Some("G") if list.len() == alleles => pick(&[0, allele]),
+        _ => values.to_string(),
+    }
+}
+
+/// Biallelic record for ALT allele `index`; other ALT alleles become REF in genotypes
+fn split_allele(variant: &SNPVariant, index: usize, numbers: &FieldNumbers) -> SNPVariant {
+    let alternates: Vec<&str> = variant.alternative.split(',').collect();
+    let alleles = alternates.len() + 1;
+    let allele = index + 1;
+
+    let info = variant
+        .info
+        .split(';')
+        .map(|entry| match entry.split_once('=') {
+            Some((key, value)) => {
+                format!("{}={}", key, subset_values(value, numbers.info.get(key).map(String::as_str), allele, alleles))
+            }
+            None => entry.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let keys: Vec<&str> = variant.format.split(':').collect();
+    let sample_fields: Vec<String> = variant
+        .sample_fields
+        .iter()
+        .map(|fields| {
+            fields
+                .split(':')
+                .zip(&keys)
+                .map(|(value, key)| {
+                    if *key == "GT" {
+                        let (gt, phased) = parse_gt(value);
+                        let mut gt: Vec<Option<usize>> =
+                            gt.iter().map(|a| a.map(|a| usize::from(a == allele))).collect();
+                        if !phased {
+                            gt.sort();
+                        }
+                        format_gt(&gt, phased)
+                    } else {
+                        subset_values(value, numbers.format.get(*key).map(String::as_str), allele, alleles)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(":")
+        })
+        .collect();
+
+    let mut split = variant.clone();
+    split.alternative = alternates[index].to_string();
+    split.info = info;
+    set_sample_fields(&mut split, sample_fields);
+    split
+}
+
+fn set_sample_fields(variant: &mut SNPVariant, sample_fields: Vec<String>) {
+    let gt_index = variant.format.split(':').position(|key| key == "GT");
+    variant.samples = sample_fields
+        .iter()
+        .map(|f| gt_index.and_then(|i| f.split(':').nth(i)).unwrap_or(".").to_string())
+        .collect();
+    variant.sample_fields = sample_fields;
+}
+
+/// Merge runs of records at the same position into multiallelic records
+fn join_records(variants: Vec<SNPVariant>, numbers: &FieldNumbers) -> Vec<SNPVariant> {
+    let mut joined: Vec<SNPVariant> = Vec::with_capacity(variants.len());
+    let mut group: Vec<SNPVariant> = Vec::new();
+    for variant in variants {
+        let joins = group.first().is_some_and(|first| {
+            first.chromosome == variant.chromosome
+                && first.position == variant.position
+                && (first.reference.starts_with(&variant.reference) || variant.reference.starts_with(&first.reference))
+        });
+        if !joins && !group.is_empty() {
+            joined.push(merge_group(std::mem::take(&mut group), numbers));
+        }
+        group.push(variant);
+    }
+    if !group.is_empty() {
+        joined.push(merge_group(group, numbers));
+    }
+    joined
+}
+
+/// One record from several at the same position, REF extended to the longest
+fn merge_group(mut group: Vec<SNPVariant>, numbers: &FieldNumbers) -> SNPVariant {
+    if group.len() == 1 {
+        return group.remove(0);
+    }
+    let reference = group.iter().map(|v| v.reference.clone()).max_by_key(|r| r.len()).unwrap_or_default();
+
+    // Merged ALT list, and each record's local allele index -> merged index
+    let mut alternates: Vec<String> = Vec::new();
+    let mut allele_maps: Vec<Vec<usize>> = Vec::new();
+    for variant in &group {
+        let suffix = &reference[variant.reference.len()..];
+        let mut map = vec![0];
+        for alt in variant.alternative.split(',') {
+            let padded = if alt.starts_with('<') || alt == "*" { alt.to_string() } else { format!("{}{}", alt, suffix) };
+            let index = match alternates.iter().position(|a| a.eq_ignore_ascii_case(&padded)) {
+                Some(i) => i,
+                None => {
+                    alternates.push(padded);
+                    alternates.len() - 1
+                }
+            };
+            map.push(index + 1);
+        }
+        allele_maps.push(map);
+    }
+    let alleles = alternates.len() + 1;
+
+    // Collect a Number=A/R/G field across records into merged allele order
+    let merge_values = |number: Option<&str>, values: &[Option<&str>]| -> String {
+        let first = values.iter().flatten().next().copied().unwrap_or(".");
+        let lists: Vec<Option<Vec<&str>>> = values.iter().map(|v| v.map(|v| v.split(',').collect())).collect();
+        let from = |allele: usize, offset: usize| -> &str {
+            (0..group.len())
+                .find_map(|r| {
+                    let local = allele_maps[r].iter().position(|&m| m == allele)?;
+                    lists[r].as_ref()?.get(local - offset).copied()
+                })
+                .unwrap_or(".")
+        };
+        match number {
+            Some("A") => (1..alleles).map(|a| from(a, 1)).collect::<Vec<_>>().join(","),
+            Some("R") => (0..alleles)
+                .map(|a| if a == 0 { lists.iter().flatten().next().and_then(|l| l.first().copied()).unwrap_or(".") } else { from(a, 0) })
+                .collect::<Vec<_>>()
+                .join(","),
+            Some("G") => (0..alleles * (alleles + 1) / 2)
+                .map(|g| {
+                    let (a, b) = genotype_alleles(g);
+                    (0..group.len())
+                        .find_map(|r| {
+                            let x = allele_maps[r].iter().position(|&m| m == a)?;
+                            let y = allele_maps[r].iter().position(|&m| m == b)?;
+                            lists[r].as_ref()?.get(genotype_index(x, y)).copied()
+                        })
+                        .unwrap_or(".")
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            _ => first.to_string(),
+        }
+    };
+
+    // INFO keys in first-seen order
+    let mut info_keys: Vec<&str> = Vec::new();
+    for variant in &group {
+        for entry in variant.info.split(';').filter(|e| !e.is_empty() && *e != ".") {
+            let key = entry.split_once('=').map_or(entry, |(k, _)| k);
+            if !info_keys.contains(&key) {
+                info_keys.push(key);
+            }
+        }
+    }
+    let info: Vec<String> = info_keys
+        .iter()
+        .map(|key| {
+            let values: Vec<Option<&str>> = group.iter().map(|v| v.info_value(key)).collect();
+            if values.iter().flatten().all(|v| v.is_empty()) {
+                return key.to_string();
+            }
+            format!("{}={}", key, merge_values(numbers.info.get(*key).map(String::as_str), &values))
+        })
+        .collect();
+
+    let mut format_keys: Vec<&str> = Vec::new();
+    for variant in &group {
+        for key in variant.format.split(':').filter(|k| !k.is_empty()) {
+            if !format_keys.contains(&key) {
+                format_keys.push(key);
+            }
+        }
+    }
+    let samples = group[0].sample_fields.len();
+    let sample_fields: Vec<String> = (0..samples)
+        .map(|s| {
+            format_keys
+                .iter()
+                .map(|key| {
+                    let values: Vec<Option<&str>> = group.iter().map(|v| v.format_value(s, key)).collect();
+                    if *key == "GT" {
+                        merge_genotypes(&values, &allele_maps)
+                    } else {
+                        merge_values(numbers.format.get(*key).map(String::as_str), &values)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(":")
+        })
+        .collect();
+
+    let filters: Vec<&str> = group.iter().map(|v| v.filter.as_str()).collect();
+    let filter = if filters.iter().all(|f| *f == filters[0]) {
+        filters[0].to_string()
+    } else {
+        let mut failed: Vec<&str> = filters.iter().flat_map(|f| f.split(';')).filter(|f| *f != "PASS" && *f != ".").collect();
+        failed.dedup();
+        if failed.is_empty() { "PASS".to_string() } else { failed.join(";") }
+    };
+
+    let mut merged = group[0].clone();
+    merged.reference = reference;
+    merged.alternative = alternates.join(",");
+    merged.quality = group.iter().map(|v| v.quality).filter(|q| !q.is_nan()).reduce(f64::max).unwrap_or(f64::NAN);
+    merged.filter = filter;
+    merged.info = if info.is_empty() { ".".to_string() } else { info.join(";") };
+    merged.format = format_keys.join(":");
+    set_sample_fields(&mut merged, sample_fields);
+    merged
+}
+
+/// Combine a sample's biallelic genotypes: each record's ALT copies fill the REF slots
+fn merge_genotypes(values: &[Option<&str>], allele_maps: &[Vec<usize>]) -> String {
+    let parsed: Vec<(Vec<Option<usize>>, bool)> = values.iter().map(|v| parse_gt(v.unwrap_or("."))).collect();
+    let Some((base, phased)) = parsed.iter().find(|(gt, _)| gt.iter().any(Option::is_some)).cloned() else {
+        return parsed.first().map_or(".".to_string(), |(gt, phased)| format_gt(gt, *phased));
+    };
+    let mut merged: Vec<Option<usize>> = base.iter().map(|a| a.map(|_| 0)).collect();
+    if phased {
+        // Phased genotypes keep each ALT on its haplotype
+        for ((gt, _), map) in parsed.iter().zip(allele_maps) {
+            for (slot, allele) in merged.iter_mut().zip(gt) {
+                if let (Some(0), Some(a)) = (*slot, allele) {
+                    if *a > 0 {
+                        *slot = map.get(*a).copied();
+                    }
+                }
+            }
+        }
+        return format_gt(&merged, true);
+    }
+    let mut alts: Vec<usize> = parsed
+        .iter()
+        .zip(allele_maps)
+        .flat_map(|((gt, _), map)| gt.iter().flatten().filter(|&&a| a > 0).filter_map(|&a| map.get(a).copied()).collect::<Vec<_>>())
+        .collect();
+    alts.truncate(merged.len());
+    for (slot, alt) in merged.iter_mut().rev().zip(alts.iter().rev()) {
+        *slot = Some(*alt);
+    }
+    merged.sort();
+    format_gt(&merged, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(sequence: &str) -> Normalizer {
+        Normalizer {
+            fasta: None,
+            contigs: vec!["chr1".to_string()],
+            cached: Some(("chr1".to_string(), sequence.as_bytes().to_vec())),
+            params: NormParams::default(),
+        }
+    }
+
+    fn realign(sequence: &str, position: u64, reference: &str, alternative: &str) -> (Option<bool>, u64, String, String) {
+        let mut variant = SNPVariant {
+            chromosome: "chr1".to_string(),
+            position,
+            id: ".".to_string(),
+            reference: reference.to_string(),
+            alternative: alternative.to_string(),
+            quality: f64::NAN,
+            filter: ".".to_string(),
+            info: ".".to_string(),
+            format: String::new(),
+            samples: Vec::new(),
+            sample_fields: Vec::new(),
+        };
+        let changed = normalizer(sequence).realign(&mut variant).unwrap();
+        (changed, variant.position, variant.reference, variant.alternative)
+    }
+
+    #[test]
+    fn deletion_in_a_repeat_moves_left() {
+        assert_eq!(realign("GCACACAT", 4, "CAC", "C"), (Some(true), 1, "GCA".into(), "G".into()));
+    }
+
+    #[test]
+    fn contig_start_is_right_padded() {
+        // Left-shifting runs into POS 1, so the next reference base anchors the allele
+        assert_eq!(realign("AAAC", 2, "AA", "A"), (Some(true), 1, "AA".into(), "A".into()));
+        assert_eq!(realign("AAAC", 1, "AA", "A"), (Some(false), 1, "AA".into(), "A".into()));
+        assert_eq!(realign("ACGT", 1, "A", "TA"), (Some(false), 1, "A".into(), "TA".into()));
+        assert_eq!(realign("TTTG", 3, "T", "TT"), (Some(true), 1, "T".into(), "TT".into()));
+    }
+
+    #[test]
+    fn whole_contig_deletion_is_left_alone() {
+        assert_eq!(realign("AA", 1, "AA", "A"), (Some(false), 1, "AA".into(), "A".into()));
+    }
+
+    #[test]
+    fn mismatched_reference_is_reported() {
+        assert_eq!(realign("ACGT", 2, "G", "T"), (None, 2, "G".into(), "T".into()));
+    }
+
+    #[test]
+    fn multiallelic_record_trims_shared_bases() {
+        assert_eq!(realign("ACGTTT", 3, "GTT", "GT,G"), (Some(false), 3, "GTT".into(), "GT,G".into()));
+        assert_eq!(realign("ACGTTT", 2, "CGTT", "CGT,CGTTT"), (Some(true), 3, "GT".into(), "G,GTT".into()));
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
 use anyhow::{Result, Context};
 use flate2::read::GzDecoder;
@@ -12,8 +12,49 @@ pub struct SNPVariant {
     pub id: String,
     pub reference: String,
     pub alternative: String,
+    /// NaN when the QUAL column is missing (`.`)
     pub quality: f64,
+    pub filter: String,
+    pub info: String,
+    pub format: String,
+    /// Genotype (GT) of each sample
     pub samples: Vec<String>,
+    /// Full FORMAT values of each sample
+    pub sample_fields: Vec<String>,
+}
+
+impl SNPVariant {
+    /// Value of an INFO key; flags yield an empty string
+    pub fn info_value(&self, key: &str) -> Option<&str> {
+        self.info.split(';').find_map(|entry| match entry.split_once('=') {
+            Some((k, value)) if k == key => Some(value),
+            None if entry == key => Some(""),
+            _ => None,
+        })
+    }
+
+    /// Value of a FORMAT key for one sample
+    pub fn format_value(&self, sample: usize, key: &str) -> Option<&str> {
+        let index = self.format.split(':').position(|k| k == key)?;
+        self.sample_fields.get(sample)?.split(':').nth(index)
+    }
+
+    pub fn to_vcf_line(&self) -> String {
+        let quality = if self.quality.is_nan() { ".".to_string() } else { self.quality.to_string() };
+        let mut line = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.chromosome, self.position, self.id, self.reference, self.alternative, quality, self.filter, self.info
+        );
+        if !self.sample_fields.is_empty() {
+            line.push('\t');
+            line.push_str(&self.format);
+            for fields in &self.sample_fields {
+                line.push('\t');
+                line.push_str(fields);
+            }
+        }
+        line
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +68,8 @@ pub struct Population {
 pub struct VCFProcessor {
     pub variants: Vec<SNPVariant>,
     pub samples: Vec<String>,
+    /// `##` meta-information lines, in file order
+    pub meta_lines: Vec<String>,
     pub populations: HashMap<String, Population>,
 }
 
@@ -35,6 +78,7 @@ impl VCFProcessor {
         Self {
             variants: Vec::new(),
             samples: Vec::new(),
+            meta_lines: Vec::new(),
             populations: HashMap::new(),
         }
     }
@@ -60,8 +104,9 @@ impl VCFProcessor {
             let line = line?;
             line_count += 1;
 
-            // Skip comments and meta-information
+            // Keep meta-information for writing the file back out
             if line.starts_with("##") {
+                self.meta_lines.push(line);
                 continue;
             }
 
@@ -94,6 +139,26 @@ impl VCFProcessor {
         Ok(())
     }
 
+    /// Write the loaded variants back out as a VCF
+    pub fn write_vcf(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create VCF file: {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        for line in &self.meta_lines {
+            writeln!(out, "{}", line)?;
+        }
+        write!(out, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO")?;
+        if !self.samples.is_empty() {
+            write!(out, "\tFORMAT\t{}", self.samples.join("\t"))?;
+        }
+        writeln!(out)?;
+        for variant in &self.variants {
+            writeln!(out, "{}", variant.to_vcf_line())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
     /// Parse population panel file (e.g., from 1000 Genomes)
     pub fn parse_population_panel(&mut self, panel_path: &Path) -> Result<()> {
         println!("🌍 Loading population data: {}", panel_path.display());
@@ -263,16 +328,19 @@ impl VCFProcessor {
     fn parse_variant_line(&self, line: &str) -> Result<SNPVariant> {
         let fields: Vec<&str> = line.split('\t').collect();
         
-        if fields.len() < 9 {
+        if fields.len() < 8 {
             return Err(anyhow::anyhow!("Invalid VCF line format"));
         }
 
-        let quality = fields[5].parse::<f64>().unwrap_or(0.0);
+        let quality = fields[5].parse::<f64>().unwrap_or(f64::NAN);
+        let format = fields.get(8).copied().unwrap_or("");
         
-        // Parse sample genotypes
+        // Parse sample genotypes, keeping only the GT subfield
+        let gt_index = format.split(':').position(|key| key == "GT").unwrap_or(0);
         let mut sample_genotypes = Vec::new();
-        for i in 9..fields.len() {
-            sample_genotypes.push(fields[i].to_string());
+        for sample in fields.iter().skip(9) {
+            let genotype = sample.split(':').nth(gt_index).unwrap_or(".");
+            sample_genotypes.push(genotype.to_string());
         }
 
         Ok(SNPVariant {
@@ -282,7 +350,11 @@ impl VCFProcessor {
             reference: fields[3].to_string(),
             alternative: fields[4].to_string(),
             quality,
+            filter: fields[6].to_string(),
+            info: fields[7].to_string(),
+            format: format.to_string(),
             samples: sample_genotypes,
+            sample_fields: fields.iter().skip(9).map(|f| f.to_string()).collect(),
         })
     }
 